@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{fs::File, io, os::unix::io::AsRawFd};
+use std::{fs::File, io, os::unix::io::AsRawFd, time::Duration};
 
 use libstratis::{
     engine::{DeleteAction, KeyActions, KeyDescription, MappingCreateAction, StratKeyActions},
@@ -14,17 +14,22 @@ use libstratis::{
 /// is true, this command assumes that no TTY is available for setting terminal
 /// settings and settings such as `NOECHO` are not set. This option should be
 /// used carefully as it will cause the password to be echoed on the screen if
-/// invoked interactively.
-pub fn key_set(key_desc: &KeyDescription, keyfile_path: Option<&str>) -> StratisResult<()> {
+/// invoked interactively. If a timeout is given, the key is removed from the
+/// kernel keyring once the timeout has elapsed.
+pub fn key_set(
+    key_desc: &KeyDescription,
+    keyfile_path: Option<&str>,
+    timeout: Option<Duration>,
+) -> StratisResult<()> {
     let ret = match keyfile_path {
         Some(kp) => {
             let file = File::open(kp)?;
-            StratKeyActions.set(key_desc, file.as_raw_fd())?
+            StratKeyActions.set(key_desc, file.as_raw_fd(), timeout)?
         }
         None => {
             let stdin_fd = io::stdin().as_raw_fd();
             println!("Enter desired key data followed by the return key:");
-            StratKeyActions.set(key_desc, stdin_fd)?
+            StratKeyActions.set(key_desc, stdin_fd, timeout)?
         }
     };
     match ret {
@@ -49,9 +54,16 @@ pub fn key_unset(key_desc: &KeyDescription) -> StratisResult<()> {
 
 pub fn key_list() -> StratisResult<()> {
     let keys = StratKeyActions.list()?;
-    println!("Key description");
-    for key in keys.iter() {
-        println!("{}", key.as_application_str());
+    println!("{:<40} Remaining lifetime", "Key description");
+    for (key, lifetime) in keys.iter() {
+        println!(
+            "{:<40} {}",
+            key.as_application_str(),
+            match lifetime {
+                Some(l) => format!("{}s", l.as_secs()),
+                None => "permanent".to_string(),
+            }
+        );
     }
     Ok(())
 }
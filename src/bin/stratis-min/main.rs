@@ -2,36 +2,71 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, time::Duration};
 
 use clap::{App, Arg, ArgGroup, SubCommand};
-use libstratis::engine::KeyDescription;
+use libstratis::engine::{KeyDescription, PoolUuid};
 
 mod key;
+mod pool;
 
 fn parse_args() -> App<'static, 'static> {
-    App::new("stratis-min").subcommands(vec![SubCommand::with_name("key").subcommands(vec![
-        SubCommand::with_name("set")
-            .group(
-                ArgGroup::with_name("key_method")
-                    .arg("capture_key")
-                    .arg("keyfile_path")
-                    .required(true),
-            )
-            .arg(
-                Arg::with_name("capture_key")
-                    .long("--capture-key")
-                    .takes_value(false),
-            )
-            .arg(
-                Arg::with_name("keyfile_path")
-                    .long("--keyfile-path")
-                    .takes_value(true),
-            )
-            .arg(Arg::with_name("key_desc").required(true)),
-        SubCommand::with_name("list"),
-        SubCommand::with_name("unset").arg(Arg::with_name("key_desc").required(true)),
-    ])])
+    App::new("stratis-min").subcommands(vec![
+        SubCommand::with_name("key").subcommands(vec![
+            SubCommand::with_name("set")
+                .group(
+                    ArgGroup::with_name("key_method")
+                        .arg("capture_key")
+                        .arg("keyfile_path")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("capture_key")
+                        .long("--capture-key")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("keyfile_path")
+                        .long("--keyfile-path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("--timeout")
+                        .takes_value(true)
+                        .help("Number of seconds after which the key expires"),
+                )
+                .arg(Arg::with_name("key_desc").required(true)),
+            SubCommand::with_name("list"),
+            SubCommand::with_name("unset").arg(Arg::with_name("key_desc").required(true)),
+        ]),
+        SubCommand::with_name("pool").subcommands(vec![
+            SubCommand::with_name("metadata-backup")
+                .about("Write an archive of the metadata of a pool that is not set up")
+                .arg(
+                    Arg::with_name("output")
+                        .long("--output")
+                        .takes_value(true)
+                        .help("New file to which the archive is written, instead of stdout"),
+                )
+                .arg(Arg::with_name("pool_uuid").required(true)),
+            SubCommand::with_name("metadata-restore")
+                .about("Restore the metadata of a pool that is not set up from an archive")
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("--dry-run")
+                        .takes_value(false)
+                        .help("Verify the archive against the devices, but write nothing"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("--force")
+                        .takes_value(false)
+                        .help("Restore even if the pool layout in the archive differs from that on the devices or can not be verified, or a device without a signature can not be identified by its WWN"),
+                )
+                .arg(Arg::with_name("archive").required(true)),
+        ]),
+    ])
 }
 
 fn main() -> Result<(), String> {
@@ -42,10 +77,20 @@ fn main() -> Result<(), String> {
     let args = app.get_matches();
     if let Some(subcommand) = args.subcommand_matches("key") {
         if let Some(args) = subcommand.subcommand_matches("set") {
+            let timeout = match args.value_of("timeout") {
+                Some(secs) => Some(Duration::from_secs(secs.parse::<u64>().map_err(|e| {
+                    format!(
+                        "Invalid timeout {}; expected a number of seconds: {}",
+                        secs, e
+                    )
+                })?)),
+                None => None,
+            };
             key::key_set(
                 &KeyDescription::try_from(args.value_of("key_desc").expect("required").to_owned())
                     .map_err(|e| e.to_string())?,
                 args.value_of("keyfile_path"),
+                timeout,
             )
             .map_err(|e| e.to_string())
         } else if let Some(args) = subcommand.subcommand_matches("unset") {
@@ -57,6 +102,25 @@ fn main() -> Result<(), String> {
         } else {
             key::key_list().map_err(|e| e.to_string())
         }
+    } else if let Some(subcommand) = args.subcommand_matches("pool") {
+        if let Some(args) = subcommand.subcommand_matches("metadata-backup") {
+            let uuid = args.value_of("pool_uuid").expect("required");
+            pool::pool_metadata_backup(
+                PoolUuid::parse_str(uuid).map_err(|e| e.to_string())?,
+                args.value_of("output"),
+            )
+            .map_err(|e| e.to_string())
+        } else if let Some(args) = subcommand.subcommand_matches("metadata-restore") {
+            pool::pool_metadata_restore(
+                args.value_of("archive").expect("required"),
+                args.is_present("dry_run"),
+                args.is_present("force"),
+            )
+            .map_err(|e| e.to_string())
+        } else {
+            println!("{}", help);
+            Ok(())
+        }
     } else {
         println!("{}", help);
         Ok(())
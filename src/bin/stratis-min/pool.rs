@@ -0,0 +1,44 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+};
+
+use serde_json::Value;
+
+use libstratis::{
+    engine::{backup_pool_metadata, restore_pool_metadata, PoolUuid},
+    stratis::StratisResult,
+};
+
+/// This method writes an archive of the metadata of a pool that is not set
+/// up to the given file, or to stdout if no file is given. The file must not
+/// already exist.
+pub fn pool_metadata_backup(pool_uuid: PoolUuid, output_path: Option<&str>) -> StratisResult<()> {
+    let archive = serde_json::to_string_pretty(&backup_pool_metadata(pool_uuid)?)?;
+    match output_path {
+        Some(path) => {
+            let mut f = OpenOptions::new().write(true).create_new(true).open(path)?;
+            f.write_all(archive.as_bytes())?;
+            f.sync_all()?;
+        }
+        None => println!("{}", archive),
+    }
+    Ok(())
+}
+
+/// This method restores the metadata of a pool that is not set up from the
+/// archive in the given file and prints what was restored. If `dry_run` is
+/// true, the archive is verified against the devices but nothing is written.
+/// If `force` is true, the metadata is restored even if the layout of the pool
+/// in the archive differs from that recorded on the devices or can not be
+/// verified, or if some device without a signature can not be identified.
+pub fn pool_metadata_restore(archive_path: &str, dry_run: bool, force: bool) -> StratisResult<()> {
+    let archive: Value = serde_json::from_reader(File::open(archive_path)?)?;
+    let report = restore_pool_metadata(&archive, dry_run, force)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
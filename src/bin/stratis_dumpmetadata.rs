@@ -2,64 +2,101 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{
-    env,
-    fs::OpenOptions,
-    io::{Seek, SeekFrom},
-    process,
-    vec::Vec,
-};
+use std::{path::Path, process};
 
-use serde_json::Value;
+use clap::{App, Arg, ArgGroup};
 
-use libstratis::engine::BDA;
+use libstratis::engine::{dump_device, dump_filesystem_records, PoolUuid};
 
-fn run(devpath: &str) -> Result<(), String> {
-    let mut devfile = OpenOptions::new()
-        .read(true)
-        .open(&devpath)
-        .map_err(|the_io_error| format!("Error opening device: {}", the_io_error))?;
+fn parse_args() -> App<'static, 'static> {
+    App::new("stratis_dumpmetadata")
+        .about("Print the Stratis metadata on a device, or the filesystem records of a pool, as JSON")
+        .after_help(
+            "For a device, prints both copies of the signature block and every MDA \
+             region header, whether or not they are valid. With --filesystems, the \
+             pool must be stopped; its metadata volume is mounted read-only on a \
+             temporary device while its records are read.",
+        )
+        .group(
+            ArgGroup::with_name("target")
+                .arg("device")
+                .arg("filesystems")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("device")
+                .help("Device whose metadata is printed"),
+        )
+        .arg(
+            Arg::with_name("decode")
+                .long("--decode")
+                .requires("device")
+                .help("Include the pool metadata held in each MDA region"),
+        )
+        .arg(
+            Arg::with_name("region")
+                .long("--region")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("device")
+                .help("Print only the MDA region with this index; may be given more than once"),
+        )
+        .arg(
+            Arg::with_name("filesystems")
+                .long("--filesystems")
+                .takes_value(true)
+                .value_name("POOL_UUID")
+                .help("Print the filesystem records on the metadata volume of the pool with this UUID"),
+        )
+}
 
-    let bda = BDA::load(&mut devfile)
-        .map_err(|bda_load_error| format!("BDA detected but error found: {}", bda_load_error))?
-        .ok_or_else(|| "No Stratis BDA metadata found".to_string())?;
-    println!("{:#?}", bda);
+fn run() -> Result<(), String> {
+    let args = parse_args().get_matches();
 
-    devfile
-        .seek(SeekFrom::Start(0))
-        .map_err(|seek_err| format!("Error during seek: {}", seek_err))?;
+    let output = match args.value_of("filesystems") {
+        Some(uuid) => {
+            let pool_uuid = PoolUuid::parse_str(uuid)
+                .map_err(|e| format!("Invalid pool UUID {}: {}", uuid, e))?;
+            dump_filesystem_records(pool_uuid)
+                .map_err(|e| format!("Error reading filesystem records: {}", e))?
+        }
+        None => {
+            let devpath = args.value_of("device").expect("required by group");
+            let regions = match args.values_of("region") {
+                Some(indices) => Some(
+                    indices
+                        .map(|index| {
+                            index
+                                .parse::<usize>()
+                                .map_err(|e| format!("Invalid region index {}: {}", index, e))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+                None => None,
+            };
 
-    let loaded_state = bda
-        .load_state(&mut devfile)
-        .map_err(|stateload_err| format!("Error during load state: {}", stateload_err))?;
-    println!("Pool metadata:");
+            dump_device(
+                Path::new(devpath),
+                args.is_present("decode"),
+                regions.as_deref(),
+            )
+            .map_err(|e| format!("Error reading device: {}", e))?
+        }
+    };
 
-    if let Some(loaded_state) = loaded_state {
-        let state_json: Value = serde_json::from_slice(&loaded_state)
-            .map_err(|extract_err| format!("Error during state JSON extract: {}", extract_err))?;
-        let state_json_pretty: String = serde_json::to_string_pretty(&state_json)
-            .map_err(|parse_err| format!("Error during state JSON parse: {}", parse_err))?;
-        println!("{}", state_json_pretty);
-    } else {
-        println!("None found");
-    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&output)
+            .map_err(|e| format!("Error during JSON output: {}", e))?
+    );
 
     Ok(())
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: stratis_dumpmetadata <device>");
-        process::exit(2);
-    }
-    let devpath = &args[1];
-
-    match run(devpath) {
-        Ok(()) => {}
-        Err(e) => {
-            eprintln!("Error encountered: {}", e);
-            process::exit(1);
-        }
+    if let Err(e) = run() {
+        eprintln!("Error encountered: {}", e);
+        process::exit(1);
     }
 }
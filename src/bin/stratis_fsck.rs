@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{path::PathBuf, process};
+
+use chrono::{DateTime, Utc};
+use clap::{App, Arg, ArgGroup};
+use serde_json::Value;
+
+use libstratis::engine::{fsck_devices, fsck_pool, repair_devices, repair_pool, PoolUuid};
+
+/// Exit code if the check could be run but found errors in the metadata.
+const EXIT_ERRORS_FOUND: i32 = 4;
+
+/// Exit code if the check could not be run.
+const EXIT_FAILED: i32 = 8;
+
+fn parse_args() -> App<'static, 'static> {
+    App::new("stratis_fsck")
+        .about("Check the metadata of a Stratis pool that is not set up, without modifying it")
+        .after_help(
+            "Prints a JSON report of the problems found, with suggested repairs. \
+             With --repair, stop stratisd and tear down the pool first; its devices \
+             are held exclusively during the repair, and the report is of the \
+             repaired metadata. Exits with 0 if no errors were found, 4 if errors were \
+             found, and 8 if the check or the repair could not be run.",
+        )
+        .group(
+            ArgGroup::with_name("target")
+                .arg("pool_uuid")
+                .arg("devices")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("pool_uuid")
+                .long("--pool")
+                .takes_value(true)
+                .help("UUID of the pool to check; all its devices are located using udev"),
+        )
+        .arg(
+            Arg::with_name("devices")
+                .multiple(true)
+                .help("Devices of the pool to check; only these devices are read"),
+        )
+        .arg(
+            Arg::with_name("repair")
+                .long("--repair")
+                .help("Before checking, rewrite the metadata on every device of the pool with a good copy"),
+        )
+        .arg(
+            Arg::with_name("copy_time")
+                .long("--copy-time")
+                .takes_value(true)
+                .requires("repair")
+                .help("Repair with the copy of the metadata written at this time, as given in the report, rather than the newest good copy"),
+        )
+}
+
+fn run() -> Result<bool, String> {
+    let args = parse_args().get_matches();
+
+    let pool_uuid = match args.value_of("pool_uuid") {
+        Some(uuid) => Some(
+            PoolUuid::parse_str(uuid).map_err(|e| format!("Invalid pool UUID {}: {}", uuid, e))?,
+        ),
+        None => None,
+    };
+    let devices = args
+        .values_of("devices")
+        .map(|devices| devices.map(PathBuf::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let repaired = if args.is_present("repair") {
+        let copy_time = match args.value_of("copy_time") {
+            Some(time) => Some(
+                DateTime::parse_from_rfc3339(time)
+                    .map_err(|e| format!("Invalid time {}: {}", time, e))?
+                    .with_timezone(&Utc),
+            ),
+            None => None,
+        };
+        let repaired = match pool_uuid {
+            Some(pool_uuid) => repair_pool(pool_uuid, copy_time),
+            None => repair_devices(&devices, copy_time),
+        }
+        .map_err(|e| format!("Error repairing pool metadata: {}", e))?;
+        Some(repaired)
+    } else {
+        None
+    };
+
+    let report = match pool_uuid {
+        Some(pool_uuid) => fsck_pool(pool_uuid),
+        None => fsck_devices(&devices),
+    }
+    .map_err(|e| format!("Error checking pool metadata: {}", e))?;
+
+    let mut report_json: Value = (&report).into();
+    if let (Some(repaired), Value::Object(map)) = (repaired, &mut report_json) {
+        map.insert(
+            "repaired_devices".into(),
+            Value::Array(
+                repaired
+                    .iter()
+                    .map(|devnode| Value::from(devnode.display().to_string()))
+                    .collect(),
+            ),
+        );
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report_json)
+            .map_err(|e| format!("Error during report JSON output: {}", e))?
+    );
+
+    Ok(report.has_errors())
+}
+
+fn main() {
+    match run() {
+        Ok(false) => {}
+        Ok(true) => process::exit(EXIT_ERRORS_FOUND),
+        Err(e) => {
+            eprintln!("Error encountered: {}", e);
+            process::exit(EXIT_FAILED);
+        }
+    }
+}
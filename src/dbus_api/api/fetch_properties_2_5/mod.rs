@@ -0,0 +1,4 @@
+mod api;
+mod methods;
+
+pub use api::{get_all_properties_method, get_properties_method};
@@ -18,7 +18,7 @@ use crate::{
 };
 
 pub fn create_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
-    create_pool_shared(m, false)
+    create_pool_shared(m, false, false)
 }
 
 pub fn destroy_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
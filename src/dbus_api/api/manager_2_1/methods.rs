@@ -19,11 +19,11 @@ use crate::{
 };
 
 pub fn create_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
-    create_pool_shared(m, true)
+    create_pool_shared(m, true, false)
 }
 
 pub fn set_key(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
-    set_key_shared(m)
+    set_key_shared(m, false)
 }
 
 pub fn unset_key(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
@@ -60,5 +60,5 @@ pub fn unset_key(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
 }
 
 pub fn unlock_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
-    unlock_pool_shared(m, false)
+    unlock_pool_shared(m, false, false)
 }
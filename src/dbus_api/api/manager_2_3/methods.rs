@@ -7,5 +7,5 @@ use dbus::tree::{MTFn, MethodInfo, MethodResult};
 use crate::dbus_api::{api::shared::unlock_pool_shared, types::TData};
 
 pub fn unlock_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
-    unlock_pool_shared(m, true)
+    unlock_pool_shared(m, true, false)
 }
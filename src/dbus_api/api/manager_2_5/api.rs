@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::tree::{Factory, MTFn, Method};
+
+use crate::dbus_api::{
+    api::manager_2_5::methods::{create_pool, encrypt_pool, set_key, unlock_pool},
+    types::TData,
+};
+
+pub fn create_pool_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("CreatePool", (), create_pool)
+        .in_arg(("name", "s"))
+        .in_arg(("redundancy", "(bq)"))
+        .in_arg(("devices", "as"))
+        // Optional key description of key in the kernel keyring
+        // b: true if the pool should be encrypted
+        // s: key description
+        //
+        // Rust representation: (bool, String)
+        .in_arg(("key_desc", "(bs)"))
+        // Optional parameters with which to format the encrypted devices
+        // b: true if the parameters are specified
+        // a{sv}: Map from the name of each parameter to its value; any
+        //        parameter not in the map takes its default value
+        //   Cipher: s, e.g., "aes-xts-plain64"
+        //   KeySize: u, size of the media encryption key in bits
+        //   Pbkdf: s, one of "pbkdf2", "argon2i", "argon2id"
+        //   PbkdfMemory: u, memory cost of an argon2 PBKDF in KiB
+        //   PbkdfTime: u, target time to spend on the PBKDF in ms
+        //   PbkdfParallelThreads: u, threads used by an argon2 PBKDF
+        //   SectorSize: u, encryption sector size in bytes
+        //
+        // Rust representation: (bool, HashMap<String, Variant<Box<dyn RefArg>>>)
+        .in_arg(("encryption_params", "(ba{sv})"))
+        // In order from left to right:
+        // b: true if a pool was created and object paths were returned
+        // o: Object path for Pool
+        // a(o): Array of object paths for block devices
+        //
+        // Rust representation: (bool, (dbus::Path, Vec<dbus::Path>))
+        .out_arg(("result", "(b(oao))"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn set_key_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("SetKey", (), set_key)
+        .in_arg(("key_desc", "s"))
+        .in_arg(("key_fd", "h"))
+        .in_arg(("interactive", "b"))
+        // Optional timeout after which the key expires and is removed from
+        // the kernel keyring
+        // b: true if the key should expire
+        // u: timeout in seconds; must be at least 1
+        //
+        // Rust representation: (bool, u32)
+        .in_arg(("timeout", "(bu)"))
+        // b: true if the key state was changed in the kernel keyring.
+        // b: true if the key description already existed in the kernel keyring and
+        //    the key data or its expiry has been changed.
+        //
+        // Rust representation: (bool, bool)
+        .out_arg(("result", "(bb)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn encrypt_pool_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("EncryptPool", (), encrypt_pool)
+        .in_arg(("pool_uuid", "s"))
+        // Key description of the key in the kernel keyring with which to
+        // encrypt the data devices
+        .in_arg(("key_desc", "s"))
+        // Optional parameters with which to format the encrypted devices,
+        // as for CreatePool
+        //
+        // Rust representation: (bool, HashMap<String, Variant<Box<dyn RefArg>>>)
+        .in_arg(("encryption_params", "(ba{sv})"))
+        // b: true if the data devices of the pool were encrypted
+        .out_arg(("result", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn unlock_pool_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("UnlockPool", (), unlock_pool)
+        .in_arg(("pool_uuid", "s"))
+        // One of "keyring", "clevis" or "passphrase"
+        .in_arg(("unlock_method", "s"))
+        // Optional file descriptor from which to read the passphrase; must be
+        // given if and only if the unlock method is "passphrase". The
+        // passphrase is not stored in the kernel keyring.
+        // b: true if a file descriptor is given
+        // h: file descriptor; ignored if b is false
+        //
+        // Rust representation: (bool, OwnedFd)
+        .in_arg(("passphrase_fd", "(bh)"))
+        // b: true if some encrypted devices were newly opened.
+        // as: array of device UUIDs converted to Strings of all of the newly opened
+        //     devices.
+        //
+        // Rust representation: (bool, Vec<DevUuid>)
+        .out_arg(("result", "(bas)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
@@ -0,0 +1,143 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use dbus::{
+    arg::{RefArg, Variant},
+    tree::{MTFn, MethodInfo, MethodResult},
+    Message,
+};
+
+use crate::{
+    dbus_api::{
+        api::shared::{
+            create_pool_shared, encryption_params_from_map, set_key_shared, unlock_pool_shared,
+        },
+        blockdev::create_dbus_blockdev,
+        consts,
+        filesystem::create_dbus_filesystem,
+        pool::create_dbus_pool,
+        types::TData,
+        util::{
+            engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok, tuple_to_option,
+        },
+    },
+    engine::{CreateAction, KeyDescription, PoolUuid},
+    stratis::{ErrorEnum, StratisError},
+};
+
+pub fn create_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    create_pool_shared(m, true, true)
+}
+
+pub fn set_key(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    set_key_shared(m, true)
+}
+
+pub fn unlock_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    unlock_pool_shared(m, true, true)
+}
+
+pub fn encrypt_pool(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    let pool_uuid_str: &str = get_next_arg(&mut iter, 0)?;
+    let key_desc_str: String = get_next_arg(&mut iter, 1)?;
+    let encryption_params_tuple: (bool, HashMap<String, Variant<Box<dyn RefArg>>>) =
+        get_next_arg(&mut iter, 2)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let pool_uuid = match PoolUuid::parse_str(pool_uuid_str) {
+        Ok(uuid) => uuid,
+        Err(e) => {
+            let e = StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!("Malformed UUID passed to EncryptPool: {}", e),
+            );
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+    let key_desc = match KeyDescription::try_from(key_desc_str) {
+        Ok(kd) => kd,
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+    let encryption_params = match tuple_to_option(encryption_params_tuple) {
+        Some(map) => match encryption_params_from_map(&map) {
+            Ok(params) => Some(params),
+            Err(e) => {
+                let (rc, rs) = engine_to_dbus_err_tuple(&e);
+                return Ok(vec![return_message.append3(default_return, rc, rs)]);
+            }
+        },
+        None => None,
+    };
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    // The pool is stopped while its devices are encrypted, so its objects
+    // are replaced by new ones once it has been set up again.
+    let old_pool_path = engine
+        .get_pool(pool_uuid)
+        .and_then(|(_, pool)| pool.get_dbus_path().0.clone());
+
+    let result = log_action!(engine.encrypt_pool(pool_uuid, &key_desc, encryption_params));
+
+    if !matches!(result, Ok(CreateAction::Identity)) {
+        if let Some(ref path) = old_pool_path {
+            if result.is_ok() || engine.get_pool(pool_uuid).is_none() {
+                dbus_context.actions.borrow_mut().push_remove(
+                    path,
+                    m.tree,
+                    consts::pool_interface_list(),
+                );
+            }
+        }
+    }
+
+    let msg = match result {
+        Ok(CreateAction::Created(_)) => {
+            let (pool_name, pool) =
+                get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+            let pool_path = create_dbus_pool(
+                dbus_context,
+                object_path.clone(),
+                &pool_name,
+                pool_uuid,
+                pool,
+            );
+            for (fs_name, fs_uuid, fs) in pool.filesystems_mut() {
+                create_dbus_filesystem(
+                    dbus_context,
+                    pool_path.clone(),
+                    &pool_name,
+                    &fs_name,
+                    fs_uuid,
+                    fs,
+                );
+            }
+            for (uuid, tier, bd) in pool.blockdevs_mut() {
+                create_dbus_blockdev(dbus_context, pool_path.clone(), uuid, tier, bd);
+            }
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Ok(CreateAction::Identity) => {
+            return_message.append3(default_return, msg_code_ok(), msg_string_ok())
+        }
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
@@ -0,0 +1,4 @@
+mod api;
+mod methods;
+
+pub use api::{create_pool_method, encrypt_pool_method, set_key_method, unlock_pool_method};
@@ -2,10 +2,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{convert::TryFrom, os::unix::io::AsRawFd, path::Path, vec::Vec};
+use std::{
+    collections::HashMap, convert::TryFrom, os::unix::io::AsRawFd, path::Path, time::Duration,
+    vec::Vec,
+};
 
 use dbus::{
-    arg::{Array, OwnedFd},
+    arg::{Array, OwnedFd, RefArg, Variant},
     tree::{MTFn, MethodInfo, MethodResult},
     Message,
 };
@@ -13,23 +16,82 @@ use dbus::{
 use crate::{
     dbus_api::{
         blockdev::create_dbus_blockdev,
+        consts,
         pool::create_dbus_pool,
         types::TData,
         util::{
-            engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok, tuple_to_option,
+            engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok, option_to_tuple,
+            tuple_to_option,
         },
     },
     engine::{
-        CreateAction, EngineAction, KeyDescription, MappingCreateAction, Name, PoolUuid,
-        UnlockMethod,
+        CreateAction, EncryptionParams, EngineAction, KeyDescription, MappingCreateAction, Name,
+        Pbkdf, PoolUuid, UnlockMethod,
     },
-    stratis::{ErrorEnum, StratisError},
+    stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
+/// Get encryption parameters from their D-Bus representation, a map from the
+/// name of each parameter to its value. Parameters absent from the map take
+/// their default values.
+pub fn encryption_params_from_map(
+    map: &HashMap<String, Variant<Box<dyn RefArg>>>,
+) -> StratisResult<EncryptionParams> {
+    let invalid = |key: &str| {
+        StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!("Value for encryption parameter {} has the wrong type", key),
+        )
+    };
+    let get_u32 = |key: &str, value: &Variant<Box<dyn RefArg>>| {
+        value
+            .0
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| invalid(key))
+    };
+
+    let mut params = EncryptionParams::default();
+    for (key, value) in map.iter() {
+        match key.as_str() {
+            consts::ENCRYPTION_PARAMS_CIPHER => {
+                params.cipher = value.0.as_str().ok_or_else(|| invalid(key))?.to_string();
+            }
+            consts::ENCRYPTION_PARAMS_KEY_SIZE => params.key_size = get_u32(key, value)?,
+            consts::ENCRYPTION_PARAMS_PBKDF => {
+                params.pbkdf = Some(Pbkdf::try_from(
+                    value.0.as_str().ok_or_else(|| invalid(key))?,
+                )?);
+            }
+            consts::ENCRYPTION_PARAMS_PBKDF_MEMORY => {
+                params.pbkdf_memory_kb = Some(get_u32(key, value)?)
+            }
+            consts::ENCRYPTION_PARAMS_PBKDF_TIME => {
+                params.pbkdf_time_ms = Some(get_u32(key, value)?)
+            }
+            consts::ENCRYPTION_PARAMS_PBKDF_PARALLEL_THREADS => {
+                params.pbkdf_parallel_threads = Some(get_u32(key, value)?)
+            }
+            consts::ENCRYPTION_PARAMS_SECTOR_SIZE => params.sector_size = get_u32(key, value)?,
+            _ => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!("{} is not an encryption parameter", key),
+                ))
+            }
+        }
+    }
+    Ok(params)
+}
+
 /// Shared code for the creation of pools using the D-Bus API without the option
 /// for a key description or with an optional key description in later versions of
-/// the interface.
-pub fn create_pool_shared(m: &MethodInfo<MTFn<TData>, TData>, has_key_desc: bool) -> MethodResult {
+/// the interface. The latest version also takes optional encryption parameters.
+pub fn create_pool_shared(
+    m: &MethodInfo<MTFn<TData>, TData>,
+    has_key_desc: bool,
+    has_encryption_params: bool,
+) -> MethodResult {
     let message: &Message = m.msg;
     let mut iter = message.iter_init();
 
@@ -41,6 +103,12 @@ pub fn create_pool_shared(m: &MethodInfo<MTFn<TData>, TData>, has_key_desc: bool
     } else {
         None
     };
+    let encryption_params_tuple: Option<(bool, HashMap<String, Variant<Box<dyn RefArg>>>)> =
+        if has_encryption_params {
+            Some(get_next_arg(&mut iter, 4)?)
+        } else {
+            None
+        };
 
     let return_message = message.method_return();
 
@@ -58,6 +126,17 @@ pub fn create_pool_shared(m: &MethodInfo<MTFn<TData>, TData>, has_key_desc: bool
         None => None,
     };
 
+    let encryption_params = match encryption_params_tuple.and_then(tuple_to_option) {
+        Some(map) => match encryption_params_from_map(&map) {
+            Ok(params) => Some(params),
+            Err(e) => {
+                let (rc, rs) = engine_to_dbus_err_tuple(&e);
+                return Ok(vec![return_message.append3(default_return, rc, rs)]);
+            }
+        },
+        None => None,
+    };
+
     let object_path = m.path.get_name();
     let dbus_context = m.tree.get_data();
     let mut engine = dbus_context.engine.borrow_mut();
@@ -66,6 +145,7 @@ pub fn create_pool_shared(m: &MethodInfo<MTFn<TData>, TData>, has_key_desc: bool
         &devs.map(|x| Path::new(x)).collect::<Vec<&Path>>(),
         tuple_to_option(redundancy_tuple),
         key_desc,
+        encryption_params,
     ));
 
     let msg = match result {
@@ -118,18 +198,51 @@ pub fn list_keys(info: &MethodInfo<MTFn<TData>, TData>) -> Result<Vec<String>, S
         .list()
         .map(|v| {
             v.into_iter()
-                .map(|kd| kd.as_application_str().to_string())
+                .map(|(kd, _)| kd.as_application_str().to_string())
                 .collect()
         })
         .map_err(|e| e.to_string())
 }
 
-pub fn set_key_shared(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+/// List the key descriptions of all keys set by Stratis, each with the
+/// remaining lifetime of the key in seconds, if the key expires.
+pub fn list_keys_with_lifetimes(
+    info: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<HashMap<String, (bool, u64)>, String> {
+    let dbus_context = info.tree.get_data();
+
+    let engine = dbus_context.engine.borrow();
+    engine
+        .get_key_handler()
+        .list()
+        .map(|v| {
+            v.into_iter()
+                .map(|(kd, lifetime)| {
+                    (
+                        kd.as_application_str().to_string(),
+                        option_to_tuple(lifetime.map(|l| l.as_secs()), 0),
+                    )
+                })
+                .collect()
+        })
+        .map_err(|e| e.to_string())
+}
+
+pub fn set_key_shared(m: &MethodInfo<MTFn<TData>, TData>, take_timeout_arg: bool) -> MethodResult {
     let message: &Message = m.msg;
     let mut iter = message.iter_init();
 
     let key_desc_str: String = get_next_arg(&mut iter, 0)?;
     let key_fd: OwnedFd = get_next_arg(&mut iter, 1)?;
+    let timeout = if take_timeout_arg {
+        // The interactive argument is accepted but unused, as in earlier
+        // revisions of SetKey.
+        let _: bool = get_next_arg(&mut iter, 2)?;
+        let timeout_tuple: (bool, u32) = get_next_arg(&mut iter, 3)?;
+        tuple_to_option(timeout_tuple).map(|secs| Duration::from_secs(u64::from(secs)))
+    } else {
+        None
+    };
 
     let dbus_context = m.tree.get_data();
     let default_return = (false, false);
@@ -144,6 +257,7 @@ pub fn set_key_shared(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
             }
         },
         key_fd.as_raw_fd(),
+        timeout,
     )) {
         Ok(idem_resp) => {
             let return_value = match idem_resp {
@@ -172,9 +286,28 @@ pub fn locked_pool_uuids(info: &MethodInfo<MTFn<TData>, TData>) -> Result<Vec<St
         .collect())
 }
 
+pub fn locked_pools(
+    info: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<HashMap<String, String>, String> {
+    let dbus_context = info.tree.get_data();
+
+    let engine = dbus_context.engine.borrow();
+    Ok(engine
+        .locked_pools()
+        .into_iter()
+        .map(|(u, info)| {
+            (
+                u.to_simple_ref().to_string(),
+                info.key_description.as_application_str().to_string(),
+            )
+        })
+        .collect())
+}
+
 pub fn unlock_pool_shared(
     m: &MethodInfo<MTFn<TData>, TData>,
     take_unlock_arg: bool,
+    take_passphrase_arg: bool,
 ) -> MethodResult {
     let message: &Message = m.msg;
     let mut iter = message.iter_init();
@@ -208,12 +341,18 @@ pub fn unlock_pool_shared(
     } else {
         UnlockMethod::Keyring
     };
+    let passphrase_fd = if take_passphrase_arg {
+        let passphrase_fd_tuple: (bool, OwnedFd) = get_next_arg(&mut iter, 2)?;
+        tuple_to_option(passphrase_fd_tuple)
+    } else {
+        None
+    };
 
-    let msg = match log_action!(dbus_context
-        .engine
-        .borrow_mut()
-        .unlock_pool(pool_uuid, unlock_method))
-    {
+    let msg = match log_action!(dbus_context.engine.borrow_mut().unlock_pool(
+        pool_uuid,
+        unlock_method,
+        passphrase_fd.as_ref().map(|fd| fd.as_raw_fd()),
+    )) {
         Ok(unlock_action) => match unlock_action.changed() {
             Some(vec) => {
                 let str_uuids: Vec<_> = vec
@@ -0,0 +1,23 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::tree::{Access, EmitsChangedSignal, Factory, MTFn, Property};
+
+use crate::dbus_api::{
+    blockdev::blockdev_2_5::props::get_blockdev_reencryption_progress, consts, types::TData,
+};
+
+pub fn reencryption_progress_property(
+    f: &Factory<MTFn<TData>, TData>,
+) -> Property<MTFn<TData>, TData> {
+    // b: false if the device is not being reencrypted
+    // s: number of bytes reencrypted so far
+    // s: total number of bytes to reencrypt
+    //
+    // Rust representation: (bool, (String, String))
+    f.property::<(bool, (&str, &str)), _>(consts::BLOCKDEV_REENCRYPTION_PROGRESS_PROP, ())
+        .access(Access::Read)
+        .emits_changed(EmitsChangedSignal::False)
+        .on_get(get_blockdev_reencryption_progress)
+}
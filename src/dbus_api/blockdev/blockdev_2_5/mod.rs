@@ -0,0 +1,4 @@
+mod api;
+mod props;
+
+pub use api::reencryption_progress_property;
@@ -0,0 +1,23 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::{
+    arg::IterAppend,
+    tree::{MTFn, MethodErr, PropInfo},
+};
+
+use crate::dbus_api::{
+    blockdev::shared::{self, get_blockdev_property},
+    types::TData,
+};
+
+/// Get the progress of the reencryption of a blockdev.
+pub fn get_blockdev_reencryption_progress(
+    i: &mut IterAppend,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    get_blockdev_property(i, p, |_, p| {
+        Ok(shared::blockdev_reencryption_progress_prop(p))
+    })
+}
@@ -15,6 +15,7 @@ use crate::{
 
 mod blockdev_2_0;
 mod blockdev_2_2;
+mod blockdev_2_5;
 mod fetch_properties_2_0;
 mod shared;
 
@@ -62,6 +63,19 @@ pub fn create_dbus_blockdev<'a>(
                 .add_p(blockdev_2_0::uuid_property(&f))
                 .add_p(blockdev_2_2::physical_path_property(&f)),
         )
+        .add(
+            f.interface(consts::BLOCKDEV_INTERFACE_NAME_2_5, ())
+                .add_m(blockdev_2_0::set_userid_method(&f))
+                .add_p(blockdev_2_0::devnode_property(&f))
+                .add_p(blockdev_2_0::hardware_info_property(&f))
+                .add_p(blockdev_2_0::initialization_time_property(&f))
+                .add_p(blockdev_2_0::pool_property(&f))
+                .add_p(blockdev_2_0::tier_property(&f))
+                .add_p(blockdev_2_0::user_info_property(&f))
+                .add_p(blockdev_2_0::uuid_property(&f))
+                .add_p(blockdev_2_2::physical_path_property(&f))
+                .add_p(blockdev_2_5::reencryption_progress_property(&f)),
+        )
         .add(
             f.interface(consts::PROPERTY_FETCH_INTERFACE_NAME, ())
                 .add_m(fetch_properties_2_0::get_all_properties_method(&f))
@@ -115,10 +129,21 @@ pub fn get_initial_properties(
             consts::BLOCKDEV_HARDWARE_INFO_PROP => shared::blockdev_hardware_info_prop(dev),
             consts::BLOCKDEV_USER_INFO_PROP => shared::blockdev_user_info_prop(dev),
             consts::BLOCKDEV_INIT_TIME_PROP => shared::blockdev_init_time_prop(dev),
-            consts::BLOCKDEV_POOL_PROP => parent,
+            consts::BLOCKDEV_POOL_PROP => parent.to_owned(),
             consts::BLOCKDEV_UUID_PROP => uuid_to_string!(dev_uuid),
             consts::BLOCKDEV_TIER_PROP => shared::blockdev_tier_prop(tier),
             consts::BLOCKDEV_PHYSICAL_PATH_PROP => shared::blockdev_physical_path_prop(dev)
+        },
+        consts::BLOCKDEV_INTERFACE_NAME_2_5 => {
+            consts::BLOCKDEV_DEVNODE_PROP => shared::blockdev_devnode_prop(dev),
+            consts::BLOCKDEV_HARDWARE_INFO_PROP => shared::blockdev_hardware_info_prop(dev),
+            consts::BLOCKDEV_USER_INFO_PROP => shared::blockdev_user_info_prop(dev),
+            consts::BLOCKDEV_INIT_TIME_PROP => shared::blockdev_init_time_prop(dev),
+            consts::BLOCKDEV_POOL_PROP => parent,
+            consts::BLOCKDEV_UUID_PROP => uuid_to_string!(dev_uuid),
+            consts::BLOCKDEV_TIER_PROP => shared::blockdev_tier_prop(tier),
+            consts::BLOCKDEV_PHYSICAL_PATH_PROP => shared::blockdev_physical_path_prop(dev),
+            consts::BLOCKDEV_REENCRYPTION_PROGRESS_PROP => shared::blockdev_reencryption_progress_prop(dev)
         }
     }
 }
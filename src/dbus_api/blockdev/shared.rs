@@ -126,3 +126,20 @@ pub fn blockdev_tier_prop(tier: BlockDevTier) -> u16 {
 pub fn blockdev_physical_path_prop(dev: &dyn BlockDev) -> String {
     dev.devnode().display().to_string()
 }
+
+/// Generate D-Bus representation of reencryption progress property.
+#[inline]
+pub fn blockdev_reencryption_progress_prop(dev: &dyn BlockDev) -> (bool, (String, String)) {
+    dev.reencryption_progress().map_or_else(
+        || (false, ("".to_owned(), "".to_owned())),
+        |progress| {
+            (
+                true,
+                (
+                    (*progress.processed).to_string(),
+                    (*progress.total).to_string(),
+                ),
+            )
+        },
+    )
+}
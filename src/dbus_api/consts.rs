@@ -12,12 +12,14 @@ pub const MANAGER_INTERFACE_NAME_2_1: &str = "org.storage.stratis2.Manager.r1";
 pub const MANAGER_INTERFACE_NAME_2_2: &str = "org.storage.stratis2.Manager.r2";
 pub const MANAGER_INTERFACE_NAME_2_3: &str = "org.storage.stratis2.Manager.r3";
 pub const MANAGER_INTERFACE_NAME_2_4: &str = "org.storage.stratis2.Manager.r4";
+pub const MANAGER_INTERFACE_NAME_2_5: &str = "org.storage.stratis2.Manager.r5";
 pub const REPORT_INTERFACE_NAME_2_1: &str = "org.storage.stratis2.Report.r1";
 
 pub const PROPERTY_FETCH_INTERFACE_NAME: &str = "org.storage.stratis2.FetchProperties";
 pub const PROPERTY_FETCH_INTERFACE_NAME_2_1: &str = "org.storage.stratis2.FetchProperties.r1";
 pub const PROPERTY_FETCH_INTERFACE_NAME_2_2: &str = "org.storage.stratis2.FetchProperties.r2";
 pub const PROPERTY_FETCH_INTERFACE_NAME_2_3: &str = "org.storage.stratis2.FetchProperties.r3";
+pub const PROPERTY_FETCH_INTERFACE_NAME_2_5: &str = "org.storage.stratis2.FetchProperties.r5";
 
 pub const KEY_LIST_PROP: &str = "KeyList";
 
@@ -27,6 +29,7 @@ pub const LOCKED_POOLS: &str = "LockedPools";
 pub const POOL_INTERFACE_NAME: &str = "org.storage.stratis2.pool";
 pub const POOL_INTERFACE_NAME_2_1: &str = "org.storage.stratis2.pool.r1";
 pub const POOL_INTERFACE_NAME_2_3: &str = "org.storage.stratis2.pool.r3";
+pub const POOL_INTERFACE_NAME_2_5: &str = "org.storage.stratis2.pool.r5";
 pub const POOL_NAME_PROP: &str = "Name";
 pub const POOL_UUID_PROP: &str = "Uuid";
 pub const POOL_HAS_CACHE_PROP: &str = "HasCache";
@@ -35,17 +38,38 @@ pub const POOL_ENCRYPTION_KEY_DESC: &str = "KeyDescription";
 pub const POOL_TOTAL_SIZE_PROP: &str = "TotalPhysicalSize";
 pub const POOL_TOTAL_USED_PROP: &str = "TotalPhysicalUsed";
 pub const POOL_CLEVIS_INFO: &str = "ClevisInfo";
+pub const POOL_CLEVIS_BINDINGS: &str = "ClevisBindings";
+pub const POOL_ENCRYPTION_PARAMS: &str = "EncryptionParams";
+pub const POOL_OVERPROV_PROP: &str = "Overprovisioning";
+pub const POOL_AUTO_UNLOCK_PROP: &str = "AutoUnlock";
+pub const POOL_ENCRYPTION_SPACE_RESERVED_PROP: &str = "EncryptionSpaceReserved";
+pub const POOL_CACHE_STATISTICS_PROP: &str = "CacheStatistics";
+pub const POOL_THIN_POOL_USAGE_PROP: &str = "ThinPoolUsage";
+pub const POOL_THIN_POOL_STATUS_PROP: &str = "ThinPoolStatus";
+pub const POOL_SPACE_THRESHOLDS_PROP: &str = "SpaceThresholds";
+pub const POOL_SPACE_ALERT_SIGNAL: &str = "PoolSpaceAlert";
+
+pub const ENCRYPTION_PARAMS_CIPHER: &str = "Cipher";
+pub const ENCRYPTION_PARAMS_KEY_SIZE: &str = "KeySize";
+pub const ENCRYPTION_PARAMS_PBKDF: &str = "Pbkdf";
+pub const ENCRYPTION_PARAMS_PBKDF_MEMORY: &str = "PbkdfMemory";
+pub const ENCRYPTION_PARAMS_PBKDF_TIME: &str = "PbkdfTime";
+pub const ENCRYPTION_PARAMS_PBKDF_PARALLEL_THREADS: &str = "PbkdfParallelThreads";
+pub const ENCRYPTION_PARAMS_SECTOR_SIZE: &str = "SectorSize";
 
 pub const FILESYSTEM_INTERFACE_NAME: &str = "org.storage.stratis2.filesystem";
+pub const FILESYSTEM_INTERFACE_NAME_2_5: &str = "org.storage.stratis2.filesystem.r5";
 pub const FILESYSTEM_NAME_PROP: &str = "Name";
 pub const FILESYSTEM_UUID_PROP: &str = "Uuid";
 pub const FILESYSTEM_USED_PROP: &str = "Used";
 pub const FILESYSTEM_DEVNODE_PROP: &str = "Devnode";
 pub const FILESYSTEM_POOL_PROP: &str = "Pool";
 pub const FILESYSTEM_CREATED_PROP: &str = "Created";
+pub const FILESYSTEM_SIZE_LIMIT_PROP: &str = "SizeLimit";
 
 pub const BLOCKDEV_INTERFACE_NAME: &str = "org.storage.stratis2.blockdev";
 pub const BLOCKDEV_INTERFACE_NAME_2_2: &str = "org.storage.stratis2.blockdev.r2";
+pub const BLOCKDEV_INTERFACE_NAME_2_5: &str = "org.storage.stratis2.blockdev.r5";
 pub const BLOCKDEV_DEVNODE_PROP: &str = "Devnode";
 pub const BLOCKDEV_HARDWARE_INFO_PROP: &str = "HardwareInfo";
 pub const BLOCKDEV_USER_INFO_PROP: &str = "UserInfo";
@@ -54,6 +78,7 @@ pub const BLOCKDEV_POOL_PROP: &str = "Pool";
 pub const BLOCKDEV_UUID_PROP: &str = "Uuid";
 pub const BLOCKDEV_TIER_PROP: &str = "Tier";
 pub const BLOCKDEV_PHYSICAL_PATH_PROP: &str = "PhysicalPath";
+pub const BLOCKDEV_REENCRYPTION_PROGRESS_PROP: &str = "ReencryptionProgress";
 
 pub const BLOCKDEV_TOTAL_SIZE_PROP: &str = "TotalPhysicalSize";
 
@@ -63,6 +88,7 @@ pub fn fetch_properties_interfaces() -> Vec<String> {
         PROPERTY_FETCH_INTERFACE_NAME,
         PROPERTY_FETCH_INTERFACE_NAME_2_1,
         PROPERTY_FETCH_INTERFACE_NAME_2_2,
+        PROPERTY_FETCH_INTERFACE_NAME_2_5,
     ]
     .iter()
     .map(|s| (*s).to_string())
@@ -76,6 +102,7 @@ pub fn standard_pool_interfaces() -> Vec<String> {
         POOL_INTERFACE_NAME,
         POOL_INTERFACE_NAME_2_1,
         POOL_INTERFACE_NAME_2_3,
+        POOL_INTERFACE_NAME_2_5,
     ]
     .iter()
     .map(|s| (*s).to_string())
@@ -85,7 +112,7 @@ pub fn standard_pool_interfaces() -> Vec<String> {
 /// Get a list of all the standard filesystem interfaces; i.e., all the
 /// revisions of org.storage.stratis2.filesystem.
 pub fn standard_filesystem_interfaces() -> Vec<String> {
-    [FILESYSTEM_INTERFACE_NAME]
+    [FILESYSTEM_INTERFACE_NAME, FILESYSTEM_INTERFACE_NAME_2_5]
         .iter()
         .map(|s| (*s).to_string())
         .collect()
@@ -94,10 +121,14 @@ pub fn standard_filesystem_interfaces() -> Vec<String> {
 /// Get a list of all the standard blockdev interfaces; i.e., all the
 /// revisions of org.storage.stratis2.blockdev.
 pub fn standard_blockdev_interfaces() -> Vec<String> {
-    [BLOCKDEV_INTERFACE_NAME]
-        .iter()
-        .map(|s| (*s).to_string())
-        .collect()
+    [
+        BLOCKDEV_INTERFACE_NAME,
+        BLOCKDEV_INTERFACE_NAME_2_2,
+        BLOCKDEV_INTERFACE_NAME_2_5,
+    ]
+    .iter()
+    .map(|s| (*s).to_string())
+    .collect()
 }
 
 /// Get a list of all interfaces supported by a pool object.
@@ -7,7 +7,10 @@ use std::{cell::RefCell, rc::Rc};
 use dbus::ffidisp::Connection;
 
 use crate::{
-    dbus_api::{consts, util::prop_changed_dispatch},
+    dbus_api::{
+        consts,
+        util::{prop_changed_dispatch, space_alert_dispatch},
+    },
     engine::{EngineEvent, EngineListener, MaybeDbusPath},
 };
 
@@ -67,6 +70,30 @@ impl EngineListener for EventHandler {
                     });
                 }
             }
+            EngineEvent::PoolSpaceAlert {
+                dbus_path,
+                pool_uuid,
+                level,
+                used,
+                total,
+            } => {
+                if let MaybeDbusPath(Some(ref dbus_path)) = *dbus_path {
+                    space_alert_dispatch(
+                        &self.dbus_conn.borrow(),
+                        dbus_path,
+                        pool_uuid,
+                        level,
+                        used,
+                        total,
+                    )
+                    .unwrap_or_else(|()| {
+                        warn!(
+                            "PoolSpaceAlert: {} level: {} failed to send dbus signal.",
+                            dbus_path, level,
+                        );
+                    });
+                }
+            }
         }
     }
 }
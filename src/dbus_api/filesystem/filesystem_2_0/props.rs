@@ -7,33 +7,11 @@ use dbus::{
     tree::{MTFn, MethodErr, PropInfo},
 };
 
-use crate::{
-    dbus_api::{
-        filesystem::shared::{self, filesystem_operation},
-        types::TData,
-    },
-    engine::{Filesystem, Name},
+use crate::dbus_api::{
+    filesystem::shared::{self, get_filesystem_property},
+    types::TData,
 };
 
-/// Get a filesystem property and place it on the D-Bus. The property is
-/// found by means of the getter method which takes a reference to a
-/// Filesystem and obtains the property from the filesystem.
-fn get_filesystem_property<F, R>(
-    i: &mut IterAppend,
-    p: &PropInfo<MTFn<TData>, TData>,
-    getter: F,
-) -> Result<(), MethodErr>
-where
-    F: Fn((Name, Name, &dyn Filesystem)) -> Result<R, String>,
-    R: dbus::arg::Append,
-{
-    i.append(
-        filesystem_operation(p.tree, p.path.get_name(), getter)
-            .map_err(|ref e| MethodErr::failed(e))?,
-    );
-    Ok(())
-}
-
 /// Get the devnode for an object path.
 pub fn get_filesystem_devnode(
     i: &mut IterAppend,
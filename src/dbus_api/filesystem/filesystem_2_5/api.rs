@@ -0,0 +1,30 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::tree::{Access, EmitsChangedSignal, Factory, MTFn, Method, Property};
+
+use crate::dbus_api::{
+    consts,
+    filesystem::filesystem_2_5::{methods::set_size, props::get_filesystem_size_limit},
+    types::TData,
+};
+
+pub fn set_size_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("SetSize", (), set_size)
+        // s: New size of the filesystem in bytes
+        .in_arg(("size", "s"))
+        // b: Indicates if the filesystem was resized
+        //
+        // Rust representation: bool
+        .out_arg(("results", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn size_limit_property(f: &Factory<MTFn<TData>, TData>) -> Property<MTFn<TData>, TData> {
+    f.property::<(bool, &str), _>(consts::FILESYSTEM_SIZE_LIMIT_PROP, ())
+        .access(Access::Read)
+        .emits_changed(EmitsChangedSignal::Const)
+        .on_get(get_filesystem_size_limit)
+}
@@ -0,0 +1,71 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::{
+    tree::{MTFn, MethodInfo, MethodResult},
+    Message,
+};
+
+use crate::{
+    dbus_api::{
+        types::{DbusErrorEnum, TData},
+        util::{
+            engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok, parse_size_bytes,
+        },
+    },
+    engine::PropChangeAction,
+};
+
+pub fn set_size(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    let size_string: &str = get_next_arg(&mut iter, 0)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let new_size = match parse_size_bytes(size_string) {
+        Ok(size) => size,
+        Err(error_message) => {
+            let (rc, rs) = (DbusErrorEnum::ERROR as u16, error_message);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+
+    let filesystem_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let filesystem_data = get_data!(filesystem_path; default_return; return_message);
+
+    let pool_path = get_parent!(m; filesystem_data; default_return; return_message);
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (_, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let uuid = typed_uuid!(filesystem_data.uuid; Fs; default_return; return_message);
+    let msg = match log_action!(pool.set_filesystem_size(uuid, new_size)) {
+        Ok(PropChangeAction::Identity) => {
+            return_message.append3(false, msg_code_ok(), msg_string_ok())
+        }
+        Ok(PropChangeAction::NewValue(_)) => {
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Err(err) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&err);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+
+    Ok(vec![msg])
+}
@@ -0,0 +1,5 @@
+mod api;
+mod methods;
+mod props;
+
+pub use api::{set_size_method, size_limit_property};
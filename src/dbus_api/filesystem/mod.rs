@@ -15,6 +15,7 @@ use crate::{
 
 mod fetch_properties_2_0;
 mod filesystem_2_0;
+mod filesystem_2_5;
 mod shared;
 
 pub fn create_dbus_filesystem<'a>(
@@ -48,6 +49,17 @@ pub fn create_dbus_filesystem<'a>(
                 .add_p(filesystem_2_0::uuid_property(&f))
                 .add_p(filesystem_2_0::created_property(&f)),
         )
+        .add(
+            f.interface(consts::FILESYSTEM_INTERFACE_NAME_2_5, ())
+                .add_m(filesystem_2_0::rename_method(&f))
+                .add_m(filesystem_2_5::set_size_method(&f))
+                .add_p(filesystem_2_0::devnode_property(&f))
+                .add_p(filesystem_2_0::name_property(&f))
+                .add_p(filesystem_2_0::pool_property(&f))
+                .add_p(filesystem_2_0::uuid_property(&f))
+                .add_p(filesystem_2_0::created_property(&f))
+                .add_p(filesystem_2_5::size_limit_property(&f)),
+        )
         .add(
             f.interface(consts::PROPERTY_FETCH_INTERFACE_NAME, ())
                 .add_m(fetch_properties_2_0::get_all_properties_method(&f))
@@ -92,8 +104,16 @@ pub fn get_initial_properties(
             consts::FILESYSTEM_NAME_PROP => shared::fs_name_prop(fs_name),
             consts::FILESYSTEM_UUID_PROP => uuid_to_string!(fs_uuid),
             consts::FILESYSTEM_DEVNODE_PROP => shared::fs_devnode_prop(fs, pool_name, fs_name),
-            consts::FILESYSTEM_POOL_PROP => parent,
+            consts::FILESYSTEM_POOL_PROP => parent.clone(),
             consts::FILESYSTEM_CREATED_PROP => shared::fs_created_prop(fs)
+        },
+        consts::FILESYSTEM_INTERFACE_NAME_2_5 => {
+            consts::FILESYSTEM_NAME_PROP => shared::fs_name_prop(fs_name),
+            consts::FILESYSTEM_UUID_PROP => uuid_to_string!(fs_uuid),
+            consts::FILESYSTEM_DEVNODE_PROP => shared::fs_devnode_prop(fs, pool_name, fs_name),
+            consts::FILESYSTEM_POOL_PROP => parent,
+            consts::FILESYSTEM_CREATED_PROP => shared::fs_created_prop(fs),
+            consts::FILESYSTEM_SIZE_LIMIT_PROP => shared::fs_size_limit_prop(fs)
         }
     }
 }
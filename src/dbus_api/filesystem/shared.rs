@@ -4,12 +4,13 @@
 
 use chrono::SecondsFormat;
 use dbus::{
-    tree::{MTFn, Tree},
+    arg::IterAppend,
+    tree::{MTFn, MethodErr, PropInfo, Tree},
     Path,
 };
 
 use crate::{
-    dbus_api::types::TData,
+    dbus_api::{types::TData, util::option_to_tuple},
     engine::{Filesystem, Name},
 };
 
@@ -59,6 +60,25 @@ where
     closure((pool_name, fs_name, fs))
 }
 
+/// Get a filesystem property and place it on the D-Bus. The property is
+/// found by means of the getter method which takes a reference to a
+/// Filesystem and obtains the property from the filesystem.
+pub fn get_filesystem_property<F, R>(
+    i: &mut IterAppend,
+    p: &PropInfo<MTFn<TData>, TData>,
+    getter: F,
+) -> Result<(), MethodErr>
+where
+    F: Fn((Name, Name, &dyn Filesystem)) -> Result<R, String>,
+    R: dbus::arg::Append,
+{
+    i.append(
+        filesystem_operation(p.tree, p.path.get_name(), getter)
+            .map_err(|ref e| MethodErr::failed(e))?,
+    );
+    Ok(())
+}
+
 /// Generate D-Bus representation of name property.
 #[inline]
 pub fn fs_name_prop(name: &Name) -> String {
@@ -78,3 +98,12 @@ pub fn fs_devnode_prop(fs: &dyn Filesystem, pool_name: &Name, fs_name: &Name) ->
 pub fn fs_created_prop(fs: &dyn Filesystem) -> String {
     fs.created().to_rfc3339_opts(SecondsFormat::Secs, true)
 }
+
+/// Generate D-Bus representation of size limit property.
+#[inline]
+pub fn fs_size_limit_prop(fs: &dyn Filesystem) -> (bool, String) {
+    option_to_tuple(
+        fs.size_limit().map(|limit| (*limit).to_string()),
+        String::new(),
+    )
+}
@@ -16,9 +16,11 @@ use crate::{
 mod fetch_properties_2_0;
 mod fetch_properties_2_1;
 mod fetch_properties_2_3;
+mod fetch_properties_2_5;
 mod pool_2_0;
 mod pool_2_1;
 mod pool_2_3;
+mod pool_2_5;
 mod shared;
 
 pub fn create_dbus_pool<'a>(
@@ -81,6 +83,34 @@ pub fn create_dbus_pool<'a>(
                 .add_p(pool_2_0::uuid_property(&f))
                 .add_p(pool_2_1::encrypted_property(&f)),
         )
+        .add(
+            f.interface(consts::POOL_INTERFACE_NAME_2_5, ())
+                .add_m(pool_2_5::create_filesystems_method(&f))
+                .add_m(pool_2_0::destroy_filesystems_method(&f))
+                .add_m(pool_2_0::snapshot_filesystem_method(&f))
+                .add_m(pool_2_0::add_blockdevs_method(&f))
+                .add_m(pool_2_5::remove_datadevs_method(&f))
+                .add_m(pool_2_5::bind_clevis_method(&f))
+                .add_m(pool_2_5::unbind_clevis_method(&f))
+                .add_m(pool_2_5::rebind_clevis_method(&f))
+                .add_m(pool_2_5::rebind_keyring_method(&f))
+                .add_m(pool_2_5::reencrypt_method(&f))
+                .add_m(pool_2_5::init_cache_method(&f))
+                .add_m(pool_2_1::add_cachedevs_method(&f))
+                .add_m(pool_2_5::remove_cachedevs_method(&f))
+                .add_m(pool_2_5::destroy_cache_method(&f))
+                .add_m(pool_2_5::set_cache_mode_method(&f))
+                .add_m(pool_2_5::backup_metadata_method(&f))
+                .add_m(pool_2_0::rename_method(&f))
+                .add_p(pool_2_0::name_property(&f))
+                .add_p(pool_2_0::uuid_property(&f))
+                .add_p(pool_2_1::encrypted_property(&f))
+                .add_p(pool_2_5::overprov_property(&f))
+                .add_p(pool_2_5::auto_unlock_property(&f))
+                .add_p(pool_2_5::encryption_space_reserved_property(&f))
+                .add_p(pool_2_5::space_thresholds_property(&f))
+                .add_s(pool_2_5::space_alert_signal(&f)),
+        )
         .add(
             f.interface(consts::PROPERTY_FETCH_INTERFACE_NAME, ())
                 .add_m(fetch_properties_2_0::get_all_properties_method(&f))
@@ -100,6 +130,11 @@ pub fn create_dbus_pool<'a>(
             f.interface(consts::PROPERTY_FETCH_INTERFACE_NAME_2_3, ())
                 .add_m(fetch_properties_2_3::get_all_properties_method(&f))
                 .add_m(fetch_properties_2_3::get_properties_method(&f)),
+        )
+        .add(
+            f.interface(consts::PROPERTY_FETCH_INTERFACE_NAME_2_5, ())
+                .add_m(fetch_properties_2_5::get_all_properties_method(&f))
+                .add_m(fetch_properties_2_5::get_properties_method(&f)),
         );
 
     let path = object_path.get_name().to_owned();
@@ -127,6 +162,16 @@ pub fn get_initial_properties(
             consts::POOL_NAME_PROP => shared::pool_name_prop(pool_name),
             consts::POOL_UUID_PROP => uuid_to_string!(pool_uuid),
             consts::POOL_ENCRYPTED_PROP => shared::pool_enc_prop(pool)
+        },
+        consts::POOL_INTERFACE_NAME_2_5 => {
+            consts::POOL_NAME_PROP => shared::pool_name_prop(pool_name),
+            consts::POOL_UUID_PROP => uuid_to_string!(pool_uuid),
+            consts::POOL_ENCRYPTED_PROP => shared::pool_enc_prop(pool),
+            consts::POOL_OVERPROV_PROP => shared::pool_overprov_prop(pool),
+            consts::POOL_AUTO_UNLOCK_PROP => shared::pool_auto_unlock_prop(pool),
+            consts::POOL_ENCRYPTION_SPACE_RESERVED_PROP =>
+                shared::pool_encryption_space_reserved_prop(pool),
+            consts::POOL_SPACE_THRESHOLDS_PROP => shared::pool_space_thresholds_prop(pool)
         }
     }
 }
@@ -15,11 +15,13 @@ use crate::{
     dbus_api::{
         consts::filesystem_interface_list,
         filesystem::create_dbus_filesystem,
-        pool::shared::{add_blockdevs, BlockDevOp},
+        pool::shared::{add_blockdevs, create_filesystems_shared, BlockDevOp},
         types::{DbusErrorEnum, TData},
         util::{engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok},
     },
-    engine::{CreateAction, EngineAction, FilesystemUuid, Name, PoolUuid, RenameAction},
+    engine::{
+        CacheConfig, CreateAction, EngineAction, FilesystemUuid, Name, PoolUuid, RenameAction,
+    },
 };
 
 pub fn create_filesystems(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
@@ -27,79 +29,13 @@ pub fn create_filesystems(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
     let mut iter = message.iter_init();
 
     let filesystems: Array<&str, _> = get_next_arg(&mut iter, 0)?;
-    let dbus_context = m.tree.get_data();
-
-    let object_path = m.path.get_name();
-    let return_message = message.method_return();
-    let default_return: (bool, Vec<(dbus::Path, &str)>) = (false, Vec::new());
-
-    if filesystems.count() > 1 {
-        let error_message = "only 1 filesystem per request allowed";
-        let (rc, rs) = (DbusErrorEnum::ERROR as u16, error_message);
-        return Ok(vec![return_message.append3(default_return, rc, rs)]);
-    }
-
-    let pool_path = m
-        .tree
-        .get(object_path)
-        .expect("implicit argument must be in tree");
-    let pool_uuid = typed_uuid!(
-        get_data!(pool_path; default_return; return_message).uuid;
-        Pool;
-        default_return;
-        return_message
-    );
 
-    let mut engine = dbus_context.engine.borrow_mut();
-    let (pool_name, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
-    let result = log_action!(pool.create_filesystems(
-        pool_uuid,
+    create_filesystems_shared(
+        m,
         &filesystems
-            .map(|x| (x, None))
-            .collect::<Vec<(&str, Option<Sectors>)>>(),
-    ));
-
-    let infos = match result {
-        Ok(created_set) => created_set.changed(),
-        Err(err) => {
-            let (rc, rs) = engine_to_dbus_err_tuple(&err);
-            return Ok(vec![return_message.append3(default_return, rc, rs)]);
-        }
-    };
-
-    let return_value = match infos {
-        Some(ref newly_created_filesystems) => {
-            let v = newly_created_filesystems
-                .iter()
-                .map(|&(name, uuid)| {
-                    // FIXME: To avoid this expect, modify create_filesystem
-                    // so that it returns a mutable reference to the
-                    // filesystem created.
-                    (
-                        create_dbus_filesystem(
-                            dbus_context,
-                            object_path.clone(),
-                            &pool_name,
-                            &Name::new(name.to_string()),
-                            uuid,
-                            pool.get_mut_filesystem(uuid)
-                                .expect("just inserted by create_filesystems")
-                                .1,
-                        ),
-                        name,
-                    )
-                })
-                .collect::<Vec<_>>();
-            (true, v)
-        }
-        None => default_return,
-    };
-
-    Ok(vec![return_message.append3(
-        return_value,
-        msg_code_ok(),
-        msg_string_ok(),
-    )])
+            .map(|x| (x, None, None))
+            .collect::<Vec<(&str, Option<Sectors>, Option<Sectors>)>>(),
+    )
 }
 
 pub fn destroy_filesystems(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
@@ -140,6 +76,7 @@ pub fn destroy_filesystems(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
     }
 
     let result = log_action!(pool.destroy_filesystems(
+        pool_uuid,
         &pool_name,
         &filesystem_map.keys().cloned().collect::<Vec<_>>(),
     ));
@@ -277,7 +214,7 @@ pub fn add_cachedevs(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
         if cache_initialized {
             BlockDevOp::AddCache
         } else {
-            BlockDevOp::InitCache
+            BlockDevOp::InitCache(CacheConfig::default())
         },
     )
 }
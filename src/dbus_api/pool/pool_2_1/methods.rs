@@ -4,13 +4,16 @@
 
 use dbus::tree::{MTFn, MethodInfo, MethodResult};
 
-use crate::dbus_api::{
-    pool::shared::{add_blockdevs, BlockDevOp},
-    types::TData,
+use crate::{
+    dbus_api::{
+        pool::shared::{add_blockdevs, BlockDevOp},
+        types::TData,
+    },
+    engine::CacheConfig,
 };
 
 pub fn init_cache(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
-    add_blockdevs(m, BlockDevOp::InitCache)
+    add_blockdevs(m, BlockDevOp::InitCache(CacheConfig::default()))
 }
 
 pub fn add_cachedevs(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
@@ -84,15 +84,36 @@ pub fn unbind_clevis(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
     let mut engine = dbus_context.engine.borrow_mut();
     let (_, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
 
-    let msg = match log_action!(pool.unbind_clevis()) {
-        Ok(DeleteAction::Identity) => return_message.append3(false, msg_code_ok(), msg_string_ok()),
-        Ok(DeleteAction::Deleted(_)) => {
-            return_message.append3(true, msg_code_ok(), msg_string_ok())
-        }
-        Err(e) => {
-            let (rc, rs) = engine_to_dbus_err_tuple(&e);
-            return_message.append3(default_return, rc, rs)
+    // This revision of the interface knows about a single clevis binding
+    // per pool; unbinding removes all of the pool's clevis bindings.
+    let binding_ids = match pool.encryption_info() {
+        Some(info) => info
+            .clevis_info
+            .iter()
+            .map(|binding| binding.id)
+            .collect::<Vec<_>>(),
+        None => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&StratisError::Error(
+                "Requested pool does not appear to be encrypted".to_string(),
+            ));
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
         }
     };
-    Ok(vec![msg])
+
+    let mut changed = false;
+    for binding_id in binding_ids {
+        match log_action!(pool.unbind_clevis(binding_id)) {
+            Ok(DeleteAction::Identity) => (),
+            Ok(DeleteAction::Deleted(_)) => changed = true,
+            Err(e) => {
+                let (rc, rs) = engine_to_dbus_err_tuple(&e);
+                return Ok(vec![return_message.append3(default_return, rc, rs)]);
+            }
+        }
+    }
+    Ok(vec![return_message.append3(
+        changed,
+        msg_code_ok(),
+        msg_string_ok(),
+    )])
 }
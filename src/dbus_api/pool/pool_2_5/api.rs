@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::tree::{Access, EmitsChangedSignal, Factory, MTFn, Method, Property, Signal};
+
+use crate::dbus_api::{
+    consts,
+    pool::pool_2_5::{
+        methods::{
+            backup_metadata, bind_clevis, create_filesystems, destroy_cache, init_cache,
+            rebind_clevis, rebind_keyring, reencrypt, remove_cachedevs, remove_datadevs,
+            set_cache_mode, unbind_clevis,
+        },
+        props::{
+            get_pool_auto_unlock, get_pool_encryption_space_reserved, get_pool_overprov,
+            get_pool_space_thresholds, set_pool_auto_unlock, set_pool_encryption_space_reserved,
+            set_pool_overprov, set_pool_space_thresholds,
+        },
+    },
+    types::TData,
+};
+
+pub fn create_filesystems_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("CreateFilesystems", (), create_filesystems)
+        // s: Name of the filesystem
+        // (bs): Optional initial size of the filesystem in bytes
+        // (bs): Optional size limit of the filesystem in bytes
+        //
+        // Rust representation: Vec<(String, (bool, String), (bool, String))>
+        .in_arg(("specs", "a(s(bs)(bs))"))
+        // b: true if filesystems were created
+        // a(os): Array of tuples with object paths and names
+        //
+        // Rust representation: (bool, Vec<(dbus::Path, String)>)
+        .out_arg(("results", "(ba(os))"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn remove_datadevs_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("RemoveDataDevs", (), remove_datadevs)
+        .in_arg(("devices", "ao"))
+        // b: true if data devices were removed
+        // as: Array of UUIDs of removed data devices
+        //
+        // Rust representation: (bool, Vec<String>)
+        .out_arg(("results", "(bas)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn init_cache_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("InitCache", (), init_cache)
+        .in_arg(("devices", "as"))
+        // (bs): Optional cache mode: writethrough, writeback, or passthrough
+        .in_arg(("mode", "(bs)"))
+        // (bs): Optional name of the replacement policy
+        .in_arg(("policy", "(bs)"))
+        // a{ss}: Replacement policy tunables, as key/value pairs
+        .in_arg(("policy_args", "a{ss}"))
+        // (bs): Optional cache block size in bytes
+        .in_arg(("block_size", "(bs)"))
+        // b: Indicates if any cache devices were added
+        // ao: Array of object paths of created cache devices
+        //
+        // Rust representation: (bool, Vec<dbus::path>)
+        .out_arg(("results", "(bao)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn set_cache_mode_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("SetCacheMode", (), set_cache_mode)
+        // s: The new cache mode: writethrough, writeback, or passthrough
+        .in_arg(("mode", "s"))
+        // b: Indicates if the cache mode was changed
+        //
+        // Rust representation: bool
+        .out_arg(("results", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn bind_clevis_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("Bind", (), bind_clevis)
+        .in_arg(("pin", "s"))
+        .in_arg(("json", "s"))
+        // b: Indicates if a new clevis binding was added
+        // u: ID of the new clevis binding
+        //
+        // Rust representation: (bool, u32)
+        .out_arg(("results", "(bu)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn unbind_clevis_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("Unbind", (), unbind_clevis)
+        // u: ID of the clevis binding to remove
+        .in_arg(("binding_id", "u"))
+        // b: Indicates if the clevis binding was removed
+        //
+        // Rust representation: bool
+        .out_arg(("results", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn rebind_clevis_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("RebindClevis", (), rebind_clevis)
+        // u: ID of the clevis binding to regenerate
+        .in_arg(("binding_id", "u"))
+        // b: Indicates if the clevis binding was regenerated
+        //
+        // Rust representation: bool
+        .out_arg(("results", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn rebind_keyring_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("RebindKeyring", (), rebind_keyring)
+        // s: Key description of the new key in the kernel keyring
+        .in_arg(("key_desc", "s"))
+        // b: Indicates if the pool was bound to the new key description
+        //
+        // Rust representation: bool
+        .out_arg(("results", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn reencrypt_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("Reencrypt", (), reencrypt)
+        // b: Indicates if reencryption was started or resumed
+        //
+        // Rust representation: bool
+        .out_arg(("results", "b"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn backup_metadata_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("BackupMetadata", (), backup_metadata)
+        // s: JSON archive of the pool's metadata as a string.
+        //
+        // Rust representation: Value
+        .out_arg(("result", "s"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn remove_cachedevs_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("RemoveCacheDevs", (), remove_cachedevs)
+        .in_arg(("devices", "ao"))
+        // b: true if cache devices were removed
+        // as: Array of UUIDs of removed cache devices
+        //
+        // Rust representation: (bool, Vec<String>)
+        .out_arg(("results", "(bas)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn destroy_cache_method(f: &Factory<MTFn<TData>, TData>) -> Method<MTFn<TData>, TData> {
+    f.method("DestroyCache", (), destroy_cache)
+        // b: true if the cache was destroyed
+        // as: Array of UUIDs of removed cache devices
+        //
+        // Rust representation: (bool, Vec<String>)
+        .out_arg(("results", "(bas)"))
+        .out_arg(("return_code", "q"))
+        .out_arg(("return_string", "s"))
+}
+
+pub fn overprov_property(f: &Factory<MTFn<TData>, TData>) -> Property<MTFn<TData>, TData> {
+    f.property::<bool, _>(consts::POOL_OVERPROV_PROP, ())
+        .access(Access::ReadWrite)
+        .emits_changed(EmitsChangedSignal::True)
+        .on_get(get_pool_overprov)
+        .on_set(set_pool_overprov)
+}
+
+pub fn auto_unlock_property(f: &Factory<MTFn<TData>, TData>) -> Property<MTFn<TData>, TData> {
+    f.property::<bool, _>(consts::POOL_AUTO_UNLOCK_PROP, ())
+        .access(Access::ReadWrite)
+        .emits_changed(EmitsChangedSignal::True)
+        .on_get(get_pool_auto_unlock)
+        .on_set(set_pool_auto_unlock)
+}
+
+pub fn encryption_space_reserved_property(
+    f: &Factory<MTFn<TData>, TData>,
+) -> Property<MTFn<TData>, TData> {
+    f.property::<bool, _>(consts::POOL_ENCRYPTION_SPACE_RESERVED_PROP, ())
+        .access(Access::ReadWrite)
+        .emits_changed(EmitsChangedSignal::True)
+        .on_get(get_pool_encryption_space_reserved)
+        .on_set(set_pool_encryption_space_reserved)
+}
+
+pub fn space_thresholds_property(f: &Factory<MTFn<TData>, TData>) -> Property<MTFn<TData>, TData> {
+    // y: Percentage of the pool's space in use at which to warn
+    // y: Percentage of the pool's space in use considered critical
+    f.property::<(u8, u8), _>(consts::POOL_SPACE_THRESHOLDS_PROP, ())
+        .access(Access::ReadWrite)
+        .emits_changed(EmitsChangedSignal::True)
+        .on_get(get_pool_space_thresholds)
+        .on_set(set_pool_space_thresholds)
+}
+
+pub fn space_alert_signal(f: &Factory<MTFn<TData>, TData>) -> Signal<TData> {
+    f.signal(consts::POOL_SPACE_ALERT_SIGNAL, ())
+        .sarg::<&str, _>("pool_uuid")
+        // s: One of "normal", "warning", or "critical"
+        .sarg::<&str, _>("level")
+        // s: Space in use in bytes
+        .sarg::<&str, _>("used")
+        // s: Total space in bytes
+        .sarg::<&str, _>("total")
+}
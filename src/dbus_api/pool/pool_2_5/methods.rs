@@ -0,0 +1,513 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use dbus::{
+    arg::Array,
+    tree::{MTFn, MethodInfo, MethodResult},
+    Message,
+};
+use devicemapper::Sectors;
+use serde_json::Value;
+
+use crate::{
+    dbus_api::{
+        consts::blockdev_interface_list,
+        pool::shared::{add_blockdevs, create_filesystems_shared, BlockDevOp},
+        types::{DbusErrorEnum, TData},
+        util::{
+            engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok, parse_size_bytes,
+            tuple_to_option,
+        },
+    },
+    engine::{
+        BlockDev, BlockDevTier, CacheConfig, CacheMode, Clevis, CreateAction, DeleteAction,
+        DevUuid, EngineAction, KeyDescription, PropChangeAction, RenameAction,
+    },
+    stratis::StratisError,
+};
+
+/// Parse an optional size in bytes, as represented on the D-Bus, into
+/// sectors. The size must be a multiple of the sector size.
+fn parse_size(size: (bool, &str)) -> Result<Option<Sectors>, String> {
+    tuple_to_option(size).map(parse_size_bytes).transpose()
+}
+
+/// Parse the configuration of a cache, as represented on the D-Bus. Any
+/// component which is not specified takes its default value.
+fn parse_cache_config(
+    mode: (bool, &str),
+    policy: (bool, &str),
+    policy_args: HashMap<String, String>,
+    block_size: (bool, &str),
+) -> Result<CacheConfig, String> {
+    let default = CacheConfig::default();
+    Ok(CacheConfig {
+        mode: match tuple_to_option(mode) {
+            Some(mode) => CacheMode::try_from(mode).map_err(|err| err.to_string())?,
+            None => default.mode,
+        },
+        policy: tuple_to_option(policy)
+            .map(|policy| policy.to_string())
+            .unwrap_or(default.policy),
+        policy_args,
+        block_size: parse_size(block_size)?.unwrap_or(default.block_size),
+    })
+}
+
+pub fn create_filesystems(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    let filesystems: Array<(&str, (bool, &str), (bool, &str)), _> = get_next_arg(&mut iter, 0)?;
+
+    let return_message = message.method_return();
+    let default_return: (bool, Vec<(dbus::Path, &str)>) = (false, Vec::new());
+
+    let specs = match filesystems
+        .map(|(name, size, size_limit)| Ok((name, parse_size(size)?, parse_size(size_limit)?)))
+        .collect::<Result<Vec<(&str, Option<Sectors>, Option<Sectors>)>, String>>()
+    {
+        Ok(specs) => specs,
+        Err(error_message) => {
+            let (rc, rs) = (DbusErrorEnum::ERROR as u16, error_message);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+
+    create_filesystems_shared(m, &specs)
+}
+
+/// The blockdev-removing operations; op parameters of this type determine
+/// which method of the engine's Pool interface remove_blockdevs() invokes.
+enum BlockDevRemoveOp {
+    DestroyCache,
+    RemoveCache,
+    RemoveData,
+}
+
+/// A method shared by all blockdev-removing operations. The object paths of
+/// the blockdevs to remove are read from the message unless the whole cache
+/// is being destroyed, in which case all the cachedevs are removed. The
+/// D-Bus objects of the blockdevs actually removed are removed as well.
+fn remove_blockdevs(m: &MethodInfo<MTFn<TData>, TData>, op: BlockDevRemoveOp) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return: (bool, Vec<String>) = (false, Vec::new());
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let mut blockdev_map: HashMap<DevUuid, dbus::Path<'static>> = HashMap::new();
+    if let BlockDevRemoveOp::DestroyCache = op {
+        for (uuid, tier, bd) in pool.blockdevs() {
+            if let (BlockDevTier::Cache, Some(ref path)) = (tier, &bd.get_dbus_path().0) {
+                blockdev_map.insert(uuid, path.clone());
+            }
+        }
+    } else {
+        let blockdevs: Array<dbus::Path<'static>, _> = get_next_arg(&mut iter, 0)?;
+        for path in blockdevs {
+            if let Some((u, path)) = m.tree.get(&path).and_then(|op| {
+                op.get_data()
+                    .as_ref()
+                    .map(|d| (&d.uuid, op.get_name().clone()))
+            }) {
+                let uuid = *typed_uuid!(u; Dev; default_return; return_message);
+                blockdev_map.insert(uuid, path);
+            }
+        }
+    }
+
+    let uuids = blockdev_map.keys().cloned().collect::<Vec<_>>();
+    let result = match op {
+        BlockDevRemoveOp::DestroyCache => log_action!(pool.destroy_cache(pool_uuid, &pool_name)),
+        BlockDevRemoveOp::RemoveCache => {
+            log_action!(pool.remove_cachedevs(pool_uuid, &pool_name, &uuids))
+        }
+        BlockDevRemoveOp::RemoveData => {
+            log_action!(pool.remove_blockdevs(pool_uuid, &pool_name, &uuids))
+        }
+    };
+    let msg = match result {
+        Ok(uuids) => {
+            let uuid_vec: Vec<String> = if let Some(ref changed_uuids) = uuids.changed() {
+                for uuid in changed_uuids {
+                    if let Some(op) = blockdev_map.get(uuid) {
+                        dbus_context.actions.borrow_mut().push_remove(
+                            op,
+                            m.tree,
+                            blockdev_interface_list(),
+                        );
+                    }
+                }
+                changed_uuids
+                    .iter()
+                    .map(|uuid| uuid_to_string!(uuid))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            return_message.append3((true, uuid_vec), msg_code_ok(), msg_string_ok())
+        }
+        Err(err) => {
+            // The blockdevs may have been removed from the pool even though
+            // the operation as a whole failed.
+            for (uuid, op) in blockdev_map.iter() {
+                if pool.get_blockdev(*uuid).is_none() {
+                    dbus_context.actions.borrow_mut().push_remove(
+                        op,
+                        m.tree,
+                        blockdev_interface_list(),
+                    );
+                }
+            }
+            let (rc, rs) = engine_to_dbus_err_tuple(&err);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
+
+pub fn remove_datadevs(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    remove_blockdevs(m, BlockDevRemoveOp::RemoveData)
+}
+
+pub fn remove_cachedevs(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    remove_blockdevs(m, BlockDevRemoveOp::RemoveCache)
+}
+
+pub fn destroy_cache(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    remove_blockdevs(m, BlockDevRemoveOp::DestroyCache)
+}
+
+pub fn init_cache(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    // The devices are read by add_blockdevs().
+    let _: Array<&str, _> = get_next_arg(&mut iter, 0)?;
+    let mode: (bool, &str) = get_next_arg(&mut iter, 1)?;
+    let policy: (bool, &str) = get_next_arg(&mut iter, 2)?;
+    let policy_args: HashMap<String, String> = get_next_arg(&mut iter, 3)?;
+    let block_size: (bool, &str) = get_next_arg(&mut iter, 4)?;
+
+    let return_message = message.method_return();
+    let default_return: (bool, Vec<dbus::Path>) = (false, Vec::new());
+
+    match parse_cache_config(mode, policy, policy_args, block_size) {
+        Ok(cache_config) => add_blockdevs(m, BlockDevOp::InitCache(cache_config)),
+        Err(error_message) => {
+            let (rc, rs) = (DbusErrorEnum::ERROR as u16, error_message);
+            Ok(vec![return_message.append3(default_return, rc, rs)])
+        }
+    }
+}
+
+pub fn set_cache_mode(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    let mode_string: &str = get_next_arg(&mut iter, 0)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let mode = match CacheMode::try_from(mode_string) {
+        Ok(mode) => mode,
+        Err(err) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&err);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let msg = match log_action!(pool.set_cache_mode(&pool_name, mode)) {
+        Ok(PropChangeAction::Identity) => {
+            return_message.append3(false, msg_code_ok(), msg_string_ok())
+        }
+        Ok(PropChangeAction::NewValue(_)) => {
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Err(err) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&err);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+
+    Ok(vec![msg])
+}
+
+pub fn rebind_keyring(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+
+    let key_desc_str: String = get_next_arg(&mut iter, 0)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let key_desc = match KeyDescription::try_from(key_desc_str) {
+        Ok(kd) => kd,
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (_, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let msg = match log_action!(pool.rebind_keyring(&key_desc)) {
+        Ok(RenameAction::Renamed(_)) => {
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Ok(_) => return_message.append3(false, msg_code_ok(), msg_string_ok()),
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
+
+pub fn bind_clevis(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+    let pin: String = get_next_arg(&mut iter, 0)?;
+    let json_string: String = get_next_arg(&mut iter, 1)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = (false, 0u32);
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (_, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let json: Value = match serde_json::from_str(&json_string) {
+        Ok(j) => j,
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&StratisError::Serde(e));
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+    let msg = match log_action!(pool.bind_clevis(pin, json)) {
+        Ok(CreateAction::Identity) => {
+            return_message.append3(default_return, msg_code_ok(), msg_string_ok())
+        }
+        Ok(CreateAction::Created(Clevis(id))) => {
+            return_message.append3((true, id), msg_code_ok(), msg_string_ok())
+        }
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
+
+pub fn unbind_clevis(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+    let binding_id: u32 = get_next_arg(&mut iter, 0)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (_, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let msg = match log_action!(pool.unbind_clevis(binding_id)) {
+        Ok(DeleteAction::Identity) => return_message.append3(false, msg_code_ok(), msg_string_ok()),
+        Ok(DeleteAction::Deleted(_)) => {
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
+
+pub fn rebind_clevis(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+    let mut iter = message.iter_init();
+    let binding_id: u32 = get_next_arg(&mut iter, 0)?;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (_, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let msg = match log_action!(pool.rebind_clevis(binding_id)) {
+        Ok(RenameAction::Renamed(_)) => {
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Ok(RenameAction::Identity) => return_message.append3(false, msg_code_ok(), msg_string_ok()),
+        Ok(RenameAction::NoSource) => {
+            let error_message = format!("pool has no clevis binding with ID {}", binding_id);
+            let (rc, rs) = (DbusErrorEnum::NOTFOUND as u16, error_message);
+            return_message.append3(default_return, rc, rs)
+        }
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
+
+pub fn reencrypt(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = false;
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+
+    let msg = match log_action!(pool.reencrypt(pool_uuid, &pool_name)) {
+        Ok(CreateAction::Created(_)) => {
+            return_message.append3(true, msg_code_ok(), msg_string_ok())
+        }
+        Ok(CreateAction::Identity) => return_message.append3(false, msg_code_ok(), msg_string_ok()),
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
+
+pub fn backup_metadata(m: &MethodInfo<MTFn<TData>, TData>) -> MethodResult {
+    let message: &Message = m.msg;
+
+    let dbus_context = m.tree.get_data();
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return = String::new();
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let engine = dbus_context.engine.borrow();
+    let (pool_name, pool) = get_pool!(engine; pool_uuid; default_return; return_message);
+
+    let msg = match pool
+        .backup_metadata(pool_uuid, &pool_name)
+        .and_then(|archive| Ok(serde_json::to_string(&archive)?))
+    {
+        Ok(string) => return_message.append3(string, msg_code_ok(), msg_string_ok()),
+        Err(e) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&e);
+            return_message.append3(default_return, rc, rs)
+        }
+    };
+    Ok(vec![msg])
+}
@@ -0,0 +1,11 @@
+mod api;
+mod methods;
+mod props;
+
+pub use api::{
+    auto_unlock_property, backup_metadata_method, bind_clevis_method, create_filesystems_method,
+    destroy_cache_method, encryption_space_reserved_property, init_cache_method, overprov_property,
+    rebind_clevis_method, rebind_keyring_method, reencrypt_method, remove_cachedevs_method,
+    remove_datadevs_method, set_cache_mode_method, space_alert_signal, space_thresholds_property,
+    unbind_clevis_method,
+};
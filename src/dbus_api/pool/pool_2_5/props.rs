@@ -0,0 +1,219 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use dbus::{
+    arg::{Iter, IterAppend},
+    tree::{MTFn, MethodErr, PropInfo, Tree},
+};
+
+use crate::{
+    dbus_api::{
+        pool::shared::{self, get_pool_property},
+        types::TData,
+    },
+    engine::SpaceThresholds,
+};
+
+pub fn get_pool_overprov(
+    i: &mut IterAppend,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    get_pool_property(i, p, |(_, _, pool)| Ok(shared::pool_overprov_prop(pool)))
+}
+
+/// Enable or disable overprovisioning for the pool with the given object
+/// path.
+fn set_overprov_mode(
+    tree: &Tree<MTFn<TData>, TData>,
+    object_path: &dbus::Path<'static>,
+    enabled: bool,
+) -> Result<(), String> {
+    let dbus_context = tree.get_data();
+
+    let pool_path = tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+
+    let pool_uuid = typed_uuid_string_err!(
+        pool_path
+            .get_data()
+            .as_ref()
+            .ok_or_else(|| format!("no data for object path {}", object_path))?
+            .uuid;
+        Pool
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = engine
+        .get_mut_pool(pool_uuid)
+        .ok_or_else(|| format!("no pool corresponding to uuid {}", &pool_uuid))?;
+
+    log_action!(pool.set_overprov_mode(&pool_name, enabled))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn set_pool_overprov(i: &mut Iter, p: &PropInfo<MTFn<TData>, TData>) -> Result<(), MethodErr> {
+    let enabled: bool = i
+        .get()
+        .ok_or_else(|| MethodErr::failed(&"Overprovisioning mode must be a boolean"))?;
+    set_overprov_mode(p.tree, p.path.get_name(), enabled).map_err(|ref e| MethodErr::failed(e))
+}
+
+pub fn get_pool_auto_unlock(
+    i: &mut IterAppend,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    get_pool_property(i, p, |(_, _, pool)| Ok(shared::pool_auto_unlock_prop(pool)))
+}
+
+/// Set whether the pool with the given object path is unlocked with its
+/// Clevis bindings as soon as its devices appear.
+fn set_auto_unlock(
+    tree: &Tree<MTFn<TData>, TData>,
+    object_path: &dbus::Path<'static>,
+    auto_unlock: bool,
+) -> Result<(), String> {
+    let dbus_context = tree.get_data();
+
+    let pool_path = tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+
+    let pool_uuid = typed_uuid_string_err!(
+        pool_path
+            .get_data()
+            .as_ref()
+            .ok_or_else(|| format!("no data for object path {}", object_path))?
+            .uuid;
+        Pool
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (_, pool) = engine
+        .get_mut_pool(pool_uuid)
+        .ok_or_else(|| format!("no pool corresponding to uuid {}", &pool_uuid))?;
+
+    log_action!(pool.set_auto_unlock(auto_unlock))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn set_pool_auto_unlock(
+    i: &mut Iter,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    let auto_unlock: bool = i
+        .get()
+        .ok_or_else(|| MethodErr::failed(&"Automatic unlocking must be a boolean"))?;
+    set_auto_unlock(p.tree, p.path.get_name(), auto_unlock).map_err(|ref e| MethodErr::failed(e))
+}
+
+pub fn get_pool_encryption_space_reserved(
+    i: &mut IterAppend,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    get_pool_property(i, p, |(_, _, pool)| {
+        Ok(shared::pool_encryption_space_reserved_prop(pool))
+    })
+}
+
+/// Reserve or stop reserving the space needed to encrypt the data devices
+/// of the pool with the given object path in place.
+fn set_encryption_space_reserved(
+    tree: &Tree<MTFn<TData>, TData>,
+    object_path: &dbus::Path<'static>,
+    reserved: bool,
+) -> Result<(), String> {
+    let dbus_context = tree.get_data();
+
+    let pool_path = tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+
+    let pool_uuid = typed_uuid_string_err!(
+        pool_path
+            .get_data()
+            .as_ref()
+            .ok_or_else(|| format!("no data for object path {}", object_path))?
+            .uuid;
+        Pool
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = engine
+        .get_mut_pool(pool_uuid)
+        .ok_or_else(|| format!("no pool corresponding to uuid {}", &pool_uuid))?;
+
+    log_action!(pool.set_encryption_space_reserved(&pool_name, reserved))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn set_pool_encryption_space_reserved(
+    i: &mut Iter,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    let reserved: bool = i
+        .get()
+        .ok_or_else(|| MethodErr::failed(&"Reserving encryption space must be a boolean"))?;
+    set_encryption_space_reserved(p.tree, p.path.get_name(), reserved)
+        .map_err(|ref e| MethodErr::failed(e))
+}
+
+pub fn get_pool_space_thresholds(
+    i: &mut IterAppend,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    get_pool_property(i, p, |(_, _, pool)| {
+        Ok(shared::pool_space_thresholds_prop(pool))
+    })
+}
+
+/// Set the warning and critical space thresholds for the pool with the
+/// given object path.
+fn set_space_thresholds(
+    tree: &Tree<MTFn<TData>, TData>,
+    object_path: &dbus::Path<'static>,
+    thresholds: SpaceThresholds,
+) -> Result<(), String> {
+    let dbus_context = tree.get_data();
+
+    let pool_path = tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+
+    let pool_uuid = typed_uuid_string_err!(
+        pool_path
+            .get_data()
+            .as_ref()
+            .ok_or_else(|| format!("no data for object path {}", object_path))?
+            .uuid;
+        Pool
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = engine
+        .get_mut_pool(pool_uuid)
+        .ok_or_else(|| format!("no pool corresponding to uuid {}", &pool_uuid))?;
+
+    log_action!(pool.set_space_thresholds(pool_uuid, &pool_name, thresholds))
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn set_pool_space_thresholds(
+    i: &mut Iter,
+    p: &PropInfo<MTFn<TData>, TData>,
+) -> Result<(), MethodErr> {
+    let (warning, critical): (u8, u8) = i
+        .get()
+        .ok_or_else(|| MethodErr::failed(&"Space thresholds must be a pair of percentages"))?;
+    set_space_thresholds(
+        p.tree,
+        p.path.get_name(),
+        SpaceThresholds { warning, critical },
+    )
+    .map_err(|ref e| MethodErr::failed(e))
+}
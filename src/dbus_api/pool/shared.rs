@@ -2,27 +2,34 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use dbus::{
-    arg::{Array, IterAppend},
+    arg::{Array, IterAppend, RefArg, Variant},
     tree::{MTFn, MethodErr, MethodInfo, MethodResult, PropInfo, Tree},
     Message,
 };
 
+use devicemapper::Sectors;
+
 use crate::{
     dbus_api::{
         blockdev::create_dbus_blockdev,
-        types::TData,
+        consts,
+        filesystem::create_dbus_filesystem,
+        types::{DbusErrorEnum, TData},
         util::{
             engine_to_dbus_err_tuple, get_next_arg, msg_code_ok, msg_string_ok, option_to_tuple,
         },
     },
-    engine::{BlockDevTier, EngineAction, Name, Pool, PoolUuid},
+    engine::{
+        BlockDevTier, CacheConfig, CacheStatistics, EncryptionParams, EngineAction, Name, Pool,
+        PoolUuid,
+    },
 };
 
 pub enum BlockDevOp {
-    InitCache,
+    InitCache(CacheConfig),
     AddCache,
     AddData,
 }
@@ -91,19 +98,249 @@ pub fn get_pool_total_used(m: &MethodInfo<MTFn<TData>, TData>) -> Result<String,
     })
 }
 
+/// Get the pin and configuration of the clevis binding of a pool. If the
+/// pool has several clevis bindings, the one with the lowest ID is returned.
 pub fn get_pool_clevis_info(
     m: &MethodInfo<MTFn<TData>, TData>,
 ) -> Result<(bool, (String, String)), String> {
     pool_operation(m.tree, m.path.get_name(), |(_, _, pool)| {
         Ok(option_to_tuple(
             pool.encryption_info()
-                .and_then(|i| i.clevis_info.as_ref())
-                .map(|(pin, config)| (pin.to_owned(), config.to_string())),
+                .and_then(|i| i.clevis_info.iter().min_by_key(|binding| binding.id))
+                .map(|binding| (binding.pin.to_owned(), binding.config.to_string())),
             (String::new(), String::new()),
         ))
     })
 }
 
+/// Get the ID, pin and configuration of each clevis binding of a pool.
+pub fn get_pool_clevis_bindings(
+    m: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<Vec<(u32, String, String)>, String> {
+    pool_operation(m.tree, m.path.get_name(), |(_, _, pool)| {
+        Ok(pool
+            .encryption_info()
+            .map(|i| {
+                i.clevis_info
+                    .iter()
+                    .map(|binding| {
+                        (
+                            binding.id,
+                            binding.pin.to_owned(),
+                            binding.config.to_string(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    })
+}
+
+/// Get the parameters with which the pool's devices were formatted as a map
+/// from the name of each parameter to its value. PBKDF parameters that were
+/// left to cryptsetup's defaults are omitted. The first member of the
+/// returned tuple is false if the pool is not encrypted.
+pub fn get_pool_encryption_params(
+    m: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<(bool, HashMap<String, Variant<Box<dyn RefArg>>>), String> {
+    pool_operation(m.tree, m.path.get_name(), |(_, _, pool)| {
+        Ok(option_to_tuple(
+            pool.encryption_info()
+                .map(|i| encryption_params_to_map(&i.encryption_params)),
+            HashMap::new(),
+        ))
+    })
+}
+
+fn encryption_params_to_map(
+    params: &EncryptionParams,
+) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+    let mut map: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+    map.insert(
+        consts::ENCRYPTION_PARAMS_CIPHER.to_string(),
+        Variant(Box::new(params.cipher.clone())),
+    );
+    map.insert(
+        consts::ENCRYPTION_PARAMS_KEY_SIZE.to_string(),
+        Variant(Box::new(params.key_size)),
+    );
+    map.insert(
+        consts::ENCRYPTION_PARAMS_SECTOR_SIZE.to_string(),
+        Variant(Box::new(params.sector_size)),
+    );
+    if let Some(pbkdf) = params.pbkdf {
+        map.insert(
+            consts::ENCRYPTION_PARAMS_PBKDF.to_string(),
+            Variant(Box::new(pbkdf.to_string())),
+        );
+    }
+    for (key, value) in vec![
+        (
+            consts::ENCRYPTION_PARAMS_PBKDF_MEMORY,
+            params.pbkdf_memory_kb,
+        ),
+        (consts::ENCRYPTION_PARAMS_PBKDF_TIME, params.pbkdf_time_ms),
+        (
+            consts::ENCRYPTION_PARAMS_PBKDF_PARALLEL_THREADS,
+            params.pbkdf_parallel_threads,
+        ),
+    ] {
+        if let Some(value) = value {
+            map.insert(key.to_string(), Variant(Box::new(value)));
+        }
+    }
+    map
+}
+
+/// Get the statistics of the pool's cache as a map from the name of each
+/// statistic to its value. The first member of the returned tuple is false
+/// if the pool has no cache.
+pub fn get_pool_cache_statistics(
+    m: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<(bool, HashMap<String, u64>), String> {
+    pool_operation(m.tree, m.path.get_name(), |(_, _, pool)| {
+        pool.cache_statistics()
+            .map_err(|e| e.to_string())
+            .map(|statistics| {
+                option_to_tuple(
+                    statistics.as_ref().map(cache_statistics_to_map),
+                    HashMap::new(),
+                )
+            })
+    })
+}
+
+fn cache_statistics_to_map(statistics: &CacheStatistics) -> HashMap<String, u64> {
+    vec![
+        ("ReadHits", statistics.read_hits),
+        ("ReadMisses", statistics.read_misses),
+        ("WriteHits", statistics.write_hits),
+        ("WriteMisses", statistics.write_misses),
+        ("DirtyBlocks", statistics.dirty),
+        ("Promotions", statistics.promotions),
+        ("Demotions", statistics.demotions),
+        ("UsedMetaBlocks", *statistics.used_meta),
+        ("TotalMetaBlocks", *statistics.total_meta),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+/// Get the usage of the data and metadata devices of the pool's thin pool,
+/// in blocks, as a map from the name of each quantity to its value.
+pub fn get_pool_thin_pool_usage(
+    m: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<HashMap<String, u64>, String> {
+    pool_operation(m.tree, m.path.get_name(), |(_, _, pool)| {
+        pool.thin_pool_usage()
+            .map_err(|e| e.to_string())
+            .map(|usage| {
+                vec![
+                    ("DataUsed", *usage.used_data),
+                    ("DataTotal", *usage.total_data),
+                    ("MetaUsed", *usage.used_meta),
+                    ("MetaTotal", *usage.total_meta),
+                ]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect()
+            })
+    })
+}
+
+/// Get the digest of the status of the pool's thin pool. The first member
+/// of the returned tuple is false if the status is not yet known.
+pub fn get_pool_thin_pool_status(
+    m: &MethodInfo<MTFn<TData>, TData>,
+) -> Result<(bool, String), String> {
+    pool_operation(m.tree, m.path.get_name(), |(_, _, pool)| {
+        Ok(option_to_tuple(
+            pool.thin_pool_status().map(|digest| digest.to_string()),
+            String::new(),
+        ))
+    })
+}
+
+/// A method shared by all revisions of the CreateFilesystems method. Each
+/// revision is responsible for reading its own representation of the
+/// filesystem specs off the D-Bus; specs holds the name, the optional size,
+/// and the optional size limit of each filesystem to create.
+pub fn create_filesystems_shared(
+    m: &MethodInfo<MTFn<TData>, TData>,
+    specs: &[(&str, Option<Sectors>, Option<Sectors>)],
+) -> MethodResult {
+    let message: &Message = m.msg;
+    let dbus_context = m.tree.get_data();
+
+    let object_path = m.path.get_name();
+    let return_message = message.method_return();
+    let default_return: (bool, Vec<(dbus::Path, &str)>) = (false, Vec::new());
+
+    if specs.len() > 1 {
+        let error_message = "only 1 filesystem per request allowed";
+        let (rc, rs) = (DbusErrorEnum::ERROR as u16, error_message);
+        return Ok(vec![return_message.append3(default_return, rc, rs)]);
+    }
+
+    let pool_path = m
+        .tree
+        .get(object_path)
+        .expect("implicit argument must be in tree");
+    let pool_uuid = typed_uuid!(
+        get_data!(pool_path; default_return; return_message).uuid;
+        Pool;
+        default_return;
+        return_message
+    );
+
+    let mut engine = dbus_context.engine.borrow_mut();
+    let (pool_name, pool) = get_mut_pool!(engine; pool_uuid; default_return; return_message);
+    let result = log_action!(pool.create_filesystems(pool_uuid, specs));
+
+    let infos = match result {
+        Ok(created_set) => created_set.changed(),
+        Err(err) => {
+            let (rc, rs) = engine_to_dbus_err_tuple(&err);
+            return Ok(vec![return_message.append3(default_return, rc, rs)]);
+        }
+    };
+
+    let return_value = match infos {
+        Some(ref newly_created_filesystems) => {
+            let v = newly_created_filesystems
+                .iter()
+                .map(|&(name, uuid)| {
+                    // FIXME: To avoid this expect, modify create_filesystem
+                    // so that it returns a mutable reference to the
+                    // filesystem created.
+                    (
+                        create_dbus_filesystem(
+                            dbus_context,
+                            object_path.clone(),
+                            &pool_name,
+                            &Name::new(name.to_string()),
+                            uuid,
+                            pool.get_mut_filesystem(uuid)
+                                .expect("just inserted by create_filesystems")
+                                .1,
+                        ),
+                        name,
+                    )
+                })
+                .collect::<Vec<_>>();
+            (true, v)
+        }
+        None => default_return,
+    };
+
+    Ok(vec![return_message.append3(
+        return_value,
+        msg_code_ok(),
+        msg_string_ok(),
+    )])
+}
+
 /// A method shared by all pool interfaces and by all blockdev-adding
 /// operations, including cache initialization, which is considered a
 /// blockdev-adding operation because when a cache is initialized, the
@@ -144,7 +381,9 @@ pub fn add_blockdevs(m: &MethodInfo<MTFn<TData>, TData>, op: BlockDevOp) -> Meth
     let blockdevs = devs.map(|x| Path::new(x)).collect::<Vec<&Path>>();
 
     let result = match op {
-        BlockDevOp::InitCache => log_action!(pool.init_cache(pool_uuid, &*pool_name, &blockdevs)),
+        BlockDevOp::InitCache(ref cache_config) => {
+            log_action!(pool.init_cache(pool_uuid, &*pool_name, &blockdevs, cache_config.clone()))
+        }
         BlockDevOp::AddCache => {
             log_action!(pool.add_blockdevs(pool_uuid, &*pool_name, &blockdevs, BlockDevTier::Cache))
         }
@@ -216,3 +455,31 @@ pub fn pool_name_prop(name: &Name) -> String {
 pub fn pool_enc_prop(pool: &dyn Pool) -> bool {
     pool.is_encrypted()
 }
+
+/// Generate D-Bus representation of overprovisioning property.
+#[inline]
+pub fn pool_overprov_prop(pool: &dyn Pool) -> bool {
+    pool.overprov_enabled()
+}
+
+/// Generate D-Bus representation of automatic unlocking property.
+#[inline]
+pub fn pool_auto_unlock_prop(pool: &dyn Pool) -> bool {
+    pool.encryption_info()
+        .map(|info| info.unlocks_automatically())
+        .unwrap_or(false)
+}
+
+/// Generate D-Bus representation of the property for reserving the space
+/// needed for in place encryption.
+#[inline]
+pub fn pool_encryption_space_reserved_prop(pool: &dyn Pool) -> bool {
+    pool.encryption_space_reserved()
+}
+
+/// Generate D-Bus representation of space thresholds property.
+#[inline]
+pub fn pool_space_thresholds_prop(pool: &dyn Pool) -> (u8, u8) {
+    let thresholds = pool.space_thresholds();
+    (thresholds.warning, thresholds.critical)
+}
@@ -7,15 +7,17 @@ use dbus::{
     ffidisp::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Connection},
     message::SignalArgs,
     tree::{MTFn, MethodErr, PropInfo},
+    Message,
 };
 
-use devicemapper::DmError;
+use devicemapper::{Bytes, DmError, Sectors, SECTOR_SIZE};
 
 use crate::{
     dbus_api::{
         consts,
         types::{DbusContext, DbusErrorEnum, TData},
     },
+    engine::{PoolUuid, SpaceAlertLevel},
     stratis::{ErrorEnum, StratisError},
 };
 
@@ -36,6 +38,24 @@ pub fn option_to_tuple<T>(value: Option<T>, default: T) -> (bool, T) {
     }
 }
 
+/// Parse a size in bytes, as represented on the D-Bus, into sectors. The
+/// size must be a multiple of the sector size.
+pub fn parse_size_bytes(size: &str) -> Result<Sectors, String> {
+    let bytes = size
+        .parse::<u128>()
+        .map(Bytes)
+        .map_err(|_| format!("{} is not a valid size in bytes", size))?;
+    let sectors = bytes.sectors();
+    if sectors.bytes() == bytes {
+        Ok(sectors)
+    } else {
+        Err(format!(
+            "{} is not a multiple of the sector size, {} bytes",
+            size, SECTOR_SIZE
+        ))
+    }
+}
+
 /// Map a result obtained for the FetchProperties interface to a value used
 /// to represent an option.  An error in the result
 /// argument yields a false in the return value, indicating that the value
@@ -172,3 +192,27 @@ where
 
     Ok(())
 }
+
+/// Place a space alert signal on the D-Bus for the pool with the given
+/// object path. The amounts of space used and in total are represented as
+/// strings of bytes.
+pub fn space_alert_dispatch(
+    conn: &Connection,
+    path: &dbus::Path,
+    pool_uuid: PoolUuid,
+    level: SpaceAlertLevel,
+    used: Sectors,
+    total: Sectors,
+) -> Result<(), ()> {
+    let msg = Message::new_signal(
+        &**path,
+        consts::POOL_INTERFACE_NAME_2_5,
+        consts::POOL_SPACE_ALERT_SIGNAL,
+    )
+    .map_err(|_| ())?
+    .append2(uuid_to_string!(pool_uuid), level.to_string())
+    .append2((*used.bytes()).to_string(), (*total.bytes()).to_string());
+    conn.send(msg)?;
+
+    Ok(())
+}
@@ -7,6 +7,7 @@ use std::{
     fmt::Debug,
     os::unix::io::RawFd,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use chrono::{DateTime, Utc};
@@ -16,9 +17,11 @@ use devicemapper::{Bytes, Sectors, DM};
 
 use crate::{
     engine::types::{
-        BlockDevTier, Clevis, CreateAction, DeleteAction, DevUuid, EncryptionInfo, FilesystemUuid,
-        Key, KeyDescription, MappingCreateAction, MaybeDbusPath, Name, PoolUuid, RenameAction,
-        ReportType, SetCreateAction, SetDeleteAction, SetUnlockAction, UnlockMethod,
+        BlockDevTier, CacheConfig, CacheMode, CacheStatistics, Clevis, CreateAction, DeleteAction,
+        DevUuid, EncryptionInfo, EncryptionParams, FilesystemUuid, Key, KeyDescription,
+        MappingCreateAction, MaybeDbusPath, Name, PoolUuid, PropChangeAction, Reencryption,
+        ReencryptionProgress, RenameAction, ReportType, SetCreateAction, SetDeleteAction,
+        SetUnlockAction, SpaceThresholds, ThinPoolStatusDigest, ThinPoolUsage, UnlockMethod,
     },
     stratis::StratisResult,
 };
@@ -26,6 +29,9 @@ use crate::{
 pub const DEV_PATH: &str = "/dev/stratis";
 /// The maximum size of pool passphrases stored in the kernel keyring
 pub const MAX_STRATIS_PASS_SIZE: usize = 512 / 8;
+/// The time spent reencrypting the devices of a pool before returning to
+/// the event loop, so that other events can be handled.
+pub const REENCRYPT_STEP_DURATION: Duration = Duration::from_millis(500);
 
 pub trait KeyActions {
     /// Set a key in the kernel keyring. The output is an idempotent return type
@@ -39,15 +45,21 @@ pub trait KeyActions {
     /// keyring.
     /// * `Ok(MappingCreateAction::Changed)`: The key description was already present
     /// in the keyring but the key data was updated.
+    ///
+    /// If a timeout is given, the key expires and is removed from the keyring
+    /// once the timeout has elapsed; otherwise, the key never expires. The
+    /// timeout replaces any previous timeout even if the key data is unchanged.
     fn set(
         &mut self,
         key_desc: &KeyDescription,
         key_fd: RawFd,
+        timeout: Option<Duration>,
     ) -> StratisResult<MappingCreateAction<Key>>;
 
     /// Return a list of all key descriptions of keys added to the keyring by
-    /// Stratis that are still valid.
-    fn list(&self) -> StratisResult<Vec<KeyDescription>>;
+    /// Stratis that are still valid, each with the remaining lifetime of the
+    /// key if it expires.
+    fn list(&self) -> StratisResult<Vec<(KeyDescription, Option<Duration>)>>;
 
     /// Unset a key with the given key description in the root persistent kernel
     /// keyring.
@@ -78,6 +90,12 @@ pub trait Filesystem: Debug {
     /// The amount of data stored on the filesystem, including overhead.
     fn used(&self) -> StratisResult<Bytes>;
 
+    /// The logical size of the filesystem.
+    fn size(&self) -> Bytes;
+
+    /// The size beyond which the filesystem will not be extended, if any.
+    fn size_limit(&self) -> Option<Bytes>;
+
     /// Set dbus path associated with the Pool.
     fn set_dbus_path(&mut self, path: MaybeDbusPath);
 
@@ -116,10 +134,15 @@ pub trait BlockDev: Debug {
 
     /// Get the status of whether a block device is encrypted or not.
     fn is_encrypted(&self) -> bool;
+
+    /// The progress of the reencryption of the block device under a new
+    /// volume key, if it is being reencrypted.
+    fn reencryption_progress(&self) -> Option<ReencryptionProgress>;
 }
 
 pub trait Pool: Debug {
-    /// Initialize the cache with the provided cache block devices.
+    /// Initialize the cache with the provided cache block devices. The cache
+    /// is configured as specified by cache_config.
     /// Returns a list of the the block devices that were actually added as cache
     /// devices. In practice, this will have three types of return values:
     /// * An error if the cache has already been initialized with a different set
@@ -132,23 +155,60 @@ pub trait Pool: Debug {
     /// This ensures the contract of providing a truly idempotent API as the cache
     /// can only be initialized once and if an attempt is made to initialize it
     /// twice with different sets of block devices, the user should be notified
-    /// of their error.
+    /// of their error. The configuration of an existing cache is not
+    /// compared with cache_config, since its mode may have been changed.
     fn init_cache(
         &mut self,
         pool_uuid: PoolUuid,
         pool_name: &str,
         blockdevs: &[&Path],
+        cache_config: CacheConfig,
     ) -> StratisResult<SetCreateAction<DevUuid>>;
 
-    /// Creates the filesystems specified by specs.
+    /// Switch the pool's cache to the specified mode.
+    /// Returns an error if the pool has no cache, or if the cache contains
+    /// dirty blocks and the requested mode is passthrough.
+    fn set_cache_mode(
+        &mut self,
+        pool_name: &str,
+        mode: CacheMode,
+    ) -> StratisResult<PropChangeAction<CacheMode>>;
+
+    /// Destroy the cache of the pool. Any dirty blocks in the cache are
+    /// first written back to the data tier. The cachedevs are wiped.
+    /// Returns the UUIDs of the removed cachedevs; if the pool has no cache
+    /// the list is empty.
+    fn destroy_cache(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+    ) -> StratisResult<SetDeleteAction<DevUuid>>;
+
+    /// Remove the cachedevs specified by uuids from the pool. The cache is
+    /// flushed and then made anew, and empty, on the remaining cachedevs. If
+    /// no cachedevs remain the cache is destroyed. The removed cachedevs are
+    /// wiped.
+    /// Returns a list of the uuids of the cachedevs actually removed; uuids
+    /// that do not belong to the pool are ignored.
+    /// Returns an error if a uuid belongs to a data tier blockdev.
+    fn remove_cachedevs(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+        uuids: &[DevUuid],
+    ) -> StratisResult<SetDeleteAction<DevUuid>>;
+
+    /// Creates the filesystems specified by specs. Each spec consists of a
+    /// name, an optional initial size, and an optional limit beyond which
+    /// the filesystem will never be extended.
     /// Returns a list of the names of filesystems actually created.
     /// Returns an error if any of the specified names are already in use
     /// for filesystems in this pool. If the same name is passed multiple
-    /// times, the size associated with the last item is used.
+    /// times, the size and size limit associated with the last item are used.
     fn create_filesystems<'a, 'b>(
         &'a mut self,
         pool_uuid: PoolUuid,
-        specs: &[(&'b str, Option<Sectors>)],
+        specs: &[(&'b str, Option<Sectors>, Option<Sectors>)],
     ) -> StratisResult<SetCreateAction<(&'b str, FilesystemUuid)>>;
 
     /// Adds blockdevs specified by paths to pool.
@@ -165,16 +225,65 @@ pub trait Pool: Debug {
         tier: BlockDevTier,
     ) -> StratisResult<SetCreateAction<DevUuid>>;
 
+    /// Removes the data tier blockdevs specified by uuids from the pool.
+    /// Any data on the blockdevs is first moved to free space on the
+    /// remaining data tier blockdevs, and the removed blockdevs are wiped.
+    /// Returns a list of the uuids of the blockdevs actually removed; uuids
+    /// that do not belong to the pool are ignored.
+    /// Returns an error if a uuid belongs to a cache tier blockdev, if all the
+    /// data tier blockdevs would be removed, or if there is not sufficient
+    /// free space on the remaining blockdevs to accommodate the data moved.
+    fn remove_blockdevs(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+        uuids: &[DevUuid],
+    ) -> StratisResult<SetDeleteAction<DevUuid>>;
+
     /// Bind all devices in the given pool for automated unlocking
-    /// using clevis.
+    /// using clevis. A pool may have several clevis bindings, any one of
+    /// which can unlock it; the id of the new binding is returned.
+    /// Binding with a pin and configuration the pool is already bound
+    /// with changes nothing.
     fn bind_clevis(
         &mut self,
         pin: String,
         clevis_info: Value,
     ) -> StratisResult<CreateAction<Clevis>>;
 
-    /// Unbind all devices in the given pool from using clevis.
-    fn unbind_clevis(&mut self) -> StratisResult<DeleteAction<Clevis>>;
+    /// Unbind all devices in the given pool from the clevis binding with
+    /// the given id.
+    fn unbind_clevis(&mut self, binding_id: u32) -> StratisResult<DeleteAction<Clevis>>;
+
+    /// Regenerate the clevis binding with the given id on all devices in
+    /// the given pool against the current advertisement of the tang servers
+    /// it uses, e.g., after the tang servers have rotated their keys. The
+    /// binding keeps its id. Returns RenameAction::NoSource if the pool has
+    /// no clevis binding with the given id.
+    fn rebind_clevis(&mut self, binding_id: u32) -> StratisResult<RenameAction<Clevis>>;
+
+    /// Bind all devices in the given pool to the key in the kernel keyring
+    /// with the given key description in place of their current key.
+    fn rebind_keyring(&mut self, new_key_desc: &KeyDescription)
+        -> StratisResult<RenameAction<Key>>;
+
+    /// Set whether the encrypted pool is unlocked with its clevis bindings
+    /// as soon as its devices appear, e.g., when stratisd starts. Enabling
+    /// automatic unlocking requires the pool to have a clevis binding; if
+    /// all of its clevis bindings are removed later, the pool is no longer
+    /// unlocked automatically.
+    fn set_auto_unlock(&mut self, auto_unlock: bool) -> StratisResult<PropChangeAction<bool>>;
+
+    /// Start reencrypting the data devices of an encrypted pool under newly
+    /// generated volume keys, or resume the reencryption if it was paused
+    /// by an error. The data is reencrypted in the background while the
+    /// pool remains in use; see Engine::reencrypt_step().
+    /// Returns an error if the pool is degraded.
+    fn reencrypt(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+    ) -> StratisResult<CreateAction<Reencryption>>;
 
     /// Ensures that all designated filesystems are gone from pool.
     /// Returns a list of the filesystems found, and actually destroyed.
@@ -182,6 +291,7 @@ pub trait Pool: Debug {
     /// Precondition: All filesystems given must be unmounted.
     fn destroy_filesystems<'a>(
         &'a mut self,
+        pool_uuid: PoolUuid,
         pool_name: &str,
         fs_uuids: &[FilesystemUuid],
     ) -> StratisResult<SetDeleteAction<FilesystemUuid>>;
@@ -198,6 +308,55 @@ pub trait Pool: Debug {
         new_name: &str,
     ) -> StratisResult<RenameAction<FilesystemUuid>>;
 
+    /// Set the logical size of the filesystem with the given UUID to new_size.
+    /// Only growing the filesystem is supported; a new_size smaller than the
+    /// current size or larger than the filesystem's size limit results in an
+    /// error.
+    fn set_filesystem_size(
+        &mut self,
+        uuid: FilesystemUuid,
+        new_size: Sectors,
+    ) -> StratisResult<PropChangeAction<Sectors>>;
+
+    /// Whether the sum of the logical sizes of the pool's filesystems may
+    /// exceed the space available to the pool for storing filesystem data.
+    fn overprov_enabled(&self) -> bool;
+
+    /// Enable or disable overprovisioning for this pool. Disabling
+    /// overprovisioning fails if the pool is already overprovisioned.
+    fn set_overprov_mode(
+        &mut self,
+        pool_name: &str,
+        enabled: bool,
+    ) -> StratisResult<PropChangeAction<bool>>;
+
+    /// Whether the space needed to encrypt the pool's unencrypted data
+    /// devices in place is kept free at the end of each device.
+    fn encryption_space_reserved(&self) -> bool;
+
+    /// Reserve or stop reserving the space needed to encrypt the pool's
+    /// unencrypted data devices in place. Any data in that space is moved
+    /// to free space on the other data devices, now if there is enough free
+    /// space, otherwise when data devices are next added to the pool.
+    fn set_encryption_space_reserved(
+        &mut self,
+        pool_name: &str,
+        reserved: bool,
+    ) -> StratisResult<PropChangeAction<bool>>;
+
+    /// The percentages of the pool's capacity for filesystem data which,
+    /// once in use, trigger a warning and a critical space alert.
+    fn space_thresholds(&self) -> SpaceThresholds;
+
+    /// Set the space thresholds of this pool. The space alert level is
+    /// reevaluated immediately.
+    fn set_space_thresholds(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+        thresholds: SpaceThresholds,
+    ) -> StratisResult<PropChangeAction<SpaceThresholds>>;
+
     /// Snapshot filesystem
     /// Create a CoW snapshot of the origin
     fn snapshot_filesystem(
@@ -221,6 +380,14 @@ pub trait Pool: Debug {
     /// store user data.
     fn total_physical_used(&self) -> StratisResult<Sectors>;
 
+    /// The usage of the data and metadata devices of the pool's thin pool
+    /// as of the most recent check of the pool.
+    fn thin_pool_usage(&self) -> StratisResult<ThinPoolUsage>;
+
+    /// The digest of the status of the pool's thin pool as of the most
+    /// recent check of the pool. None if the pool has not yet been checked.
+    fn thin_pool_status(&self) -> Option<ThinPoolStatusDigest>;
+
     /// Get all the filesystems belonging to this pool.
     fn filesystems(&self) -> Vec<(Name, FilesystemUuid, &dyn Filesystem)>;
 
@@ -264,11 +431,21 @@ pub trait Pool: Debug {
     /// true if the pool has a cache, otherwise false
     fn has_cache(&self) -> bool;
 
+    /// Statistics on the operation of the pool's cache, None if the pool
+    /// has no cache.
+    fn cache_statistics(&self) -> StratisResult<Option<CacheStatistics>>;
+
     /// Determine if the pool's data is encrypted
     fn is_encrypted(&self) -> bool;
 
     /// Get all encryption information for this pool.
     fn encryption_info(&self) -> Option<&EncryptionInfo>;
+
+    /// A versioned JSON archive of the pool's metadata: the signature block
+    /// of each of its devices, the pool-level metadata and the record of
+    /// each of its filesystems. The metadata can be restored from it while
+    /// the pool is not set up.
+    fn backup_metadata(&self, pool_uuid: PoolUuid, pool_name: &str) -> StratisResult<Value>;
 }
 
 pub trait Engine: Debug + Report {
@@ -276,12 +453,17 @@ pub trait Engine: Debug + Report {
     /// Returns the UUID of the newly created pool.
     /// Returns an error if the redundancy code does not correspond to a
     /// supported redundancy.
+    /// If the pool is encrypted, its block devices are formatted with
+    /// encryption_params if specified, otherwise with the defaults.
+    /// Returns an error if encryption_params is specified and the pool is
+    /// not encrypted.
     fn create_pool(
         &mut self,
         name: &str,
         blockdev_paths: &[&Path],
         redundancy: Option<u16>,
         key_desc: Option<KeyDescription>,
+        encryption_params: Option<EncryptionParams>,
     ) -> StratisResult<CreateAction<PoolUuid>>;
 
     /// Handle a libudev event.
@@ -306,16 +488,37 @@ pub trait Engine: Debug + Report {
         new_name: &str,
     ) -> StratisResult<RenameAction<PoolUuid>>;
 
+    /// Encrypt the contents of the data devices of an unencrypted pool in
+    /// place, using the key with the given key description and the given
+    /// encryption parameters, or the defaults if none are specified.
+    /// The pool is stopped while its devices are encrypted, so none of its
+    /// filesystems may be mounted, and the last part of each data device
+    /// must be unallocated.
+    /// Returns an error if the pool is encrypted with a different key
+    /// description.
+    fn encrypt_pool(
+        &mut self,
+        uuid: PoolUuid,
+        key_desc: &KeyDescription,
+        encryption_params: Option<EncryptionParams>,
+    ) -> StratisResult<CreateAction<Key>>;
+
     /// Unlock all encrypted devices registered under a given pool UUID.
     /// This method returns a `Vec<DevUuid>`. This `Vec` will contain UUIDs of
     /// devices that were newly unlocked while ignoring devices that are already
     /// in the unlocked state. If some devices are able to be unlocked
     /// and some fail, an error is returned as all devices should be able to
     /// be unlocked if the necessary key is in the keyring.
+    ///
+    /// If the unlock method is UnlockMethod::Passphrase, the passphrase is
+    /// read from passphrase_fd and is used only for the duration of the
+    /// unlock; it is never stored in the kernel keyring. passphrase_fd must
+    /// be None for any other unlock method.
     fn unlock_pool(
         &mut self,
         uuid: PoolUuid,
         unlock_method: UnlockMethod,
+        passphrase_fd: Option<RawFd>,
     ) -> StratisResult<SetUnlockAction<DevUuid>>;
 
     /// Find the pool designated by uuid.
@@ -346,6 +549,20 @@ pub trait Engine: Debug + Report {
     /// Notify the engine that an event has occurred on the DM file descriptor.
     fn evented(&mut self) -> StratisResult<()>;
 
+    /// Reencrypt the data devices of the pools being reencrypted for a short
+    /// while. Returns true if some reencryption remains to be done, in which
+    /// case this method should be called again as soon as other events have
+    /// been handled.
+    fn reencrypt_step(&mut self) -> bool;
+
+    /// Start the attempts to unlock pools automatically that are due, e.g.,
+    /// because the locked devices of a pool have appeared or because an
+    /// earlier attempt failed, and collect the results of the attempts that
+    /// have finished. The attempts are made without blocking the caller.
+    /// Returns the time until this method must next be called, if any
+    /// attempt remains, so that the caller can arrange to call it by then.
+    fn process_auto_unlocks(&mut self) -> Option<Duration>;
+
     /// Get the handler for kernel keyring operations.
     fn get_key_handler(&self) -> &dyn KeyActions;
 
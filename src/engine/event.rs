@@ -4,7 +4,9 @@
 
 use std::{fmt::Debug, sync::Once};
 
-use crate::engine::types::MaybeDbusPath;
+use devicemapper::Sectors;
+
+use crate::engine::types::{MaybeDbusPath, PoolUuid, SpaceAlertLevel};
 
 static INIT: Once = Once::new();
 static mut ENGINE_LISTENER_LIST: Option<EngineListenerList> = None;
@@ -21,6 +23,13 @@ pub enum EngineEvent<'a> {
         from: &'a str,
         to: &'a str,
     },
+    PoolSpaceAlert {
+        dbus_path: &'a MaybeDbusPath,
+        pool_uuid: PoolUuid,
+        level: SpaceAlertLevel,
+        used: Sectors,
+        total: Sectors,
+    },
 }
 
 pub trait EngineListener: Debug {
@@ -3,15 +3,21 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 pub use self::{
-    engine::{BlockDev, Engine, Filesystem, KeyActions, Pool, Report},
+    engine::{BlockDev, Engine, Filesystem, KeyActions, Pool, Report, REENCRYPT_STEP_DURATION},
     event::{get_engine_listener_list_mut, EngineEvent, EngineListener},
     sim_engine::SimEngine,
-    strat_engine::{StratEngine, StratKeyActions, BDA},
+    strat_engine::{
+        backup_pool_metadata, dump_device, dump_filesystem_records, fsck_devices, fsck_pool,
+        repair_devices, repair_pool, restore_pool_metadata, FsckReport, Problem, Severity,
+        StratEngine, StratKeyActions, BDA,
+    },
     types::{
-        BlockDevState, BlockDevTier, CreateAction, DeleteAction, DevUuid, EngineAction,
-        FilesystemUuid, KeyDescription, MappingCreateAction, MaybeDbusPath, Name, PoolUuid,
-        Redundancy, RenameAction, ReportType, SetCreateAction, SetDeleteAction, StratisUuid,
-        UnlockMethod,
+        BlockDevState, BlockDevTier, CacheConfig, CacheMode, CacheStatistics, CreateAction,
+        DeleteAction, DevUuid, EncryptionParams, EngineAction, FilesystemUuid, KeyDescription,
+        MappingCreateAction, MaybeDbusPath, Name, Pbkdf, PoolUuid, PropChangeAction, Redundancy,
+        Reencryption, ReencryptionProgress, RenameAction, ReportType, SetCreateAction,
+        SetDeleteAction, SpaceAlertLevel, SpaceThresholds, StratisUuid, ThinPoolStatusDigest,
+        ThinPoolUsage, UnlockMethod,
     },
 };
 
@@ -8,30 +8,38 @@ use std::{
     io::Read,
     os::unix::io::{AsRawFd, FromRawFd, RawFd},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use nix::poll::{poll, PollFd, PollFlags};
 use regex::Regex;
+use serde_json::{json, Value};
 
-use devicemapper::Bytes;
+use devicemapper::{Bytes, Sectors, MAX_CACHE_BLOCK_SIZE, MIN_CACHE_BLOCK_SIZE};
 use libcryptsetup_rs::SafeMemHandle;
 
 use crate::{
     engine::{
-        engine::{Pool, MAX_STRATIS_PASS_SIZE},
-        types::{BlockDevTier, CreateAction, DevUuid, PoolUuid, SetCreateAction, SizedKeyMemory},
+        engine::{Engine, Pool, MAX_STRATIS_PASS_SIZE},
+        types::{
+            BlockDevTier, CacheConfig, CreateAction, DevUuid, EncryptionInfo, EncryptionParams,
+            KeyDescription, Pbkdf, PoolUuid, SetCreateAction, SizedKeyMemory, SpaceThresholds,
+            UnlockMethod,
+        },
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
 /// Called when the name of a requested pool coincides with the name of an
 /// existing pool. Returns an error if the specifications of the requested
-/// pool differ from the specifications of the existing pool, otherwise
-/// returns Ok(CreateAction::Identity).
+/// pool, including its key description and encryption parameters, differ
+/// from the specifications of the existing pool, otherwise returns
+/// Ok(CreateAction::Identity).
 pub fn create_pool_idempotent_or_err(
     pool: &dyn Pool,
     pool_name: &str,
     blockdev_paths: &[&Path],
+    encryption_info: Option<&EncryptionInfo>,
 ) -> StratisResult<CreateAction<PoolUuid>> {
     let input_devices: HashSet<PathBuf, RandomState> =
         blockdev_paths.iter().map(|p| p.to_path_buf()).collect();
@@ -48,9 +56,7 @@ pub fn create_pool_idempotent_or_err(
         })
         .collect();
 
-    if input_devices == existing_paths {
-        Ok(CreateAction::Identity)
-    } else {
+    if input_devices != existing_paths {
         let in_input = input_devices
             .difference(&existing_paths)
             .map(|path| path.display().to_string())
@@ -59,9 +65,40 @@ pub fn create_pool_idempotent_or_err(
             .difference(&input_devices)
             .map(|path| path.display().to_string())
             .collect::<Vec<_>>();
-        Err(StratisError::Engine(
+        return Err(StratisError::Engine(
             ErrorEnum::Invalid,
             create_pool_generate_error_string!(pool_name, in_input, in_pool),
+        ));
+    }
+
+    let describe = |info: Option<&EncryptionInfo>| match info {
+        Some(info) => format!(
+            "encrypted with key description {} and parameters ({})",
+            info.key_description.as_application_str(),
+            info.encryption_params
+        ),
+        None => "unencrypted".to_string(),
+    };
+    let existing_info = pool.encryption_info();
+    let same_encryption = match (encryption_info, existing_info) {
+        (None, None) => true,
+        (Some(requested), Some(existing)) => {
+            requested.key_description == existing.key_description
+                && requested.encryption_params == existing.encryption_params
+        }
+        _ => false,
+    };
+    if same_encryption {
+        Ok(CreateAction::Identity)
+    } else {
+        Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "A pool named {} already exists and is {}, but the requested pool is {}",
+                pool_name,
+                describe(existing_info),
+                describe(encryption_info)
+            ),
         ))
     }
 }
@@ -124,6 +161,107 @@ pub fn set_key_shared(key_fd: RawFd) -> StratisResult<SizedKeyMemory> {
     Ok(sized_memory)
 }
 
+/// Validate a timeout after which a key is to expire. The kernel keyring
+/// measures timeouts in whole seconds and treats a timeout of 0 seconds as no
+/// timeout at all, so a timeout of less than one second is rejected.
+pub fn validate_key_timeout(timeout: Option<Duration>) -> StratisResult<()> {
+    match timeout {
+        Some(t) if t.as_secs() == 0 => Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "A key timeout must be at least one second".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Shared implementation of reading the passphrase with which to unlock a
+/// pool for both the strat_engine and sim_engine.
+/// A passphrase file descriptor must be given if and only if the unlock
+/// method is UnlockMethod::Passphrase. The passphrase is read into memory
+/// only; it is never placed in the kernel keyring.
+pub fn unlock_passphrase_shared(
+    unlock_method: UnlockMethod,
+    passphrase_fd: Option<RawFd>,
+) -> StratisResult<Option<SizedKeyMemory>> {
+    match (unlock_method, passphrase_fd) {
+        (UnlockMethod::Passphrase, Some(fd)) => set_key_shared(fd).map(Some),
+        (UnlockMethod::Passphrase, None) => Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "Unlocking with a passphrase requires a passphrase file descriptor".to_string(),
+        )),
+        (_, Some(_)) => Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "A passphrase file descriptor may only be given when unlocking with a passphrase"
+                .to_string(),
+        )),
+        (_, None) => Ok(None),
+    }
+}
+
+/// Shared implementation of the missing keys report for both the
+/// strat_engine and sim_engine.
+///
+/// For every encrypted pool, whether locked or set up, the report shows
+/// whether the key description required to unlock the pool is present in
+/// the kernel keyring, whether the pool is bound with Clevis, and which
+/// unlock methods are currently viable. Unlocking with a passphrase given
+/// with the unlock request is always viable.
+pub fn missing_keys_report(engine: &dyn Engine) -> Value {
+    let key_descs = match engine.get_key_handler().list() {
+        Ok(keys) => keys
+            .into_iter()
+            .map(|(kd, _)| kd)
+            .collect::<HashSet<KeyDescription>>(),
+        Err(e) => {
+            return json!({
+                "error": format!("Failed to list the keys in the kernel keyring: {}", e),
+            })
+        }
+    };
+
+    let pool_entry = |pool_uuid: PoolUuid, locked: bool, info: &EncryptionInfo| {
+        let key_present = key_descs.contains(&info.key_description);
+        let clevis_bound = !info.clevis_info.is_empty();
+        let mut unlock_methods = Vec::new();
+        if key_present {
+            unlock_methods.push("keyring");
+        }
+        if clevis_bound {
+            unlock_methods.push("clevis");
+        }
+        unlock_methods.push("passphrase");
+        json!({
+            "pool_uuid": pool_uuid.to_simple_ref().to_string(),
+            "locked": locked,
+            "key_description": info.key_description.as_application_str(),
+            "key_present": key_present,
+            "clevis_bound": clevis_bound,
+            "viable_unlock_methods": unlock_methods,
+        })
+    };
+
+    let mut locked_pools = engine.locked_pools().into_iter().collect::<Vec<_>>();
+    locked_pools.sort_by_key(|(pool_uuid, _)| pool_uuid.0);
+    let mut pools = locked_pools
+        .iter()
+        .map(|(pool_uuid, info)| pool_entry(*pool_uuid, true, info))
+        .collect::<Vec<_>>();
+    pools.extend(
+        engine
+            .pools()
+            .into_iter()
+            .filter_map(|(name, pool_uuid, pool)| {
+                pool.encryption_info().map(|info| {
+                    let mut entry = pool_entry(pool_uuid, false, info);
+                    entry["pool_name"] = Value::from(name.to_string());
+                    entry
+                })
+            }),
+    );
+
+    json!({ "pools": pools })
+}
+
 /// Validate a str for use as a Pool or Filesystem name.
 pub fn validate_name(name: &str) -> StratisResult<()> {
     if name.contains('\u{0}') {
@@ -211,6 +349,243 @@ pub fn validate_paths(paths: &[&Path]) -> StratisResult<()> {
     }
 }
 
+/// Verify that a cache configuration can be expressed in a DM cache table
+/// and that its block size is one that the kernel accepts.
+pub fn validate_cache_config(config: &CacheConfig) -> StratisResult<()> {
+    if config.block_size < MIN_CACHE_BLOCK_SIZE
+        || config.block_size > MAX_CACHE_BLOCK_SIZE
+        || config.block_size % MIN_CACHE_BLOCK_SIZE != Sectors(0)
+    {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "Cache block size {} is not a multiple of {} between {} and {}",
+                config.block_size, MIN_CACHE_BLOCK_SIZE, MIN_CACHE_BLOCK_SIZE, MAX_CACHE_BLOCK_SIZE
+            ),
+        ));
+    }
+
+    // The table of a DM device is a list of whitespace separated words.
+    let mut words = vec![config.policy.as_str()];
+    for (key, value) in config.policy_args.iter() {
+        words.push(key);
+        words.push(value);
+    }
+    if let Some(word) = words
+        .iter()
+        .find(|word| word.is_empty() || word.chars().any(|c| c.is_whitespace() || c.is_control()))
+    {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "Cache policy name or argument \"{}\" is empty or contains whitespace or control characters",
+                word
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Verify that the space thresholds are percentages, that the warning
+/// threshold does not exceed the critical threshold, and that the critical
+/// threshold leaves some space free, so that the thin pool can signal that
+/// it has been crossed.
+pub fn validate_space_thresholds(thresholds: SpaceThresholds) -> StratisResult<()> {
+    if thresholds.warning == 0
+        || thresholds.warning > thresholds.critical
+        || thresholds.critical >= 100
+    {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "Space thresholds must satisfy 0 < warning <= critical < 100, but warning is {} and critical is {}",
+                thresholds.warning, thresholds.critical
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The amount by which the sum of the logical sizes of a pool's filesystems
+/// may still grow without exceeding data_size, the space available for
+/// filesystems. None if overprovisioning is enabled, in which case there is
+/// no such restriction.
+pub fn overprov_available_space(
+    enable_overprov: bool,
+    total_logical_size: Sectors,
+    data_size: Sectors,
+) -> Option<Sectors> {
+    if enable_overprov {
+        return None;
+    }
+    Some(if total_logical_size < data_size {
+        data_size - total_logical_size
+    } else {
+        Sectors(0)
+    })
+}
+
+/// Return an error if adding additional_size to the sum of the logical sizes
+/// of a pool's filesystems would exceed available, the value obtained from
+/// overprov_available_space().
+pub fn check_overprov_request(
+    available: Option<Sectors>,
+    additional_size: Sectors,
+) -> StratisResult<()> {
+    match available {
+        Some(available) if additional_size > available => Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "Overprovisioning is disabled; {} requested but only {} is available for filesystems",
+                additional_size, available
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Return an error if overprovisioning can not be disabled because the sum
+/// of the logical sizes of a pool's filesystems already exceeds data_size,
+/// the space available for filesystems.
+pub fn check_overprov_disable(
+    total_logical_size: Sectors,
+    data_size: Sectors,
+) -> StratisResult<()> {
+    if total_logical_size > data_size {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "Overprovisioning can not be disabled; the filesystems' logical sizes sum to {}, but only {} is available for filesystems",
+                total_logical_size, data_size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Get the encryption info of a pool to be created from the key description
+/// and the optional encryption parameters, if the pool is to be encrypted.
+/// Returns an error if the encryption parameters are invalid or are
+/// specified for an unencrypted pool.
+pub fn create_pool_encryption_info(
+    key_desc: Option<KeyDescription>,
+    encryption_params: Option<EncryptionParams>,
+) -> StratisResult<Option<EncryptionInfo>> {
+    match (key_desc, encryption_params) {
+        (None, None) => Ok(None),
+        (None, Some(_)) => Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "Encryption parameters may only be specified for an encrypted pool".to_string(),
+        )),
+        (Some(key_description), encryption_params) => {
+            let encryption_params = encryption_params.unwrap_or_default();
+            validate_encryption_params(&encryption_params)?;
+            Ok(Some(EncryptionInfo {
+                key_description,
+                clevis_info: Vec::new(),
+                encryption_params,
+                auto_unlock: false,
+            }))
+        }
+    }
+}
+
+/// The largest media encryption key, in bits, that LUKS2 can hold.
+const MAX_KEY_SIZE: u32 = 512 * 8;
+
+/// The smallest and largest encryption sector sizes dm-crypt supports.
+const MIN_ENCRYPTION_SECTOR_SIZE: u32 = 512;
+const MAX_ENCRYPTION_SECTOR_SIZE: u32 = 4096;
+
+/// The bounds libcryptsetup places on the costs of the argon2 PBKDFs.
+const MIN_PBKDF_MEMORY_KB: u32 = 32;
+const MAX_PBKDF_MEMORY_KB: u32 = 4 * 1024 * 1024;
+const MAX_PBKDF_PARALLEL_THREADS: u32 = 4;
+
+/// Verify that the encryption parameters are well formed. Whether the cipher
+/// is supported by the kernel, and with the given key size, is only known
+/// once a device is formatted.
+pub fn validate_encryption_params(params: &EncryptionParams) -> StratisResult<()> {
+    let invalid = |msg: String| Err(StratisError::Engine(ErrorEnum::Invalid, msg));
+
+    let (cipher, mode) = params.cipher_and_mode();
+    if cipher.is_empty()
+        || mode.is_empty()
+        || params
+            .cipher
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return invalid(format!(
+            "Cipher \"{}\" is not of the form <cipher>-<mode>, e.g., aes-xts-plain64",
+            params.cipher
+        ));
+    }
+
+    if params.key_size == 0 || params.key_size % 8 != 0 || params.key_size > MAX_KEY_SIZE {
+        return invalid(format!(
+            "Key size {} is not a multiple of 8 bits between 8 and {} bits",
+            params.key_size, MAX_KEY_SIZE
+        ));
+    }
+
+    if !params.sector_size.is_power_of_two()
+        || params.sector_size < MIN_ENCRYPTION_SECTOR_SIZE
+        || params.sector_size > MAX_ENCRYPTION_SECTOR_SIZE
+    {
+        return invalid(format!(
+            "Encryption sector size {} is not a power of 2 between {} and {} bytes",
+            params.sector_size, MIN_ENCRYPTION_SECTOR_SIZE, MAX_ENCRYPTION_SECTOR_SIZE
+        ));
+    }
+
+    match params.pbkdf {
+        None => {
+            if params.pbkdf_memory_kb.is_some()
+                || params.pbkdf_time_ms.is_some()
+                || params.pbkdf_parallel_threads.is_some()
+            {
+                return invalid(
+                    "PBKDF costs may only be specified together with a PBKDF".to_string(),
+                );
+            }
+        }
+        Some(Pbkdf::Pbkdf2) => {
+            if params.pbkdf_memory_kb.is_some() || params.pbkdf_parallel_threads.is_some() {
+                return invalid(
+                    "A memory cost or number of threads may only be specified for an argon2 PBKDF"
+                        .to_string(),
+                );
+            }
+        }
+        Some(Pbkdf::Argon2i) | Some(Pbkdf::Argon2id) => {
+            if let Some(memory) = params.pbkdf_memory_kb {
+                if memory < MIN_PBKDF_MEMORY_KB || memory > MAX_PBKDF_MEMORY_KB {
+                    return invalid(format!(
+                        "PBKDF memory cost {} KiB is not between {} and {} KiB",
+                        memory, MIN_PBKDF_MEMORY_KB, MAX_PBKDF_MEMORY_KB
+                    ));
+                }
+            }
+            if let Some(threads) = params.pbkdf_parallel_threads {
+                if threads == 0 || threads > MAX_PBKDF_PARALLEL_THREADS {
+                    return invalid(format!(
+                        "PBKDF thread count {} is not between 1 and {}",
+                        threads, MAX_PBKDF_PARALLEL_THREADS
+                    ));
+                }
+            }
+        }
+    }
+
+    if params.pbkdf_time_ms == Some(0) {
+        return invalid("PBKDF time must be at least 1 ms".to_string());
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -252,4 +627,153 @@ mod tests {
         assert_matches!(validate_name("ユニコード"), Ok(_));
         assert_matches!(validate_name("ユニコード?"), Err(_));
     }
+
+    #[test]
+    fn test_overprov_checks() {
+        assert_eq!(
+            overprov_available_space(true, Sectors(20), Sectors(10)),
+            None
+        );
+        assert_eq!(
+            overprov_available_space(false, Sectors(4), Sectors(10)),
+            Some(Sectors(6))
+        );
+        assert_eq!(
+            overprov_available_space(false, Sectors(20), Sectors(10)),
+            Some(Sectors(0))
+        );
+
+        assert_matches!(check_overprov_request(None, Sectors(20)), Ok(_));
+        assert_matches!(check_overprov_request(Some(Sectors(6)), Sectors(6)), Ok(_));
+        assert_matches!(check_overprov_request(Some(Sectors(6)), Sectors(7)), Err(_));
+
+        assert_matches!(check_overprov_disable(Sectors(10), Sectors(10)), Ok(_));
+        assert_matches!(check_overprov_disable(Sectors(11), Sectors(10)), Err(_));
+    }
+
+    #[test]
+    fn test_validate_cache_config() {
+        assert_matches!(validate_cache_config(&CacheConfig::default()), Ok(_));
+
+        let config = |block_size: Sectors| CacheConfig {
+            block_size,
+            ..CacheConfig::default()
+        };
+        assert_matches!(validate_cache_config(&config(MIN_CACHE_BLOCK_SIZE)), Ok(_));
+        assert_matches!(validate_cache_config(&config(MAX_CACHE_BLOCK_SIZE)), Ok(_));
+        assert_matches!(validate_cache_config(&config(Sectors(0))), Err(_));
+        assert_matches!(validate_cache_config(&config(Sectors(96))), Err(_));
+        assert_matches!(
+            validate_cache_config(&config(MAX_CACHE_BLOCK_SIZE + MIN_CACHE_BLOCK_SIZE)),
+            Err(_)
+        );
+
+        let mut config = CacheConfig::default();
+        config
+            .policy_args
+            .insert("migration_threshold".to_string(), "4096".to_string());
+        assert_matches!(validate_cache_config(&config), Ok(_));
+        config
+            .policy_args
+            .insert("sequential_threshold".to_string(), "".to_string());
+        assert_matches!(validate_cache_config(&config), Err(_));
+
+        let config = CacheConfig {
+            policy: "two words".to_string(),
+            ..CacheConfig::default()
+        };
+        assert_matches!(validate_cache_config(&config), Err(_));
+    }
+
+    #[test]
+    fn test_validate_space_thresholds() {
+        let thresholds = |warning, critical| SpaceThresholds { warning, critical };
+        assert_matches!(validate_space_thresholds(SpaceThresholds::default()), Ok(_));
+        assert_matches!(validate_space_thresholds(thresholds(90, 90)), Ok(_));
+        assert_matches!(validate_space_thresholds(thresholds(1, 99)), Ok(_));
+        assert_matches!(validate_space_thresholds(thresholds(0, 95)), Err(_));
+        assert_matches!(validate_space_thresholds(thresholds(95, 90)), Err(_));
+        assert_matches!(validate_space_thresholds(thresholds(80, 100)), Err(_));
+    }
+
+    #[test]
+    fn test_validate_encryption_params() {
+        assert_matches!(
+            validate_encryption_params(&EncryptionParams::default()),
+            Ok(_)
+        );
+
+        let params = EncryptionParams {
+            cipher: "aes-xts-plain64".to_string(),
+            key_size: 256,
+            pbkdf: Some(Pbkdf::Argon2id),
+            pbkdf_memory_kb: Some(1024 * 1024),
+            pbkdf_time_ms: Some(2000),
+            pbkdf_parallel_threads: Some(4),
+            sector_size: 4096,
+        };
+        assert_matches!(validate_encryption_params(&params), Ok(_));
+
+        let cipher = |cipher: &str| EncryptionParams {
+            cipher: cipher.to_string(),
+            ..EncryptionParams::default()
+        };
+        assert_matches!(
+            validate_encryption_params(&cipher("serpent-cbc-essiv:sha256")),
+            Ok(_)
+        );
+        assert_matches!(validate_encryption_params(&cipher("aes")), Err(_));
+        assert_matches!(validate_encryption_params(&cipher("aes-")), Err(_));
+        assert_matches!(
+            validate_encryption_params(&cipher("aes xts-plain64")),
+            Err(_)
+        );
+
+        let key_size = |key_size| EncryptionParams {
+            key_size,
+            ..EncryptionParams::default()
+        };
+        assert_matches!(validate_encryption_params(&key_size(0)), Err(_));
+        assert_matches!(validate_encryption_params(&key_size(255)), Err(_));
+        assert_matches!(
+            validate_encryption_params(&key_size(MAX_KEY_SIZE + 8)),
+            Err(_)
+        );
+
+        let sector_size = |sector_size| EncryptionParams {
+            sector_size,
+            ..EncryptionParams::default()
+        };
+        assert_matches!(validate_encryption_params(&sector_size(2048)), Ok(_));
+        assert_matches!(validate_encryption_params(&sector_size(256)), Err(_));
+        assert_matches!(validate_encryption_params(&sector_size(1000)), Err(_));
+        assert_matches!(validate_encryption_params(&sector_size(8192)), Err(_));
+
+        let pbkdf = |pbkdf, pbkdf_memory_kb, pbkdf_parallel_threads| EncryptionParams {
+            pbkdf,
+            pbkdf_memory_kb,
+            pbkdf_parallel_threads,
+            ..EncryptionParams::default()
+        };
+        assert_matches!(
+            validate_encryption_params(&pbkdf(None, Some(1024), None)),
+            Err(_)
+        );
+        assert_matches!(
+            validate_encryption_params(&pbkdf(Some(Pbkdf::Pbkdf2), None, None)),
+            Ok(_)
+        );
+        assert_matches!(
+            validate_encryption_params(&pbkdf(Some(Pbkdf::Pbkdf2), Some(1024), None)),
+            Err(_)
+        );
+        assert_matches!(
+            validate_encryption_params(&pbkdf(Some(Pbkdf::Argon2i), Some(16), None)),
+            Err(_)
+        );
+        assert_matches!(
+            validate_encryption_params(&pbkdf(Some(Pbkdf::Argon2i), None, Some(0))),
+            Err(_)
+        );
+    }
 }
@@ -12,7 +12,10 @@ use devicemapper::{Bytes, Sectors, IEC};
 use crate::{
     engine::{
         engine::BlockDev,
-        types::{DevUuid, EncryptionInfo, MaybeDbusPath},
+        types::{
+            ClevisBinding, DevUuid, EncryptionInfo, KeyDescription, MaybeDbusPath,
+            ReencryptionProgress,
+        },
     },
     stratis::StratisResult,
 };
@@ -26,6 +29,7 @@ pub struct SimDev {
     initialization_time: u64,
     dbus_path: MaybeDbusPath,
     encryption_info: Option<EncryptionInfo>,
+    reencryption_progress: Option<ReencryptionProgress>,
 }
 
 impl SimDev {
@@ -75,6 +79,10 @@ impl BlockDev for SimDev {
     fn is_encrypted(&self) -> bool {
         self.encryption_info.is_some()
     }
+
+    fn reencryption_progress(&self) -> Option<ReencryptionProgress> {
+        self.reencryption_progress
+    }
 }
 
 impl SimDev {
@@ -89,6 +97,7 @@ impl SimDev {
                 initialization_time: Utc::now().timestamp() as u64,
                 dbus_path: MaybeDbusPath(None),
                 encryption_info: encryption_info.cloned(),
+                reencryption_progress: None,
             },
         )
     }
@@ -100,18 +109,77 @@ impl SimDev {
         set_blockdev_user_info!(self; user_info)
     }
 
-    /// Set the clevis info for a block device.
-    pub fn set_clevis_info(&mut self, pin: String, config: Value) {
+    /// Set the encryption info for a block device that has been encrypted.
+    pub fn set_encryption_info(&mut self, encryption_info: EncryptionInfo) {
+        self.encryption_info = Some(encryption_info);
+    }
+
+    /// Add a clevis binding to a block device.
+    pub fn add_clevis_binding(&mut self, binding: ClevisBinding) {
         if let Some(ref mut info) = self.encryption_info {
-            info.clevis_info = Some((pin, config));
+            info.clevis_info.push(binding);
         }
     }
 
-    /// Unset the clevis info for a block device.
-    pub fn unset_clevis_info(&mut self) {
+    /// Set whether the device is unlocked automatically, if it is encrypted.
+    pub fn set_auto_unlock(&mut self, auto_unlock: bool) {
         if let Some(ref mut info) = self.encryption_info {
-            info.clevis_info = None;
+            info.auto_unlock = auto_unlock;
+        }
+    }
+
+    /// Set the key description for a block device.
+    pub fn set_key_description(&mut self, key_description: KeyDescription) {
+        if let Some(ref mut info) = self.encryption_info {
+            info.key_description = key_description;
+        }
+    }
+
+    /// Remove the clevis binding with the given id from a block device.
+    pub fn remove_clevis_binding(&mut self, binding_id: u32) {
+        if let Some(ref mut info) = self.encryption_info {
+            info.clevis_info.retain(|binding| binding.id != binding_id);
+        }
+    }
+
+    /// Start simulated reencryption of this block device, or resume it if
+    /// it is paused.
+    /// Returns true if reencryption was started or resumed, false if it
+    /// was already running.
+    pub fn start_reencryption(&mut self) -> bool {
+        match self.reencryption_progress {
+            Some(ref mut progress) => {
+                let paused = progress.paused;
+                progress.paused = false;
+                paused
+            }
+            None => {
+                self.reencryption_progress = Some(ReencryptionProgress {
+                    processed: Bytes(0),
+                    total: self.size().bytes(),
+                    paused: false,
+                });
+                true
+            }
+        }
+    }
+
+    /// Advance simulated reencryption by a quarter of the device.
+    /// Returns true if reencryption is still in progress.
+    pub fn reencrypt_step(&mut self) -> bool {
+        match self.reencryption_progress {
+            Some(ref mut progress) if !progress.paused => {
+                progress.processed = Bytes(std::cmp::min(
+                    *progress.total,
+                    *progress.processed + *progress.total / 4,
+                ));
+                if progress.processed == progress.total {
+                    self.reencryption_progress = None;
+                }
+            }
+            _ => (),
         }
+        self.reencryption_progress.is_some()
     }
 
     /// Get encryption information for this block device.
@@ -130,15 +198,21 @@ impl<'a> Into<Value> for &'a SimDev {
         if let Some(EncryptionInfo {
             ref key_description,
             ref clevis_info,
+            ref encryption_params,
+            auto_unlock,
         }) = self.encryption_info
         {
             json.insert(
                 "key_description".to_string(),
                 Value::from(key_description.as_application_str()),
             );
-            if let Some((ref pin, ref config)) = clevis_info {
-                json.insert("clevis_pin".to_string(), Value::from(pin.to_owned()));
-                json.insert("clevis_config".to_string(), config.to_owned());
+            json.insert("encryption_params".to_string(), json!(encryption_params));
+            json.insert("auto_unlock".to_string(), Value::from(auto_unlock));
+            if !clevis_info.is_empty() {
+                json.insert(
+                    "clevis_bindings".to_string(),
+                    Value::Array(clevis_info.iter().map(|b| b.into()).collect()),
+                );
             }
         }
         Value::from(json)
@@ -5,7 +5,9 @@
 use std::{
     collections::{hash_map::RandomState, HashMap, HashSet},
     iter::FromIterator,
+    os::unix::io::RawFd,
     path::Path,
+    time::Duration,
 };
 
 use serde_json::{json, Value};
@@ -16,12 +18,16 @@ use crate::{
     engine::{
         engine::{Engine, KeyActions, Pool, Report},
         event::get_engine_listener_list,
-        shared::{create_pool_idempotent_or_err, validate_name, validate_paths},
+        shared::{
+            create_pool_encryption_info, create_pool_idempotent_or_err, missing_keys_report,
+            unlock_passphrase_shared, validate_name, validate_paths,
+        },
         sim_engine::{keys::SimKeyActions, pool::SimPool},
         structures::Table,
         types::{
-            CreateAction, DeleteAction, DevUuid, EncryptionInfo, KeyDescription, Name, PoolUuid,
-            RenameAction, ReportType, SetUnlockAction, UnlockMethod,
+            CreateAction, DeleteAction, DevUuid, EncryptionInfo, EncryptionParams, Key,
+            KeyDescription, Name, PoolUuid, RenameAction, ReportType, SetUnlockAction,
+            UnlockMethod,
         },
         EngineEvent,
     },
@@ -72,6 +78,7 @@ impl Report for SimEngine {
                 "errored_pools": json!([]),
                 "hopeless_devices": json!([]),
             }),
+            ReportType::MissingKeys => missing_keys_report(self),
         }
     }
 }
@@ -83,6 +90,7 @@ impl Engine for SimEngine {
         blockdev_paths: &[&Path],
         redundancy: Option<u16>,
         key_desc: Option<KeyDescription>,
+        encryption_params: Option<EncryptionParams>,
     ) -> StratisResult<CreateAction<PoolUuid>> {
         let redundancy = calculate_redundancy!(redundancy);
 
@@ -102,8 +110,12 @@ impl Engine for SimEngine {
             }
         }
 
+        let encryption_info = create_pool_encryption_info(key_desc, encryption_params)?;
+
         match self.pools.get_by_name(name) {
-            Some((_, pool)) => create_pool_idempotent_or_err(pool, name, blockdev_paths),
+            Some((_, pool)) => {
+                create_pool_idempotent_or_err(pool, name, blockdev_paths, encryption_info.as_ref())
+            }
             None => {
                 if blockdev_paths.is_empty() {
                     Err(StratisError::Engine(
@@ -114,14 +126,7 @@ impl Engine for SimEngine {
                     let device_set: HashSet<_, RandomState> = HashSet::from_iter(blockdev_paths);
                     let devices = device_set.into_iter().cloned().collect::<Vec<&Path>>();
 
-                    let (pool_uuid, pool) = SimPool::new(
-                        &devices,
-                        redundancy,
-                        key_desc.map(|kd| EncryptionInfo {
-                            key_description: kd,
-                            clevis_info: None,
-                        }),
-                    );
+                    let (pool_uuid, pool) = SimPool::new(&devices, redundancy, encryption_info);
 
                     self.pools
                         .insert(Name::new(name.to_owned()), pool_uuid, pool);
@@ -181,11 +186,60 @@ impl Engine for SimEngine {
     fn unlock_pool(
         &mut self,
         _pool_uuid: PoolUuid,
-        _unlock_method: UnlockMethod,
+        unlock_method: UnlockMethod,
+        passphrase_fd: Option<RawFd>,
     ) -> StratisResult<SetUnlockAction<DevUuid>> {
+        unlock_passphrase_shared(unlock_method, passphrase_fd)?;
         Ok(SetUnlockAction::empty())
     }
 
+    fn encrypt_pool(
+        &mut self,
+        pool_uuid: PoolUuid,
+        key_desc: &KeyDescription,
+        encryption_params: Option<EncryptionParams>,
+    ) -> StratisResult<CreateAction<Key>> {
+        let encryption_info =
+            create_pool_encryption_info(Some(key_desc.clone()), encryption_params)?
+                .expect("a key description was specified");
+
+        let (name, pool) = self.pools.get_mut_by_uuid(pool_uuid).ok_or_else(|| {
+            StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!("No pool with UUID {} was found", pool_uuid.to_simple_ref()),
+            )
+        })?;
+        match pool.encryption_info() {
+            Some(info) if &info.key_description == key_desc => {
+                return Ok(CreateAction::Identity);
+            }
+            Some(info) => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Pool {} is already encrypted with key description {}",
+                        name,
+                        info.key_description.as_application_str()
+                    ),
+                ));
+            }
+            None => (),
+        }
+
+        if !self.key_handler.contains_key(key_desc) {
+            return Err(StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!(
+                    "Key {} was not found in the keyring",
+                    key_desc.as_application_str()
+                ),
+            ));
+        }
+
+        pool.encrypt(&encryption_info);
+        Ok(CreateAction::Created(Key))
+    }
+
     fn get_pool(&self, uuid: PoolUuid) -> Option<(Name, &dyn Pool)> {
         get_pool!(self; uuid)
     }
@@ -220,6 +274,16 @@ impl Engine for SimEngine {
         Ok(())
     }
 
+    fn reencrypt_step(&mut self) -> bool {
+        self.pools
+            .iter_mut()
+            .fold(false, |acc, (_, _, pool)| pool.reencrypt_step() || acc)
+    }
+
+    fn process_auto_unlocks(&mut self) -> Option<Duration> {
+        None
+    }
+
     fn get_key_handler(&self) -> &dyn KeyActions {
         &self.key_handler as &dyn KeyActions
     }
@@ -236,11 +300,14 @@ impl Engine for SimEngine {
 #[cfg(test)]
 mod tests {
 
-    use std::{self, path::Path};
+    use std::{self, convert::TryFrom, path::Path};
+
+    use nix::unistd::close;
 
     use crate::{
         engine::{
-            types::{EngineAction, RenameAction},
+            sim_engine::tests::{passphrase_fd, set_test_key},
+            types::{EngineAction, MappingCreateAction, Pbkdf, RenameAction},
             Engine,
         },
         stratis::{ErrorEnum, StratisError},
@@ -270,6 +337,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -282,7 +350,7 @@ mod tests {
     fn destroy_pool_w_devices() {
         let mut engine = SimEngine::default();
         let uuid = engine
-            .create_pool("name", strs_to_paths!(["/s/d"]), None, None)
+            .create_pool("name", strs_to_paths!(["/s/d"]), None, None, None)
             .unwrap()
             .changed()
             .unwrap();
@@ -295,13 +363,14 @@ mod tests {
         let mut engine = SimEngine::default();
         let pool_name = "pool_name";
         let uuid = engine
-            .create_pool(pool_name, strs_to_paths!(["/s/d"]), None, None)
+            .create_pool(pool_name, strs_to_paths!(["/s/d"]), None, None, None)
             .unwrap()
             .changed()
             .unwrap();
         {
             let pool = engine.get_mut_pool(uuid).unwrap().1;
-            pool.create_filesystems(uuid, &[("test", None)]).unwrap();
+            pool.create_filesystems(uuid, &[("test", None, None)])
+                .unwrap();
         }
         assert_matches!(engine.destroy_pool(uuid), Err(_));
     }
@@ -313,9 +382,9 @@ mod tests {
         let name = "name";
         let mut engine = SimEngine::default();
         let devices = strs_to_paths!(["/s/d"]);
-        engine.create_pool(name, devices, None, None).unwrap();
+        engine.create_pool(name, devices, None, None, None).unwrap();
         assert_matches!(
-            engine.create_pool(name, devices, None, None),
+            engine.create_pool(name, devices, None, None, None),
             Ok(CreateAction::Identity)
         );
     }
@@ -326,7 +395,7 @@ mod tests {
         let name = "name";
         let mut engine = SimEngine::default();
         engine
-            .create_pool(name, strs_to_paths!(["/s/d"]), None, None)
+            .create_pool(name, strs_to_paths!(["/s/d"]), None, None, None)
             .unwrap();
         assert_matches!(
             engine.create_pool(
@@ -334,6 +403,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             ),
             Err(StratisError::Engine(ErrorEnum::Invalid, _))
         );
@@ -346,7 +416,7 @@ mod tests {
         let mut engine = SimEngine::default();
         assert_matches!(
             engine
-                .create_pool("name", strs_to_paths!([path, path]), None, None)
+                .create_pool("name", strs_to_paths!([path, path]), None, None, None)
                 .unwrap()
                 .changed()
                 .map(|uuid| engine.get_pool(uuid).unwrap().1.blockdevs().len()),
@@ -364,6 +434,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 Some(std::u16::MAX),
                 None,
+                None,
             ),
             Err(_)
         );
@@ -390,6 +461,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -410,6 +482,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -431,6 +504,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -441,6 +515,7 @@ mod tests {
                 strs_to_paths!(["/dev/four", "/dev/five", "/dev/six"]),
                 None,
                 None,
+                None,
             )
             .unwrap();
         assert_matches!(
@@ -460,6 +535,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap();
         assert_matches!(
@@ -467,4 +543,285 @@ mod tests {
             Ok(RenameAction::NoSource)
         );
     }
+
+    #[test]
+    /// Encryption parameters are recorded for an encrypted pool and rejected
+    /// if they are invalid or if the pool is not encrypted. Creating the pool
+    /// again is idempotent only if the encryption parameters are unchanged.
+    fn create_pool_encryption_params() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        set_test_key(&mut engine, &key_desc);
+
+        let params = EncryptionParams {
+            pbkdf: Some(Pbkdf::Argon2id),
+            pbkdf_memory_kb: Some(1024 * 1024),
+            sector_size: 4096,
+            ..EncryptionParams::default()
+        };
+
+        assert_matches!(
+            engine.create_pool(
+                "name",
+                strs_to_paths!(["/dev/one"]),
+                None,
+                None,
+                Some(params.clone()),
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            engine.create_pool(
+                "name",
+                strs_to_paths!(["/dev/one"]),
+                None,
+                Some(key_desc.clone()),
+                Some(EncryptionParams {
+                    sector_size: 1000,
+                    ..params.clone()
+                }),
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        let uuid = engine
+            .create_pool(
+                "name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc.clone()),
+                Some(params.clone()),
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (_, pool) = engine.get_pool(uuid).unwrap();
+        assert_eq!(pool.encryption_info().unwrap().encryption_params, params);
+
+        assert_matches!(
+            engine.create_pool(
+                "name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc.clone()),
+                Some(params.clone()),
+            ),
+            Ok(CreateAction::Identity)
+        );
+        assert_matches!(
+            engine.create_pool(
+                "name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc.clone()),
+                Some(EncryptionParams {
+                    sector_size: 512,
+                    ..params
+                }),
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            engine.create_pool(
+                "name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                None,
+                None,
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+    }
+
+    #[test]
+    /// Encrypting an unencrypted pool records the encryption info on all of
+    /// its data devices; encrypting it again with the same key description
+    /// is idempotent and with a different one is an error.
+    fn encrypt_pool() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        let other_key_desc = KeyDescription::try_from("other".to_string()).unwrap();
+
+        assert_matches!(
+            engine.encrypt_pool(PoolUuid::new_v4(), &key_desc, None),
+            Err(StratisError::Engine(ErrorEnum::NotFound, _))
+        );
+
+        let uuid = engine
+            .create_pool(
+                "name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+
+        assert_matches!(
+            engine.encrypt_pool(uuid, &key_desc, None),
+            Err(StratisError::Engine(ErrorEnum::NotFound, _))
+        );
+
+        set_test_key(&mut engine, &key_desc);
+
+        assert_matches!(
+            engine.encrypt_pool(uuid, &key_desc, None),
+            Ok(CreateAction::Created(_))
+        );
+        let (_, pool) = engine.get_pool(uuid).unwrap();
+        assert!(pool.is_encrypted());
+        assert!(pool.blockdevs().iter().all(|(_, _, bd)| bd.is_encrypted()));
+        assert_eq!(pool.encryption_info().unwrap().key_description, key_desc);
+
+        assert_matches!(
+            engine.encrypt_pool(uuid, &key_desc, None),
+            Ok(CreateAction::Identity)
+        );
+        assert_matches!(
+            engine.encrypt_pool(uuid, &other_key_desc, None),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+    }
+
+    #[test]
+    /// A passphrase file descriptor must be given when unlocking with a
+    /// passphrase and must not be given for any other unlock method.
+    fn unlock_pool_passphrase_fd() {
+        let mut engine = SimEngine::default();
+        let uuid = PoolUuid::new_v4();
+
+        assert_matches!(
+            engine.unlock_pool(uuid, UnlockMethod::Passphrase, None),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        assert_matches!(
+            engine.unlock_pool(uuid, UnlockMethod::Keyring, Some(passphrase_fd())),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        assert_matches!(
+            engine.unlock_pool(uuid, UnlockMethod::Passphrase, Some(passphrase_fd())),
+            Ok(_)
+        );
+    }
+
+    #[test]
+    /// A key set with a timeout is listed with its remaining lifetime and is
+    /// gone once the timeout has elapsed; a timeout of zero is rejected.
+    fn set_key_timeout() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+
+        let read_fd = passphrase_fd();
+        assert_matches!(
+            engine.get_key_handler_mut().set(
+                &key_desc,
+                read_fd,
+                Some(std::time::Duration::from_secs(0))
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        close(read_fd).unwrap();
+
+        engine
+            .get_key_handler_mut()
+            .set(
+                &key_desc,
+                passphrase_fd(),
+                Some(std::time::Duration::from_secs(1)),
+            )
+            .unwrap();
+
+        let keys = engine.get_key_handler().list().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].0, key_desc);
+        assert_matches!(keys[0].1, Some(lifetime) if lifetime <= std::time::Duration::from_secs(1));
+
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert!(engine.get_key_handler().list().unwrap().is_empty());
+        assert_matches!(
+            engine.get_key_handler_mut().unset(&key_desc),
+            Ok(DeleteAction::Identity)
+        );
+    }
+
+    #[test]
+    /// Setting a key with unchanged key data is idempotent only if the key
+    /// neither expires nor is given a timeout.
+    fn set_key_timeout_changed() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        let timeout = Some(std::time::Duration::from_secs(60));
+
+        assert_matches!(
+            set_test_key(&mut engine, &key_desc),
+            MappingCreateAction::Created(_)
+        );
+        assert_matches!(
+            set_test_key(&mut engine, &key_desc),
+            MappingCreateAction::Identity
+        );
+        assert_matches!(
+            engine
+                .get_key_handler_mut()
+                .set(&key_desc, passphrase_fd(), timeout)
+                .unwrap(),
+            MappingCreateAction::ValueChanged(_)
+        );
+        assert_matches!(
+            set_test_key(&mut engine, &key_desc),
+            MappingCreateAction::ValueChanged(_)
+        );
+        assert_matches!(
+            set_test_key(&mut engine, &key_desc),
+            MappingCreateAction::Identity
+        );
+    }
+
+    #[test]
+    /// The missing keys report shows whether the key of an encrypted pool is
+    /// in the keyring and omits unencrypted pools.
+    fn missing_keys_report_pools() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        set_test_key(&mut engine, &key_desc);
+
+        engine
+            .create_pool("plain", strs_to_paths!(["/dev/one"]), None, None, None)
+            .unwrap();
+        let uuid = engine
+            .create_pool(
+                "encrypted",
+                strs_to_paths!(["/dev/two"]),
+                None,
+                Some(key_desc.clone()),
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+
+        let report = engine.get_report(ReportType::MissingKeys);
+        let pools = report["pools"].as_array().unwrap();
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0]["pool_uuid"], uuid.to_simple_ref().to_string());
+        assert_eq!(pools[0]["locked"], false);
+        assert_eq!(pools[0]["key_present"], true);
+        assert_eq!(pools[0]["clevis_bound"], false);
+        assert_eq!(
+            pools[0]["viable_unlock_methods"],
+            json!(["keyring", "passphrase"])
+        );
+
+        engine.get_key_handler_mut().unset(&key_desc).unwrap();
+        let report = engine.get_report(ReportType::MissingKeys);
+        assert_eq!(report["pools"][0]["key_present"], false);
+        assert_eq!(
+            report["pools"][0]["viable_unlock_methods"],
+            json!(["passphrase"])
+        );
+    }
 }
@@ -4,29 +4,77 @@
 
 use chrono::{DateTime, Utc};
 
-use std::path::PathBuf;
+use std::{cmp::min, path::PathBuf};
 
-use devicemapper::Bytes;
+use devicemapper::{Bytes, Sectors, IEC};
 
 use crate::{
     engine::{types::MaybeDbusPath, Filesystem},
-    stratis::StratisResult,
+    stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
+pub const DEFAULT_SIZE: Sectors = Sectors(2 * IEC::Gi); // 1 TiB
+
 #[derive(Debug)]
 pub struct SimFilesystem {
     rand: u32,
     created: DateTime<Utc>,
+    size: Sectors,
+    size_limit: Option<Sectors>,
     dbus_path: MaybeDbusPath,
 }
 
 impl SimFilesystem {
-    pub fn new() -> SimFilesystem {
-        SimFilesystem {
+    pub fn new(size: Option<Sectors>, size_limit: Option<Sectors>) -> StratisResult<SimFilesystem> {
+        let size = match (size, size_limit) {
+            (Some(size), Some(limit)) if size > limit => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Requested size {} exceeds requested size limit {}",
+                        size, limit
+                    ),
+                ));
+            }
+            (Some(size), _) => size,
+            (None, Some(limit)) => min(DEFAULT_SIZE, limit),
+            (None, None) => DEFAULT_SIZE,
+        };
+        Ok(SimFilesystem {
             rand: rand::random::<u32>(),
             created: Utc::now(),
+            size,
+            size_limit,
             dbus_path: MaybeDbusPath(None),
+        })
+    }
+
+    /// Set the size of the filesystem. Shrinking is not supported.
+    /// Returns true if the size was changed.
+    pub fn set_size(&mut self, new_size: Sectors) -> StratisResult<bool> {
+        if new_size < self.size {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Requested size {} is smaller than current size {}; XFS filesystems can not be shrunk",
+                    new_size, self.size
+                ),
+            ));
+        }
+        if let Some(limit) = self.size_limit {
+            if new_size > limit {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Requested size {} exceeds the filesystem's size limit {}",
+                        new_size, limit
+                    ),
+                ));
+            }
         }
+        let changed = new_size != self.size;
+        self.size = new_size;
+        Ok(changed)
     }
 }
 
@@ -49,6 +97,14 @@ impl Filesystem for SimFilesystem {
         Ok(Bytes(12_345_678))
     }
 
+    fn size(&self) -> Bytes {
+        self.size.bytes()
+    }
+
+    fn size_limit(&self) -> Option<Bytes> {
+        self.size_limit.map(|l| l.bytes())
+    }
+
     fn set_dbus_path(&mut self, path: MaybeDbusPath) {
         self.dbus_path = path
     }
@@ -2,7 +2,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{collections::HashMap, io::Write, os::unix::io::RawFd};
+use std::{
+    collections::HashMap,
+    io::Write,
+    os::unix::io::RawFd,
+    time::{Duration, Instant},
+};
 
 use libcryptsetup_rs::SafeMemHandle;
 
@@ -15,19 +20,29 @@ use crate::{
     stratis::StratisResult,
 };
 
+/// A map from key description to the key data and the time at which the key
+/// expires, if it expires.
 #[derive(Debug, Default)]
-pub struct SimKeyActions(HashMap<KeyDescription, SizedKeyMemory>);
+pub struct SimKeyActions(HashMap<KeyDescription, (SizedKeyMemory, Option<Instant>)>);
 
 impl SimKeyActions {
     pub fn contains_key(&self, key_desc: &KeyDescription) -> bool {
-        self.0.contains_key(key_desc)
+        self.get(key_desc).is_some()
+    }
+
+    /// Get the key with the given key description and the time at which it
+    /// expires, unless it has already expired.
+    fn get(&self, key_desc: &KeyDescription) -> Option<&(SizedKeyMemory, Option<Instant>)> {
+        self.0
+            .get(key_desc)
+            .filter(|(_, expiry)| expiry.map(|e| e > Instant::now()).unwrap_or(true))
     }
 
     /// Read the contents of a key from the simulated keyring or return `None`
     /// if no key with the given key description exists.
     fn read(&self, key_desc: &KeyDescription) -> StratisResult<Option<SizedKeyMemory>> {
-        match self.0.get(key_desc) {
-            Some(key) => {
+        match self.get(key_desc) {
+            Some((key, _)) => {
                 let mut key_clone = SafeMemHandle::alloc(MAX_STRATIS_PASS_SIZE)?;
                 let size = key_clone.as_mut().write(key.as_ref())?;
                 Ok(Some(SizedKeyMemory::new(key_clone, size)))
@@ -42,34 +57,51 @@ impl KeyActions for SimKeyActions {
         &mut self,
         key_desc: &KeyDescription,
         key_fd: RawFd,
+        timeout: Option<Duration>,
     ) -> StratisResult<MappingCreateAction<Key>> {
+        shared::validate_key_timeout(timeout)?;
         let memory = shared::set_key_shared(key_fd)?;
+        let expiry = timeout.map(|t| Instant::now() + t);
+        // Setting a timeout restarts the lifetime of the key, so only leaving
+        // a key that does not expire without a timeout leaves its expiry
+        // unchanged.
+        let expiry_changed = expiry.is_some() || self.get(key_desc).and_then(|(_, e)| *e).is_some();
 
         match self.read(key_desc) {
             Ok(Some(key_data)) => {
-                if key_data.as_ref() == memory.as_ref() {
+                if key_data.as_ref() == memory.as_ref() && !expiry_changed {
+                    self.0.insert((*key_desc).clone(), (key_data, expiry));
                     Ok(MappingCreateAction::Identity)
                 } else {
-                    self.0.insert((*key_desc).clone(), memory);
+                    self.0.insert((*key_desc).clone(), (memory, expiry));
                     Ok(MappingCreateAction::ValueChanged(Key))
                 }
             }
             Ok(None) => {
-                self.0.insert((*key_desc).clone(), memory);
+                self.0.insert((*key_desc).clone(), (memory, expiry));
                 Ok(MappingCreateAction::Created(Key))
             }
             Err(e) => Err(e),
         }
     }
 
-    fn list(&self) -> StratisResult<Vec<KeyDescription>> {
-        Ok(self.0.keys().cloned().collect())
+    fn list(&self) -> StratisResult<Vec<(KeyDescription, Option<Duration>)>> {
+        let now = Instant::now();
+        Ok(self
+            .0
+            .iter()
+            .filter(|(_, (_, expiry))| expiry.map(|e| e > now).unwrap_or(true))
+            .map(|(kd, (_, expiry))| (kd.clone(), expiry.map(|e| e - now)))
+            .collect())
     }
 
     fn unset(&mut self, key_desc: &KeyDescription) -> StratisResult<DeleteAction<Key>> {
-        match self.0.remove(key_desc) {
-            Some(_) => Ok(DeleteAction::Deleted(Key)),
-            None => Ok(DeleteAction::Identity),
+        let present = self.contains_key(key_desc);
+        self.0.remove(key_desc);
+        if present {
+            Ok(DeleteAction::Deleted(Key))
+        } else {
+            Ok(DeleteAction::Identity)
         }
     }
 }
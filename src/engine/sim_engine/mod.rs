@@ -5,3 +5,5 @@ mod engine;
 mod filesystem;
 mod keys;
 mod pool;
+#[cfg(test)]
+mod tests;
@@ -3,12 +3,14 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
+    cmp::min,
     collections::{hash_map::RandomState, HashMap, HashSet},
     iter::FromIterator,
     path::Path,
     vec::Vec,
 };
 
+use chrono::Utc;
 use serde_json::{Map, Value};
 
 use devicemapper::{Sectors, IEC};
@@ -17,13 +19,22 @@ use crate::{
     engine::{
         engine::{BlockDev, Filesystem, Pool},
         event::get_engine_listener_list,
-        shared::{init_cache_idempotent_or_err, validate_name, validate_paths},
-        sim_engine::{blockdev::SimDev, filesystem::SimFilesystem},
+        shared::{
+            check_overprov_disable, check_overprov_request, init_cache_idempotent_or_err,
+            overprov_available_space, validate_cache_config, validate_name, validate_paths,
+            validate_space_thresholds,
+        },
+        sim_engine::{
+            blockdev::SimDev,
+            filesystem::{SimFilesystem, DEFAULT_SIZE},
+        },
         structures::Table,
         types::{
-            BlockDevTier, Clevis, CreateAction, DeleteAction, DevUuid, EncryptionInfo,
-            FilesystemUuid, MaybeDbusPath, Name, PoolUuid, Redundancy, RenameAction,
-            SetCreateAction, SetDeleteAction,
+            BlockDevTier, CacheConfig, CacheMode, CacheStatistics, Clevis, ClevisBinding,
+            CreateAction, DeleteAction, DevUuid, EncryptionInfo, FilesystemUuid, Key,
+            KeyDescription, MaybeDbusPath, Name, PoolUuid, PropChangeAction, Redundancy,
+            Reencryption, RenameAction, SetCreateAction, SetDeleteAction, SpaceThresholds,
+            ThinPoolStatusDigest, ThinPoolUsage, METADATA_ARCHIVE_VERSION,
         },
         EngineEvent,
     },
@@ -36,6 +47,10 @@ pub struct SimPool {
     cache_devs: HashMap<DevUuid, SimDev>,
     filesystems: Table<FilesystemUuid, SimFilesystem>,
     redundancy: Redundancy,
+    enable_overprov: bool,
+    reserve_encryption_space: bool,
+    cache_mode: CacheMode,
+    space_thresholds: SpaceThresholds,
     dbus_path: MaybeDbusPath,
 }
 
@@ -54,6 +69,10 @@ impl SimPool {
                 cache_devs: HashMap::new(),
                 filesystems: Table::default(),
                 redundancy,
+                enable_overprov: true,
+                reserve_encryption_space: false,
+                cache_mode: CacheMode::default(),
+                space_thresholds: SpaceThresholds::default(),
                 dbus_path: MaybeDbusPath(None),
             },
         )
@@ -90,23 +109,65 @@ impl SimPool {
             .and_then(|(_, bd)| bd.encryption_info())
     }
 
-    fn add_clevis_info(&mut self, pin: String, config: Value) {
+    /// Encrypt the data devices of the pool.
+    pub fn encrypt(&mut self, encryption_info: &EncryptionInfo) {
+        self.block_devs
+            .iter_mut()
+            .for_each(|(_, bd)| bd.set_encryption_info(encryption_info.clone()))
+    }
+
+    fn add_clevis_binding(&mut self, binding: ClevisBinding) {
+        self.block_devs
+            .iter_mut()
+            .for_each(|(_, bd)| bd.add_clevis_binding(binding.clone()))
+    }
+
+    fn remove_clevis_binding(&mut self, binding_id: u32) {
         self.block_devs
             .iter_mut()
-            .for_each(|(_, bd)| bd.set_clevis_info(pin.clone(), config.clone()))
+            .for_each(|(_, bd)| bd.remove_clevis_binding(binding_id))
+    }
+
+    /// The sum of the logical sizes of all the filesystems in the pool.
+    fn total_logical_size(&self) -> Sectors {
+        self.filesystems
+            .iter()
+            .map(|(_, _, fs)| fs.size().sectors())
+            .sum()
+    }
+
+    /// The amount by which the sum of the logical sizes of the filesystems
+    /// may still grow without exceeding the size of the pool. None if
+    /// overprovisioning is enabled, in which case there is no such
+    /// restriction.
+    fn available_logical_space(&self) -> Option<Sectors> {
+        overprov_available_space(
+            self.enable_overprov,
+            self.total_logical_size(),
+            self.total_physical_size(),
+        )
+    }
+
+    /// Return an error if overprovisioning is disabled and adding
+    /// additional_size to the sum of the logical sizes of the filesystems
+    /// would make it exceed the size of the pool.
+    fn check_overprov(&self, additional_size: Sectors) -> StratisResult<()> {
+        check_overprov_request(self.available_logical_space(), additional_size)
     }
 
-    fn clear_clevis_info(&mut self) {
+    /// Advance the simulated reencryption of the data devices.
+    /// Returns true if any data device is still being reencrypted.
+    pub fn reencrypt_step(&mut self) -> bool {
         self.block_devs
             .iter_mut()
-            .for_each(|(_, bd)| bd.unset_clevis_info())
+            .fold(false, |acc, (_, bd)| bd.reencrypt_step() || acc)
     }
 }
 
 // Precondition: SimDev::into() always returns a value that matches Value::Object(_).
 impl<'a> Into<Value> for &'a SimPool {
     fn into(self) -> Value {
-        json!({
+        let mut json = json!({
             "filesystems": Value::Array(
                 self.filesystems.iter()
                     .map(|(name, uuid, _)| json!({
@@ -145,7 +206,13 @@ impl<'a> Into<Value> for &'a SimPool {
                         .collect()
                 ),
             },
-        })
+        });
+        if let Ok(Some(statistics)) = self.cache_statistics() {
+            json.as_object_mut()
+                .expect("Created a JSON object above")
+                .insert("cache_statistics".to_string(), (&statistics).into());
+        }
+        json
     }
 }
 
@@ -155,8 +222,10 @@ impl Pool for SimPool {
         _pool_uuid: PoolUuid,
         _pool_name: &str,
         blockdevs: &[&Path],
+        cache_config: CacheConfig,
     ) -> StratisResult<SetCreateAction<DevUuid>> {
         validate_paths(blockdevs)?;
+        validate_cache_config(&cache_config)?;
 
         if self.is_encrypted() {
             return Err(StratisError::Engine(
@@ -174,6 +243,7 @@ impl Pool for SimPool {
             let blockdev_pairs: Vec<_> = blockdevs.iter().map(|p| SimDev::new(p, None)).collect();
             let blockdev_uuids: Vec<_> = blockdev_pairs.iter().map(|(uuid, _)| *uuid).collect();
             self.cache_devs.extend(blockdev_pairs);
+            self.cache_mode = cache_config.mode;
             Ok(SetCreateAction::new(blockdev_uuids))
         } else {
             init_cache_idempotent_or_err(
@@ -185,25 +255,83 @@ impl Pool for SimPool {
         }
     }
 
+    fn destroy_cache(
+        &mut self,
+        _pool_uuid: PoolUuid,
+        _pool_name: &str,
+    ) -> StratisResult<SetDeleteAction<DevUuid>> {
+        Ok(SetDeleteAction::new(
+            self.cache_devs.drain().map(|(uuid, _)| uuid).collect(),
+        ))
+    }
+
+    fn remove_cachedevs(
+        &mut self,
+        _pool_uuid: PoolUuid,
+        _pool_name: &str,
+        uuids: &[DevUuid],
+    ) -> StratisResult<SetDeleteAction<DevUuid>> {
+        if let Some(uuid) = uuids
+            .iter()
+            .find(|&uuid| self.block_devs.contains_key(uuid))
+        {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Blockdev with UUID {} belongs to the data tier; use remove_blockdevs to remove it",
+                    uuid.to_simple_ref()
+                ),
+            ));
+        }
+
+        let removed = uuids
+            .iter()
+            .filter(|&uuid| self.cache_devs.remove(uuid).is_some())
+            .cloned()
+            .collect();
+        Ok(SetDeleteAction::new(removed))
+    }
+
     fn create_filesystems<'a, 'b>(
         &'a mut self,
         _pool_uuid: PoolUuid,
-        specs: &[(&'b str, Option<Sectors>)],
+        specs: &[(&'b str, Option<Sectors>, Option<Sectors>)],
     ) -> StratisResult<SetCreateAction<(&'b str, FilesystemUuid)>> {
-        let names: HashMap<_, _> = specs.iter().map(|&tup| (tup.0, tup.1)).collect();
+        let names: HashMap<_, _> = specs
+            .iter()
+            .map(|&(name, size, size_limit)| (name, (size, size_limit)))
+            .collect();
 
         names.iter().fold(Ok(()), |res, (name, _)| {
             res.and_then(|()| validate_name(name))
         })?;
 
         let mut result = Vec::new();
-        for name in names.keys() {
+        for (name, (size, size_limit)) in names {
             if !self.filesystems.contains_name(name) {
+                let size = match self.available_logical_space() {
+                    Some(available) => {
+                        if available == Sectors(0) {
+                            return Err(StratisError::Engine(
+                                ErrorEnum::Invalid,
+                                "Overprovisioning is disabled and no space remains for filesystems"
+                                    .into(),
+                            ));
+                        }
+                        let size = size.unwrap_or_else(|| {
+                            let size = min(DEFAULT_SIZE, available);
+                            size_limit.map_or(size, |limit| min(size, limit))
+                        });
+                        self.check_overprov(size)?;
+                        Some(size)
+                    }
+                    None => size,
+                };
                 let uuid = FilesystemUuid::new_v4();
-                let new_filesystem = SimFilesystem::new();
+                let new_filesystem = SimFilesystem::new(size, size_limit)?;
                 self.filesystems
-                    .insert(Name::new((&**name).to_owned()), uuid, new_filesystem);
-                result.push((*name, uuid));
+                    .insert(Name::new(name.to_owned()), uuid, new_filesystem);
+                result.push((name, uuid));
             }
         }
 
@@ -265,46 +393,164 @@ impl Pool for SimPool {
         Ok(SetCreateAction::new(ret_uuids))
     }
 
+    fn remove_blockdevs(
+        &mut self,
+        _pool_uuid: PoolUuid,
+        _pool_name: &str,
+        uuids: &[DevUuid],
+    ) -> StratisResult<SetDeleteAction<DevUuid>> {
+        if let Some(uuid) = uuids
+            .iter()
+            .find(|&uuid| self.cache_devs.contains_key(uuid))
+        {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Blockdev with UUID {} belongs to the cache tier; only data tier blockdevs can be removed",
+                    uuid.to_simple_ref()
+                ),
+            ));
+        }
+
+        let removed = uuids
+            .iter()
+            .filter(|&uuid| self.block_devs.contains_key(uuid))
+            .cloned()
+            .collect::<HashSet<_>>();
+        if !removed.is_empty() && removed.len() == self.block_devs.len() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "At least one blockdev must remain in the data tier".to_string(),
+            ));
+        }
+
+        for uuid in removed.iter() {
+            self.block_devs.remove(uuid);
+        }
+        Ok(SetDeleteAction::new(removed.into_iter().collect()))
+    }
+
     fn bind_clevis(
         &mut self,
         pin: String,
         clevis_info: Value,
     ) -> StratisResult<CreateAction<Clevis>> {
-        let encryption_info = self.encryption_info();
-        let clevis_info_current = encryption_info.and_then(|info| info.clevis_info.as_ref());
-        if encryption_info.is_some() {
-            if let Some(info) = clevis_info_current {
-                let clevis_tuple = (pin, clevis_info);
-                if info == &clevis_tuple {
-                    Ok(CreateAction::Identity)
-                } else {
-                    Err(StratisError::Error(format!(
-                        "This pool is already bound with clevis pin and config {:?};
-                        this differs from the requested pin and config {:?}",
-                        info, clevis_tuple,
-                    )))
-                }
-            } else {
-                self.add_clevis_info(pin, clevis_info);
-                Ok(CreateAction::Created(Clevis))
+        let encryption_info = match self.encryption_info() {
+            Some(info) => info,
+            None => {
+                return Err(StratisError::Error(
+                    "Requested pool does not appear to be encrypted".to_string(),
+                ))
             }
-        } else {
-            Err(StratisError::Error(
+        };
+        if encryption_info
+            .clevis_info
+            .iter()
+            .any(|binding| binding.pin == pin && binding.config == clevis_info)
+        {
+            return Ok(CreateAction::Identity);
+        }
+        let id = (0..)
+            .find(|id| !encryption_info.clevis_info.iter().any(|b| b.id == *id))
+            .expect("a pool has fewer than u32::MAX bindings");
+        self.add_clevis_binding(ClevisBinding {
+            id,
+            pin,
+            config: clevis_info,
+        });
+        Ok(CreateAction::Created(Clevis(id)))
+    }
+
+    fn rebind_clevis(&mut self, binding_id: u32) -> StratisResult<RenameAction<Clevis>> {
+        match self.encryption_info() {
+            Some(info)
+                if info
+                    .clevis_info
+                    .iter()
+                    .any(|binding| binding.id == binding_id) =>
+            {
+                Ok(RenameAction::Renamed(Clevis(binding_id)))
+            }
+            Some(_) => Ok(RenameAction::NoSource),
+            None => Err(StratisError::Error(
                 "Requested pool does not appear to be encrypted".to_string(),
-            ))
+            )),
+        }
+    }
+
+    fn rebind_keyring(
+        &mut self,
+        new_key_desc: &KeyDescription,
+    ) -> StratisResult<RenameAction<Key>> {
+        match self.encryption_info() {
+            Some(info) if &info.key_description == new_key_desc => Ok(RenameAction::Identity),
+            Some(_) => {
+                self.block_devs
+                    .iter_mut()
+                    .for_each(|(_, bd)| bd.set_key_description(new_key_desc.clone()));
+                Ok(RenameAction::Renamed(Key))
+            }
+            None => Err(StratisError::Error(
+                "Requested pool does not appear to be encrypted".to_string(),
+            )),
+        }
+    }
+
+    fn set_auto_unlock(&mut self, auto_unlock: bool) -> StratisResult<PropChangeAction<bool>> {
+        match self.encryption_info() {
+            Some(info) if info.auto_unlock == auto_unlock => Ok(PropChangeAction::Identity),
+            Some(info) if auto_unlock && info.clevis_info.is_empty() => Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "Automatic unlocking requires the pool to be bound with Clevis".to_string(),
+            )),
+            Some(_) => {
+                self.block_devs
+                    .iter_mut()
+                    .for_each(|(_, bd)| bd.set_auto_unlock(auto_unlock));
+                Ok(PropChangeAction::NewValue(auto_unlock))
+            }
+            None => Err(StratisError::Error(
+                "Requested pool does not appear to be encrypted".to_string(),
+            )),
+        }
+    }
+
+    fn reencrypt(
+        &mut self,
+        _pool_uuid: PoolUuid,
+        _pool_name: &str,
+    ) -> StratisResult<CreateAction<Reencryption>> {
+        if self.encryption_info().is_none() {
+            return Err(StratisError::Error(
+                "Requested pool does not appear to be encrypted".to_string(),
+            ));
         }
+        let started = self
+            .block_devs
+            .iter_mut()
+            .fold(false, |acc, (_, bd)| bd.start_reencryption() || acc);
+        Ok(if started {
+            CreateAction::Created(Reencryption)
+        } else {
+            CreateAction::Identity
+        })
     }
 
-    fn unbind_clevis(&mut self) -> StratisResult<DeleteAction<Clevis>> {
+    fn unbind_clevis(&mut self, binding_id: u32) -> StratisResult<DeleteAction<Clevis>> {
         let encryption_info = self.encryption_info();
-        let clevis_info = encryption_info.and_then(|info| info.clevis_info.as_ref());
-        if encryption_info.is_some() {
-            Ok(if clevis_info.is_some() {
-                self.clear_clevis_info();
-                DeleteAction::Deleted(Clevis)
-            } else {
-                DeleteAction::Identity
-            })
+        if let Some(info) = encryption_info {
+            Ok(
+                if info
+                    .clevis_info
+                    .iter()
+                    .any(|binding| binding.id == binding_id)
+                {
+                    self.remove_clevis_binding(binding_id);
+                    DeleteAction::Deleted(Clevis(binding_id))
+                } else {
+                    DeleteAction::Identity
+                },
+            )
         } else {
             Err(StratisError::Error(
                 "Requested pool does not appear to be encrypted".to_string(),
@@ -314,6 +560,7 @@ impl Pool for SimPool {
 
     fn destroy_filesystems<'a>(
         &'a mut self,
+        _pool_uuid: PoolUuid,
         _pool_name: &str,
         fs_uuids: &[FilesystemUuid],
     ) -> StratisResult<SetDeleteAction<FilesystemUuid>> {
@@ -353,6 +600,108 @@ impl Pool for SimPool {
         Ok(RenameAction::Renamed(uuid))
     }
 
+    fn set_filesystem_size(
+        &mut self,
+        uuid: FilesystemUuid,
+        new_size: Sectors,
+    ) -> StratisResult<PropChangeAction<Sectors>> {
+        if let Some((_, filesystem)) = self.filesystems.get_by_uuid(uuid) {
+            let current_size = filesystem.size().sectors();
+            if new_size > current_size {
+                self.check_overprov(new_size - current_size)?;
+            }
+        }
+
+        let (_, filesystem) = self.filesystems.get_mut_by_uuid(uuid).ok_or_else(|| {
+            StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!("Filesystem not found with UUID of {}", uuid),
+            )
+        })?;
+
+        if filesystem.set_size(new_size)? {
+            Ok(PropChangeAction::NewValue(new_size))
+        } else {
+            Ok(PropChangeAction::Identity)
+        }
+    }
+
+    fn overprov_enabled(&self) -> bool {
+        self.enable_overprov
+    }
+
+    fn set_overprov_mode(
+        &mut self,
+        _pool_name: &str,
+        enabled: bool,
+    ) -> StratisResult<PropChangeAction<bool>> {
+        if self.enable_overprov == enabled {
+            Ok(PropChangeAction::Identity)
+        } else {
+            if !enabled {
+                check_overprov_disable(self.total_logical_size(), self.total_physical_size())?;
+            }
+            self.enable_overprov = enabled;
+            Ok(PropChangeAction::NewValue(enabled))
+        }
+    }
+
+    fn encryption_space_reserved(&self) -> bool {
+        self.reserve_encryption_space
+    }
+
+    fn set_encryption_space_reserved(
+        &mut self,
+        _pool_name: &str,
+        reserved: bool,
+    ) -> StratisResult<PropChangeAction<bool>> {
+        if self.reserve_encryption_space == reserved {
+            Ok(PropChangeAction::Identity)
+        } else {
+            self.reserve_encryption_space = reserved;
+            Ok(PropChangeAction::NewValue(reserved))
+        }
+    }
+
+    fn space_thresholds(&self) -> SpaceThresholds {
+        self.space_thresholds
+    }
+
+    fn set_space_thresholds(
+        &mut self,
+        _pool_uuid: PoolUuid,
+        _pool_name: &str,
+        thresholds: SpaceThresholds,
+    ) -> StratisResult<PropChangeAction<SpaceThresholds>> {
+        validate_space_thresholds(thresholds)?;
+
+        if self.space_thresholds == thresholds {
+            Ok(PropChangeAction::Identity)
+        } else {
+            self.space_thresholds = thresholds;
+            Ok(PropChangeAction::NewValue(thresholds))
+        }
+    }
+
+    fn set_cache_mode(
+        &mut self,
+        _pool_name: &str,
+        mode: CacheMode,
+    ) -> StratisResult<PropChangeAction<CacheMode>> {
+        if !self.has_cache() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "The pool has no cache whose mode could be set".to_string(),
+            ));
+        }
+        if self.cache_mode == mode {
+            Ok(PropChangeAction::Identity)
+        } else {
+            self.cache_mode = mode;
+            Ok(PropChangeAction::NewValue(mode))
+        }
+    }
+
     fn snapshot_filesystem(
         &mut self,
         _pool_uuid: PoolUuid,
@@ -365,9 +714,15 @@ impl Pool for SimPool {
             return Ok(CreateAction::Identity);
         }
 
+        if let Some((_, filesystem)) = self.get_filesystem(origin_uuid) {
+            self.check_overprov(filesystem.size().sectors())?;
+        }
         let uuid = FilesystemUuid::new_v4();
         let snapshot = match self.get_filesystem(origin_uuid) {
-            Some(_filesystem) => SimFilesystem::new(),
+            Some((_, filesystem)) => SimFilesystem::new(
+                Some(filesystem.size().sectors()),
+                filesystem.size_limit().map(|l| l.sectors()),
+            )?,
             None => {
                 return Err(StratisError::Engine(
                     ErrorEnum::NotFound,
@@ -396,6 +751,14 @@ impl Pool for SimPool {
         Ok(Sectors(0))
     }
 
+    fn thin_pool_usage(&self) -> StratisResult<ThinPoolUsage> {
+        Ok(ThinPoolUsage::default())
+    }
+
+    fn thin_pool_status(&self) -> Option<ThinPoolStatusDigest> {
+        Some(ThinPoolStatusDigest::Good)
+    }
+
     fn filesystems(&self) -> Vec<(Name, FilesystemUuid, &dyn Filesystem)> {
         self.filesystems
             .iter()
@@ -494,6 +857,14 @@ impl Pool for SimPool {
         !self.cache_devs.is_empty()
     }
 
+    fn cache_statistics(&self) -> StratisResult<Option<CacheStatistics>> {
+        Ok(if self.has_cache() {
+            Some(CacheStatistics::default())
+        } else {
+            None
+        })
+    }
+
     fn is_encrypted(&self) -> bool {
         self.datadevs_encrypted()
     }
@@ -501,16 +872,116 @@ impl Pool for SimPool {
     fn encryption_info(&self) -> Option<&EncryptionInfo> {
         self.encryption_info_impl()
     }
+
+    fn backup_metadata(&self, pool_uuid: PoolUuid, pool_name: &str) -> StratisResult<Value> {
+        // The simulator lays nothing out on its devices, so the archive has
+        // the schema of a real one, but records no allocations and no
+        // metadata sizes. Devices and filesystems are ordered by UUID.
+        fn sorted(tier: &HashMap<DevUuid, SimDev>) -> Vec<(&DevUuid, &SimDev)> {
+            let mut devs = tier.iter().collect::<Vec<_>>();
+            devs.sort_by_key(|(uuid, _)| uuid.0);
+            devs
+        }
+        let devs = |tier: &HashMap<DevUuid, SimDev>| -> Value {
+            json!({
+                "allocs": json!([]),
+                "devs": Value::Array(
+                    sorted(tier)
+                        .into_iter()
+                        .map(|(uuid, bd)| json!({
+                            "uuid": Value::from(uuid.to_string()),
+                            "user_info": bd.user_info().map(Value::from).unwrap_or(Value::Null),
+                            "hardware_info":
+                                bd.hardware_info().map(Value::from).unwrap_or(Value::Null),
+                        }))
+                        .collect()
+                ),
+            })
+        };
+
+        let cache_tier = if self.cache_devs.is_empty() {
+            Value::Null
+        } else {
+            json!({
+                "blockdev": devs(&self.cache_devs),
+                "mode": serde_json::to_value(&self.cache_mode)?,
+            })
+        };
+
+        let mut filesystems = self.filesystems.iter().collect::<Vec<_>>();
+        filesystems.sort_by_key(|(_, uuid, _)| uuid.0);
+
+        Ok(json!({
+            "version": Value::from(METADATA_ARCHIVE_VERSION),
+            "created": Value::from(Utc::now().to_rfc3339()),
+            "pool_uuid": Value::from(pool_uuid.to_string()),
+            "devices": Value::Array(
+                sorted(&self.block_devs)
+                    .into_iter()
+                    .chain(sorted(&self.cache_devs))
+                    .map(|(uuid, bd)| json!({
+                        "devnode": Value::from(bd.devnode().display().to_string()),
+                        "device_uuid": Value::from(uuid.to_string()),
+                        "blkdev_size": Value::from(*bd.size()),
+                        "mda_size": Value::from(0),
+                        "initialization_time":
+                            Value::from(bd.initialization_time().timestamp() as u64),
+                    }))
+                    .collect()
+            ),
+            "pool": {
+                "name": Value::from(pool_name),
+                "backstore": {
+                    "data_tier": {
+                        "blockdev": devs(&self.block_devs),
+                        "reserve_encryption_space": Value::from(self.reserve_encryption_space),
+                    },
+                    "cap": {
+                        "allocs": json!([]),
+                    },
+                    "cache_tier": cache_tier,
+                },
+                "flex_devs": {
+                    "meta_dev": json!([]),
+                    "thin_meta_dev": json!([]),
+                    "thin_data_dev": json!([]),
+                    "thin_meta_dev_spare": json!([]),
+                },
+                "thinpool_dev": {
+                    "data_block_size": Value::from(0),
+                },
+                "overprovisioning": Value::from(self.enable_overprov),
+                "space_thresholds": serde_json::to_value(&self.space_thresholds)?,
+            },
+            "filesystems": Value::Array(
+                filesystems
+                    .into_iter()
+                    .enumerate()
+                    .map(|(thin_id, (name, uuid, fs))| json!({
+                        "name": Value::from(name.to_string()),
+                        "uuid": Value::from(uuid.to_string()),
+                        "thin_id": Value::from(thin_id as u64),
+                        "size": Value::from(*fs.size().sectors()),
+                        "created": Value::from(fs.created().timestamp() as u64),
+                        "size_limit": fs
+                            .size_limit()
+                            .map(|limit| Value::from(*limit.sectors()))
+                            .unwrap_or(Value::Null),
+                    }))
+                    .collect()
+            ),
+        }))
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::path::Path;
+    use std::{convert::TryFrom, path::Path};
 
     use crate::engine::Engine;
 
-    use crate::engine::sim_engine::SimEngine;
+    use crate::engine::sim_engine::{tests::set_test_key, SimEngine};
 
     use crate::engine::types::EngineAction;
 
@@ -527,6 +998,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -549,13 +1021,14 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
         let infos = pool
-            .create_filesystems(uuid, &[("old_name", None)])
+            .create_filesystems(uuid, &[("old_name", None, None)])
             .unwrap()
             .changed()
             .unwrap();
@@ -579,13 +1052,14 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
         let results = pool
-            .create_filesystems(uuid, &[(old_name, None), (new_name, None)])
+            .create_filesystems(uuid, &[(old_name, None, None), (new_name, None, None)])
             .unwrap()
             .changed()
             .unwrap();
@@ -608,6 +1082,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -620,8 +1095,9 @@ mod tests {
     }
 
     #[test]
-    /// Removing an empty list of filesystems should always succeed
-    fn destroy_fs_empty() {
+    /// Growing a filesystem succeeds, setting the same size again is a no-op,
+    /// and shrinking it fails.
+    fn set_fs_size() {
         let mut engine = SimEngine::default();
         let pool_name = "pool_name";
         let uuid = engine
@@ -630,20 +1106,46 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
-        assert!(match pool.destroy_filesystems(pool_name, &[]) {
-            Ok(uuids) => !uuids.is_changed(),
-            _ => false,
-        });
+        let fs_size = Sectors(IEC::Mi);
+        let fs_uuid = pool
+            .create_filesystems(uuid, &[("fs_name", Some(fs_size), None)])
+            .unwrap()
+            .changed()
+            .unwrap()[0]
+            .1;
+        let new_size = fs_size * 2u64;
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, new_size),
+            Ok(PropChangeAction::NewValue(size)) if size == new_size
+        );
+        assert_eq!(
+            pool.get_filesystem(fs_uuid).unwrap().1.size(),
+            new_size.bytes()
+        );
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, new_size),
+            Ok(PropChangeAction::Identity)
+        );
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, fs_size),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            pool.set_filesystem_size(FilesystemUuid::new_v4(), new_size),
+            Err(StratisError::Engine(ErrorEnum::NotFound, _))
+        );
     }
 
     #[test]
-    /// Removing a non-empty list of filesystems should succeed on empty pool
-    fn destroy_fs_some() {
+    /// A filesystem may not be created larger than its size limit, and may
+    /// not be grown beyond it.
+    fn fs_size_limit() {
         let mut engine = SimEngine::default();
         let pool_name = "pool_name";
         let uuid = engine
@@ -652,20 +1154,41 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
+        let size_limit = Sectors(IEC::Mi);
         assert_matches!(
-            pool.destroy_filesystems(pool_name, &[FilesystemUuid::new_v4()]),
-            Ok(_)
+            pool.create_filesystems(
+                uuid,
+                &[("fs_name", Some(size_limit * 2u64), Some(size_limit))]
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        let fs_uuid = pool
+            .create_filesystems(uuid, &[("fs_name", None, Some(size_limit))])
+            .unwrap()
+            .changed()
+            .unwrap()[0]
+            .1;
+        let (_, filesystem) = pool.get_filesystem(fs_uuid).unwrap();
+        assert_eq!(filesystem.size(), size_limit.bytes());
+        assert_eq!(filesystem.size_limit(), Some(size_limit.bytes()));
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, size_limit * 2u64),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
         );
     }
 
     #[test]
-    /// Removing a non-empty list of filesystems should succeed on any pool
-    fn destroy_fs_any() {
+    /// When overprovisioning is disabled, filesystems may not be created,
+    /// snapshotted, or grown beyond the size of the pool, and
+    /// overprovisioning may not be disabled once the pool is
+    /// overprovisioned.
+    fn overprov() {
         let mut engine = SimEngine::default();
         let pool_name = "pool_name";
         let uuid = engine
@@ -674,26 +1197,56 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
-        let fs_results = pool
-            .create_filesystems(uuid, &[("fs_name", None)])
+        let pool_size = pool.total_physical_size();
+        assert_matches!(
+            pool.set_overprov_mode(pool_name, false),
+            Ok(PropChangeAction::NewValue(false))
+        );
+        assert_matches!(
+            pool.create_filesystems(uuid, &[("fs_name", Some(pool_size + Sectors(1)), None)]),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        let fs_uuid = pool
+            .create_filesystems(uuid, &[("fs_name", Some(pool_size / 2u64), None)])
             .unwrap()
             .changed()
+            .unwrap()[0]
+            .1;
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, pool_size),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, pool_size / 2u64 + Sectors(1)),
+            Ok(PropChangeAction::NewValue(_))
+        );
+        assert_matches!(
+            pool.snapshot_filesystem(uuid, fs_uuid, "snapshot_name"),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        assert_matches!(
+            pool.set_overprov_mode(pool_name, true),
+            Ok(PropChangeAction::NewValue(true))
+        );
+        pool.snapshot_filesystem(uuid, fs_uuid, "snapshot_name")
             .unwrap();
-        let fs_uuid = fs_results[0].1;
-        assert!(match pool.destroy_filesystems(pool_name, &[fs_uuid]) {
-            Ok(filesystems) => filesystems == SetDeleteAction::new(vec![fs_uuid]),
-            _ => false,
-        });
+        assert_matches!(
+            pool.set_overprov_mode(pool_name, false),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert!(pool.overprov_enabled());
     }
 
     #[test]
-    /// Creating an empty list of filesystems should succeed, always
-    fn create_fs_none() {
+    /// Removing an empty list of filesystems should always succeed
+    fn destroy_fs_empty() {
         let mut engine = SimEngine::default();
         let pool_name = "pool_name";
         let uuid = engine
@@ -702,13 +1255,91 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
-        let fs = pool.create_filesystems(uuid, &[]).unwrap();
-        assert!(!fs.is_changed())
+        assert!(match pool.destroy_filesystems(uuid, pool_name, &[]) {
+            Ok(uuids) => !uuids.is_changed(),
+            _ => false,
+        });
+    }
+
+    #[test]
+    /// Removing a non-empty list of filesystems should succeed on empty pool
+    fn destroy_fs_some() {
+        let mut engine = SimEngine::default();
+        let pool_name = "pool_name";
+        let uuid = engine
+            .create_pool(
+                pool_name,
+                strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+        assert_matches!(
+            pool.destroy_filesystems(uuid, pool_name, &[FilesystemUuid::new_v4()]),
+            Ok(_)
+        );
+    }
+
+    #[test]
+    /// Removing a non-empty list of filesystems should succeed on any pool
+    fn destroy_fs_any() {
+        let mut engine = SimEngine::default();
+        let pool_name = "pool_name";
+        let uuid = engine
+            .create_pool(
+                pool_name,
+                strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+        let fs_results = pool
+            .create_filesystems(uuid, &[("fs_name", None, None)])
+            .unwrap()
+            .changed()
+            .unwrap();
+        let fs_uuid = fs_results[0].1;
+        assert!(
+            match pool.destroy_filesystems(uuid, pool_name, &[fs_uuid]) {
+                Ok(filesystems) => filesystems == SetDeleteAction::new(vec![fs_uuid]),
+                _ => false,
+            }
+        );
+    }
+
+    #[test]
+    /// Creating an empty list of filesystems should succeed, always
+    fn create_fs_none() {
+        let mut engine = SimEngine::default();
+        let pool_name = "pool_name";
+        let uuid = engine
+            .create_pool(
+                pool_name,
+                strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+        let fs = pool.create_filesystems(uuid, &[]).unwrap();
+        assert!(!fs.is_changed())
     }
 
     #[test]
@@ -722,13 +1353,14 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
         assert!(match pool
-            .create_filesystems(uuid, &[("name", None)])
+            .create_filesystems(uuid, &[("name", None, None)])
             .ok()
             .and_then(|fs| fs.changed())
         {
@@ -749,13 +1381,17 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
-        pool.create_filesystems(uuid, &[(fs_name, None)]).unwrap();
-        let set_create_action = pool.create_filesystems(uuid, &[(fs_name, None)]).unwrap();
+        pool.create_filesystems(uuid, &[(fs_name, None, None)])
+            .unwrap();
+        let set_create_action = pool
+            .create_filesystems(uuid, &[(fs_name, None, None)])
+            .unwrap();
         assert!(!set_create_action.is_changed());
     }
 
@@ -771,13 +1407,14 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
             .unwrap();
         let pool = engine.get_mut_pool(uuid).unwrap().1;
         assert!(match pool
-            .create_filesystems(uuid, &[(fs_name, None), (fs_name, None)])
+            .create_filesystems(uuid, &[(fs_name, None, None), (fs_name, None, None)])
             .ok()
             .and_then(|fs| fs.changed())
         {
@@ -796,6 +1433,7 @@ mod tests {
                 strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
                 None,
                 None,
+                None,
             )
             .unwrap()
             .changed()
@@ -811,4 +1449,502 @@ mod tests {
             _ => false,
         });
     }
+
+    #[test]
+    /// Removing data devices should remove exactly the devices specified
+    /// that belong to the pool, but never the last data device.
+    fn remove_device_data() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two", "/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        let mut dev_uuids = pool
+            .blockdevs()
+            .iter()
+            .map(|(uuid, _, _)| *uuid)
+            .collect::<Vec<_>>();
+        let remaining = dev_uuids.pop().unwrap();
+
+        assert_matches!(
+            pool.remove_blockdevs(uuid, &*pool_name, &[DevUuid::new_v4()])
+                .ok()
+                .and_then(|c| c.changed()),
+            None
+        );
+        assert_matches!(
+            pool.remove_blockdevs(uuid, &*pool_name, &[remaining, dev_uuids[0]]),
+            Err(_)
+        );
+
+        assert!(match pool
+            .remove_blockdevs(uuid, &*pool_name, &dev_uuids)
+            .ok()
+            .and_then(|c| c.changed())
+        {
+            Some(devs) => devs.len() == dev_uuids.len(),
+            _ => false,
+        });
+        assert_eq!(
+            pool.blockdevs()
+                .iter()
+                .map(|(uuid, _, _)| *uuid)
+                .collect::<Vec<_>>(),
+            vec![remaining]
+        );
+    }
+
+    #[test]
+    /// Removing all the cachedevs destroys the cache; removing data devices
+    /// as cachedevs is an error.
+    fn remove_cachedevs() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool("pool_name", strs_to_paths!(["/dev/one"]), None, None, None)
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        let data_uuid = pool.blockdevs()[0].0;
+        let cache_uuids = pool
+            .init_cache(
+                uuid,
+                &*pool_name,
+                strs_to_paths!(["/dev/two", "/dev/three"]),
+                CacheConfig::default(),
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+
+        assert_matches!(
+            pool.remove_cachedevs(uuid, &*pool_name, &[data_uuid]),
+            Err(_)
+        );
+
+        assert_eq!(
+            pool.remove_cachedevs(uuid, &*pool_name, &cache_uuids[..1])
+                .unwrap()
+                .changed(),
+            Some(vec![cache_uuids[0]])
+        );
+        assert!(pool.has_cache());
+
+        assert_eq!(
+            pool.destroy_cache(uuid, &*pool_name).unwrap().changed(),
+            Some(vec![cache_uuids[1]])
+        );
+        assert!(!pool.has_cache());
+        assert_matches!(
+            pool.destroy_cache(uuid, &*pool_name).unwrap().changed(),
+            None
+        );
+    }
+
+    #[test]
+    /// The mode of a cache may be set only once the cache exists; setting it
+    /// to the mode it is already in changes nothing.
+    fn set_cache_mode() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool("pool_name", strs_to_paths!(["/dev/one"]), None, None, None)
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        assert_matches!(
+            pool.set_cache_mode(&*pool_name, CacheMode::Writeback),
+            Err(_)
+        );
+
+        let config = CacheConfig {
+            block_size: Sectors(96),
+            ..CacheConfig::default()
+        };
+        assert_matches!(
+            pool.init_cache(uuid, &*pool_name, strs_to_paths!(["/dev/two"]), config),
+            Err(_)
+        );
+
+        let config = CacheConfig {
+            mode: CacheMode::Writeback,
+            ..CacheConfig::default()
+        };
+        pool.init_cache(uuid, &*pool_name, strs_to_paths!(["/dev/two"]), config)
+            .unwrap();
+        assert_eq!(
+            pool.set_cache_mode(&*pool_name, CacheMode::Writeback)
+                .unwrap(),
+            PropChangeAction::Identity
+        );
+        assert_eq!(
+            pool.set_cache_mode(&*pool_name, CacheMode::Passthrough)
+                .unwrap(),
+            PropChangeAction::NewValue(CacheMode::Passthrough)
+        );
+    }
+
+    #[test]
+    /// Cache statistics are only available once a cache is initialized.
+    fn cache_statistics() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool("pool_name", strs_to_paths!(["/dev/one"]), None, None, None)
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        assert_eq!(pool.cache_statistics().unwrap(), None);
+
+        pool.init_cache(
+            uuid,
+            &*pool_name,
+            strs_to_paths!(["/dev/two"]),
+            CacheConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            pool.cache_statistics().unwrap(),
+            Some(CacheStatistics::default())
+        );
+    }
+
+    #[test]
+    /// Space thresholds must be consistent percentages; setting them to
+    /// their current values changes nothing.
+    fn set_space_thresholds() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool("pool_name", strs_to_paths!(["/dev/one"]), None, None, None)
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+
+        let thresholds = SpaceThresholds {
+            warning: 96,
+            critical: 95,
+        };
+        assert_matches!(
+            pool.set_space_thresholds(uuid, &*pool_name, thresholds),
+            Err(_)
+        );
+        assert_eq!(
+            pool.set_space_thresholds(uuid, &*pool_name, SpaceThresholds::default())
+                .unwrap(),
+            PropChangeAction::Identity
+        );
+
+        let thresholds = SpaceThresholds {
+            warning: 70,
+            critical: 90,
+        };
+        assert_eq!(
+            pool.set_space_thresholds(uuid, &*pool_name, thresholds)
+                .unwrap(),
+            PropChangeAction::NewValue(thresholds)
+        );
+        assert_eq!(pool.space_thresholds(), thresholds);
+    }
+
+    #[test]
+    /// Rebinding the keyring of an encrypted pool changes the key
+    /// description of all its devices; rebinding to the current key
+    /// description changes nothing and an unencrypted pool can not be
+    /// rebound.
+    fn rebind_keyring() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        let new_key_desc = KeyDescription::try_from("new_key".to_string()).unwrap();
+        set_test_key(&mut engine, &key_desc);
+
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc.clone()),
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+        assert_matches!(pool.rebind_keyring(&key_desc), Ok(RenameAction::Identity));
+        assert_matches!(
+            pool.rebind_keyring(&new_key_desc),
+            Ok(RenameAction::Renamed(Key))
+        );
+        assert_eq!(
+            pool.encryption_info().map(|info| &info.key_description),
+            Some(&new_key_desc)
+        );
+
+        let uuid = engine
+            .create_pool(
+                "other_pool",
+                strs_to_paths!(["/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+        assert_matches!(pool.rebind_keyring(&new_key_desc), Err(_));
+    }
+
+    #[test]
+    /// Reencrypting an encrypted pool reports progress on its devices until
+    /// all the data has been processed; starting it again while it runs
+    /// changes nothing and an unencrypted pool can not be reencrypted.
+    fn reencrypt() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        set_test_key(&mut engine, &key_desc);
+
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc),
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        assert_matches!(
+            pool.reencrypt(uuid, &pool_name),
+            Ok(CreateAction::Created(Reencryption))
+        );
+        assert_matches!(pool.reencrypt(uuid, &pool_name), Ok(CreateAction::Identity));
+        assert!(pool
+            .blockdevs()
+            .iter()
+            .all(|(_, _, bd)| bd.reencryption_progress().is_some()));
+
+        while engine.reencrypt_step() {}
+        let pool = engine.get_pool(uuid).unwrap().1;
+        assert!(pool
+            .blockdevs()
+            .iter()
+            .all(|(_, _, bd)| bd.reencryption_progress().is_none()));
+
+        let uuid = engine
+            .create_pool(
+                "other_pool",
+                strs_to_paths!(["/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        assert_matches!(pool.reencrypt(uuid, &pool_name), Err(_));
+    }
+
+    #[test]
+    /// An encrypted pool may be bound with several clevis configurations,
+    /// each identified by its own binding id; binding with a configuration
+    /// the pool is already bound with changes nothing and unbinding removes
+    /// only the binding with the given id. Only an existing binding can be
+    /// regenerated.
+    fn multiple_clevis_bindings() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        set_test_key(&mut engine, &key_desc);
+
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc),
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+
+        let tang_one = json!({"url": "http://one"});
+        let tang_two = json!({"url": "http://two"});
+        let id_one = match pool.bind_clevis("tang".to_string(), tang_one.clone()) {
+            Ok(CreateAction::Created(Clevis(id))) => id,
+            _ => panic!("binding an encrypted pool must succeed"),
+        };
+        let id_two = match pool.bind_clevis("tang".to_string(), tang_two.clone()) {
+            Ok(CreateAction::Created(Clevis(id))) => id,
+            _ => panic!("binding an encrypted pool must succeed"),
+        };
+        assert_ne!(id_one, id_two);
+        assert_matches!(
+            pool.bind_clevis("tang".to_string(), tang_one),
+            Ok(CreateAction::Identity)
+        );
+        assert_eq!(
+            pool.encryption_info()
+                .map(|info| info.clevis_info.len())
+                .unwrap(),
+            2
+        );
+
+        assert_matches!(
+            pool.unbind_clevis(id_one),
+            Ok(DeleteAction::Deleted(Clevis(id))) if id == id_one
+        );
+        assert_matches!(pool.unbind_clevis(id_one), Ok(DeleteAction::Identity));
+        assert_matches!(pool.rebind_clevis(id_one), Ok(RenameAction::NoSource));
+        assert_matches!(
+            pool.rebind_clevis(id_two),
+            Ok(RenameAction::Renamed(Clevis(id))) if id == id_two
+        );
+        assert_eq!(
+            pool.encryption_info().map(|info| info
+                .clevis_info
+                .iter()
+                .map(|binding| (binding.id, binding.config.clone()))
+                .collect::<Vec<_>>()),
+            Some(vec![(id_two, tang_two)])
+        );
+    }
+
+    #[test]
+    /// Automatic unlocking may only be enabled for an encrypted pool with
+    /// a clevis binding; setting it to its current value changes nothing.
+    fn set_auto_unlock() {
+        let mut engine = SimEngine::default();
+        let key_desc = KeyDescription::try_from("key".to_string()).unwrap();
+        set_test_key(&mut engine, &key_desc);
+
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                Some(key_desc),
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+
+        assert_matches!(pool.set_auto_unlock(true), Err(_));
+        assert_matches!(pool.set_auto_unlock(false), Ok(PropChangeAction::Identity));
+
+        pool.bind_clevis("tang".to_string(), json!({"url": "http://one"}))
+            .unwrap();
+        assert_matches!(
+            pool.set_auto_unlock(true),
+            Ok(PropChangeAction::NewValue(true))
+        );
+        assert_matches!(pool.set_auto_unlock(true), Ok(PropChangeAction::Identity));
+        assert!(pool.encryption_info().unwrap().auto_unlock);
+
+        let uuid = engine
+            .create_pool(
+                "other_pool",
+                strs_to_paths!(["/dev/three"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let pool = engine.get_mut_pool(uuid).unwrap().1;
+        assert_matches!(pool.set_auto_unlock(false), Err(_));
+    }
+
+    #[test]
+    /// The metadata archive of a pool is versioned and records every device,
+    /// including cache devices, and every filesystem.
+    fn backup_metadata() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        pool.init_cache(
+            uuid,
+            &*pool_name,
+            strs_to_paths!(["/dev/three"]),
+            CacheConfig::default(),
+        )
+        .unwrap();
+        pool.create_filesystems(uuid, &[("fs_name", None, None), ("other_fs", None, None)])
+            .unwrap();
+
+        let archive = pool.backup_metadata(uuid, &*pool_name).unwrap();
+        assert_eq!(archive["version"], json!(METADATA_ARCHIVE_VERSION));
+        assert_eq!(archive["pool_uuid"], json!(uuid.to_string()));
+        assert_eq!(archive["pool"]["name"], json!("pool_name"));
+        assert_eq!(archive["devices"].as_array().unwrap().len(), 3);
+        assert!(archive["devices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|dev| dev["mda_size"].is_u64() && dev["initialization_time"].is_u64()));
+        assert_eq!(
+            archive["pool"]["backstore"]["data_tier"]["blockdev"]["devs"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            archive["pool"]["backstore"]["cache_tier"]["blockdev"]["devs"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            archive["pool"]["backstore"]["data_tier"]["reserve_encryption_space"],
+            json!(false)
+        );
+
+        let filesystems = archive["filesystems"].as_array().unwrap();
+        let mut uuids = filesystems
+            .iter()
+            .map(|fs| fs["uuid"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        uuids.sort();
+        assert_eq!(
+            filesystems
+                .iter()
+                .map(|fs| (
+                    fs["uuid"].as_str().unwrap().to_string(),
+                    fs["thin_id"].clone()
+                ))
+                .collect::<Vec<_>>(),
+            uuids
+                .into_iter()
+                .enumerate()
+                .map(|(thin_id, uuid)| (uuid, json!(thin_id)))
+                .collect::<Vec<_>>()
+        );
+    }
 }
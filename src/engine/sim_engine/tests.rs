@@ -0,0 +1,33 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Helpers shared by the tests of the simulated engine.
+
+use std::os::unix::io::RawFd;
+
+use nix::unistd::{close, pipe, write};
+
+use crate::engine::{
+    sim_engine::SimEngine,
+    types::{Key, KeyDescription, MappingCreateAction},
+    Engine,
+};
+
+/// Return the read end of a pipe from which the passphrase used by the
+/// tests can be read.
+pub fn passphrase_fd() -> RawFd {
+    let (read_fd, write_fd) = pipe().unwrap();
+    write(write_fd, b"passphrase").unwrap();
+    close(write_fd).unwrap();
+    read_fd
+}
+
+/// Set the key with the specified description to the passphrase used by
+/// the tests. The key does not expire.
+pub fn set_test_key(engine: &mut SimEngine, key_desc: &KeyDescription) -> MappingCreateAction<Key> {
+    engine
+        .get_key_handler_mut()
+        .set(key_desc, passphrase_fd(), None)
+        .unwrap()
+}
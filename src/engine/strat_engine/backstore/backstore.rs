@@ -4,21 +4,29 @@
 
 // Code to handle the backing store of a pool.
 
-use std::{cmp, path::Path};
+use std::{
+    cmp, iter,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
 
 use chrono::{DateTime, Utc};
 use serde_json::Value;
 
-use devicemapper::{CacheDev, Device, DmDevice, LinearDev, Sectors};
+use devicemapper::{
+    device_exists, CacheDev, CacheDevStatus, DevId, Device, DmDevice, DmFlags, DmName, DmOptions,
+    LinearDev, LinearDevTargetParams, Sectors, TargetLine,
+};
 
 use crate::{
     engine::{
         strat_engine::{
             backstore::{
                 blockdev::StratBlockDev,
-                blockdevmgr::{map_to_dm, BlockDevMgr},
+                blockdevmgr::{map_to_dm, BlkDevSegment, BlockDevMgr},
                 cache_tier::CacheTier,
-                data_tier::DataTier,
+                data_tier::{DataTier, RelocatedSegments, RemovedBlockdevs},
             },
             dm::get_dm,
             metadata::MDADataSize,
@@ -26,23 +34,141 @@ use crate::{
             serde_structs::{BackstoreSave, CapSave, Recordable},
             writing::wipe_sectors,
         },
-        types::{BlockDevTier, DevUuid, EncryptionInfo, KeyDescription, PoolUuid},
+        types::{
+            BlockDevTier, CacheConfig, CacheMode, CacheStatistics, DevUuid, EncryptionInfo,
+            KeyDescription, PoolUuid, DEFAULT_CACHE_POLICY,
+        },
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
-/// Use a cache block size that the kernel docs indicate is the largest
-/// typical size.
-const CACHE_BLOCK_SIZE: Sectors = Sectors(2048); // 1024 KiB
+/// The dm-cache replacement policy which writes back all dirty blocks and
+/// promotes no new ones.
+const CLEANER_POLICY: &str = "cleaner";
+
+/// How long to wait between checks of the number of dirty blocks while
+/// flushing the cache.
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the cache to become clean while flushing it. The
+/// devices stacked on the cap device are suspended for as long as this.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whether the table that CacheDev generates for a cache device, which is
+/// always in writethrough mode and uses the default policy without any
+/// arguments, is the table that config requires.
+fn uses_default_table(config: &CacheConfig) -> bool {
+    config.mode == CacheMode::Writethrough
+        && config.policy == DEFAULT_CACHE_POLICY
+        && config.policy_args.is_empty()
+}
+
+/// Load a table for the cache device which uses the mode and policy
+/// specified by config. CacheDev loads the table it generated whenever the
+/// table of one of its sub-devices is changed, so this must be done
+/// afterward as well as when the cache device is first set up.
+/// The caller must resume the device for the table to take effect.
+fn load_configured_table(cache: &CacheDev, config: &CacheConfig) -> StratisResult<()> {
+    let mut table = cache.table().clone();
+    let params = &mut table.table.params;
+    params.feature_args = iter::once(config.mode.to_string()).collect();
+    params.policy = config.policy.clone();
+    params.policy_args = config.policy_args.clone();
+    cache.table_load(get_dm(), &table)?;
+    Ok(())
+}
+
+/// Replace the table of an existing cache device with the table that
+/// CacheDev would generate for it. CacheDev refuses to take over a device
+/// whose mode or policy arguments differ from those of its own table.
+fn load_default_table(
+    dm_name: &DmName,
+    meta: &LinearDev,
+    cache: &LinearDev,
+    origin: &LinearDev,
+    block_size: Sectors,
+) -> StratisResult<()> {
+    let params = format!(
+        "{} {} {} {} 1 {} {} 0",
+        meta.device(),
+        cache.device(),
+        origin.device(),
+        *block_size,
+        CacheMode::Writethrough,
+        DEFAULT_CACHE_POLICY
+    );
+    let id = DevId::Name(dm_name);
+    let mut options = DmOptions::new();
+    options.set_flags(DmFlags::DM_SUSPEND);
+    get_dm().device_suspend(&id, &options)?;
+    let load_result = get_dm().table_load(&id, &[(0, *origin.size(), "cache".to_string(), params)]);
+    get_dm().device_suspend(&id, &DmOptions::new())?;
+    load_result?;
+    Ok(())
+}
+
+/// Reload the table of the cache sub-device of the cache device and then the
+/// table of the cache device, configured as specified by config, and resume
+/// the cache device.
+fn reload_cache_sub_device(
+    cache: &mut CacheDev,
+    config: &CacheConfig,
+    cache_table: Vec<TargetLine<LinearDevTargetParams>>,
+) -> StratisResult<()> {
+    cache.set_cache_table(get_dm(), cache_table)?;
+    load_configured_table(cache, config)?;
+    cache.resume(get_dm())?;
+    Ok(())
+}
 
-/// Make a DM cache device. If the cache device is being made new,
-/// take extra steps to make it clean.
+/// Wait until the cache status reports no dirty blocks. Return an error if
+/// the cache has failed or if it is not clean within FLUSH_TIMEOUT.
+fn wait_until_clean(cache: &CacheDev) -> StratisResult<()> {
+    let start = Instant::now();
+    loop {
+        match cache.status(get_dm())? {
+            CacheDevStatus::Working(status) => {
+                if status.performance.dirty == 0 {
+                    return Ok(());
+                }
+                if start.elapsed() > FLUSH_TIMEOUT {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Busy,
+                        format!(
+                            "The cache device still had {} dirty blocks after {} seconds of flushing",
+                            status.performance.dirty,
+                            FLUSH_TIMEOUT.as_secs()
+                        ),
+                    ));
+                }
+            }
+            CacheDevStatus::Error => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Error,
+                    "Could not obtain the status of the cache device while flushing it".to_string(),
+                ));
+            }
+            CacheDevStatus::Fail => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Error,
+                    "The cache device has failed; its dirty blocks can not be flushed".to_string(),
+                ));
+            }
+        }
+        thread::sleep(FLUSH_POLL_INTERVAL);
+    }
+}
+
+/// Make a DM cache device, configured as specified by the cache tier.
+/// If the cache device is being made new, take extra steps to make it clean.
 fn make_cache(
     pool_uuid: PoolUuid,
     cache_tier: &CacheTier,
     origin: LinearDev,
     new: bool,
 ) -> StratisResult<CacheDev> {
+    let config = &cache_tier.config;
+
     let (dm_name, dm_uuid) = format_backstore_ids(pool_uuid, CacheRole::MetaSub);
     let meta = LinearDev::setup(
         get_dm(),
@@ -69,20 +195,34 @@ fn make_cache(
     )?;
 
     let (dm_name, dm_uuid) = format_backstore_ids(pool_uuid, CacheRole::Cache);
-    Ok(CacheDev::setup(
+    if !uses_default_table(config) && device_exists(get_dm(), &dm_name)? {
+        load_default_table(&dm_name, &meta, &cache, &origin, config.block_size)?;
+    }
+    let mut cache_device = CacheDev::setup(
         get_dm(),
         &dm_name,
         Some(&dm_uuid),
         meta,
         cache,
         origin,
-        CACHE_BLOCK_SIZE,
-    )?)
+        config.block_size,
+    )?;
+
+    if !uses_default_table(config) {
+        cache_device.suspend(get_dm(), true)?;
+        let load_result = load_configured_table(&cache_device, config);
+        cache_device.resume(get_dm())?;
+        load_result?;
+    }
+
+    Ok(cache_device)
 }
 
 /// This structure can allocate additional space to the upper layer, but it
 /// cannot accept returned space. When it is extended to be able to accept
 /// returned space the allocation algorithm will have to be revised.
+/// Data devices may be removed, but only by moving the space allocated from
+/// them elsewhere; the cap device never shrinks.
 #[derive(Debug)]
 pub struct Backstore {
     /// A cache DM Device.
@@ -178,13 +318,13 @@ impl Backstore {
         pool_uuid: PoolUuid,
         paths: &[&Path],
         mda_data_size: MDADataSize,
-        key_desc: Option<&KeyDescription>,
+        encryption_info: Option<&EncryptionInfo>,
     ) -> StratisResult<Backstore> {
         let data_tier = DataTier::new(BlockDevMgr::initialize(
             pool_uuid,
             paths,
             mda_data_size,
-            key_desc,
+            encryption_info,
         )?);
 
         Ok(Backstore {
@@ -196,7 +336,8 @@ impl Backstore {
         })
     }
 
-    /// Initialize the cache tier and add cachedevs to the backstore.
+    /// Initialize the cache tier and add cachedevs to the backstore. The
+    /// cache device is configured as specified by config.
     ///
     /// Returns all `DevUuid`s of devices that were added to the cache on initialization.
     ///
@@ -208,6 +349,7 @@ impl Backstore {
         &mut self,
         pool_uuid: PoolUuid,
         paths: &[&Path],
+        config: CacheConfig,
     ) -> StratisResult<Vec<DevUuid>> {
         match self.cache_tier {
             Some(_) => unreachable!("self.cache.is_none()"),
@@ -226,7 +368,7 @@ impl Backstore {
                 // that the MDA region is set to the correct size.
                 let bdm = BlockDevMgr::initialize(pool_uuid, paths, MDADataSize::default(), None)?;
 
-                let cache_tier = CacheTier::new(bdm)?;
+                let cache_tier = CacheTier::new(bdm, config)?;
 
                 let linear = self.linear
                     .take()
@@ -250,50 +392,364 @@ impl Backstore {
         }
     }
 
-    /// Add cachedevs to the backstore.
+    /// Add cachedevs to the backstore. Return the UUIDs of the new
+    /// blockdevs and, if the cache must be rebuilt, the device number of the
+    /// origin sub-device.
+    ///
+    /// If only the cache sub-device must be extended, it is extended in
+    /// place by reloading its table. If the table can not be reloaded, the
+    /// previous table is restored, the new blockdevs are released, and an
+    /// error is returned.
     ///
-    /// If the addition of the cache devs would result in a cache with a
-    /// cache sub-device size greater than 32 TiB return an error.
-    /// FIXME: This restriction on the size of the cache sub-device is
-    /// expected to be removed in subsequent versions.
+    /// dm-cache can not make use of additional space in a meta sub-device
+    /// that it has already formatted. If the meta sub-device must be
+    /// extended to hold the metadata of the enlarged cache, the cache is
+    /// flushed instead and the device number of the origin sub-device is
+    /// returned. It is then the responsibility of the caller to switch the
+    /// devices stacked on the cap device to the origin sub-device and to
+    /// invoke rebuild_cache(). If the cache can not be flushed, the new
+    /// blockdevs are released and an error is returned.
+    ///
+    /// If the addition of the cache devs would result in a cache whose
+    /// metadata does not fit in the largest meta sub-device that dm-cache
+    /// supports return an error.
     ///
     /// Precondition: Must be invoked only after some space has been allocated
     /// from the backstore. This ensures that there is certainly a cap device.
-    // Precondition: self.linear.is_none() && self.cache.is_some()
-    // Precondition: self.cache_key_desc has the desired key description
-    // Precondition: self.cache.is_some() && self.linear.is_none()
+    /// Precondition: any devices stacked on the cap device are suspended.
+    // Precondition: self.cache.is_some()
     pub fn add_cachedevs(
         &mut self,
         pool_uuid: PoolUuid,
         paths: &[&Path],
-    ) -> StratisResult<Vec<DevUuid>> {
-        match self.cache_tier {
-            Some(ref mut cache_tier) => {
-                let cache_device = self
-                    .cache
-                    .as_mut()
-                    .expect("cache_tier.is_some() <=> self.cache.is_some()");
-                let (uuids, (cache_change, meta_change)) = cache_tier.add(pool_uuid, paths)?;
+    ) -> StratisResult<(Vec<DevUuid>, Option<Device>)> {
+        let cache_tier = self
+            .cache_tier
+            .as_mut()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+        let old_meta_segments = cache_tier.meta_segments.clone();
+        let old_cache_segments = cache_tier.cache_segments.clone();
+        let (uuids, (cache_change, meta_change)) = cache_tier.add(pool_uuid, paths)?;
+
+        let result = if meta_change {
+            self.flush_cache().map(Some)
+        } else if cache_change {
+            let cache_tier = self
+                .cache_tier
+                .as_ref()
+                .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+            let cache = self
+                .cache
+                .as_mut()
+                .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+            reload_cache_sub_device(
+                cache,
+                &cache_tier.config,
+                map_to_dm(&cache_tier.cache_segments),
+            )
+            .map_err(|err| {
+                if let Err(restore_err) = reload_cache_sub_device(
+                    cache,
+                    &cache_tier.config,
+                    map_to_dm(&old_cache_segments),
+                ) {
+                    error!(
+                        "Failed to restore the previous table of the cache sub-device of pool with UUID {}: {}",
+                        pool_uuid.to_simple_ref(),
+                        restore_err
+                    );
+                }
+                err
+            })
+            .map(|_| None)
+        } else {
+            Ok(None)
+        };
 
-                if cache_change {
-                    let table = map_to_dm(&cache_tier.cache_segments);
-                    cache_device.set_cache_table(get_dm(), table)?;
-                    cache_device.resume(get_dm())?;
+        match result {
+            Ok(origin) => Ok((uuids, origin)),
+            Err(err) => {
+                let cache_tier = self
+                    .cache_tier
+                    .as_mut()
+                    .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+                cache_tier.meta_segments = old_meta_segments;
+                cache_tier.cache_segments = old_cache_segments;
+                if let Err(remove_err) = cache_tier.block_mgr.remove_blockdevs(&uuids) {
+                    warn!(
+                        "Failed to release cachedevs that could not be added to pool with UUID {}: {}",
+                        pool_uuid.to_simple_ref(),
+                        remove_err
+                    );
                 }
+                Err(err)
+            }
+        }
+    }
 
-                // NOTE: currently CacheTier::add() does not ever update the
-                // meta segments. That means that this code is dead. But,
-                // when CacheTier::add() is fixed, this code will become live.
-                if meta_change {
-                    let table = map_to_dm(&cache_tier.meta_segments);
-                    cache_device.set_meta_table(get_dm(), table)?;
-                    cache_device.resume(get_dm())?;
+    /// Make a new, empty, cache from the cache tier, formatting the cache's
+    /// metadata anew, so that the cache makes use of the whole meta
+    /// sub-device. Return an empty vector.
+    ///
+    /// If the cache can not be made, the cache tier is removed and the
+    /// origin sub-device remains the cap device. The blockdevs of the cache
+    /// tier are returned. Their Stratis metadata has not been erased; it is
+    /// the responsibility of the caller to wipe them once the pool metadata
+    /// no longer refers to them.
+    ///
+    /// Precondition: add_cachedevs() has returned the origin sub-device and
+    /// the devices stacked on the cap device have been switched to it, so
+    /// that nothing refers to the cache DM device any longer.
+    ///
+    /// WARNING: metadata changing event
+    // Precondition: self.cache.is_some()
+    pub fn rebuild_cache(&mut self, pool_uuid: PoolUuid) -> StratisResult<Vec<StratBlockDev>> {
+        let origin = self.teardown_cache(pool_uuid)?;
+        let mut cache_tier = self
+            .cache_tier
+            .take()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+        match make_cache(pool_uuid, &cache_tier, origin, true) {
+            Ok(cache) => {
+                self.cache = Some(cache);
+                self.cache_tier = Some(cache_tier);
+                Ok(Vec::new())
+            }
+            Err(err) => {
+                error!(
+                    "Failed to rebuild the cache of pool with UUID {}; all its cachedevs are removed: {}",
+                    pool_uuid.to_simple_ref(),
+                    err
+                );
+                self.remove_cache_tier(pool_uuid, &mut cache_tier)
+            }
+        }
+    }
+
+    /// Switch the cache to the specified mode by reloading the table of the
+    /// cache device. Return true if the mode was changed, false if the cache
+    /// was already in the specified mode.
+    ///
+    /// Return an error if there is no cache. The kernel refuses to switch a
+    /// cache that contains dirty blocks to passthrough mode.
+    ///
+    /// WARNING: metadata changing event
+    pub fn set_cache_mode(&mut self, mode: CacheMode) -> StratisResult<bool> {
+        let cache_tier = match self.cache_tier {
+            Some(ref mut cache_tier) => cache_tier,
+            None => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    "The pool has no cache whose mode could be set".to_string(),
+                ))
+            }
+        };
+        if cache_tier.config.mode == mode {
+            return Ok(false);
+        }
+
+        let config = CacheConfig {
+            mode,
+            ..cache_tier.config.clone()
+        };
+        let cache = self
+            .cache
+            .as_mut()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+        cache.suspend(get_dm(), true)?;
+        let load_result = load_configured_table(cache, &config);
+        cache.resume(get_dm())?;
+        load_result?;
+
+        cache_tier.config = config;
+        Ok(true)
+    }
+
+    /// Flush the cache, so that the origin sub-device holds all the data in
+    /// the cap device. Switch the cache to the cleaner policy, which takes
+    /// no policy arguments, and wait until the cache status reports no dirty
+    /// blocks. Return the device number of the origin sub-device.
+    ///
+    /// If the cache is not clean within FLUSH_TIMEOUT, or the cache fails,
+    /// the cache is switched back to its configured policy and an error is
+    /// returned. The caller must then resume the devices stacked on the cap
+    /// device without switching them to the origin sub-device.
+    ///
+    /// Precondition: any devices stacked on the cap device are suspended,
+    /// so that no new dirty blocks can appear.
+    // Precondition: self.cache.is_some()
+    pub fn flush_cache(&mut self) -> StratisResult<Device> {
+        let cache = self
+            .cache
+            .as_mut()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+        let config = &self
+            .cache_tier
+            .as_ref()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()")
+            .config;
+
+        let mut table = cache.table().clone();
+        table.table.params.policy = CLEANER_POLICY.to_string();
+        table.table.params.policy_args.clear();
+        cache.suspend(get_dm(), false)?;
+        let load_result = cache.table_load(get_dm(), &table);
+        cache.resume(get_dm())?;
+        load_result?;
+
+        if let Err(err) = wait_until_clean(cache) {
+            cache.suspend(get_dm(), false)?;
+            let load_result = load_configured_table(cache, config);
+            cache.resume(get_dm())?;
+            if let Err(load_err) = load_result {
+                warn!(
+                    "Failed to restore the configured policy of the cache device after an unsuccessful flush: {}",
+                    load_err
+                );
+            }
+            return Err(err);
+        }
+
+        Ok(table.table.params.origin)
+    }
+
+    /// Remove the specified cachedevs from the backstore. The cache DM
+    /// device is torn down. If any cachedevs remain, a new, empty, cache is
+    /// made from them. Otherwise, the cache tier is destroyed and the origin
+    /// sub-device becomes the cap device.
+    ///
+    /// Returns the removed blockdevs. Their Stratis metadata has not been
+    /// erased; it is the responsibility of the caller to wipe them once the
+    /// pool metadata no longer refers to them.
+    ///
+    /// If the new cache can not be made, the remaining cachedevs are removed
+    /// as well, along with the cache tier, and the origin sub-device remains
+    /// the cap device. The removal is nonetheless reported as successful,
+    /// since the pool metadata must be updated to reflect it.
+    ///
+    /// Precondition: flush_cache() has succeeded and the devices stacked on
+    /// the cap device have been switched to the origin sub-device, so that
+    /// nothing refers to the cache DM device any longer.
+    /// Precondition: uuids contains no duplicates.
+    ///
+    /// WARNING: metadata changing event
+    // Precondition: self.cache.is_some()
+    pub fn remove_cachedevs(
+        &mut self,
+        pool_uuid: PoolUuid,
+        uuids: &[DevUuid],
+    ) -> StratisResult<Vec<StratBlockDev>> {
+        let cache_tier = self
+            .cache_tier
+            .as_ref()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+        if let Some(uuid) = uuids
+            .iter()
+            .find(|&&uuid| cache_tier.get_blockdev_by_uuid(uuid).is_none())
+        {
+            return Err(StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!(
+                    "Blockdev corresponding to UUID: {} not found in the cache tier.",
+                    uuid.to_simple_ref()
+                ),
+            ));
+        }
+
+        let origin = self.teardown_cache(pool_uuid)?;
+
+        let mut cache_tier = self
+            .cache_tier
+            .take()
+            .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+        if cache_tier.blockdevs().len() == uuids.len() {
+            self.linear = Some(origin);
+            return cache_tier.block_mgr.detach_blockdevs(uuids);
+        }
+
+        // The cache tier and the cache DM device exist together or not at
+        // all, so if no cache can be made from the remaining cachedevs, they
+        // are removed as well.
+        let (mut removed, err) = match cache_tier.remove(uuids) {
+            Ok(removed) => match make_cache(pool_uuid, &cache_tier, origin, true) {
+                Ok(cache) => {
+                    self.cache = Some(cache);
+                    self.cache_tier = Some(cache_tier);
+                    return Ok(removed);
                 }
+                Err(err) => (removed, err),
+            },
+            Err(err) => (Vec::new(), err),
+        };
+        error!(
+            "Failed to make a cache from the remaining cachedevs of pool with UUID {}; all its cachedevs are removed: {}",
+            pool_uuid.to_simple_ref(),
+            err
+        );
+        removed.extend(self.remove_cache_tier(pool_uuid, &mut cache_tier)?);
 
-                Ok(uuids)
+        Ok(removed)
+    }
+
+    /// Make the origin sub-device the cap device in the place of the cache
+    /// DM device, which has been torn down, and detach all the blockdevs of
+    /// cache_tier, which has been taken from self. Return the detached
+    /// blockdevs.
+    fn remove_cache_tier(
+        &mut self,
+        pool_uuid: PoolUuid,
+        cache_tier: &mut CacheTier,
+    ) -> StratisResult<Vec<StratBlockDev>> {
+        self.linear = Some(self.origin(pool_uuid)?);
+        let uuids = cache_tier
+            .blockdevs()
+            .iter()
+            .map(|&(uuid, _)| uuid)
+            .collect::<Vec<_>>();
+        cache_tier.block_mgr.detach_blockdevs(&uuids)
+    }
+
+    /// Obtain a handle on the origin sub-device, which already exists.
+    fn origin(&self, pool_uuid: PoolUuid) -> StratisResult<LinearDev> {
+        let (dm_name, dm_uuid) = format_backstore_ids(pool_uuid, CacheRole::OriginSub);
+        Ok(LinearDev::setup(
+            get_dm(),
+            &dm_name,
+            Some(&dm_uuid),
+            map_to_dm(&self.data_tier.segments),
+        )?)
+    }
+
+    /// Tear down the cache DM device and its meta and cache sub-devices.
+    /// Return the origin sub-device, which is retained.
+    // Precondition: self.cache.is_some()
+    fn teardown_cache(&mut self, pool_uuid: PoolUuid) -> StratisResult<LinearDev> {
+        // The origin sub-device is retained, so obtain a handle on it before
+        // the cache device is torn down.
+        let origin = self.origin(pool_uuid)?;
+
+        {
+            let cache = self
+                .cache
+                .as_ref()
+                .expect("self.cache.is_some() <=> self.cache_tier.is_some()");
+            get_dm().device_remove(&DevId::Name(cache.name()), &DmOptions::new())?;
+        }
+        self.cache = None;
+
+        for role in &[CacheRole::MetaSub, CacheRole::CacheSub] {
+            let (dm_name, _) = format_backstore_ids(pool_uuid, *role);
+            if let Err(err) = get_dm().device_remove(&DevId::Name(&dm_name), &DmOptions::new()) {
+                warn!(
+                    "Failed to remove cache sub-device {} of pool with UUID {}: {}",
+                    &*dm_name,
+                    pool_uuid.to_simple_ref(),
+                    err
+                );
             }
-            None => unreachable!("self.cache.is_some()"),
         }
+
+        Ok(origin)
     }
 
     /// Add datadevs to the backstore. The data tier always exists if the
@@ -306,6 +762,124 @@ impl Backstore {
         self.data_tier.add(pool_uuid, paths)
     }
 
+    /// Remove the specified datadevs from the backstore. All space allocated
+    /// from them to the cap device is first moved to free space on the
+    /// remaining datadevs and the cap device is reloaded so that it maps
+    /// the new locations. The cap device is suspended while the data is
+    /// being moved.
+    ///
+    /// Returns the removed blockdevs, with what is required to undo their
+    /// removal. Their Stratis metadata has not been erased; it is the
+    /// responsibility of the caller to wipe them once the pool metadata no
+    /// longer refers to them.
+    ///
+    /// Precondition: any devices stacked on the cap device are suspended.
+    ///
+    /// WARNING: metadata changing event
+    pub fn remove_datadevs(&mut self, uuids: &[DevUuid]) -> StratisResult<RemovedBlockdevs> {
+        self.reload_cap_device(|data_tier, reload| data_tier.remove(uuids, reload))
+    }
+
+    /// Undo remove_datadevs(), e.g., because the pool metadata recording
+    /// the removal could not be written. The cap device is reloaded so that
+    /// it maps the original locations of the data again and the removed
+    /// blockdevs are returned to the data tier.
+    ///
+    /// Precondition: any devices stacked on the cap device have been
+    /// suspended since remove_datadevs() was invoked.
+    pub fn undo_remove_datadevs(&mut self, removed: RemovedBlockdevs) -> StratisResult<()> {
+        self.reload_cap_device(|data_tier, reload| data_tier.undo_remove(removed, reload))
+    }
+
+    /// Whether the space needed to encrypt the unencrypted datadevs in place
+    /// is reserved.
+    pub fn reserves_encryption_space(&self) -> bool {
+        self.data_tier.reserves_encryption_space()
+    }
+
+    /// Reserve or stop reserving the space at the end of each unencrypted
+    /// datadev that is needed to encrypt it in place.
+    ///
+    /// WARNING: metadata changing event
+    pub fn set_reserve_encryption_space(&mut self, reserve: bool) -> StratisResult<()> {
+        self.data_tier.set_reserve_encryption_space(reserve)
+    }
+
+    /// Whether data must be moved out of the space at the end of some
+    /// datadevs before that space can be reserved for in place encryption.
+    pub fn encryption_space_pending(&self) -> bool {
+        self.data_tier.encryption_space_pending()
+    }
+
+    /// Move the data out of the space at the end of the datadevs that
+    /// should be reserved for in place encryption onto free space on other
+    /// datadevs, and reload the cap device so that it maps the new
+    /// locations. Returns None if no data needs to be moved.
+    ///
+    /// The space that the data was moved out of is reserved by
+    /// finish_relocation(), which must be invoked once the pool metadata
+    /// records the new locations.
+    ///
+    /// Precondition: any devices stacked on the cap device are suspended.
+    ///
+    /// WARNING: metadata changing event
+    pub fn relocate_encryption_space(&mut self) -> StratisResult<Option<RelocatedSegments>> {
+        self.reload_cap_device(|data_tier, reload| data_tier.relocate_encryption_space(reload))
+    }
+
+    /// Complete relocate_encryption_space().
+    pub fn finish_relocation(&mut self, relocated: RelocatedSegments) -> StratisResult<()> {
+        self.data_tier.finish_relocation(relocated)
+    }
+
+    /// Undo relocate_encryption_space(), e.g., because the pool metadata
+    /// recording the new locations could not be written.
+    ///
+    /// Precondition: any devices stacked on the cap device have been
+    /// suspended since relocate_encryption_space() was invoked.
+    pub fn undo_relocation(&mut self, relocated: RelocatedSegments) -> StratisResult<()> {
+        self.reload_cap_device(|data_tier, reload| data_tier.undo_relocation(relocated, reload))
+    }
+
+    /// Suspend the cap device, if any, and invoke f with the data tier and
+    /// a function which loads a table mapping the given data tier segments
+    /// into the cap device. Resume the cap device afterwards.
+    fn reload_cap_device<T, F>(&mut self, f: F) -> StratisResult<T>
+    where
+        F: FnOnce(
+            &mut DataTier,
+            &mut dyn FnMut(&[BlkDevSegment]) -> StratisResult<()>,
+        ) -> StratisResult<T>,
+    {
+        match (self.cache.as_mut(), self.linear.as_mut()) {
+            (None, None) => f(&mut self.data_tier, &mut |_| Ok(())),
+            (Some(cache), None) => {
+                let config = &self
+                    .cache_tier
+                    .as_ref()
+                    .expect("self.cache.is_some() <=> self.cache_tier.is_some()")
+                    .config;
+                cache.suspend(get_dm(), true)?;
+                let result = f(&mut self.data_tier, &mut |segments| {
+                    cache.set_origin_table(get_dm(), map_to_dm(segments))?;
+                    load_configured_table(cache, config)
+                });
+                cache.resume(get_dm())?;
+                result
+            }
+            (None, Some(linear)) => {
+                linear.suspend(get_dm(), true)?;
+                let result = f(&mut self.data_tier, &mut |segments| {
+                    linear.set_table(get_dm(), map_to_dm(segments))?;
+                    Ok(())
+                });
+                linear.resume(get_dm())?;
+                result
+            }
+            _ => panic!("NOT (self.cache().is_some() AND self.linear.is_some())"),
+        }
+    }
+
     /// Extend the cap device whether it is a cache or not. Create the DM
     /// device if it does not already exist. Return an error if DM
     /// operations fail. Use all segments currently allocated in the data tier.
@@ -313,8 +887,14 @@ impl Backstore {
         let create = match (self.cache.as_mut(), self.linear.as_mut()) {
             (None, None) => true,
             (Some(cache), None) => {
+                let config = &self
+                    .cache_tier
+                    .as_ref()
+                    .expect("self.cache.is_some() <=> self.cache_tier.is_some()")
+                    .config;
                 let table = map_to_dm(&self.data_tier.segments);
                 cache.set_origin_table(get_dm(), table)?;
+                load_configured_table(cache, config)?;
                 cache.resume(get_dm())?;
                 false
             }
@@ -537,24 +1117,20 @@ impl Backstore {
     /// Destroy the entire store.
     pub fn destroy(&mut self) -> StratisResult<()> {
         match self.cache {
-            Some(ref mut cache) => {
-                cache.teardown(get_dm())?;
-                self.cache_tier
-                    .as_mut()
-                    .expect("if dm_device is cache, cache tier exists")
-                    .destroy()?;
-            }
+            Some(ref mut cache) => cache.teardown(get_dm())?,
             None => {
                 if let Some(ref mut linear) = self.linear {
                     linear.teardown(get_dm())?;
                 }
             }
         };
+        if let Some(ref mut cache_tier) = self.cache_tier {
+            cache_tier.destroy()?;
+        }
         self.data_tier.destroy()
     }
 
     /// Teardown the DM devices in the backstore.
-    #[cfg(test)]
     pub fn teardown(&mut self) -> StratisResult<()> {
         match self.cache {
             Some(ref mut cache) => cache.teardown(get_dm()),
@@ -656,12 +1232,68 @@ impl Backstore {
         self.cache_tier.is_some()
     }
 
-    pub fn bind_clevis(&mut self, pin: String, clevis_info: Value) -> StratisResult<bool> {
+    /// Obtain statistics on the operation of the cache from the kernel.
+    /// Return None if there is no cache.
+    pub fn cache_statistics(&self) -> StratisResult<Option<CacheStatistics>> {
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return Ok(None),
+        };
+
+        match cache.status(get_dm())? {
+            CacheDevStatus::Working(status) => {
+                let performance = &status.performance;
+                Ok(Some(CacheStatistics {
+                    read_hits: performance.read_hits,
+                    read_misses: performance.read_misses,
+                    write_hits: performance.write_hits,
+                    write_misses: performance.write_misses,
+                    dirty: performance.dirty,
+                    promotions: performance.promotions,
+                    demotions: performance.demotions,
+                    used_meta: status.usage.used_meta,
+                    total_meta: status.usage.total_meta,
+                }))
+            }
+            CacheDevStatus::Error => Err(StratisError::Engine(
+                ErrorEnum::Error,
+                "Could not obtain the status of the cache device".to_string(),
+            )),
+            CacheDevStatus::Fail => Err(StratisError::Engine(
+                ErrorEnum::Error,
+                "The cache device has failed".to_string(),
+            )),
+        }
+    }
+
+    pub fn bind_clevis(&mut self, pin: String, clevis_info: Value) -> StratisResult<Option<u32>> {
         self.data_tier.block_mgr.bind_clevis(pin, clevis_info)
     }
 
-    pub fn unbind_clevis(&mut self) -> StratisResult<bool> {
-        self.data_tier.block_mgr.unbind_clevis()
+    pub fn unbind_clevis(&mut self, binding_id: u32) -> StratisResult<bool> {
+        self.data_tier.block_mgr.unbind_clevis(binding_id)
+    }
+
+    pub fn rebind_clevis(&mut self, binding_id: u32) -> StratisResult<bool> {
+        self.data_tier.block_mgr.rebind_clevis(binding_id)
+    }
+
+    pub fn rebind_keyring(&mut self, new_key_desc: &KeyDescription) -> StratisResult<bool> {
+        self.data_tier.block_mgr.rebind_keyring(new_key_desc)
+    }
+
+    pub fn set_auto_unlock(&mut self, auto_unlock: bool) -> StratisResult<bool> {
+        self.data_tier.block_mgr.set_auto_unlock(auto_unlock)
+    }
+
+    pub fn start_reencryption(&mut self) -> StratisResult<bool> {
+        self.data_tier.block_mgr.start_reencryption()
+    }
+
+    /// Reencrypt the data devices for approximately the given duration at
+    /// most. Returns true if some reencryption remains to be done.
+    pub fn reencrypt_step(&mut self, duration: Duration) -> bool {
+        self.data_tier.block_mgr.reencrypt_step(duration)
     }
 }
 
@@ -698,19 +1330,22 @@ impl Recordable<BackstoreSave> for Backstore {
 
 #[cfg(test)]
 mod tests {
-    use std::fs::OpenOptions;
+    use std::{collections::HashSet, fs::OpenOptions};
 
     use devicemapper::{CacheDevStatus, DataBlocks, IEC};
 
-    use crate::engine::strat_engine::{
-        cmd,
-        metadata::device_identifiers,
-        tests::{loopbacked, real},
+    use crate::engine::{
+        strat_engine::{
+            cmd,
+            metadata::device_identifiers,
+            tests::{loopbacked, real},
+        },
+        types::DEFAULT_CACHE_BLOCK_SIZE,
     };
 
     use super::*;
 
-    const INITIAL_BACKSTORE_ALLOCATION: Sectors = CACHE_BLOCK_SIZE;
+    const INITIAL_BACKSTORE_ALLOCATION: Sectors = DEFAULT_CACHE_BLOCK_SIZE;
 
     /// Assert some invariants of the backstore
     /// * backstore.cache_tier.is_some() <=> backstore.cache.is_some() &&
@@ -741,11 +1376,21 @@ mod tests {
     /// When cachedevs are added, cache tier, etc. must exist.
     /// Nonetheless, because nothing is written or read, cache usage ought
     /// to be 0. Adding some more cachedevs exercises different code path
-    /// from adding initial cachedevs.
+    /// from adding initial cachedevs. The meta sub-device must be extended
+    /// to hold the metadata of the enlarged cache, so the cache is rebuilt.
     fn test_add_cache_devs(paths: &[&Path]) {
         assert!(paths.len() > 3);
 
-        let meta_size = Sectors(IEC::Mi);
+        fn meta_size(backstore: &Backstore) -> Sectors {
+            backstore
+                .cache_tier
+                .as_ref()
+                .unwrap()
+                .meta_segments
+                .iter()
+                .map(|x| x.segment.length)
+                .sum()
+        }
 
         let (initcachepaths, paths) = paths.split_at(1);
         let (cachedevpaths, paths) = paths.split_at(1);
@@ -762,35 +1407,47 @@ mod tests {
             .alloc(pool_uuid, &[INITIAL_BACKSTORE_ALLOCATION])
             .unwrap();
 
-        let cache_uuids = backstore.init_cache(pool_uuid, initcachepaths).unwrap();
+        let cache_uuids = backstore
+            .init_cache(pool_uuid, initcachepaths, CacheConfig::default())
+            .unwrap();
 
         invariant(&backstore);
 
         assert_eq!(cache_uuids.len(), initcachepaths.len());
         assert_matches!(backstore.linear, None);
 
+        let initial_meta_size = meta_size(&backstore);
+
         let cache_status = backstore
             .cache
             .as_ref()
             .map(|c| c.status(get_dm()).unwrap())
             .unwrap();
 
-        match cache_status {
+        let initial_total_cache = match cache_status {
             CacheDevStatus::Working(status) => {
                 let usage = &status.usage;
                 assert_eq!(usage.used_cache, DataBlocks(0));
-                assert_eq!(usage.total_meta, meta_size.metablocks());
+                assert_eq!(usage.total_meta, meta_size(&backstore).metablocks());
                 assert!(usage.total_cache > DataBlocks(0));
+                usage.total_cache
             }
             CacheDevStatus::Error => panic!("cache status could not be obtained"),
             CacheDevStatus::Fail => panic!("cache is in a failed state"),
-        }
+        };
+
+        let statistics = backstore.cache_statistics().unwrap().unwrap();
+        assert_eq!(statistics.dirty, 0);
+        assert_eq!(statistics.total_meta, meta_size(&backstore).metablocks());
 
         let data_uuids = backstore.add_datadevs(pool_uuid, datadevpaths).unwrap();
         invariant(&backstore);
         assert_eq!(data_uuids.len(), datadevpaths.len());
 
-        let cache_uuids = backstore.add_cachedevs(pool_uuid, cachedevpaths).unwrap();
+        let (cache_uuids, origin) = backstore.add_cachedevs(pool_uuid, cachedevpaths).unwrap();
+        assert!(origin.is_some());
+        assert!(meta_size(&backstore) > initial_meta_size);
+        assert!(backstore.rebuild_cache(pool_uuid).unwrap().is_empty());
         invariant(&backstore);
         assert_eq!(cache_uuids.len(), cachedevpaths.len());
 
@@ -804,8 +1461,8 @@ mod tests {
             CacheDevStatus::Working(status) => {
                 let usage = &status.usage;
                 assert_eq!(usage.used_cache, DataBlocks(0));
-                assert_eq!(usage.total_meta, meta_size.metablocks());
-                assert!(usage.total_cache > DataBlocks(0));
+                assert_eq!(usage.total_meta, meta_size(&backstore).metablocks());
+                assert!(usage.total_cache > initial_total_cache);
             }
             CacheDevStatus::Error => panic!("cache status could not be obtained"),
             CacheDevStatus::Fail => panic!("cache is in a failed state"),
@@ -898,6 +1555,83 @@ mod tests {
         loopbacked::test_with_spec(&loopbacked::DeviceLimits::Range(1, 3, None), test_request);
     }
 
+    /// Verify that undoing the removal of a datadev restores the blockdev,
+    /// the arrangement of the data tier and the table of the cap device, and
+    /// frees the space to which the data on the removed datadev was copied.
+    fn test_undo_remove_datadevs(paths: &[&Path]) {
+        assert!(paths.len() > 1);
+
+        let (paths1, paths2) = paths.split_at(1);
+
+        let pool_uuid = PoolUuid::new_v4();
+        let mut backstore =
+            Backstore::initialize(pool_uuid, paths1, MDADataSize::default(), None).unwrap();
+        backstore
+            .alloc(pool_uuid, &[INITIAL_BACKSTORE_ALLOCATION])
+            .unwrap()
+            .unwrap();
+        backstore.add_datadevs(pool_uuid, paths2).unwrap();
+        invariant(&backstore);
+
+        let available = |backstore: &Backstore| {
+            backstore
+                .datadevs()
+                .iter()
+                .map(|(_, bd)| bd.available())
+                .sum::<Sectors>()
+        };
+
+        let removed_uuid = backstore.data_tier.segments[0].uuid;
+        let record: BackstoreSave = backstore.record();
+        let blockdevs = |record: &BackstoreSave| {
+            record
+                .data_tier
+                .blockdev
+                .devs
+                .iter()
+                .map(|dev| dev.uuid)
+                .collect::<HashSet<_>>()
+        };
+        let table = backstore.linear.as_ref().unwrap().table().clone();
+        let available_before = available(&backstore);
+
+        let removed = backstore.remove_datadevs(&[removed_uuid]).unwrap();
+        invariant(&backstore);
+        assert!(backstore.get_blockdev_by_uuid(removed_uuid).is_none());
+        assert_ne!(backstore.linear.as_ref().unwrap().table(), &table);
+
+        backstore.undo_remove_datadevs(removed).unwrap();
+        invariant(&backstore);
+        assert!(backstore.get_blockdev_by_uuid(removed_uuid).is_some());
+        let new_record: BackstoreSave = backstore.record();
+        assert_eq!(
+            new_record.data_tier.blockdev.allocs,
+            record.data_tier.blockdev.allocs
+        );
+        assert_eq!(new_record.cap, record.cap);
+        assert_eq!(blockdevs(&new_record), blockdevs(&record));
+        assert_eq!(backstore.linear.as_ref().unwrap().table(), &table);
+        assert_eq!(available(&backstore), available_before);
+
+        backstore.destroy().unwrap();
+    }
+
+    #[test]
+    fn loop_test_undo_remove_datadevs() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_undo_remove_datadevs,
+        );
+    }
+
+    #[test]
+    fn real_test_undo_remove_datadevs() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(2, None, None),
+            test_undo_remove_datadevs,
+        );
+    }
+
     /// Create a backstore.
     /// Initialize a cache and verify that there is a new device representing
     /// the cache.
@@ -930,7 +1664,9 @@ mod tests {
 
         let old_device = backstore.device();
 
-        backstore.init_cache(pool_uuid, paths2).unwrap();
+        backstore
+            .init_cache(pool_uuid, paths2, CacheConfig::default())
+            .unwrap();
 
         for path in paths2 {
             assert_eq!(
@@ -963,4 +1699,119 @@ mod tests {
     fn travis_test_setup() {
         loopbacked::test_with_spec(&loopbacked::DeviceLimits::Range(2, 3, None), test_setup);
     }
+
+    /// Initialize a cache with a configuration other than the default one
+    /// and verify that the cache device's table reflects it. Change the
+    /// mode of the cache and verify the table again, both before and after
+    /// the cache device has been taken over anew, as it would be after
+    /// stratisd is restarted.
+    fn test_cache_config(paths: &[&Path]) {
+        assert!(paths.len() > 1);
+
+        let (paths1, paths2) = paths.split_at(paths.len() / 2);
+
+        let pool_uuid = PoolUuid::new_v4();
+
+        let mut backstore =
+            Backstore::initialize(pool_uuid, paths1, MDADataSize::default(), None).unwrap();
+
+        // Allocate space from the backstore so that the cap device is made.
+        backstore
+            .alloc(pool_uuid, &[INITIAL_BACKSTORE_ALLOCATION])
+            .unwrap();
+
+        let mut config = CacheConfig {
+            mode: CacheMode::Writeback,
+            policy: "smq".to_string(),
+            block_size: Sectors(128),
+            ..CacheConfig::default()
+        };
+        config
+            .policy_args
+            .insert("migration_threshold".to_string(), "4096".to_string());
+        backstore
+            .init_cache(pool_uuid, paths2, config.clone())
+            .unwrap();
+        invariant(&backstore);
+
+        let kernel_params = |backstore: &Backstore| {
+            let cache = backstore.cache.as_ref().unwrap();
+            CacheDev::read_kernel_table(get_dm(), &DevId::Name(cache.name()))
+                .unwrap()
+                .table
+                .params
+        };
+
+        let params = kernel_params(&backstore);
+        assert!(params.feature_args.contains("writeback"));
+        assert_eq!(params.policy, config.policy);
+        assert_eq!(params.policy_args, config.policy_args);
+        assert_eq!(params.cache_block_size, config.block_size);
+
+        assert!(backstore.set_cache_mode(CacheMode::Writethrough).unwrap());
+        assert!(!backstore.set_cache_mode(CacheMode::Writethrough).unwrap());
+        config.mode = CacheMode::Writethrough;
+        assert_eq!(backstore.cache_tier.as_ref().unwrap().config, config);
+
+        let params = kernel_params(&backstore);
+        assert!(params.feature_args.contains("writethrough"));
+        assert!(!params.feature_args.contains("writeback"));
+        assert_eq!(params.policy_args, config.policy_args);
+
+        assert!(backstore.set_cache_mode(CacheMode::Writeback).unwrap());
+
+        let (dm_name, dm_uuid) = format_backstore_ids(pool_uuid, CacheRole::OriginSub);
+        let origin = LinearDev::setup(
+            get_dm(),
+            &dm_name,
+            Some(&dm_uuid),
+            map_to_dm(&backstore.data_tier.segments),
+        )
+        .unwrap();
+        let cache = make_cache(
+            pool_uuid,
+            backstore.cache_tier.as_ref().unwrap(),
+            origin,
+            false,
+        )
+        .unwrap();
+        backstore.cache = Some(cache);
+        invariant(&backstore);
+
+        let params = kernel_params(&backstore);
+        assert!(params.feature_args.contains("writeback"));
+        assert_eq!(params.policy_args, config.policy_args);
+
+        let cache_tier_save = backstore.record().cache_tier.unwrap();
+        assert_eq!(cache_tier_save.mode, CacheMode::Writeback);
+        assert_eq!(cache_tier_save.policy, config.policy);
+        assert_eq!(cache_tier_save.policy_args, config.policy_args);
+        assert_eq!(cache_tier_save.block_size, config.block_size);
+
+        backstore.destroy().unwrap();
+    }
+
+    #[test]
+    fn loop_test_cache_config() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_cache_config,
+        );
+    }
+
+    #[test]
+    fn real_test_cache_config() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(2, None, None),
+            test_cache_config,
+        );
+    }
+
+    #[test]
+    fn travis_test_cache_config() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_cache_config,
+        );
+    }
 }
@@ -8,6 +8,7 @@ use std::{
     fs::OpenOptions,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use chrono::{DateTime, TimeZone, Utc};
@@ -24,10 +25,15 @@ use crate::{
                 range_alloc::{PerDevSegments, RangeAllocator},
             },
             keys::MemoryPrivateFilesystem,
-            metadata::{disown_device, BDAExtendedSize, BlockdevSize, MDADataSize, BDA},
+            metadata::{
+                disown_device, BDAExtendedSize, BlockdevSize, MDADataSize, StaticHeader, BDA,
+            },
             serde_structs::{BaseBlockDevSave, Recordable},
         },
-        types::{BlockDevPath, DevUuid, EncryptionInfo, MaybeDbusPath, PoolUuid},
+        types::{
+            BlockDevPath, DevUuid, EncryptionInfo, KeyDescription, MaybeDbusPath, PoolUuid,
+            ReencryptionProgress,
+        },
     },
     stratis::{StratisError, StratisResult},
 };
@@ -38,6 +44,7 @@ pub struct StratBlockDev {
     devnode: Arc<BlockDevPath>,
     bda: BDA,
     used: RangeAllocator,
+    reserved: Sectors,
     user_info: Option<String>,
     hardware_info: Option<String>,
     dbus_path: MaybeDbusPath,
@@ -88,6 +95,7 @@ impl StratBlockDev {
             },
             bda,
             used: allocator,
+            reserved: Sectors(0),
             user_info,
             hardware_info,
             dbus_path: MaybeDbusPath(None),
@@ -156,6 +164,11 @@ impl StratBlockDev {
         self.bda.dev_uuid()
     }
 
+    /// The device's static header.
+    pub fn header(&self) -> &StaticHeader {
+        self.bda.header()
+    }
+
     /// Find some sector ranges that could be allocated. If more
     /// sectors are needed than are available, return partial results.
     /// If all available sectors are desired, don't use this function.
@@ -165,6 +178,40 @@ impl StratBlockDev {
         self.used.request(size)
     }
 
+    /// Return previously allocated sector ranges to this device.
+    /// Fails without effect if any range is not entirely allocated.
+    pub fn release_space(&mut self, ranges: &[(Sectors, Sectors)]) -> StratisResult<()> {
+        self.used.release(ranges)
+    }
+
+    /// Reserve the last size sectors of this device, so that they are never
+    /// allocated. Returns false and reserves nothing if any of those sectors
+    /// have already been allocated.
+    pub fn reserve_end(&mut self, size: Sectors) -> bool {
+        let total_size = self.total_size().sectors();
+        if size > total_size {
+            return false;
+        }
+        let reserved = self.used.reserve(&(total_size - size, size)).is_ok();
+        if reserved {
+            self.reserved += size;
+        }
+        reserved
+    }
+
+    /// Return the sectors reserved by reserve_end() to the space that may be
+    /// allocated.
+    pub fn release_end(&mut self) -> StratisResult<()> {
+        if self.reserved == Sectors(0) {
+            return Ok(());
+        }
+        let total_size = self.total_size().sectors();
+        self.used
+            .release(&[(total_size - self.reserved, self.reserved)])?;
+        self.reserved = Sectors(0);
+        Ok(())
+    }
+
     // ALL SIZE METHODS (except size(), which is in BlockDev impl.)
     /// The number of Sectors on this device used by Stratis for metadata
     pub fn metadata_size(&self) -> BDAExtendedSize {
@@ -177,6 +224,11 @@ impl StratBlockDev {
         self.used.available()
     }
 
+    /// The number of Sectors on this device reserved by reserve_end().
+    pub fn reserved_size(&self) -> Sectors {
+        self.reserved
+    }
+
     /// The total size of the Stratis block device.
     pub fn total_size(&self) -> BlockdevSize {
         let size = self.used.size();
@@ -212,30 +264,91 @@ impl StratBlockDev {
     }
 
     /// Bind encrypted device using the given clevis configuration.
+    /// If a binding ID is given, the new binding is given that ID.
+    /// Returns the ID of the new binding.
     pub fn bind_clevis(
         &mut self,
         memfs: &MemoryPrivateFilesystem,
         pin: &str,
         clevis_info: &Value,
         yes: bool,
-    ) -> StratisResult<()> {
+        binding_id: Option<u32>,
+    ) -> StratisResult<u32> {
         let crypt_handle = self.crypt_handle.as_mut().ok_or_else(|| {
             StratisError::Error("This device does not appear to be encrypted".to_string())
         })?;
         let key_description = crypt_handle.encryption_info().key_description.clone();
         memfs.key_op(&key_description, |keyfile_path| {
             crypt_handle
-                .clevis_bind(keyfile_path, pin, clevis_info, yes)
+                .clevis_bind(keyfile_path, pin, clevis_info, yes, binding_id)
                 .map_err(StratisError::Crypt)
         })
     }
 
-    /// Unbind encrypted device using the given clevis configuration.
-    pub fn unbind_clevis(&mut self) -> StratisResult<()> {
+    /// Remove the clevis binding with the given ID from the encrypted device.
+    pub fn unbind_clevis(&mut self, binding_id: u32) -> StratisResult<()> {
+        let crypt_handle = self.crypt_handle.as_mut().ok_or_else(|| {
+            StratisError::Error("This device does not appear to be encrypted".to_string())
+        })?;
+        crypt_handle
+            .clevis_unbind(binding_id)
+            .map_err(StratisError::Crypt)
+    }
+
+    /// Give the clevis binding with ID new_id the ID of the binding with ID
+    /// binding_id, which it replaces. The replaced binding is kept under
+    /// another ID, which is returned.
+    pub fn replace_clevis(&mut self, binding_id: u32, new_id: u32) -> StratisResult<u32> {
         let crypt_handle = self.crypt_handle.as_mut().ok_or_else(|| {
             StratisError::Error("This device does not appear to be encrypted".to_string())
         })?;
-        crypt_handle.clevis_unbind().map_err(StratisError::Crypt)
+        crypt_handle
+            .clevis_replace(binding_id, new_id)
+            .map_err(StratisError::Crypt)
+    }
+
+    /// Change the key in the kernel keyring used to unlock the encrypted device.
+    pub fn rebind_keyring(&mut self, new_key_desc: &KeyDescription) -> StratisResult<()> {
+        let crypt_handle = self.crypt_handle.as_mut().ok_or_else(|| {
+            StratisError::Error("This device does not appear to be encrypted".to_string())
+        })?;
+        crypt_handle
+            .rebind_keyring(new_key_desc)
+            .map_err(StratisError::Crypt)
+    }
+
+    /// Set whether the encrypted device is unlocked with its Clevis bindings
+    /// as soon as it appears.
+    pub fn set_auto_unlock(&mut self, auto_unlock: bool) -> StratisResult<()> {
+        let crypt_handle = self.crypt_handle.as_mut().ok_or_else(|| {
+            StratisError::Error("This device does not appear to be encrypted".to_string())
+        })?;
+        crypt_handle
+            .set_auto_unlock(auto_unlock)
+            .map_err(StratisError::Crypt)
+    }
+
+    /// Start reencrypting the encrypted device under a new volume key, or
+    /// resume the reencryption if it was paused.
+    /// Returns false if the device is already being reencrypted.
+    pub fn start_reencryption(&mut self) -> StratisResult<bool> {
+        let crypt_handle = self.crypt_handle.as_mut().ok_or_else(|| {
+            StratisError::Error("This device does not appear to be encrypted".to_string())
+        })?;
+        crypt_handle
+            .start_reencryption()
+            .map_err(StratisError::Crypt)
+    }
+
+    /// Reencrypt the device for approximately the given duration at most,
+    /// if it is being reencrypted.
+    pub fn reencrypt_step(&mut self, duration: Duration) -> StratisResult<()> {
+        match self.crypt_handle {
+            Some(ref mut crypt_handle) => crypt_handle
+                .reencrypt_step(duration)
+                .map_err(StratisError::Crypt),
+            None => Ok(()),
+        }
     }
 }
 
@@ -300,6 +413,12 @@ impl BlockDev for StratBlockDev {
     fn is_encrypted(&self) -> bool {
         self.encryption_info().is_some()
     }
+
+    fn reencryption_progress(&self) -> Option<ReencryptionProgress> {
+        self.crypt_handle
+            .as_ref()
+            .and_then(|ch| ch.reencryption_progress())
+    }
 }
 
 impl Recordable<BaseBlockDevSave> for StratBlockDev {
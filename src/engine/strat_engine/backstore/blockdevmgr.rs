@@ -17,18 +17,18 @@ use devicemapper::{Bytes, Device, LinearDevTargetParams, LinearTargetParams, Sec
 
 use crate::{
     engine::{
+        engine::BlockDev,
         strat_engine::{
             backstore::{
                 blockdev::StratBlockDev,
-                crypt::{interpret_clevis_config, CryptActivationHandle},
+                crypt::{interpret_clevis_config, remove_tang_thumbprints, CryptActivationHandle},
                 devices::{initialize_devices, process_and_verify_devices, wipe_blockdevs},
             },
             keys::MemoryPrivateFilesystem,
             metadata::MDADataSize,
-            names::KeyDescription,
             serde_structs::{BaseBlockDevSave, BaseDevSave, Recordable},
         },
-        types::{DevUuid, EncryptionInfo, PoolUuid},
+        types::{DevUuid, EncryptionInfo, KeyDescription, PoolUuid},
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
@@ -136,20 +136,12 @@ impl BlockDevMgr {
         pool_uuid: PoolUuid,
         paths: &[&Path],
         mda_data_size: MDADataSize,
-        key_desc: Option<&KeyDescription>,
+        encryption_info: Option<&EncryptionInfo>,
     ) -> StratisResult<BlockDevMgr> {
         let devices = process_and_verify_devices(pool_uuid, &HashSet::new(), paths)?;
 
         Ok(BlockDevMgr::new(
-            initialize_devices(
-                devices,
-                pool_uuid,
-                mda_data_size,
-                key_desc.map(|k| EncryptionInfo {
-                    key_description: k.clone(),
-                    clevis_info: None,
-                }),
-            )?,
+            initialize_devices(devices, pool_uuid, mda_data_size, encryption_info.cloned())?,
             None,
         ))
     }
@@ -233,37 +225,55 @@ impl BlockDevMgr {
     /// from them for upper layers.
     ///
     /// If a specified blockdev is not found, returns an error and does nothing.
+    pub(super) fn remove_blockdevs(&mut self, uuids: &[DevUuid]) -> StratisResult<()> {
+        let mut removed = self.detach_blockdevs(uuids)?;
+        wipe_blockdevs(&mut removed)?;
+        Ok(())
+    }
+
+    /// Remove the specified block devs from self without erasing their
+    /// metadata and return them to the caller.
+    ///
+    /// Precondition: It is the responsibility of the caller to ensure that
+    /// none of the blockdevs are in use, that is, have had any space allocated
+    /// from them for upper layers.
+    ///
+    /// If a specified blockdev is not found, returns an error and does nothing.
     ///
     /// NOTE: This method traverses the block_devs Vec from the rear to the
     /// front, looking for blockdevs to remove. This is algorithmically
     /// inefficient, unless it is assumed that the blockdevs specified are very
     /// near the end of the Vec, which is expected to be the case. In that case,
     /// the algorithm is O(n).
-    pub(super) fn remove_blockdevs(&mut self, uuids: &[DevUuid]) -> StratisResult<()> {
+    pub(super) fn detach_blockdevs(
+        &mut self,
+        uuids: &[DevUuid],
+    ) -> StratisResult<Vec<StratBlockDev>> {
+        if let Some(uuid) = uuids
+            .iter()
+            .find(|&&uuid| self.get_blockdev_by_uuid(uuid).is_none())
+        {
+            return Err(StratisError::Engine(
+                ErrorEnum::Error,
+                format!(
+                    "Blockdev corresponding to UUID: {} not found.",
+                    uuid.to_simple_ref()
+                ),
+            ));
+        }
+
         let mut removed = Vec::new();
         for uuid in uuids {
-            let mut found = false;
-            let blockdevs_last_index = self.block_devs.len() - 1;
-            for i in 0..blockdevs_last_index {
-                let index = blockdevs_last_index - i;
-                if self.block_devs[index].uuid() == *uuid {
-                    removed.push(self.block_devs.swap_remove(index));
-                    found = true;
-                    break;
-                }
-            }
-            if !found {
-                return Err(StratisError::Engine(
-                    ErrorEnum::Error,
-                    format!(
-                        "Blockdev corresponding to UUID: {} not found.",
-                        uuid.to_simple_ref()
-                    ),
-                ));
+            if let Some(index) = self.block_devs.iter().rposition(|bd| bd.uuid() == *uuid) {
+                removed.push(self.block_devs.swap_remove(index));
             }
         }
-        wipe_blockdevs(&mut removed)?;
-        Ok(())
+        Ok(removed)
+    }
+
+    /// Return blockdevs removed by detach_blockdevs() to self.
+    pub(super) fn attach_blockdevs(&mut self, blockdevs: Vec<StratBlockDev>) {
+        self.block_devs.extend(blockdevs);
     }
 
     /// Allocate space according to sizes vector request.
@@ -272,8 +282,24 @@ impl BlockDevMgr {
     /// This method is atomic, it either allocates all requested or allocates
     /// nothing.
     pub fn alloc_space(&mut self, sizes: &[Sectors]) -> Option<Vec<Vec<BlkDevSegment>>> {
+        self.alloc_space_excluding(sizes, &[])
+    }
+
+    /// Allocate space according to sizes vector request, as alloc_space()
+    /// does, but never from the blockdevs with UUIDs in excluded.
+    pub fn alloc_space_excluding(
+        &mut self,
+        sizes: &[Sectors],
+        excluded: &[DevUuid],
+    ) -> Option<Vec<Vec<BlkDevSegment>>> {
         let total_needed: Sectors = sizes.iter().cloned().sum();
-        if self.avail_space() < total_needed {
+        let available: Sectors = self
+            .block_devs
+            .iter()
+            .filter(|bd| !excluded.contains(&bd.uuid()))
+            .map(|bd| bd.available())
+            .sum();
+        if available < total_needed {
             return None;
         }
 
@@ -288,7 +314,11 @@ impl BlockDevMgr {
             // In the context of this major inefficiency that ensues over time
             // the obvious but more minor inefficiency of this inner loop is
             // not worth worrying about.
-            for bd in &mut self.block_devs {
+            for bd in self
+                .block_devs
+                .iter_mut()
+                .filter(|bd| !excluded.contains(&bd.uuid()))
+            {
                 if alloc == needed {
                     break;
                 }
@@ -307,6 +337,35 @@ impl BlockDevMgr {
         Some(lists)
     }
 
+    /// Return the space occupied by the given segments to the blockdevs
+    /// from which it was allocated.
+    /// Return an error if a blockdev is not found or if any segment was not
+    /// entirely allocated.
+    pub fn release_space(&mut self, bsegs: &[BlkDevSegment]) -> StratisResult<()> {
+        let mut ranges: HashMap<DevUuid, Vec<(Sectors, Sectors)>> = HashMap::new();
+        for bseg in bsegs {
+            ranges
+                .entry(bseg.uuid)
+                .or_insert_with(Vec::new)
+                .push((bseg.segment.start, bseg.segment.length));
+        }
+
+        for (uuid, ranges) in ranges {
+            self.get_mut_blockdev_by_uuid(uuid)
+                .ok_or_else(|| {
+                    StratisError::Engine(
+                        ErrorEnum::NotFound,
+                        format!(
+                            "Blockdev corresponding to UUID: {} not found.",
+                            uuid.to_simple_ref()
+                        ),
+                    )
+                })?
+                .release_space(&ranges)?;
+        }
+        Ok(())
+    }
+
     /// Write the given data to all blockdevs marking with current time.
     /// Return an error if data was not written to any blockdev.
     /// Omit blockdevs which do not have sufficient space in BDA to accommodate
@@ -435,39 +494,52 @@ impl BlockDevMgr {
     }
 
     /// Bind all devices in the given blockdev manager using the given clevis
-    /// configuration.
+    /// configuration. The devices may already be bound with other clevis
+    /// configurations; the new binding is given the same ID on all devices.
     ///
-    /// * Returns Ok(true) if the binding was performed.
-    /// * Returns Ok(false) if the binding had already been previously performed and
-    /// nothing was changed.
+    /// * Returns Ok(Some(id)) with the ID of the new binding if the binding
+    /// was performed.
+    /// * Returns Ok(None) if the devices were already bound with the same pin
+    /// and configuration and nothing was changed.
     /// * Returns Err(_) if an inconsistency was found in the metadata across pools
     /// or binding failed.
-    pub fn bind_clevis(&mut self, pin: String, mut clevis_info: Value) -> StratisResult<bool> {
+    pub fn bind_clevis(
+        &mut self,
+        pin: String,
+        mut clevis_info: Value,
+    ) -> StratisResult<Option<u32>> {
         fn bind_clevis_loop<'a, I>(
             key_fs: &MemoryPrivateFilesystem,
             blockdevs: I,
             pin: &str,
             clevis_info: &Value,
             yes: bool,
-        ) -> StratisResult<()>
+        ) -> StratisResult<u32>
         where
             I: IntoIterator<Item = &'a mut StratBlockDev>,
         {
+            let mut binding_id = None;
             let mut rollback_record = Vec::new();
             for blockdev_ref in blockdevs {
-                if let Err(e) = blockdev_ref.bind_clevis(key_fs, pin, clevis_info, yes) {
-                    rollback_loop(rollback_record);
-                    return Err(e);
-                } else {
-                    rollback_record.push(blockdev_ref);
+                match blockdev_ref.bind_clevis(key_fs, pin, clevis_info, yes, binding_id) {
+                    Ok(id) => {
+                        binding_id = Some(id);
+                        rollback_record.push(blockdev_ref);
+                    }
+                    Err(e) => {
+                        if let Some(id) = binding_id {
+                            rollback_loop(rollback_record, id);
+                        }
+                        return Err(e);
+                    }
                 }
             }
-            Ok(())
+            binding_id.ok_or_else(|| StratisError::Error("Pool has no data devices".to_string()))
         }
 
-        fn rollback_loop(rollback_record: Vec<&mut StratBlockDev>) {
+        fn rollback_loop(rollback_record: Vec<&mut StratBlockDev>, binding_id: u32) {
             rollback_record.into_iter().for_each(|blockdev| {
-                if let Err(e) = blockdev.unbind_clevis() {
+                if let Err(e) = blockdev.unbind_clevis(binding_id) {
                     warn!(
                         "Failed to unbind device {} from clevis during \
                         rollback: {}",
@@ -489,17 +561,12 @@ impl BlockDevMgr {
 
         let yes = interpret_clevis_config(&pin, &mut clevis_info)?;
 
-        if let Some(info) = &encryption_info.clevis_info {
-            let clevis_tuple = (pin, clevis_info);
-            if info == &clevis_tuple {
-                return Ok(false);
-            } else {
-                return Err(StratisError::Error(format!(
-                    "Block devices have already been bound with pin {} and config {}; \
-                    requested pin {} and config {} can't be applied",
-                    info.0, info.1, clevis_tuple.0, clevis_tuple.1,
-                )));
-            }
+        if encryption_info
+            .clevis_info
+            .iter()
+            .any(|binding| binding.pin == pin && binding.config == clevis_info)
+        {
+            return Ok(None);
         }
 
         let key_fs = MemoryPrivateFilesystem::new()?;
@@ -510,12 +577,17 @@ impl BlockDevMgr {
             pin.as_str(),
             &clevis_info,
             yes,
-        )?;
-
-        Ok(true)
+        )
+        .map(Some)
     }
 
-    pub fn unbind_clevis(&mut self) -> StratisResult<bool> {
+    /// Remove the clevis binding with the given ID from all devices in the
+    /// given blockdev manager.
+    ///
+    /// * Returns Ok(true) if the binding was removed.
+    /// * Returns Ok(false) if there is no binding with the given ID.
+    /// * Returns Err(_) if the pool is not encrypted or unbinding failed.
+    pub fn unbind_clevis(&mut self, binding_id: u32) -> StratisResult<bool> {
         match self.encryption_info() {
             None => {
                 return Err(StratisError::Error(
@@ -523,14 +595,18 @@ impl BlockDevMgr {
                 ));
             }
             Some(info) => {
-                if info.clevis_info.is_none() {
+                if !info
+                    .clevis_info
+                    .iter()
+                    .any(|binding| binding.id == binding_id)
+                {
                     return Ok(false);
                 }
             }
         }
 
         for blockdev in self.blockdevs_mut().into_iter().map(|(_, bd)| bd) {
-            let res = blockdev.unbind_clevis();
+            let res = blockdev.unbind_clevis(binding_id);
             if let Err(ref e) = res {
                 warn!(
                     "Failed to unbind from the tang server using clevis: {}. \
@@ -542,6 +618,270 @@ impl BlockDevMgr {
         }
         Ok(true)
     }
+
+    /// Regenerate the clevis binding with the given ID on all devices in the
+    /// given blockdev manager against the current advertisement of the tang
+    /// servers it uses. The binding keeps its ID.
+    ///
+    /// A new binding is added to every device first; if this fails for any
+    /// device, the new bindings are removed again and the old binding is
+    /// left as it was. Then the new binding is given the ID of the old
+    /// binding on every device, while the old binding is kept under another
+    /// ID. Only once this has succeeded on every device is the old binding
+    /// removed.
+    ///
+    /// * Returns Ok(true) if the binding was regenerated.
+    /// * Returns Ok(false) if there is no binding with the given ID.
+    /// * Returns Err(_) if the pool is not encrypted or rebinding failed.
+    pub fn rebind_clevis(&mut self, binding_id: u32) -> StratisResult<bool> {
+        let binding = match self.encryption_info() {
+            None => {
+                return Err(StratisError::Error(
+                    "Requested pool does not appear to be encrypted".to_string(),
+                ));
+            }
+            Some(info) => match info.clevis_info.iter().find(|b| b.id == binding_id) {
+                Some(binding) => binding.clone(),
+                None => return Ok(false),
+            },
+        };
+
+        let mut config = binding.config;
+        remove_tang_thumbprints(&mut config);
+
+        let key_fs = MemoryPrivateFilesystem::new()?;
+
+        fn unbind_clevis_or_warn(blockdev: &mut StratBlockDev, id: u32) {
+            if let Err(e) = blockdev.unbind_clevis(id) {
+                warn!(
+                    "Failed to unbind device {} from clevis during rollback: {}",
+                    blockdev.physical_path().display(),
+                    e,
+                );
+            }
+        }
+
+        let mut new_bindings = Vec::new();
+        for blockdev in self.block_devs.iter_mut() {
+            match blockdev.bind_clevis(&key_fs, &binding.pin, &config, true, None) {
+                Ok(new_id) => new_bindings.push((blockdev, new_id)),
+                Err(e) => {
+                    for (blockdev, new_id) in new_bindings {
+                        unbind_clevis_or_warn(blockdev, new_id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut replaced = Vec::new();
+        let mut new_bindings = new_bindings.into_iter();
+        while let Some((blockdev, new_id)) = new_bindings.next() {
+            match blockdev.replace_clevis(binding_id, new_id) {
+                Ok(old_id) => replaced.push((blockdev, old_id)),
+                Err(e) => {
+                    for (blockdev, old_id) in replaced {
+                        match blockdev.replace_clevis(binding_id, old_id) {
+                            Ok(new_id) => unbind_clevis_or_warn(blockdev, new_id),
+                            Err(e) => warn!(
+                                "Failed to restore clevis binding {} of device {} during \
+                                rollback: {}",
+                                binding_id,
+                                blockdev.physical_path().display(),
+                                e,
+                            ),
+                        }
+                    }
+                    unbind_clevis_or_warn(blockdev, new_id);
+                    for (blockdev, new_id) in new_bindings {
+                        unbind_clevis_or_warn(blockdev, new_id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        // The old binding is only removed once the new binding has taken its
+        // place on every device.
+        for (blockdev, old_id) in replaced {
+            if let Err(e) = blockdev.unbind_clevis(old_id) {
+                warn!(
+                    "Failed to remove the replaced clevis binding, now with ID {}, \
+                    from device {}: {}",
+                    old_id,
+                    blockdev.physical_path().display(),
+                    e,
+                );
+            }
+        }
+        Ok(true)
+    }
+
+    /// Change the key in the kernel keyring used to unlock all of the
+    /// encrypted devices. Both the old and the new key must be in the
+    /// kernel keyring.
+    ///
+    /// * Returns Ok(true) if the devices were bound to the new key description.
+    /// * Returns Ok(false) if the devices were already bound to the new key
+    /// description and nothing was changed.
+    /// * Returns Err(_) if the pool is not encrypted or rebinding any device
+    /// failed, in which case all devices are returned to the old key description.
+    pub fn rebind_keyring(&mut self, new_key_desc: &KeyDescription) -> StratisResult<bool> {
+        fn rebind_keyring_loop<'a, I>(
+            blockdevs: I,
+            old_key_desc: &KeyDescription,
+            new_key_desc: &KeyDescription,
+        ) -> StratisResult<()>
+        where
+            I: IntoIterator<Item = &'a mut StratBlockDev>,
+        {
+            let mut rollback_record = Vec::new();
+            for blockdev_ref in blockdevs {
+                if let Err(e) = blockdev_ref.rebind_keyring(new_key_desc) {
+                    rollback_loop(rollback_record, old_key_desc);
+                    return Err(e);
+                } else {
+                    rollback_record.push(blockdev_ref);
+                }
+            }
+            Ok(())
+        }
+
+        fn rollback_loop(rollback_record: Vec<&mut StratBlockDev>, old_key_desc: &KeyDescription) {
+            rollback_record.into_iter().for_each(|blockdev| {
+                if let Err(e) = blockdev.rebind_keyring(old_key_desc) {
+                    warn!(
+                        "Failed to rebind device {} to key description {} during \
+                        rollback: {}",
+                        blockdev.physical_path().display(),
+                        old_key_desc.as_application_str(),
+                        e,
+                    );
+                }
+            });
+        }
+
+        let old_key_desc = match self.encryption_info() {
+            Some(info) => info.key_description.clone(),
+            None => {
+                return Err(StratisError::Error(
+                    "Requested pool does not appear to be encrypted".to_string(),
+                ))
+            }
+        };
+
+        if &old_key_desc == new_key_desc {
+            return Ok(false);
+        }
+
+        rebind_keyring_loop(
+            self.blockdevs_mut().into_iter().map(|(_, bd)| bd),
+            &old_key_desc,
+            new_key_desc,
+        )?;
+
+        Ok(true)
+    }
+
+    /// Set whether the encrypted devices are unlocked with their Clevis
+    /// bindings as soon as they appear. Enabling automatic unlocking
+    /// requires at least one Clevis binding.
+    ///
+    /// * Returns Ok(true) if the setting was changed.
+    /// * Returns Ok(false) if the devices already had the requested setting.
+    /// * Returns Err(_) if the pool is not encrypted, if it has no Clevis
+    /// binding and automatic unlocking was requested, or if changing the
+    /// setting on any device failed, in which case all devices are returned
+    /// to the previous setting.
+    pub fn set_auto_unlock(&mut self, auto_unlock: bool) -> StratisResult<bool> {
+        match self.encryption_info() {
+            None => {
+                return Err(StratisError::Error(
+                    "Requested pool does not appear to be encrypted".to_string(),
+                ));
+            }
+            Some(info) => {
+                if info.auto_unlock == auto_unlock {
+                    return Ok(false);
+                }
+                if auto_unlock && info.clevis_info.is_empty() {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        "Automatic unlocking requires the pool to be bound with Clevis".to_string(),
+                    ));
+                }
+            }
+        }
+
+        let mut rollback_record = Vec::new();
+        for blockdev in self.block_devs.iter_mut() {
+            if let Err(e) = blockdev.set_auto_unlock(auto_unlock) {
+                for blockdev in rollback_record {
+                    if let Err(err) = blockdev.set_auto_unlock(!auto_unlock) {
+                        warn!(
+                            "Failed to restore the automatic unlocking setting of \
+                            device {} during rollback: {}",
+                            blockdev.physical_path().display(),
+                            err,
+                        );
+                    }
+                }
+                return Err(e);
+            }
+            rollback_record.push(blockdev);
+        }
+        Ok(true)
+    }
+
+    /// Start reencrypting all of the encrypted devices under new volume
+    /// keys, or resume the reencryption of any devices on which it was
+    /// paused.
+    ///
+    /// * Returns Ok(true) if the reencryption of some device was started or
+    /// resumed.
+    /// * Returns Ok(false) if all devices were already being reencrypted.
+    /// * Returns Err(_) if the pool is not encrypted or starting the
+    /// reencryption of some device failed. Devices on which the reencryption
+    /// was already started continue to be reencrypted.
+    pub fn start_reencryption(&mut self) -> StratisResult<bool> {
+        if self.encryption_info().is_none() {
+            return Err(StratisError::Error(
+                "Requested pool does not appear to be encrypted".to_string(),
+            ));
+        }
+
+        let mut changed = false;
+        for blockdev in self.block_devs.iter_mut() {
+            changed |= blockdev.start_reencryption()?;
+        }
+        Ok(changed)
+    }
+
+    /// Reencrypt the first device which is being reencrypted for
+    /// approximately the given duration at most. The devices are reencrypted
+    /// one after the other to limit the impact on the I/O of the pool.
+    ///
+    /// Returns true if the reencryption of some device remains to be done.
+    pub fn reencrypt_step(&mut self, duration: std::time::Duration) -> bool {
+        fn is_reencrypting(blockdev: &StratBlockDev) -> bool {
+            blockdev
+                .reencryption_progress()
+                .map(|progress| !progress.paused)
+                .unwrap_or(false)
+        }
+
+        if let Some(blockdev) = self.block_devs.iter_mut().find(|bd| is_reencrypting(bd)) {
+            if let Err(e) = blockdev.reencrypt_step(duration) {
+                warn!(
+                    "Reencryption of device {} failed and has been paused: {}",
+                    blockdev.physical_path().display(),
+                    e,
+                );
+            }
+        }
+
+        self.block_devs.iter().any(is_reencrypting)
+    }
 }
 
 impl Recordable<Vec<BaseBlockDevSave>> for BlockDevMgr {
@@ -554,9 +894,12 @@ impl Recordable<Vec<BaseBlockDevSave>> for BlockDevMgr {
 mod tests {
     use std::error::Error;
 
-    use crate::engine::strat_engine::{
-        cmd,
-        tests::{crypt, loopbacked, real},
+    use crate::engine::{
+        strat_engine::{
+            cmd,
+            tests::{crypt, loopbacked, real},
+        },
+        types::{EncryptionParams, KeyDescription},
     };
 
     use super::*;
@@ -616,7 +959,12 @@ mod tests {
                 pool_uuid,
                 &paths[..2],
                 MDADataSize::default(),
-                Some(key_desc),
+                Some(&EncryptionInfo {
+                    key_description: key_desc.clone(),
+                    clevis_info: Vec::new(),
+                    encryption_params: EncryptionParams::default(),
+                    auto_unlock: false,
+                }),
             )?;
 
             if bdm.add(pool_uuid, &paths[2..3]).is_err() {
@@ -670,7 +1018,12 @@ mod tests {
                 pool_uuid,
                 &paths[..2],
                 MDADataSize::default(),
-                Some(key_desc),
+                Some(&EncryptionInfo {
+                    key_description: key_desc.clone(),
+                    clevis_info: Vec::new(),
+                    encryption_params: EncryptionParams::default(),
+                    auto_unlock: false,
+                }),
             )?;
             Ok((pool_uuid, bdm))
         }
@@ -4,7 +4,7 @@
 
 // Code to handle the backing store of a pool.
 
-use std::path::Path;
+use std::{collections::HashSet, path::Path};
 
 use devicemapper::{Sectors, IEC, SECTOR_SIZE};
 
@@ -18,18 +18,53 @@ use crate::{
             },
             serde_structs::{BaseDevSave, BlockDevSave, CacheTierSave, Recordable},
         },
-        types::{BlockDevTier, DevUuid, PoolUuid},
+        types::{BlockDevTier, CacheConfig, DevUuid, PoolUuid},
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
-/// This is a temporary maximum cache size. In the future it will be possible
-/// to dynamically increase the cache size beyond this limit. When this is
-/// achieved this constant definition should be removed. This choice of a
-/// maximum cache size is a function of the current values for the cache block
-/// size, 2 Ki-sectors, and the current value for the metadata sub-device size,
-/// 1 Mi-sectors.
-const MAX_CACHE_SIZE: Sectors = Sectors(32 * IEC::Ti / SECTOR_SIZE as u64);
+/// The number of bytes of metadata that dm-cache requires for each cache
+/// block, 16 for the mapping and 8 for the policy hint. The sum is doubled,
+/// since the metadata is updated copy-on-write.
+const META_BYTES_PER_CACHE_BLOCK: u64 = 2 * (16 + 8);
+
+/// The metadata space required regardless of the number of cache blocks,
+/// for the superblock, the space maps, and transaction overhead.
+const META_OVERHEAD: Sectors = Sectors(4 * IEC::Mi / SECTOR_SIZE as u64); // 4 MiB
+
+/// The size of a dm-cache metadata block in bytes.
+const META_BLOCK_BYTES: u64 = 4 * IEC::Ki;
+
+/// The largest metadata sub-device dm-cache can make use of, a little less
+/// than 16 GiB.
+const MAX_META_SIZE: Sectors = Sectors(255 * (1 << 14) * 8);
+
+/// The size of the meta sub-device required by a cache with a cache
+/// sub-device of cache_size and the specified block size.
+fn meta_size(cache_size: Sectors, block_size: Sectors) -> Sectors {
+    let bytes = (cache_size / block_size) * META_BYTES_PER_CACHE_BLOCK;
+    let meta_blocks = (bytes + META_BLOCK_BYTES - 1) / META_BLOCK_BYTES;
+    META_OVERHEAD + Sectors(meta_blocks * META_BLOCK_BYTES / SECTOR_SIZE as u64)
+}
+
+/// Allocate all the space available in block_mgr to the sub-devices of the
+/// cache, meta_space sectors to the meta sub-device and the remainder to the
+/// cache sub-device. Return the meta and the cache segments, in that order.
+///
+/// Precondition: meta_space < block_mgr.avail_space()
+fn alloc_sub_devices(
+    block_mgr: &mut BlockDevMgr,
+    meta_space: Sectors,
+) -> (Vec<BlkDevSegment>, Vec<BlkDevSegment>) {
+    let avail_space = block_mgr.avail_space();
+    let mut segments = block_mgr
+        .alloc_space(&[meta_space, avail_space - meta_space])
+        .expect("asked for exactly the space available, must get");
+
+    let cache_segments = segments.pop().expect("segments.len() == 2");
+    let meta_segments = segments.pop().expect("segments.len() == 1");
+    (meta_segments, cache_segments)
+}
 
 /// Handles the cache devices.
 #[derive(Debug)]
@@ -42,6 +77,8 @@ pub struct CacheTier {
     /// The list of segments granted by block_mgr and used by the metadata
     /// device.
     pub meta_segments: Vec<BlkDevSegment>,
+    /// The mode, policy, and block size of the cache device.
+    pub config: CacheConfig,
 }
 
 impl CacheTier {
@@ -74,10 +111,18 @@ impl CacheTier {
             .map(&mapper)
             .collect::<StratisResult<Vec<_>>>()?;
 
+        let config = CacheConfig {
+            mode: cache_tier_save.mode,
+            policy: cache_tier_save.policy.clone(),
+            policy_args: cache_tier_save.policy_args.clone(),
+            block_size: cache_tier_save.block_size,
+        };
+
         Ok(CacheTier {
             block_mgr,
             meta_segments,
             cache_segments,
+            config,
         })
     }
 
@@ -85,16 +130,18 @@ impl CacheTier {
     /// corresponding to the specified paths and a pair of Boolean values.
     /// The first is true if the cache sub-device's segments were changed,
     /// the second is true if the meta sub-device's segments were changed.
-    /// Adds all additional space to cache sub-device.
+    /// The meta sub-device is extended if it is too small for the
+    /// metadata of the enlarged cache sub-device; all the remaining
+    /// additional space is added to the cache sub-device.
     /// WARNING: metadata changing event
     ///
-    /// Return an error if the addition of the cachedevs would result in a
-    /// cache with a cache sub-device size greater than 32 TiB.
+    /// dm-cache can not make use of additional space in a meta sub-device
+    /// that it has already formatted. If the meta sub-device's segments
+    /// were changed, the cache's metadata must be formatted anew.
     ///
-    // FIXME: That all segments on the newly added device are added to the
-    // cache sub-device and none to the meta sub-device could lead to failure.
-    // Presumably, the size required for the meta sub-device varies directly
-    // with the size of cache sub-device.
+    /// Return an error if the metadata of the enlarged cache would not fit
+    /// in the largest meta sub-device that dm-cache supports, or if the
+    /// additional space does not suffice to extend the meta sub-device.
     pub fn add(
         &mut self,
         pool_uuid: PoolUuid,
@@ -104,83 +151,127 @@ impl CacheTier {
 
         let avail_space = self.block_mgr.avail_space();
 
-        // FIXME: This check will become unnecessary when cache metadata device
-        // can be increased dynamically.
-        if avail_space
-            + self
-                .cache_segments
-                .iter()
-                .map(|x| x.segment.length)
-                .sum::<Sectors>()
-            > MAX_CACHE_SIZE
-        {
+        let cache_space = self
+            .cache_segments
+            .iter()
+            .map(|x| x.segment.length)
+            .sum::<Sectors>();
+        let current_meta_space = self
+            .meta_segments
+            .iter()
+            .map(|x| x.segment.length)
+            .sum::<Sectors>();
+        let meta_space = meta_size(cache_space + avail_space, self.config.block_size);
+
+        let additional_meta_space = if meta_space > current_meta_space {
+            meta_space - current_meta_space
+        } else {
+            Sectors(0)
+        };
+
+        if meta_space > MAX_META_SIZE {
             self.block_mgr.remove_blockdevs(&uuids)?;
             return Err(StratisError::Engine(
                 ErrorEnum::Invalid,
                 format!(
-                    "The size of the cache sub-device may not exceed {}",
-                    MAX_CACHE_SIZE
+                    "The cache would require a meta sub-device of {}, but the meta sub-device may not exceed {}",
+                    meta_space, MAX_META_SIZE
+                ),
+            ));
+        }
+        if additional_meta_space >= avail_space {
+            self.block_mgr.remove_blockdevs(&uuids)?;
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "The meta sub-device must be extended by {}, but only {} would be added to the cache",
+                    additional_meta_space, avail_space
                 ),
             ));
         }
 
-        let segments = self
-            .block_mgr
-            .alloc_space(&[avail_space])
-            .expect("asked for exactly the space available, must get")
-            .iter()
-            .flat_map(|s| s.iter())
-            .cloned()
-            .collect::<Vec<_>>();
-        self.cache_segments = coalesce_blkdevsegs(&self.cache_segments, &segments);
+        let (meta_segments, cache_segments) =
+            alloc_sub_devices(&mut self.block_mgr, additional_meta_space);
+        self.meta_segments = coalesce_blkdevsegs(&self.meta_segments, &meta_segments);
+        self.cache_segments = coalesce_blkdevsegs(&self.cache_segments, &cache_segments);
 
-        Ok((uuids, (true, false)))
+        Ok((uuids, (true, additional_meta_space != Sectors(0))))
     }
 
-    /// Setup a new CacheTier struct from the block_mgr.
+    /// Setup a new CacheTier struct from the block_mgr. The cache device
+    /// made from the tier is to be configured as specified by config.
+    /// The meta sub-device is made big enough to hold the metadata for a
+    /// cache sub-device consisting of all the remaining space.
     ///
-    /// Returns an error if the block devices passed would make the cache
-    /// sub-device too big.
+    /// Returns an error if the metadata of the cache would not fit in the
+    /// largest meta sub-device that dm-cache supports.
     ///
     /// WARNING: metadata changing event
-    pub fn new(mut block_mgr: BlockDevMgr) -> StratisResult<CacheTier> {
+    pub fn new(mut block_mgr: BlockDevMgr, config: CacheConfig) -> StratisResult<CacheTier> {
         let avail_space = block_mgr.avail_space();
 
-        // FIXME: Come up with a better way to choose metadata device size
-        let meta_space = Sectors(IEC::Mi);
+        let meta_space = meta_size(avail_space, config.block_size);
 
         assert!(
             meta_space < avail_space,
             "every block device must be at least one GiB"
         );
 
-        // FIXME: This check will become unnecessary when cache metadata device
-        // can be increased dynamically.
-        if avail_space - meta_space > MAX_CACHE_SIZE {
+        if meta_space > MAX_META_SIZE {
             block_mgr.destroy_all()?;
             return Err(StratisError::Engine(
                 ErrorEnum::Invalid,
                 format!(
-                    "The size of the cache sub-device may not exceed {}",
-                    MAX_CACHE_SIZE
+                    "The cache would require a meta sub-device of {}, but the meta sub-device may not exceed {}",
+                    meta_space, MAX_META_SIZE
                 ),
             ));
         }
 
-        let mut segments = block_mgr
-            .alloc_space(&[meta_space, avail_space - meta_space])
-            .expect("asked for exactly the space available, must get");
-
-        let cache_segments = segments.pop().expect("segments.len() == 2");
-        let meta_segments = segments.pop().expect("segments.len() == 1");
+        let (meta_segments, cache_segments) = alloc_sub_devices(&mut block_mgr, meta_space);
 
         Ok(CacheTier {
             block_mgr,
             meta_segments,
             cache_segments,
+            config,
         })
     }
 
+    /// Remove the blockdevs with the specified UUIDs from this tier and lay
+    /// out the meta and cache sub-devices afresh on the remaining blockdevs.
+    /// The meta sub-device is sized for the smaller cache. Return the removed
+    /// blockdevs, which still carry Stratis metadata; it is the
+    /// responsibility of the caller to wipe them.
+    ///
+    /// Precondition: The cache device made from this tier has been torn
+    /// down; the contents of the sub-devices are not preserved.
+    /// Precondition: At least one blockdev remains in the tier.
+    ///
+    /// If an error is returned, no blockdevs have been removed.
+    ///
+    /// WARNING: metadata changing event
+    pub fn remove(&mut self, uuids: &[DevUuid]) -> StratisResult<Vec<StratBlockDev>> {
+        let removing = uuids.iter().cloned().collect::<HashSet<_>>();
+        let retained = self
+            .meta_segments
+            .iter()
+            .chain(self.cache_segments.iter())
+            .filter(|bseg| !removing.contains(&bseg.uuid))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.block_mgr.release_space(&retained)?;
+
+        let removed = self.block_mgr.detach_blockdevs(uuids)?;
+
+        let meta_space = meta_size(self.block_mgr.avail_space(), self.config.block_size);
+        let (meta_segments, cache_segments) = alloc_sub_devices(&mut self.block_mgr, meta_space);
+        self.meta_segments = meta_segments;
+        self.cache_segments = cache_segments;
+
+        Ok(removed)
+    }
+
     /// Destroy the tier. Wipe its blockdevs.
     pub fn destroy(&mut self) -> StratisResult<()> {
         self.block_mgr.destroy_all()
@@ -220,6 +311,10 @@ impl Recordable<CacheTierSave> for CacheTier {
                 allocs: vec![self.cache_segments.record(), self.meta_segments.record()],
                 devs: self.block_mgr.record(),
             },
+            mode: self.config.mode,
+            policy: self.config.policy.clone(),
+            policy_args: self.config.policy_args.clone(),
+            block_size: self.config.block_size,
         }
     }
 }
@@ -246,11 +341,11 @@ mod tests {
 
         let mgr = BlockDevMgr::initialize(pool_uuid, paths1, MDADataSize::default(), None).unwrap();
 
-        let mut cache_tier = CacheTier::new(mgr).unwrap();
+        let mut cache_tier = CacheTier::new(mgr, CacheConfig::default()).unwrap();
 
         // A cache tier w/ some devices and everything promptly allocated to
         // the tier.
-        let cache_metadata_size = cache_tier
+        let mut cache_metadata_size = cache_tier
             .meta_segments
             .iter()
             .map(|x| x.segment.length)
@@ -268,14 +363,21 @@ mod tests {
         assert_eq!(size - metadata_size, allocated + cache_metadata_size);
 
         let (_, (cache, meta)) = cache_tier.add(pool_uuid, paths2).unwrap();
-        // TODO: Ultimately, it should be the case that meta can be true.
         assert!(cache);
-        assert!(!meta);
+        assert!(meta);
 
         assert_eq!(cache_tier.block_mgr.avail_space(), Sectors(0));
         assert!(cache_tier.block_mgr.size() > size);
         assert!(cache_tier.block_mgr.metadata_size() > metadata_size);
 
+        let new_cache_metadata_size = cache_tier
+            .meta_segments
+            .iter()
+            .map(|x| x.segment.length)
+            .sum::<Sectors>();
+        assert!(new_cache_metadata_size > cache_metadata_size);
+        cache_metadata_size = new_cache_metadata_size;
+
         metadata_size = cache_tier.block_mgr.metadata_size();
         size = cache_tier.block_mgr.size();
         allocated = cache_tier
@@ -3,22 +3,31 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
-    convert::TryFrom,
+    cell::Cell,
+    convert::{TryFrom, TryInto},
     fmt::{self, Debug},
-    io,
+    fs::File,
+    io::{self, Read},
+    os::raw::c_void,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use base64::{decode, encode_config, CharacterSet, Config};
 use either::Either;
 use serde_json::{Map, Value};
 use sha1::{Digest, Sha1};
+use tempfile::NamedTempFile;
 
-use devicemapper::Sectors;
+use devicemapper::{Bytes, Sectors};
 use libcryptsetup_rs::{
-    c_uint, CryptActivateFlags, CryptDeactivateFlags, CryptDevice, CryptInit, CryptStatusInfo,
-    CryptVolumeKeyFlags, CryptWipePattern, EncryptionFormat, LibcryptErr, TokenInput,
+    c_int, c_uint, CryptActivateFlags, CryptDeactivateFlags, CryptDevice, CryptInit, CryptKdf,
+    CryptParamsLuks2, CryptParamsLuks2Ref, CryptParamsReencrypt, CryptPbkdfType,
+    CryptReencryptDirectionInfo, CryptReencryptFlag, CryptReencryptFlags, CryptReencryptInfo,
+    CryptReencryptModeInfo, CryptSettings, CryptStatusInfo, CryptVolumeKeyFlag,
+    CryptVolumeKeyFlags, CryptWipePattern, EncryptionFormat, KeyslotInfo, LibcryptErr,
+    SafeMemHandle, TokenInput,
 };
 
 use crate::engine::{
@@ -28,7 +37,10 @@ use crate::engine::{
         metadata::StratisIdentifiers,
         names::format_crypt_name,
     },
-    types::{BlockDevPath, EncryptionInfo, KeyDescription, SizedKeyMemory, UnlockMethod},
+    types::{
+        BlockDevPath, ClevisBinding, EncryptionInfo, EncryptionParams, KeyDescription, Pbkdf,
+        ReencryptionProgress, SizedKeyMemory, UnlockMethod,
+    },
     DevUuid, PoolUuid,
 };
 
@@ -40,23 +52,20 @@ const TOKEN_KEYSLOTS_KEY: &str = "keyslots";
 const STRATIS_TOKEN_DEVNAME_KEY: &str = "activation_name";
 const STRATIS_TOKEN_POOL_UUID_KEY: &str = "pool_uuid";
 const STRATIS_TOKEN_DEV_UUID_KEY: &str = "device_uuid";
+const STRATIS_TOKEN_ENCRYPTION_PARAMS_KEY: &str = "encryption_params";
+const STRATIS_TOKEN_ENCRYPTION_IN_PROGRESS_KEY: &str = "encryption_in_progress";
+const STRATIS_TOKEN_AUTO_UNLOCK_KEY: &str = "auto_unlock";
 
 const STRATIS_TOKEN_ID: c_uint = 0;
 const LUKS2_TOKEN_ID: c_uint = 1;
-/// NOTE: Only token IDs 0 and 1 will be used at the time of clevis bindings
-/// so until support for more clevis operations are added, this can be reliably
-/// depended upon as cryptsetup token import will use the next available token ID
-/// which, in this case, is 2.
-/// FIXME: Specify token ID when clevis adds support so that we can rely on this
-/// deterministically as we add other tokens.
-const CLEVIS_LUKS_TOKEN_ID: c_uint = 2;
+/// The number of token slots in a LUKS2 header. Each Clevis binding is held
+/// in a token of its own in one of the slots not used by Stratis; the ID of
+/// that token identifies the binding.
+const LUKS2_TOKENS_MAX: c_uint = 32;
 
 const LUKS2_TOKEN_TYPE: &str = "luks2-keyring";
 const STRATIS_TOKEN_TYPE: &str = "stratis";
-
-/// The size of the media encryption key generated by cryptsetup for
-/// each block device.
-const STRATIS_MEK_SIZE: usize = 512 / 8;
+const CLEVIS_TOKEN_TYPE: &str = "clevis";
 
 /// Sector size as determined in `cryptsetup/lib/internal.h`
 const SECTOR_SIZE: u64 = 512;
@@ -64,10 +73,49 @@ const SECTOR_SIZE: u64 = 512;
 /// Path to logical devices for encrypted devices
 const DEVICEMAPPER_PATH: &str = "/dev/mapper";
 
+/// The number of sectors at the end of a device that must be unused for its
+/// contents to be encrypted in place. Half of this space is taken up by the
+/// LUKS2 header, which is written at the beginning of the device once the
+/// contents have been shifted towards the end of the device.
+pub const IN_PLACE_ENCRYPTION_SPACE: Sectors = Sectors(64 * 1024);
+
+/// Resilience mode for in place encryption; the contents of the device are
+/// shifted by the size of the space reserved for the LUKS2 header.
+const DATA_SHIFT_RESILIENCE: &str = "datashift";
+
+/// Resilience mode for reencryption under a new volume key; the hotzone is
+/// checksummed so that an interrupted step can be recovered.
+const CHECKSUM_RESILIENCE: &str = "checksum";
+
+/// Hash used to checksum the reencryption hotzone.
+const REENCRYPT_HASH: &str = "sha256";
+
+/// Value of CRYPT_ANY_SLOT in libcryptsetup.
+const ANY_KEYSLOT: c_int = -1;
+
 /// Key in clevis configuration for tang indicating that the URL of the
 /// tang server does not need to be verified.
 const CLEVIS_TANG_TRUST_URL: &str = "stratis:tang:trust_url";
 
+thread_local! {
+    /// The deadline of the reencryption step being run on this thread, if
+    /// any, and the most recent progress reported by libcryptsetup as a pair
+    /// of the size of the device and the offset reached, in bytes.
+    static REENCRYPTION_STEP: Cell<(Option<Instant>, u64, u64)> = Cell::new((None, 0, 0));
+}
+
+/// Progress callback for libcryptsetup reencryption. Records the progress
+/// and interrupts the reencryption once the deadline of the step has passed.
+/// libcryptsetup does not pass any user data to this callback, so the state
+/// is kept in a thread local variable.
+extern "C" fn reencryption_progress(size: u64, offset: u64, _: *mut c_void) -> c_int {
+    REENCRYPTION_STEP.with(|step| {
+        let (deadline, _, _) = step.get();
+        step.set((deadline, size, offset));
+        deadline.map(|d| Instant::now() >= d).unwrap_or(false) as c_int
+    })
+}
+
 macro_rules! log_on_failure {
     ($op:expr, $fmt:tt $(, $arg:expr)*) => {{
         let result = $op;
@@ -85,17 +133,35 @@ macro_rules! log_on_failure {
 struct StratisLuks2Token {
     devname: String,
     identifiers: StratisIdentifiers,
+    encryption_params: EncryptionParams,
+    encryption_in_progress: bool,
+    auto_unlock: bool,
 }
 
 impl Into<Value> for StratisLuks2Token {
     fn into(self) -> Value {
-        json!({
+        let mut json = json!({
             TOKEN_TYPE_KEY: STRATIS_TOKEN_TYPE,
             TOKEN_KEYSLOTS_KEY: [],
             STRATIS_TOKEN_DEVNAME_KEY: self.devname,
             STRATIS_TOKEN_POOL_UUID_KEY: self.identifiers.pool_uuid.to_string(),
             STRATIS_TOKEN_DEV_UUID_KEY: self.identifiers.device_uuid.to_string(),
-        })
+            STRATIS_TOKEN_ENCRYPTION_PARAMS_KEY: self.encryption_params,
+        });
+        if self.encryption_in_progress {
+            json.as_object_mut()
+                .expect("json! macro returns an object")
+                .insert(
+                    STRATIS_TOKEN_ENCRYPTION_IN_PROGRESS_KEY.to_string(),
+                    Value::from(true),
+                );
+        }
+        if self.auto_unlock {
+            json.as_object_mut()
+                .expect("json! macro returns an object")
+                .insert(STRATIS_TOKEN_AUTO_UNLOCK_KEY.to_string(), Value::from(true));
+        }
+        json
     }
 }
 
@@ -182,9 +248,15 @@ impl<'a> TryFrom<&'a Value> for StratisLuks2Token {
             STRATIS_TOKEN_DEV_UUID_KEY,
             DevUuid
         );
+        let encryption_params = encryption_params_from_token(map)?;
+        let encryption_in_progress = encryption_in_progress_from_token(map)?;
+        let auto_unlock = auto_unlock_from_token(map)?;
         Ok(StratisLuks2Token {
             devname,
             identifiers: StratisIdentifiers::new(pool_uuid, dev_uuid),
+            encryption_params,
+            encryption_in_progress,
+            auto_unlock,
         })
     }
 }
@@ -205,14 +277,18 @@ impl CryptInitializer {
         }
     }
 
-    pub fn initialize(self, key_description: &KeyDescription) -> Result<CryptHandle> {
+    pub fn initialize(
+        self,
+        key_description: &KeyDescription,
+        encryption_params: &EncryptionParams,
+    ) -> Result<CryptHandle> {
         let mut device = log_on_failure!(
             CryptInit::init(&self.physical_path),
             "Failed to acquire context for device {} while initializing; \
             nothing to clean up",
             self.physical_path.display()
         );
-        let result = self.initialize_with_err(&mut device, key_description);
+        let result = self.initialize_with_err(&mut device, key_description, encryption_params);
         match result {
             Ok(activated_path) => Ok(CryptHandle::new(
                 device,
@@ -221,9 +297,12 @@ impl CryptInitializer {
                 self.identifiers,
                 EncryptionInfo {
                     key_description: key_description.clone(),
-                    clevis_info: None,
+                    clevis_info: Vec::new(),
+                    encryption_params: encryption_params.clone(),
+                    auto_unlock: false,
                 },
                 self.activation_name,
+                false,
             )),
             Err(e) => {
                 if let Err(err) = Self::rollback(device, &self.physical_path, self.activation_name)
@@ -243,18 +322,9 @@ impl CryptInitializer {
         &self,
         device: &mut CryptDevice,
         key_description: &KeyDescription,
+        encryption_params: &EncryptionParams,
     ) -> Result<PathBuf> {
-        log_on_failure!(
-            device.context_handle().format::<()>(
-                EncryptionFormat::Luks2,
-                ("aes", "xts-plain64"),
-                None,
-                libcryptsetup_rs::Either::Right(STRATIS_MEK_SIZE),
-                None,
-            ),
-            "Failed to format device {} with LUKS2 header",
-            self.physical_path.display()
-        );
+        format_luks2(device, &self.physical_path, encryption_params)?;
         let key_option = log_on_failure!(
             read_key(key_description),
             "Failed to read key with key description {} from keyring",
@@ -280,33 +350,279 @@ impl CryptInitializer {
         );
 
         // Initialize keyring token
+        set_keyring_token(device, key_description, &[keyslot])?;
+
+        // Initialize stratis token
         log_on_failure!(
-            device
-                .token_handle()
-                .luks2_keyring_set(Some(LUKS2_TOKEN_ID), &key_description.to_system_string()),
-            "Failed to initialize the LUKS2 token for driving keyring activation operations"
+            device.token_handle().json_set(TokenInput::ReplaceToken(
+                STRATIS_TOKEN_ID,
+                &StratisLuks2Token {
+                    devname: self.activation_name.clone(),
+                    identifiers: self.identifiers,
+                    encryption_params: encryption_params.clone(),
+                    encryption_in_progress: false,
+                    auto_unlock: false,
+                }
+                .into(),
+            )),
+            "Failed to create the Stratis token"
+        );
+
+        activate_and_check_device_path(device, key_description, &self.activation_name)
+    }
+
+    /// Encrypt the existing contents of the device in place.
+    ///
+    /// The contents of the device are shifted towards its end to make room
+    /// for the LUKS2 header, so the last `IN_PLACE_ENCRYPTION_SPACE` sectors
+    /// of the device must not be in use. The contents of the activated
+    /// logical device are the contents the device had before it was
+    /// encrypted, but the logical device is smaller than the physical device.
+    ///
+    /// The progress of the encryption is recorded in the LUKS2 metadata, so
+    /// if the encryption is interrupted, it is resumed by calling this method
+    /// again. The Stratis token of the device records that the encryption is
+    /// in progress until `CryptHandle::finish_encryption()` is called, which
+    /// must be done once the caller has adapted the contents of the device to
+    /// the size of the logical device.
+    ///
+    /// On failure, the device is not rolled back, as that would destroy its
+    /// contents.
+    pub fn encrypt_in_place(
+        self,
+        key_description: &KeyDescription,
+        encryption_params: &EncryptionParams,
+    ) -> Result<CryptHandle> {
+        let key = read_key(key_description)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Key with key description {} was not found in the kernel keyring",
+                key_description.to_system_string(),
+            ))
+        })?;
+
+        let (mut device, encryption_params) = match device_from_physical_path(&self.physical_path)?
+        {
+            Some(mut device) => {
+                self.check_in_place_encryption(&mut device, key_description)?;
+                let encryption_params = encryption_params_from_metadata(&mut device)?;
+                (device, encryption_params)
+            }
+            None => {
+                self.initialize_in_place_encryption(
+                    key_description,
+                    key.as_ref(),
+                    encryption_params,
+                )?;
+                let device = device_from_physical_path(&self.physical_path)?.ok_or_else(|| {
+                    LibcryptErr::Other(format!(
+                        "No LUKS2 header was found on device {} after it was restored",
+                        self.physical_path.display(),
+                    ))
+                })?;
+                (device, encryption_params.clone())
+            }
+        };
+
+        let status = log_on_failure!(
+            device.reencrypt_handle().status(in_place_encryption_params(
+                &encryption_params,
+                CryptReencryptFlags::empty()
+            )?),
+            "Failed to get the encryption status of device {}",
+            self.physical_path.display()
+        );
+        match status {
+            CryptReencryptInfo::None => (),
+            CryptReencryptInfo::Invalid => {
+                return Err(LibcryptErr::Other(format!(
+                    "The encryption metadata on device {} is invalid",
+                    self.physical_path.display(),
+                )));
+            }
+            CryptReencryptInfo::Clean | CryptReencryptInfo::Crash => {
+                if status == CryptReencryptInfo::Crash {
+                    log_on_failure!(
+                        device.reencrypt_handle().reencrypt_init_by_passphrase(
+                            None,
+                            key.as_ref(),
+                            ANY_KEYSLOT,
+                            ANY_KEYSLOT,
+                            encryption_params.cipher_and_mode(),
+                            in_place_encryption_params(
+                                &encryption_params,
+                                CryptReencryptFlags::new(vec![CryptReencryptFlag::Recovery]),
+                            )?,
+                        ),
+                        "Failed to recover interrupted encryption of device {}",
+                        self.physical_path.display()
+                    );
+                }
+                log_on_failure!(
+                    device.reencrypt_handle().reencrypt_init_by_passphrase(
+                        None,
+                        key.as_ref(),
+                        ANY_KEYSLOT,
+                        ANY_KEYSLOT,
+                        encryption_params.cipher_and_mode(),
+                        in_place_encryption_params(
+                            &encryption_params,
+                            CryptReencryptFlags::new(vec![CryptReencryptFlag::ResumeOnly]),
+                        )?,
+                    ),
+                    "Failed to load the encryption state of device {}",
+                    self.physical_path.display()
+                );
+                log_on_failure!(
+                    device.reencrypt_handle().reencrypt(None),
+                    "Failed to encrypt the contents of device {}",
+                    self.physical_path.display()
+                );
+            }
+        }
+
+        let activated_path = if device_is_active(&mut device, &self.activation_name) {
+            [DEVICEMAPPER_PATH, &self.activation_name].iter().collect()
+        } else {
+            activate_and_check_device_path(&mut device, key_description, &self.activation_name)?
+        };
+
+        Ok(CryptHandle::new(
+            device,
+            self.physical_path,
+            activated_path,
+            self.identifiers,
+            EncryptionInfo {
+                key_description: key_description.clone(),
+                clevis_info: Vec::new(),
+                encryption_params,
+                auto_unlock: false,
+            },
+            self.activation_name,
+            true,
+        ))
+    }
+
+    /// Check that the LUKS2 header on a device whose encryption is being
+    /// resumed belongs to this device and is unlocked by the given key.
+    fn check_in_place_encryption(
+        &self,
+        device: &mut CryptDevice,
+        key_description: &KeyDescription,
+    ) -> Result<()> {
+        if !is_encrypted_stratis_device(device) {
+            return Err(LibcryptErr::Other(format!(
+                "Device {} has a LUKS2 header that was not written by Stratis",
+                self.physical_path.display(),
+            )));
+        }
+        let identifiers = identifiers_from_metadata(device)?;
+        if identifiers != self.identifiers {
+            return Err(LibcryptErr::Other(format!(
+                "The Stratis token of device {} belongs to device {}, not to device {}",
+                self.physical_path.display(),
+                identifiers,
+                self.identifiers,
+            )));
+        }
+        let recorded_key_desc = key_desc_from_metadata(device)?;
+        if recorded_key_desc != key_description.to_system_string() {
+            return Err(LibcryptErr::Other(format!(
+                "Device {} is encrypted with key description {}, not {}",
+                self.physical_path.display(),
+                recorded_key_desc,
+                key_description.to_system_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write a LUKS2 header for in place encryption to the device. This
+    /// follows the procedure used by cryptsetup: the header is formatted in a
+    /// detached header file, the encryption is initialized without moving any
+    /// data and the header is then restored to the device.
+    fn initialize_in_place_encryption(
+        &self,
+        key_description: &KeyDescription,
+        key: &[u8],
+        encryption_params: &EncryptionParams,
+    ) -> Result<()> {
+        let header_file = NamedTempFile::new().map_err(LibcryptErr::IOError)?;
+        header_file
+            .as_file()
+            .set_len(*IN_PLACE_ENCRYPTION_SPACE / 2 * SECTOR_SIZE)
+            .map_err(LibcryptErr::IOError)?;
+
+        let mut device = log_on_failure!(
+            CryptInit::init_with_data_device(libcryptsetup_rs::Either::Right((
+                header_file.path(),
+                &self.physical_path,
+            ))),
+            "Failed to acquire context for device {} while initializing encryption",
+            self.physical_path.display()
         );
+        // The data offset is given in units of 4096 bytes.
         log_on_failure!(
-            device
-                .token_handle()
-                .assign_keyslot(LUKS2_TOKEN_ID, Some(keyslot)),
-            "Failed to assign the LUKS2 keyring token to the Stratis keyslot"
+            device.set_data_offset(*IN_PLACE_ENCRYPTION_SPACE / 2 / 8),
+            "Failed to set the data offset of device {}",
+            self.physical_path.display()
         );
+        format_luks2(&mut device, &self.physical_path, encryption_params)?;
 
-        // Initialize stratis token
+        let keyslot = log_on_failure!(
+            device
+                .keyslot_handle()
+                .add_by_key(None, None, key, CryptVolumeKeyFlags::empty()),
+            "Failed to initialize keyslot with provided key in keyring"
+        );
+        set_keyring_token(&mut device, key_description, &[keyslot])?;
         log_on_failure!(
             device.token_handle().json_set(TokenInput::ReplaceToken(
                 STRATIS_TOKEN_ID,
                 &StratisLuks2Token {
                     devname: self.activation_name.clone(),
                     identifiers: self.identifiers,
+                    encryption_params: encryption_params.clone(),
+                    encryption_in_progress: true,
+                    auto_unlock: false,
                 }
                 .into(),
             )),
             "Failed to create the Stratis token"
         );
 
-        activate_and_check_device_path(device, key_description, &self.activation_name)
+        log_on_failure!(
+            device.reencrypt_handle().reencrypt_init_by_passphrase(
+                None,
+                key,
+                ANY_KEYSLOT,
+                keyslot as c_int,
+                encryption_params.cipher_and_mode(),
+                in_place_encryption_params(
+                    encryption_params,
+                    CryptReencryptFlags::new(vec![
+                        CryptReencryptFlag::InitializeOnly,
+                        CryptReencryptFlag::MoveFirstSegment,
+                    ]),
+                )?,
+            ),
+            "Failed to initialize encryption of device {}",
+            self.physical_path.display()
+        );
+        drop(device);
+
+        let mut device = log_on_failure!(
+            CryptInit::init(&self.physical_path),
+            "Failed to acquire context for device {} while initializing encryption",
+            self.physical_path.display()
+        );
+        log_on_failure!(
+            device
+                .backup_handle()
+                .header_restore(EncryptionFormat::Luks2, header_file.path()),
+            "Failed to write the LUKS2 header to device {}",
+            self.physical_path.display()
+        );
+        Ok(())
     }
 
     pub fn rollback(mut device: CryptDevice, physical_path: &Path, name: String) -> Result<()> {
@@ -356,8 +672,16 @@ impl CryptActivationHandle {
     /// * is a LUKS2 device
     /// * has a valid Stratis LUKS2 token
     /// * has a token of the proper type for LUKS2 keyring unlocking
-    pub fn setup(physical_path: &Path, unlock_method: UnlockMethod) -> Result<Option<CryptHandle>> {
-        setup_crypt_handle(physical_path, Some(unlock_method))
+    ///
+    /// If the unlock method is UnlockMethod::Passphrase, passphrase_keyfile
+    /// must be the path of an in-memory keyfile containing the passphrase;
+    /// it is ignored for any other unlock method.
+    pub fn setup(
+        physical_path: &Path,
+        unlock_method: UnlockMethod,
+        passphrase_keyfile: Option<&Path>,
+    ) -> Result<Option<CryptHandle>> {
+        setup_crypt_handle(physical_path, Some(unlock_method), passphrase_keyfile)
     }
 }
 
@@ -374,6 +698,8 @@ pub struct CryptHandle {
     identifiers: StratisIdentifiers,
     encryption_info: EncryptionInfo,
     name: String,
+    encryption_in_progress: bool,
+    reencryption_progress: Option<ReencryptionProgress>,
 }
 
 impl Debug for CryptHandle {
@@ -381,11 +707,14 @@ impl Debug for CryptHandle {
         write!(
             f,
             "CryptHandle {{ device: CryptDevice, physical_path: {}, identifiers: {}, \
-            encryption_info: {}, name: {} }}",
+            encryption_info: {}, name: {}, encryption_in_progress: {}, \
+            reencryption_progress: {:?} }}",
             self.luks2_device_path().display(),
             self.identifiers,
             self.encryption_info,
             self.name,
+            self.encryption_in_progress,
+            self.reencryption_progress,
         )
     }
 }
@@ -398,6 +727,7 @@ impl CryptHandle {
         identifiers: StratisIdentifiers,
         encryption_info: EncryptionInfo,
         name: String,
+        encryption_in_progress: bool,
     ) -> CryptHandle {
         let path = BlockDevPath::node_with_children(
             activated_path,
@@ -409,6 +739,8 @@ impl CryptHandle {
             identifiers,
             encryption_info,
             name,
+            encryption_in_progress,
+            reencryption_progress: None,
         }
     }
 
@@ -433,7 +765,7 @@ impl CryptHandle {
     /// * has a valid Stratis LUKS2 token
     /// * has a token of the proper type for LUKS2 keyring unlocking
     pub fn setup(physical_path: &Path) -> Result<Option<CryptHandle>> {
-        setup_crypt_handle(physical_path, None)
+        setup_crypt_handle(physical_path, None, None)
     }
 
     /// Get the encryption info for this encrypted device.
@@ -441,6 +773,61 @@ impl CryptHandle {
         &self.encryption_info
     }
 
+    /// Whether the contents of the device were encrypted in place and the
+    /// encryption has not yet been marked as finished.
+    pub fn encryption_in_progress(&self) -> bool {
+        self.encryption_in_progress
+    }
+
+    /// Record in the Stratis token that the in place encryption of the device
+    /// is complete.
+    pub fn finish_encryption(&mut self) -> Result<()> {
+        if !self.encryption_in_progress {
+            return Ok(());
+        }
+        self.replace_stratis_token(false, self.encryption_info.auto_unlock)?;
+        self.encryption_in_progress = false;
+        Ok(())
+    }
+
+    /// Record in the Stratis token whether the device should be unlocked
+    /// with its Clevis bindings as soon as it appears.
+    pub fn set_auto_unlock(&mut self, auto_unlock: bool) -> Result<()> {
+        if self.encryption_info.auto_unlock == auto_unlock {
+            return Ok(());
+        }
+        self.replace_stratis_token(self.encryption_in_progress, auto_unlock)?;
+        self.encryption_info.auto_unlock = auto_unlock;
+        Ok(())
+    }
+
+    /// Rewrite the Stratis token with the given values for the keys that
+    /// may change over the lifetime of the device.
+    fn replace_stratis_token(
+        &mut self,
+        encryption_in_progress: bool,
+        auto_unlock: bool,
+    ) -> Result<()> {
+        log_on_failure!(
+            self.device
+                .token_handle()
+                .json_set(TokenInput::ReplaceToken(
+                    STRATIS_TOKEN_ID,
+                    &StratisLuks2Token {
+                        devname: self.name.clone(),
+                        identifiers: self.identifiers,
+                        encryption_params: self.encryption_info.encryption_params.clone(),
+                        encryption_in_progress,
+                        auto_unlock,
+                    }
+                    .into(),
+                )),
+            "Failed to update the Stratis token of device {}",
+            self.luks2_device_path().display()
+        );
+        Ok(())
+    }
+
     /// Get a reference to the `BlockDevPath` node representing the physical device.
     pub fn get_physical_path_ref(&self) -> Arc<BlockDevPath> {
         self.path
@@ -478,31 +865,150 @@ impl CryptHandle {
         get_keyslot_number(&mut self.device, token_id)
     }
 
-    /// Get info for the clevis binding.
-    pub fn clevis_info(&mut self) -> Result<Option<(String, Value)>> {
+    /// Get info for the clevis bindings.
+    pub fn clevis_info(&mut self) -> Result<Vec<ClevisBinding>> {
         clevis_info_from_metadata(&mut self.device)
     }
 
     /// Bind the given device using clevis.
+    ///
+    /// If a binding ID is given, the token holding the new binding is moved
+    /// to the token slot with that ID so that a binding has the same ID on
+    /// all devices of a pool. Returns the ID of the new binding.
     pub fn clevis_bind(
         &mut self,
         keyfile_path: &Path,
         pin: &str,
         json: &Value,
         yes: bool,
-    ) -> Result<()> {
+        binding_id: Option<c_uint>,
+    ) -> Result<c_uint> {
+        self.check_not_reencrypting()?;
+        let old_ids = clevis_token_ids(&mut self.device);
         clevis_luks_bind(self.luks2_device_path(), keyfile_path, pin, &json, yes)
             .map_err(|e| LibcryptErr::Other(e.to_string()))?;
-        self.encryption_info.clevis_info = Some((pin.to_string(), json.clone()));
+
+        // Clevis modifies the LUKS2 metadata independently of this handle.
+        self.reload_metadata()?;
+        let new_id = clevis_token_ids(&mut self.device)
+            .into_iter()
+            .find(|id| !old_ids.contains(id))
+            .ok_or_else(|| {
+                LibcryptErr::Other(format!(
+                    "No new Clevis token was found on device {} after binding",
+                    self.luks2_device_path().display(),
+                ))
+            })?;
+
+        let id = match binding_id {
+            Some(binding_id) if binding_id != new_id => {
+                if let Err(e) = move_token(&mut self.device, new_id, binding_id) {
+                    if let Err(err) = self.unbind_token(new_id) {
+                        warn!(
+                            "Failed to remove Clevis binding in token slot {} from device {} \
+                            during rollback: {}",
+                            new_id,
+                            self.luks2_device_path().display(),
+                            err,
+                        );
+                    }
+                    return Err(e);
+                }
+                binding_id
+            }
+            _ => new_id,
+        };
+
+        self.encryption_info.clevis_info.push(ClevisBinding {
+            id,
+            pin: pin.to_string(),
+            config: json.clone(),
+        });
+        self.encryption_info.clevis_info.sort_by_key(|b| b.id);
+        Ok(id)
+    }
+
+    /// Remove the clevis binding with the given ID from the device.
+    pub fn clevis_unbind(&mut self, binding_id: c_uint) -> Result<()> {
+        if !self
+            .encryption_info
+            .clevis_info
+            .iter()
+            .any(|b| b.id == binding_id)
+        {
+            return Err(LibcryptErr::Other(format!(
+                "Device {} has no Clevis binding with ID {}",
+                self.luks2_device_path().display(),
+                binding_id,
+            )));
+        }
+        self.unbind_token(binding_id)?;
+        self.encryption_info
+            .clevis_info
+            .retain(|b| b.id != binding_id);
         Ok(())
     }
 
-    /// Unbind the given device using clevis.
-    pub fn clevis_unbind(&mut self) -> Result<()> {
-        let keyslots = self.keyslots(CLEVIS_LUKS_TOKEN_ID)?.ok_or_else(|| {
+    /// Give the clevis binding held in the token with ID new_id the ID of
+    /// the binding with ID binding_id, which it replaces. The replaced
+    /// binding is kept in a token slot that is not otherwise in use, so that
+    /// it can be removed once the new binding is in place on all devices of
+    /// the pool, or restored by calling this method again with its new ID.
+    /// Returns the new ID of the replaced binding.
+    pub fn clevis_replace(&mut self, binding_id: c_uint, new_id: c_uint) -> Result<c_uint> {
+        let device = &mut self.device;
+        let old_id = (0..LUKS2_TOKENS_MAX)
+            .find(|id| device.token_handle().json_get(*id).is_err())
+            .ok_or_else(|| {
+                LibcryptErr::Other(format!(
+                    "Device {} has no free token slot to hold Clevis binding {} while \
+                    it is replaced",
+                    self.luks2_device_path().display(),
+                    binding_id,
+                ))
+            })?;
+
+        move_token(&mut self.device, binding_id, old_id)?;
+        if let Err(e) = move_token(&mut self.device, new_id, binding_id) {
+            if let Err(err) = move_token(&mut self.device, old_id, binding_id) {
+                warn!(
+                    "Failed to move Clevis binding {} of device {} back from token \
+                    slot {} during rollback: {}",
+                    binding_id,
+                    self.luks2_device_path().display(),
+                    old_id,
+                    err,
+                );
+            }
+            return Err(e);
+        }
+
+        for binding in self.encryption_info.clevis_info.iter_mut() {
+            if binding.id == binding_id {
+                binding.id = old_id;
+            } else if binding.id == new_id {
+                binding.id = binding_id;
+                // Read the configuration back from the token, as when the
+                // device is set up, so that it records the thumbprint of
+                // the key the binding was made with.
+                if let Ok(Some((_, config))) =
+                    clevis_binding_from_token(&mut self.device, binding_id)
+                {
+                    binding.config = config;
+                }
+            }
+        }
+        self.encryption_info.clevis_info.sort_by_key(|b| b.id);
+        Ok(old_id)
+    }
+
+    /// Unbind all keyslots assigned to the Clevis token with the given ID,
+    /// which removes the token along with them.
+    fn unbind_token(&mut self, token_id: c_uint) -> Result<()> {
+        let keyslots = self.keyslots(token_id)?.ok_or_else(|| {
             LibcryptErr::Other(format!(
                 "Token slot {} appears to be empty; could not determine keyslots",
-                CLEVIS_LUKS_TOKEN_ID,
+                token_id,
             ))
         })?;
         for keyslot in keyslots {
@@ -514,7 +1020,385 @@ impl CryptHandle {
                 );
             }
         }
-        self.encryption_info.clevis_info = None;
+        self.reload_metadata()
+    }
+
+    /// Reload the LUKS2 metadata from the device after it was modified by
+    /// an external command.
+    fn reload_metadata(&mut self) -> Result<()> {
+        self.device
+            .context_handle()
+            .load::<()>(Some(EncryptionFormat::Luks2), None)
+    }
+
+    /// Change the key in the kernel keyring used to unlock the device.
+    ///
+    /// A keyslot is added for the key with the new key description, the LUKS2
+    /// keyring token is pointed at the new key description and keyslot and
+    /// only then are the keyslots of the old key destroyed. Both keys must be
+    /// in the kernel keyring. If any step fails before a keyslot of the old
+    /// key has been destroyed, the device is returned to its previous state;
+    /// after that, the device stays bound to the new key.
+    pub fn rebind_keyring(&mut self, new_key_desc: &KeyDescription) -> Result<()> {
+        self.check_not_reencrypting()?;
+        let old_key_desc = self.encryption_info.key_description.clone();
+        let old_keyslots = self.keyslots(LUKS2_TOKEN_ID)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Token slot {} appears to be empty; could not determine keyslots",
+                LUKS2_TOKEN_ID,
+            ))
+        })?;
+        let old_key = read_key(&old_key_desc)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Key with key description {} was not found in the kernel keyring",
+                old_key_desc.to_system_string(),
+            ))
+        })?;
+        let new_key = read_key(new_key_desc)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Key with key description {} was not found in the kernel keyring",
+                new_key_desc.to_system_string(),
+            ))
+        })?;
+
+        // A freshly loaded context uses the default PBKDF, so the PBKDF the
+        // device was formatted with must be set again for the new keyslot.
+        if let Some(pbkdf) = pbkdf_type(&self.encryption_info.encryption_params)? {
+            log_on_failure!(
+                self.device.settings_handle().set_pbkdf_type(&pbkdf),
+                "Failed to set the PBKDF for the new keyslot"
+            );
+        }
+        let new_keyslot = log_on_failure!(
+            self.device.keyslot_handle().add_by_passphrase(
+                None,
+                old_key.as_ref(),
+                new_key.as_ref()
+            ),
+            "Failed to add a keyslot for the key with key description {}",
+            new_key_desc.to_system_string()
+        );
+
+        if let Err(e) = set_keyring_token(&mut self.device, new_key_desc, &[new_keyslot]) {
+            self.restore_keyring_binding(&old_key_desc, &old_keyslots, new_keyslot);
+            return Err(e);
+        }
+
+        // The token now refers to the new keyslot only. Once an old keyslot
+        // has been destroyed, the new keyslot is the only one that the token
+        // can unlock, so a keyslot of the old key that can not be destroyed
+        // is left in place rather than undoing the change.
+        for (idx, keyslot) in old_keyslots.iter().enumerate() {
+            if let Err(e) = self.device.keyslot_handle().destroy(*keyslot) {
+                if idx != 0 {
+                    warn!(
+                        "Failed to destroy keyslot {} of device {} holding the key with \
+                        key description {}; the keyslot must be removed manually: {}",
+                        keyslot,
+                        self.luks2_device_path().display(),
+                        old_key_desc.to_system_string(),
+                        e,
+                    );
+                    continue;
+                }
+                self.restore_keyring_binding(&old_key_desc, &old_keyslots, new_keyslot);
+                return Err(e);
+            }
+        }
+
+        self.encryption_info.key_description = new_key_desc.clone();
+        Ok(())
+    }
+
+    /// Point the keyring token at the keyslots of the old key again and
+    /// destroy the keyslot added for the new key. The new keyslot is only
+    /// destroyed if the token could be restored.
+    fn restore_keyring_binding(
+        &mut self,
+        old_key_desc: &KeyDescription,
+        old_keyslots: &[c_uint],
+        new_keyslot: c_uint,
+    ) {
+        if let Err(err) = set_keyring_token(&mut self.device, old_key_desc, old_keyslots)
+            .and_then(|_| self.device.keyslot_handle().destroy(new_keyslot))
+        {
+            warn!(
+                "Failed to restore the keyring binding of device {} with key \
+                description {}; the device may need to be repaired manually: {}",
+                self.luks2_device_path().display(),
+                old_key_desc.to_system_string(),
+                err,
+            );
+        }
+    }
+
+    /// The progress of the reencryption of the device under a new volume
+    /// key, if the device is being reencrypted.
+    pub fn reencryption_progress(&self) -> Option<ReencryptionProgress> {
+        self.reencryption_progress
+    }
+
+    /// Return an error if the device is being reencrypted, since its
+    /// keyslots must not be changed until the reencryption is complete.
+    fn check_not_reencrypting(&self) -> Result<()> {
+        if self.reencryption_progress.is_some() {
+            return Err(LibcryptErr::Other(format!(
+                "Device {} is being reencrypted; its keyslots can not be changed until \
+                the reencryption is complete",
+                self.luks2_device_path().display(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Start the reencryption of the contents of the device under a newly
+    /// generated volume key, or resume it if it was paused by an error.
+    ///
+    /// A keyslot for the new volume key is added for the key in the kernel
+    /// keyring and assigned to the keyring token, so that the device can
+    /// still be activated with the key while it is being reencrypted, and
+    /// the reencryption is initialized in the LUKS2 metadata. No data is
+    /// reencrypted by this method; that is done by `reencrypt_step()`.
+    ///
+    /// Returns false if the device is already being reencrypted.
+    pub fn start_reencryption(&mut self) -> Result<bool> {
+        if let Some(ref mut progress) = self.reencryption_progress {
+            let paused = progress.paused;
+            progress.paused = false;
+            return Ok(paused);
+        }
+        if self.encryption_in_progress {
+            return Err(LibcryptErr::Other(format!(
+                "The in place encryption of device {} has not been finished",
+                self.luks2_device_path().display(),
+            )));
+        }
+        if !self.encryption_info.clevis_info.is_empty() {
+            return Err(LibcryptErr::Other(format!(
+                "Device {} is bound with Clevis; the Clevis binding must be removed \
+                before the device is reencrypted and can be restored afterwards",
+                self.luks2_device_path().display(),
+            )));
+        }
+
+        let key_description = self.encryption_info.key_description.clone();
+        let encryption_params = self.encryption_info.encryption_params.clone();
+        let key = read_key(&key_description)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Key with key description {} was not found in the kernel keyring",
+                key_description.to_system_string(),
+            ))
+        })?;
+        let old_keyslots = self.keyslots(LUKS2_TOKEN_ID)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Token slot {} appears to be empty; could not determine keyslots",
+                LUKS2_TOKEN_ID,
+            ))
+        })?;
+        let total = self.logical_device_size()?.bytes();
+
+        let volume_key_size = self.device.status_handle().get_volume_key_size();
+        if volume_key_size <= 0 {
+            return Err(LibcryptErr::Other(format!(
+                "Could not determine the volume key size of device {}",
+                self.luks2_device_path().display(),
+            )));
+        }
+        let mut volume_key = SafeMemHandle::alloc(volume_key_size as usize)?;
+        File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(volume_key.as_mut()))
+            .map_err(LibcryptErr::IOError)?;
+
+        // A freshly loaded context uses the default PBKDF, so the PBKDF the
+        // device was formatted with must be set again for the new keyslot.
+        if let Some(pbkdf) = pbkdf_type(&encryption_params)? {
+            log_on_failure!(
+                self.device.settings_handle().set_pbkdf_type(&pbkdf),
+                "Failed to set the PBKDF for the new keyslot"
+            );
+        }
+        let new_keyslot = log_on_failure!(
+            self.device.keyslot_handle().add_by_key(
+                None,
+                Some(volume_key.as_ref()),
+                key.as_ref(),
+                CryptVolumeKeyFlags::new(vec![CryptVolumeKeyFlag::NoSegment]),
+            ),
+            "Failed to add a keyslot for the new volume key of device {}",
+            self.luks2_device_path().display()
+        );
+
+        let mut keyslots = old_keyslots.clone();
+        keyslots.push(new_keyslot);
+        let name = self.name.clone();
+        let result =
+            set_keyring_token(&mut self.device, &key_description, &keyslots).and_then(|_| {
+                self.device
+                    .reencrypt_handle()
+                    .reencrypt_init_by_passphrase(
+                        Some(name.as_str()),
+                        key.as_ref(),
+                        ANY_KEYSLOT,
+                        new_keyslot as c_int,
+                        encryption_params.cipher_and_mode(),
+                        reencryption_params(
+                            &encryption_params,
+                            CryptReencryptFlags::new(vec![CryptReencryptFlag::InitializeOnly]),
+                        )?,
+                    )
+                    .map(|_| ())
+            });
+        if let Err(e) = result {
+            warn!(
+                "Failed to initialize the reencryption of device {}: {}",
+                self.luks2_device_path().display(),
+                e,
+            );
+            if let Err(err) = set_keyring_token(&mut self.device, &key_description, &old_keyslots)
+                .and_then(|_| self.device.keyslot_handle().destroy(new_keyslot))
+            {
+                warn!(
+                    "Failed to remove the keyslot for the new volume key of device {}; \
+                    the device may need to be repaired manually: {}",
+                    self.luks2_device_path().display(),
+                    err,
+                );
+            }
+            return Err(e);
+        }
+
+        self.reencryption_progress = Some(ReencryptionProgress {
+            processed: Bytes(0),
+            total,
+            paused: false,
+        });
+        Ok(true)
+    }
+
+    /// Reencrypt the contents of the device under its new volume key for
+    /// approximately the given duration at most. Does nothing if the device
+    /// is not being reencrypted or if its reencryption is paused.
+    ///
+    /// If the reencryption fails, it is paused, so that it is not retried
+    /// until `start_reencryption()` is called again.
+    pub fn reencrypt_step(&mut self, duration: Duration) -> Result<()> {
+        match self.reencryption_progress {
+            Some(ReencryptionProgress { paused: false, .. }) => (),
+            _ => return Ok(()),
+        }
+
+        let result = self.reencrypt_step_with_err(duration);
+        if result.is_err() {
+            if let Some(ref mut progress) = self.reencryption_progress {
+                progress.paused = true;
+            }
+        }
+        result
+    }
+
+    fn reencrypt_step_with_err(&mut self, duration: Duration) -> Result<()> {
+        let key_description = self.encryption_info.key_description.clone();
+        let encryption_params = self.encryption_info.encryption_params.clone();
+        let key = read_key(&key_description)?.ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Key with key description {} was not found in the kernel keyring",
+                key_description.to_system_string(),
+            ))
+        })?;
+
+        let mut status = reencryption_status(&mut self.device, &encryption_params)?;
+        if status != CryptReencryptInfo::None {
+            if status == CryptReencryptInfo::Crash {
+                log_on_failure!(
+                    self.device.reencrypt_handle().reencrypt_init_by_passphrase(
+                        Some(self.name.as_str()),
+                        key.as_ref(),
+                        ANY_KEYSLOT,
+                        ANY_KEYSLOT,
+                        encryption_params.cipher_and_mode(),
+                        reencryption_params(
+                            &encryption_params,
+                            CryptReencryptFlags::new(vec![CryptReencryptFlag::Recovery]),
+                        )?,
+                    ),
+                    "Failed to recover interrupted reencryption of device {}",
+                    self.luks2_device_path().display()
+                );
+            }
+            log_on_failure!(
+                self.device.reencrypt_handle().reencrypt_init_by_passphrase(
+                    Some(self.name.as_str()),
+                    key.as_ref(),
+                    ANY_KEYSLOT,
+                    ANY_KEYSLOT,
+                    encryption_params.cipher_and_mode(),
+                    reencryption_params(
+                        &encryption_params,
+                        CryptReencryptFlags::new(vec![CryptReencryptFlag::ResumeOnly]),
+                    )?,
+                ),
+                "Failed to load the reencryption state of device {}",
+                self.luks2_device_path().display()
+            );
+
+            REENCRYPTION_STEP.with(|step| step.set((Some(Instant::now() + duration), 0, 0)));
+            let result = self.device.reencrypt_handle().reencrypt(Some(
+                reencryption_progress as unsafe extern "C" fn(u64, u64, *mut c_void) -> c_int,
+            ));
+            let (_, size, offset) = REENCRYPTION_STEP.with(|step| step.replace((None, 0, 0)));
+            log_on_failure!(
+                result,
+                "Failed to reencrypt the contents of device {}",
+                self.luks2_device_path().display()
+            );
+            if let Some(ref mut progress) = self.reencryption_progress {
+                if size > 0 {
+                    progress.processed = Bytes(u128::from(offset));
+                    progress.total = Bytes(u128::from(size));
+                }
+            }
+
+            status = reencryption_status(&mut self.device, &encryption_params)?;
+        }
+
+        if status == CryptReencryptInfo::None {
+            self.finish_reencryption()?;
+        }
+        Ok(())
+    }
+
+    /// Once the contents of the device have been reencrypted, destroy any
+    /// keyslots of the old volume key that remain and leave the keyring
+    /// token with the keyslot of the new volume key.
+    fn finish_reencryption(&mut self) -> Result<()> {
+        let keyslots = self.keyslots(LUKS2_TOKEN_ID)?.unwrap_or_default();
+        let mut remaining = Vec::new();
+        for keyslot in keyslots {
+            match log_on_failure!(
+                self.device.keyslot_handle().status(keyslot),
+                "Failed to get the status of keyslot {}",
+                keyslot
+            ) {
+                KeyslotInfo::Inactive | KeyslotInfo::Invalid => (),
+                KeyslotInfo::Unbound => log_on_failure!(
+                    self.device.keyslot_handle().destroy(keyslot),
+                    "Failed to destroy keyslot {} of the old volume key",
+                    keyslot
+                ),
+                KeyslotInfo::Active | KeyslotInfo::ActiveLast => remaining.push(keyslot),
+            }
+        }
+        set_keyring_token(
+            &mut self.device,
+            &self.encryption_info.key_description,
+            &remaining,
+        )?;
+
+        info!(
+            "Finished reencrypting device {} under a new volume key",
+            self.luks2_device_path().display()
+        );
+        self.reencryption_progress = None;
         Ok(())
     }
 
@@ -547,6 +1431,7 @@ impl CryptHandle {
 fn setup_crypt_handle(
     physical_path: &Path,
     unlock_method: Option<UnlockMethod>,
+    passphrase_keyfile: Option<&Path>,
 ) -> Result<Option<CryptHandle>> {
     let device_result = device_from_physical_path(physical_path);
     let mut device = match device_result {
@@ -574,6 +1459,9 @@ fn setup_crypt_handle(
         }
     };
     let clevis_info = clevis_info_from_metadata(&mut device)?;
+    let encryption_params = encryption_params_from_metadata(&mut device)?;
+    let encryption_in_progress = encryption_in_progress_from_metadata(&mut device)?;
+    let auto_unlock = auto_unlock_from_metadata(&mut device)?;
     let name = name_from_metadata(&mut device)?;
 
     let activated_path = match unlock_method {
@@ -581,9 +1469,49 @@ fn setup_crypt_handle(
             activate(&mut device, Either::Left(&key_description), &name)?
         }
         Some(UnlockMethod::Clevis) => activate(&mut device, Either::Right(physical_path), &name)?,
+        Some(UnlockMethod::Passphrase) => {
+            let keyfile = passphrase_keyfile.ok_or_else(|| {
+                LibcryptErr::Other(
+                    "Unlocking with a passphrase requires a keyfile containing the passphrase"
+                        .to_string(),
+                )
+            })?;
+            activate_by_keyfile_and_check_device_path(&mut device, keyfile, &name)?
+        }
         None => [DEVICEMAPPER_PATH, &name].iter().collect(),
     };
 
+    // The progress of an interrupted in place encryption is recorded in the
+    // Stratis token; any other reencryption in progress is the reencryption
+    // of the device under a new volume key, which is resumed.
+    let reencryption_progress = if encryption_in_progress {
+        None
+    } else {
+        match reencryption_status(&mut device, &encryption_params)? {
+            CryptReencryptInfo::None => None,
+            CryptReencryptInfo::Invalid => {
+                return Err(LibcryptErr::Other(format!(
+                    "The reencryption metadata on device {} is invalid",
+                    physical_path.display(),
+                )));
+            }
+            CryptReencryptInfo::Clean | CryptReencryptInfo::Crash => {
+                // How much of the device has already been reencrypted is
+                // not known until the reencryption is resumed.
+                let total = device
+                    .runtime_handle(&name)
+                    .get_active_device()
+                    .map(|active| Sectors(active.size).bytes())
+                    .unwrap_or(Bytes(0));
+                Some(ReencryptionProgress {
+                    processed: Bytes(0),
+                    total,
+                    paused: false,
+                })
+            }
+        }
+    };
+
     Ok(Some(CryptHandle {
         device,
         path: BlockDevPath::node_with_children(
@@ -594,11 +1522,135 @@ fn setup_crypt_handle(
         encryption_info: EncryptionInfo {
             key_description,
             clevis_info,
+            encryption_params,
+            auto_unlock,
         },
         name,
+        encryption_in_progress,
+        reencryption_progress,
     }))
 }
 
+/// Get the LUKS2 parameters for formatting a device with the given encryption
+/// parameters.
+fn luks2_format_params(encryption_params: &EncryptionParams) -> Result<CryptParamsLuks2> {
+    Ok(CryptParamsLuks2 {
+        pbkdf: pbkdf_type(encryption_params)?,
+        integrity: None,
+        integrity_params: None,
+        data_alignment: 0,
+        data_device: None,
+        sector_size: encryption_params.sector_size,
+        label: None,
+        subsystem: None,
+    })
+}
+
+/// Format the device with a LUKS2 header using the given encryption
+/// parameters.
+fn format_luks2(
+    device: &mut CryptDevice,
+    physical_path: &Path,
+    encryption_params: &EncryptionParams,
+) -> Result<()> {
+    // The PBKDF given here is also used for all keyslots subsequently
+    // added through this context.
+    let luks2_params = luks2_format_params(encryption_params)?;
+    let mut luks2_params_ref: CryptParamsLuks2Ref = (&luks2_params).try_into()?;
+    log_on_failure!(
+        device.context_handle().format(
+            EncryptionFormat::Luks2,
+            encryption_params.cipher_and_mode(),
+            None,
+            libcryptsetup_rs::Either::Right(encryption_params.key_size as usize / 8),
+            Some(&mut luks2_params_ref.inner),
+        ),
+        "Failed to format device {} with LUKS2 header using encryption parameters {}",
+        physical_path.display(),
+        encryption_params
+    );
+    Ok(())
+}
+
+/// Get the parameters for encrypting the contents of a device in place with
+/// the given encryption parameters.
+fn in_place_encryption_params(
+    encryption_params: &EncryptionParams,
+    flags: CryptReencryptFlags,
+) -> Result<CryptParamsReencrypt> {
+    Ok(CryptParamsReencrypt {
+        mode: CryptReencryptModeInfo::Encrypt,
+        direction: CryptReencryptDirectionInfo::Backward,
+        resilience: DATA_SHIFT_RESILIENCE.to_string(),
+        hash: REENCRYPT_HASH.to_string(),
+        data_shift: *IN_PLACE_ENCRYPTION_SPACE,
+        max_hotzone_size: 0,
+        device_size: 0,
+        luks2: luks2_format_params(encryption_params)?,
+        flags,
+    })
+}
+
+/// Get the parameters for reencrypting the contents of an encrypted device
+/// under a new volume key with the given encryption parameters.
+fn reencryption_params(
+    encryption_params: &EncryptionParams,
+    flags: CryptReencryptFlags,
+) -> Result<CryptParamsReencrypt> {
+    Ok(CryptParamsReencrypt {
+        mode: CryptReencryptModeInfo::Reencrypt,
+        direction: CryptReencryptDirectionInfo::Forward,
+        resilience: CHECKSUM_RESILIENCE.to_string(),
+        hash: REENCRYPT_HASH.to_string(),
+        data_shift: 0,
+        max_hotzone_size: 0,
+        device_size: 0,
+        luks2: luks2_format_params(encryption_params)?,
+        flags,
+    })
+}
+
+/// Get the reencryption status recorded in the LUKS2 metadata of a device.
+fn reencryption_status(
+    device: &mut CryptDevice,
+    encryption_params: &EncryptionParams,
+) -> Result<CryptReencryptInfo> {
+    Ok(log_on_failure!(
+        device.reencrypt_handle().status(reencryption_params(
+            encryption_params,
+            CryptReencryptFlags::empty()
+        )?),
+        "Failed to get the reencryption status of the device"
+    ))
+}
+
+/// Get the libcryptsetup representation of the PBKDF specified by the
+/// encryption parameters, or None if the libcryptsetup default should be used.
+/// Costs that are not specified are given their default values for the PBKDF.
+fn pbkdf_type(encryption_params: &EncryptionParams) -> Result<Option<CryptPbkdfType>> {
+    let kdf = match encryption_params.pbkdf {
+        Some(Pbkdf::Pbkdf2) => CryptKdf::Pbkdf2,
+        Some(Pbkdf::Argon2i) => CryptKdf::Argon2I,
+        Some(Pbkdf::Argon2id) => CryptKdf::Argon2Id,
+        None => return Ok(None),
+    };
+    let mut pbkdf = log_on_failure!(
+        CryptSettings::get_pbkdf_type_params(&kdf),
+        "Failed to get the default parameters for PBKDF {:?}",
+        kdf
+    );
+    if let Some(memory) = encryption_params.pbkdf_memory_kb {
+        pbkdf.max_memory_kb = memory;
+    }
+    if let Some(time) = encryption_params.pbkdf_time_ms {
+        pbkdf.time_ms = time;
+    }
+    if let Some(threads) = encryption_params.pbkdf_parallel_threads {
+        pbkdf.parallel_threads = threads;
+    }
+    Ok(Some(pbkdf))
+}
+
 /// Create a device handle and load the LUKS2 header into memory from
 /// a physical path.
 fn device_from_physical_path(physical_path: &Path) -> Result<Option<CryptDevice>> {
@@ -618,8 +1670,57 @@ fn device_from_physical_path(physical_path: &Path) -> Result<Option<CryptDevice>
     }
 }
 
-fn clevis_info_from_metadata(device: &mut CryptDevice) -> Result<Option<(String, Value)>> {
-    let json = match device.token_handle().json_get(CLEVIS_LUKS_TOKEN_ID).ok() {
+/// Get the IDs of all tokens that hold Clevis bindings.
+fn clevis_token_ids(device: &mut CryptDevice) -> Vec<c_uint> {
+    (0..LUKS2_TOKENS_MAX)
+        .filter(|id| {
+            device
+                .token_handle()
+                .json_get(*id)
+                .ok()
+                .and_then(|json| {
+                    json.get(TOKEN_TYPE_KEY)
+                        .and_then(|type_val| type_val.as_str())
+                        .map(|type_str| type_str == CLEVIS_TOKEN_TYPE)
+                })
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Move the token in one slot to another, empty, slot.
+fn move_token(device: &mut CryptDevice, from: c_uint, to: c_uint) -> Result<()> {
+    if device.token_handle().json_get(to).is_ok() {
+        return Err(LibcryptErr::Other(format!(
+            "Token slot {} is already in use",
+            to
+        )));
+    }
+    let json = device.token_handle().json_get(from)?;
+    device
+        .token_handle()
+        .json_set(TokenInput::ReplaceToken(to, &json))?;
+    device
+        .token_handle()
+        .json_set(TokenInput::RemoveToken(from))?;
+    Ok(())
+}
+
+fn clevis_info_from_metadata(device: &mut CryptDevice) -> Result<Vec<ClevisBinding>> {
+    let mut bindings = Vec::new();
+    for id in clevis_token_ids(device) {
+        if let Some((pin, config)) = clevis_binding_from_token(device, id)? {
+            bindings.push(ClevisBinding { id, pin, config });
+        }
+    }
+    Ok(bindings)
+}
+
+fn clevis_binding_from_token(
+    device: &mut CryptDevice,
+    token_id: c_uint,
+) -> Result<Option<(String, Value)>> {
+    let json = match device.token_handle().json_get(token_id).ok() {
         Some(j) => j,
         None => return Ok(None),
     };
@@ -660,7 +1761,21 @@ pub fn interpret_clevis_config(pin: &str, clevis_config: &mut Value) -> Result<b
         false
     };
 
-    Ok(yes)
+    Ok(yes)
+}
+
+/// Remove the thumbprints of the tang signing keys from a Clevis
+/// configuration, including those of tang pins nested in an sss pin, so that
+/// binding with it trusts the keys the tang servers currently advertise.
+pub fn remove_tang_thumbprints(clevis_config: &mut Value) {
+    match clevis_config {
+        Value::Object(map) => {
+            map.remove("thp");
+            map.values_mut().for_each(remove_tang_thumbprints);
+        }
+        Value::Array(array) => array.iter_mut().for_each(remove_tang_thumbprints),
+        _ => (),
+    }
 }
 
 /// Generate tang JSON
@@ -876,6 +1991,39 @@ fn activate_and_check_device_path(
         name
     );
 
+    check_device_path(crypt_device, name)
+}
+
+/// Activate device with the passphrase contained in the given keyfile then
+/// check that the logical path exists corresponding to the activation name
+/// passed into this method. Any keyslot that the passphrase opens may be
+/// used.
+fn activate_by_keyfile_and_check_device_path(
+    crypt_device: &mut CryptDevice,
+    keyfile: &Path,
+    name: &str,
+) -> Result<PathBuf> {
+    log_on_failure!(
+        crypt_device
+            .activate_handle()
+            .activate_by_keyfile_device_offset(
+                Some(name),
+                None,
+                keyfile,
+                None,
+                0,
+                CryptActivateFlags::empty(),
+            ),
+        "Failed to activate device with name {} using the provided passphrase",
+        name
+    );
+
+    check_device_path(crypt_device, name)
+}
+
+/// Check that the device was activated and that the logical path exists
+/// corresponding to the activation name passed into this method.
+fn check_device_path(crypt_device: &mut CryptDevice, name: &str) -> Result<PathBuf> {
     // Check activation status.
     if !device_is_active(crypt_device, name) {
         warn!(
@@ -957,6 +2105,30 @@ fn get_keyslot_number(device: &mut CryptDevice, token_id: c_uint) -> Result<Opti
     ))
 }
 
+/// Point the LUKS2 keyring token at the given key description and assign it
+/// the given keyslots, replacing any keyring token already present.
+fn set_keyring_token(
+    device: &mut CryptDevice,
+    key_description: &KeyDescription,
+    keyslots: &[c_uint],
+) -> Result<()> {
+    log_on_failure!(
+        device
+            .token_handle()
+            .luks2_keyring_set(Some(LUKS2_TOKEN_ID), &key_description.to_system_string()),
+        "Failed to initialize the LUKS2 token for driving keyring activation operations"
+    );
+    for keyslot in keyslots {
+        log_on_failure!(
+            device
+                .token_handle()
+                .assign_keyslot(LUKS2_TOKEN_ID, Some(*keyslot)),
+            "Failed to assign the LUKS2 keyring token to the Stratis keyslot"
+        );
+    }
+    Ok(())
+}
+
 /// Deactivate an encrypted Stratis device but do not wipe it. This is not
 /// a destructive action. `name` should be the name of the device as registered
 /// with devicemapper and cryptsetup. This method is idempotent and leaves
@@ -1142,6 +2314,103 @@ fn key_desc_from_metadata(device: &mut CryptDevice) -> Result<String> {
     Ok(key_desc)
 }
 
+/// Get the encryption parameters from the map of a Stratis token. Devices
+/// initialized before the encryption parameters were recorded in the token
+/// were all formatted with the default parameters.
+fn encryption_params_from_token(map: &Map<String, Value>) -> Result<EncryptionParams> {
+    match map.get(STRATIS_TOKEN_ENCRYPTION_PARAMS_KEY) {
+        Some(value) => serde_json::from_value(value.clone()).map_err(|e| {
+            LibcryptErr::Other(format!(
+                "Malformed JSON value for key {} in Stratis token: {}",
+                STRATIS_TOKEN_ENCRYPTION_PARAMS_KEY, e
+            ))
+        }),
+        None => Ok(EncryptionParams::default()),
+    }
+}
+
+/// Get whether the in place encryption of the device is in progress from the
+/// map of a Stratis token. The key is only present while it is.
+fn encryption_in_progress_from_token(map: &Map<String, Value>) -> Result<bool> {
+    match map.get(STRATIS_TOKEN_ENCRYPTION_IN_PROGRESS_KEY) {
+        Some(value) => value.as_bool().ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Malformed JSON value for key {} in Stratis token",
+                STRATIS_TOKEN_ENCRYPTION_IN_PROGRESS_KEY
+            ))
+        }),
+        None => Ok(false),
+    }
+}
+
+/// Query the Stratis metadata for whether the in place encryption of the
+/// device is in progress.
+fn encryption_in_progress_from_metadata(device: &mut CryptDevice) -> Result<bool> {
+    let json = log_on_failure!(
+        device.token_handle().json_get(STRATIS_TOKEN_ID),
+        "Failed to get Stratis JSON token from LUKS2 metadata"
+    );
+    let map = json
+        .as_object()
+        .ok_or_else(|| LibcryptErr::Other("Stratis JSON token is not a JSON object".to_string()))?;
+    let encryption_in_progress = log_on_failure!(
+        encryption_in_progress_from_token(map),
+        "Could not get value for key {} from Stratis JSON token",
+        STRATIS_TOKEN_ENCRYPTION_IN_PROGRESS_KEY
+    );
+    Ok(encryption_in_progress)
+}
+
+/// Get whether the device should be unlocked automatically from the map of
+/// a Stratis token. The key is only present if it should.
+fn auto_unlock_from_token(map: &Map<String, Value>) -> Result<bool> {
+    match map.get(STRATIS_TOKEN_AUTO_UNLOCK_KEY) {
+        Some(value) => value.as_bool().ok_or_else(|| {
+            LibcryptErr::Other(format!(
+                "Malformed JSON value for key {} in Stratis token",
+                STRATIS_TOKEN_AUTO_UNLOCK_KEY
+            ))
+        }),
+        None => Ok(false),
+    }
+}
+
+/// Query the Stratis metadata for whether the device should be unlocked
+/// automatically.
+fn auto_unlock_from_metadata(device: &mut CryptDevice) -> Result<bool> {
+    let json = log_on_failure!(
+        device.token_handle().json_get(STRATIS_TOKEN_ID),
+        "Failed to get Stratis JSON token from LUKS2 metadata"
+    );
+    let map = json
+        .as_object()
+        .ok_or_else(|| LibcryptErr::Other("Stratis JSON token is not a JSON object".to_string()))?;
+    let auto_unlock = log_on_failure!(
+        auto_unlock_from_token(map),
+        "Could not get value for key {} from Stratis JSON token",
+        STRATIS_TOKEN_AUTO_UNLOCK_KEY
+    );
+    Ok(auto_unlock)
+}
+
+/// Query the Stratis metadata for the parameters with which the device was
+/// formatted.
+fn encryption_params_from_metadata(device: &mut CryptDevice) -> Result<EncryptionParams> {
+    let json = log_on_failure!(
+        device.token_handle().json_get(STRATIS_TOKEN_ID),
+        "Failed to get Stratis JSON token from LUKS2 metadata"
+    );
+    let map = json
+        .as_object()
+        .ok_or_else(|| LibcryptErr::Other("Stratis JSON token is not a JSON object".to_string()))?;
+    let encryption_params = log_on_failure!(
+        encryption_params_from_token(map),
+        "Could not get value for key {} from Stratis JSON token",
+        STRATIS_TOKEN_ENCRYPTION_PARAMS_KEY
+    );
+    Ok(encryption_params)
+}
+
 /// Query the Stratis metadata for the device identifiers.
 fn identifiers_from_metadata(device: &mut CryptDevice) -> Result<StratisIdentifiers> {
     let json = log_on_failure!(
@@ -1186,8 +2455,16 @@ mod tests {
         ptr, slice,
     };
 
+    use libcryptsetup_rs::{KeyslotInfo, SafeMemHandle};
+
     use crate::{
-        engine::strat_engine::tests::{crypt, loopbacked, real},
+        engine::{
+            engine::{KeyActions, MAX_STRATIS_PASS_SIZE},
+            strat_engine::{
+                keys::{MemoryMappedKeyfile, StratKeyActions},
+                tests::{crypt, loopbacked, real},
+            },
+        },
         stratis::StratisError,
     };
 
@@ -1207,7 +2484,7 @@ mod tests {
         let dev_uuid = DevUuid::new_v4();
 
         let result = CryptInitializer::new((*path).to_owned(), pool_uuid, dev_uuid)
-            .initialize(&key_description);
+            .initialize(&key_description, &EncryptionParams::default());
 
         // Initialization cannot occur with a non-existent key
         assert!(result.is_err());
@@ -1256,7 +2533,7 @@ mod tests {
                 let dev_uuid = DevUuid::new_v4();
 
                 let handle = CryptInitializer::new((*path).to_owned(), pool_uuid, dev_uuid)
-                    .initialize(key_desc)?;
+                    .initialize(key_desc, &EncryptionParams::default())?;
                 handles.push(handle);
             }
 
@@ -1322,6 +2599,398 @@ mod tests {
         );
     }
 
+    /// Test that a device is formatted with the encryption parameters given
+    /// on initialization and that the parameters are recorded so that they
+    /// are found again when the device is set up.
+    fn test_encryption_params(paths: &[&Path]) {
+        fn crypt_test(
+            paths: &[&Path],
+            key_desc: &KeyDescription,
+            _: Option<()>,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            let path = paths[0];
+
+            let encryption_params = EncryptionParams {
+                cipher: "aes-xts-plain64".to_string(),
+                key_size: 256,
+                pbkdf: Some(Pbkdf::Argon2id),
+                pbkdf_memory_kb: Some(64 * 1024),
+                pbkdf_time_ms: Some(100),
+                pbkdf_parallel_threads: Some(1),
+                sector_size: 4096,
+            };
+
+            let mut handle =
+                CryptInitializer::new(path.to_owned(), PoolUuid::new_v4(), DevUuid::new_v4())
+                    .initialize(key_desc, &encryption_params)?;
+            assert_eq!(
+                handle.encryption_info().encryption_params,
+                encryption_params
+            );
+
+            let device = handle.as_crypt_device();
+            assert_eq!(libcryptsetup_rs::get_sector_size(Some(device)), 4096);
+            assert_eq!(device.status_handle().get_volume_key_size(), 256 / 8);
+            assert_eq!(device.status_handle().get_cipher()?, "aes");
+            assert_eq!(device.status_handle().get_cipher_mode()?, "xts-plain64");
+
+            let keyslot = handle
+                .keyslots(LUKS2_TOKEN_ID)?
+                .and_then(|keyslots| keyslots.into_iter().next())
+                .expect("a keyslot is assigned to the LUKS2 token");
+            let pbkdf = handle
+                .as_crypt_device()
+                .keyslot_handle()
+                .get_pbkdf(keyslot)?;
+            assert_eq!(pbkdf.type_, CryptKdf::Argon2Id);
+            assert!(pbkdf.max_memory_kb <= 64 * 1024);
+            assert_eq!(pbkdf.parallel_threads, 1);
+
+            let setup_handle = CryptHandle::setup(path)?.expect("device was just initialized");
+            assert_eq!(
+                setup_handle.encryption_info().encryption_params,
+                encryption_params
+            );
+
+            handle.wipe()?;
+
+            Ok(())
+        }
+
+        assert_eq!(paths.len(), 1);
+
+        crypt::insert_and_cleanup_key(paths, crypt_test);
+    }
+
+    #[test]
+    fn loop_test_encryption_params() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_encryption_params,
+        );
+    }
+
+    #[test]
+    fn real_test_encryption_params() {
+        real::test_with_spec(
+            &real::DeviceLimits::Exactly(1, None, Some(Sectors(1024 * 1024 * 1024 / 512))),
+            test_encryption_params,
+        );
+    }
+
+    #[test]
+    fn travis_test_encryption_params() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_encryption_params,
+        );
+    }
+
+    /// Test that rebinding a device to a new key in the kernel keyring points
+    /// the LUKS2 keyring token at the new key description and a new keyslot,
+    /// destroys the old keyslot and that the device can be unlocked with the
+    /// new key alone.
+    fn test_rebind_keyring(paths: &[&Path]) {
+        fn crypt_test(
+            paths: &[&Path],
+            key_desc: &KeyDescription,
+            _: Option<()>,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            let path = paths[0];
+
+            let mut handle =
+                CryptInitializer::new(path.to_owned(), PoolUuid::new_v4(), DevUuid::new_v4())
+                    .initialize(key_desc, &EncryptionParams::default())?;
+            let old_keyslots = handle
+                .keyslots(LUKS2_TOKEN_ID)?
+                .expect("a keyslot is assigned to the LUKS2 token");
+
+            let new_key_desc =
+                KeyDescription::try_from("test-rebind-description-for-stratisd".to_string())
+                    .expect("no semi-colons");
+            assert!(handle.rebind_keyring(&new_key_desc).is_err());
+            assert_eq!(&handle.encryption_info().key_description, key_desc);
+            assert_eq!(handle.keyslots(LUKS2_TOKEN_ID)?, Some(old_keyslots.clone()));
+
+            let mut mem = SafeMemHandle::alloc(MAX_STRATIS_PASS_SIZE)?;
+            File::open("/dev/urandom")?.read_exact(mem.as_mut())?;
+            StratKeyActions.set_no_fd(
+                &new_key_desc,
+                SizedKeyMemory::new(mem, MAX_STRATIS_PASS_SIZE),
+            )?;
+
+            let result = (|| -> std::result::Result<(), Box<dyn Error>> {
+                handle.rebind_keyring(&new_key_desc)?;
+                assert_eq!(handle.encryption_info().key_description, new_key_desc);
+
+                let new_keyslots = handle
+                    .keyslots(LUKS2_TOKEN_ID)?
+                    .expect("a keyslot is assigned to the LUKS2 token");
+                assert_eq!(new_keyslots.len(), 1);
+                for keyslot in old_keyslots.iter() {
+                    assert!(!new_keyslots.contains(keyslot));
+                    assert_eq!(
+                        handle.as_crypt_device().keyslot_handle().status(*keyslot)?,
+                        KeyslotInfo::Inactive
+                    );
+                }
+
+                let setup_handle = CryptHandle::setup(path)?.expect("device was just initialized");
+                assert_eq!(setup_handle.encryption_info().key_description, new_key_desc);
+                Ok(())
+            })();
+
+            StratKeyActions.unset(&new_key_desc)?;
+            handle.wipe()?;
+
+            result
+        }
+
+        assert_eq!(paths.len(), 1);
+
+        crypt::insert_and_cleanup_key(paths, crypt_test);
+    }
+
+    #[test]
+    fn loop_test_rebind_keyring() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_rebind_keyring,
+        );
+    }
+
+    #[test]
+    fn real_test_rebind_keyring() {
+        real::test_with_spec(
+            &real::DeviceLimits::Exactly(1, None, Some(Sectors(1024 * 1024 * 1024 / 512))),
+            test_rebind_keyring,
+        );
+    }
+
+    #[test]
+    fn travis_test_rebind_keyring() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_rebind_keyring,
+        );
+    }
+
+    /// Initialize an encrypted device and deactivate it. Verify that it can
+    /// not be unlocked with a passphrase unless a keyfile containing the
+    /// passphrase is given and that it can be unlocked with a keyfile
+    /// containing the key from the kernel keyring.
+    fn test_passphrase_unlock(paths: &[&Path]) {
+        fn crypt_test(
+            paths: &[&Path],
+            key_desc: &KeyDescription,
+            _: Option<()>,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            let path = paths[0];
+
+            let mut handle =
+                CryptInitializer::new(path.to_owned(), PoolUuid::new_v4(), DevUuid::new_v4())
+                    .initialize(key_desc, &EncryptionParams::default())?;
+            handle.deactivate()?;
+
+            let result = (|| -> std::result::Result<(), Box<dyn Error>> {
+                assert!(
+                    CryptActivationHandle::setup(path, UnlockMethod::Passphrase, None).is_err()
+                );
+
+                let passphrase = read_key(key_desc)?.expect("key was inserted into the keyring");
+                let tmpdir = tempfile::tempdir()?;
+                let keyfile =
+                    MemoryMappedKeyfile::new(&tmpdir.path().join("passphrase"), passphrase)?;
+                let setup_handle = CryptActivationHandle::setup(
+                    path,
+                    UnlockMethod::Passphrase,
+                    Some(keyfile.keyfile_path()),
+                )?
+                .expect("device was just initialized");
+                assert!(setup_handle.activated_device_path().exists());
+                Ok(())
+            })();
+
+            handle.wipe()?;
+
+            result
+        }
+
+        assert_eq!(paths.len(), 1);
+
+        crypt::insert_and_cleanup_key(paths, crypt_test);
+    }
+
+    #[test]
+    fn loop_test_passphrase_unlock() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_passphrase_unlock,
+        );
+    }
+
+    #[test]
+    fn real_test_passphrase_unlock() {
+        real::test_with_spec(
+            &real::DeviceLimits::Exactly(1, None, Some(Sectors(1024 * 1024 * 1024 / 512))),
+            test_passphrase_unlock,
+        );
+    }
+
+    #[test]
+    fn travis_test_passphrase_unlock() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_passphrase_unlock,
+        );
+    }
+
+    /// Initialize an encrypted device and reencrypt it under a new volume key.
+    /// Verify that starting the reencryption again while it is running
+    /// changes nothing, that progress is reported until it is finished and
+    /// that afterwards only the new keyslot is referenced by the token.
+    fn test_reencrypt(paths: &[&Path]) {
+        fn crypt_test(
+            paths: &[&Path],
+            key_desc: &KeyDescription,
+            _: Option<()>,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            let path = paths[0];
+
+            let mut handle =
+                CryptInitializer::new(path.to_owned(), PoolUuid::new_v4(), DevUuid::new_v4())
+                    .initialize(key_desc, &EncryptionParams::default())?;
+            let old_keyslots = handle
+                .keyslots(LUKS2_TOKEN_ID)?
+                .expect("a keyslot is assigned to the LUKS2 token");
+
+            let result = (|| -> std::result::Result<(), Box<dyn Error>> {
+                assert!(handle.start_reencryption()?);
+                assert!(!handle.start_reencryption()?);
+                assert_matches!(
+                    handle.reencryption_progress(),
+                    Some(ReencryptionProgress { paused: false, .. })
+                );
+
+                while handle.reencryption_progress().is_some() {
+                    handle.reencrypt_step(Duration::from_secs(1))?;
+                }
+
+                let new_keyslots = handle
+                    .keyslots(LUKS2_TOKEN_ID)?
+                    .expect("a keyslot is assigned to the LUKS2 token");
+                assert_eq!(new_keyslots.len(), 1);
+                for keyslot in old_keyslots.iter() {
+                    assert!(!new_keyslots.contains(keyslot));
+                    assert_eq!(
+                        handle.as_crypt_device().keyslot_handle().status(*keyslot)?,
+                        KeyslotInfo::Inactive
+                    );
+                }
+
+                let setup_handle = CryptHandle::setup(path)?.expect("device was just initialized");
+                assert_eq!(setup_handle.reencryption_progress(), None);
+                Ok(())
+            })();
+
+            handle.wipe()?;
+
+            result
+        }
+
+        assert_eq!(paths.len(), 1);
+
+        crypt::insert_and_cleanup_key(paths, crypt_test);
+    }
+
+    #[test]
+    fn loop_test_reencrypt() {
+        loopbacked::test_with_spec(&loopbacked::DeviceLimits::Exactly(1, None), test_reencrypt);
+    }
+
+    #[test]
+    fn real_test_reencrypt() {
+        real::test_with_spec(
+            &real::DeviceLimits::Exactly(1, None, Some(Sectors(1024 * 1024 * 1024 / 512))),
+            test_reencrypt,
+        );
+    }
+
+    #[test]
+    fn travis_test_reencrypt() {
+        loopbacked::test_with_spec(&loopbacked::DeviceLimits::Exactly(1, None), test_reencrypt);
+    }
+
+    /// Write random data to the start of a device, encrypt the device in
+    /// place and verify that the data can be read from the activated
+    /// logical device. Verify that the encryption is recorded as in progress
+    /// until it is finished.
+    fn test_encrypt_in_place(paths: &[&Path]) {
+        fn crypt_test(
+            paths: &[&Path],
+            key_desc: &KeyDescription,
+            _: Option<()>,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            let path = paths[0];
+
+            let mut data = vec![0u8; 1024 * 1024];
+            File::open("/dev/urandom")?.read_exact(&mut data)?;
+            {
+                let mut f = OpenOptions::new().write(true).open(path)?;
+                f.write_all(&data)?;
+                f.sync_all()?;
+            }
+
+            let mut handle =
+                CryptInitializer::new(path.to_owned(), PoolUuid::new_v4(), DevUuid::new_v4())
+                    .encrypt_in_place(key_desc, &EncryptionParams::default())?;
+            assert!(handle.encryption_in_progress());
+
+            let mut read_data = vec![0u8; data.len()];
+            File::open(handle.activated_device_path())?.read_exact(&mut read_data)?;
+            assert_eq!(data, read_data);
+
+            let setup_handle = CryptHandle::setup(path)?.expect("device was just encrypted");
+            assert!(setup_handle.encryption_in_progress());
+
+            handle.finish_encryption()?;
+            assert!(!handle.encryption_in_progress());
+            let setup_handle = CryptHandle::setup(path)?.expect("device was just encrypted");
+            assert!(!setup_handle.encryption_in_progress());
+
+            handle.wipe()?;
+            Ok(())
+        }
+
+        assert_eq!(paths.len(), 1);
+
+        crypt::insert_and_cleanup_key(paths, crypt_test);
+    }
+
+    #[test]
+    fn loop_test_encrypt_in_place() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_encrypt_in_place,
+        );
+    }
+
+    #[test]
+    fn real_test_encrypt_in_place() {
+        real::test_with_spec(
+            &real::DeviceLimits::Exactly(1, None, Some(Sectors(1024 * 1024 * 1024 / 512))),
+            test_encrypt_in_place,
+        );
+    }
+
+    #[test]
+    fn travis_test_encrypt_in_place() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Exactly(1, None),
+            test_encrypt_in_place,
+        );
+    }
+
     /// Test initializing and activating an encrypted device using
     /// the utilities provided here.
     ///
@@ -1351,7 +3020,7 @@ mod tests {
             let dev_uuid = DevUuid::new_v4();
 
             let mut handle = CryptInitializer::new((*path).to_owned(), pool_uuid, dev_uuid)
-                .initialize(key_desc)?;
+                .initialize(key_desc, &EncryptionParams::default())?;
             let logical_path = handle.activated_device_path();
 
             const WINDOW_SIZE: usize = 1024 * 1024;
@@ -1441,7 +3110,7 @@ mod tests {
 
             handle.deactivate()?;
 
-            let mut handle = CryptActivationHandle::setup(path, UnlockMethod::Keyring)?
+            let mut handle = CryptActivationHandle::setup(path, UnlockMethod::Keyring, None)?
                 .ok_or_else(|| {
                     Box::new(io::Error::new(
                         io::ErrorKind::Other,
@@ -4,7 +4,10 @@
 
 // Code to handle the backing store of a pool.
 
-use std::path::Path;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 use devicemapper::Sectors;
 
@@ -13,16 +16,95 @@ use crate::{
         strat_engine::{
             backstore::{
                 blockdev::StratBlockDev,
-                blockdevmgr::{BlkDevSegment, BlockDevMgr},
+                blockdevmgr::{BlkDevSegment, BlockDevMgr, Segment},
+                crypt::IN_PLACE_ENCRYPTION_SPACE,
                 shared::{coalesce_blkdevsegs, metadata_to_segment},
             },
             serde_structs::{BaseDevSave, BlockDevSave, DataTierSave, Recordable},
+            writing::copy_sectors,
         },
         types::{BlockDevTier, DevUuid, PoolUuid},
     },
-    stratis::StratisResult,
+    stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
+/// Copy the data in every segment of segments for which moving returns true
+/// to the corresponding list of segments in replacements. Return the layout
+/// that results from substituting the replacement segments for the original
+/// segments.
+///
+/// Precondition: replacements contains one list for every segment to be
+/// moved, in order, and the length of each list is the length of the
+/// segment it replaces.
+fn copy_segments<F>(
+    block_mgr: &BlockDevMgr,
+    segments: &[BlkDevSegment],
+    moving: F,
+    replacements: &[Vec<BlkDevSegment>],
+) -> StratisResult<Vec<BlkDevSegment>>
+where
+    F: Fn(&BlkDevSegment) -> bool,
+{
+    fn path_for(block_mgr: &BlockDevMgr, uuid: DevUuid) -> &Path {
+        block_mgr
+            .get_blockdev_by_uuid(uuid)
+            .expect("all segments belong to blockdevs managed by block_mgr")
+            .metadata_path()
+    }
+
+    let mut replacements = replacements.iter();
+    let mut layout = Vec::new();
+    for bseg in segments {
+        if !moving(bseg) {
+            layout = coalesce_blkdevsegs(&layout, &[bseg.clone()]);
+            continue;
+        }
+
+        let new_segs = replacements
+            .next()
+            .expect("one replacement list for every segment moved");
+        let mut offset = bseg.segment.start;
+        for new_seg in new_segs {
+            copy_sectors(
+                path_for(block_mgr, bseg.uuid),
+                offset,
+                path_for(block_mgr, new_seg.uuid),
+                new_seg.segment.start,
+                new_seg.segment.length,
+            )?;
+            offset += new_seg.segment.length;
+        }
+        layout = coalesce_blkdevsegs(&layout, new_segs);
+    }
+    Ok(layout)
+}
+
+/// The blockdevs removed from a data tier by DataTier::remove(), with what
+/// is required to undo the removal.
+#[derive(Debug)]
+pub struct RemovedBlockdevs {
+    /// The blockdevs that were removed
+    pub blockdevs: Vec<StratBlockDev>,
+    /// The arrangement of the segments of the data tier before the removal
+    previous: Vec<BlkDevSegment>,
+    /// The segments of the remaining blockdevs to which the data on the
+    /// removed blockdevs was copied
+    copies: Vec<BlkDevSegment>,
+}
+
+/// The data moved by DataTier::relocate_encryption_space(), with what is
+/// required to complete or to undo the move.
+#[derive(Debug)]
+pub struct RelocatedSegments {
+    /// The arrangement of the segments of the data tier before the move
+    previous: Vec<BlkDevSegment>,
+    /// The segments at the ends of the blockdevs from which the data was
+    /// moved
+    moved: Vec<BlkDevSegment>,
+    /// The segments to which the data was moved
+    copies: Vec<BlkDevSegment>,
+}
+
 /// Handles the lowest level, base layer of this tier.
 #[derive(Debug)]
 pub struct DataTier {
@@ -30,6 +112,9 @@ pub struct DataTier {
     pub block_mgr: BlockDevMgr,
     /// The list of segments granted by block_mgr and used by dm_device
     pub segments: Vec<BlkDevSegment>,
+    /// Whether the space needed to encrypt the unencrypted blockdevs in
+    /// place is reserved at their ends
+    reserve_encryption_space: bool,
 }
 
 impl DataTier {
@@ -45,10 +130,13 @@ impl DataTier {
             .map(&mapper)
             .collect::<StratisResult<Vec<_>>>()?;
 
-        Ok(DataTier {
+        let mut data_tier = DataTier {
             block_mgr,
             segments,
-        })
+            reserve_encryption_space: data_tier_save.reserve_encryption_space,
+        };
+        data_tier.reserve_ends();
+        Ok(data_tier)
     }
 
     /// Setup a new DataTier struct from the block_mgr.
@@ -60,6 +148,7 @@ impl DataTier {
         DataTier {
             block_mgr,
             segments: vec![],
+            reserve_encryption_space: false,
         }
     }
 
@@ -67,7 +156,205 @@ impl DataTier {
     /// corresponding to the specified paths.
     /// WARNING: metadata changing event
     pub fn add(&mut self, pool_uuid: PoolUuid, paths: &[&Path]) -> StratisResult<Vec<DevUuid>> {
-        self.block_mgr.add(pool_uuid, paths)
+        let uuids = self.block_mgr.add(pool_uuid, paths)?;
+        self.reserve_ends();
+        Ok(uuids)
+    }
+
+    /// Whether the space needed to encrypt the unencrypted blockdevs in
+    /// place is reserved.
+    pub fn reserves_encryption_space(&self) -> bool {
+        self.reserve_encryption_space
+    }
+
+    /// Reserve or stop reserving the space at the end of each unencrypted
+    /// blockdev that is needed to encrypt it in place. If some of that
+    /// space is already allocated on a blockdev, the space is reserved
+    /// once relocate_encryption_space() has moved the data out of it.
+    ///
+    /// WARNING: metadata changing event
+    pub fn set_reserve_encryption_space(&mut self, reserve: bool) -> StratisResult<()> {
+        if reserve {
+            self.reserve_encryption_space = true;
+            self.reserve_ends();
+        } else {
+            for (_, blockdev) in self.block_mgr.blockdevs_mut() {
+                blockdev.release_end()?;
+            }
+            self.reserve_encryption_space = false;
+        }
+        Ok(())
+    }
+
+    /// Reserve the space needed for in place encryption at the end of every
+    /// unencrypted blockdev on which it is free and not yet reserved.
+    fn reserve_ends(&mut self) {
+        if !self.reserve_encryption_space {
+            return;
+        }
+        for (_, blockdev) in self.block_mgr.blockdevs_mut() {
+            if blockdev.encryption_info().is_none() && blockdev.reserved_size() == Sectors(0) {
+                blockdev.reserve_end(IN_PLACE_ENCRYPTION_SPACE);
+            }
+        }
+    }
+
+    /// The UUIDs of the unencrypted blockdevs on which the space needed for
+    /// in place encryption should be reserved but is not, because some of
+    /// it is allocated, with the offset at which that space starts.
+    fn unreserved_ends(&self) -> HashMap<DevUuid, Sectors> {
+        if !self.reserve_encryption_space {
+            return HashMap::new();
+        }
+        self.block_mgr
+            .blockdevs()
+            .into_iter()
+            .filter(|(_, bd)| bd.encryption_info().is_none() && bd.reserved_size() == Sectors(0))
+            .filter_map(|(uuid, bd)| {
+                let size = bd.total_size().sectors();
+                if size > IN_PLACE_ENCRYPTION_SPACE {
+                    Some((uuid, size - IN_PLACE_ENCRYPTION_SPACE))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Whether data must be moved out of the space at the end of some
+    /// blockdevs before it can be reserved for in place encryption.
+    pub fn encryption_space_pending(&self) -> bool {
+        !self.unreserved_ends().is_empty()
+    }
+
+    /// Move the data allocated in the space at the end of the unencrypted
+    /// blockdevs that should be reserved for in place encryption onto free
+    /// space on the other blockdevs. Once the data has been copied, reload
+    /// is invoked with the new layout; it must switch the upper device to
+    /// that layout. If copying or reload fails, the newly allocated space is
+    /// returned and nothing is moved. Returns None if no data needs to be
+    /// moved.
+    ///
+    /// The space that the data was moved out of remains allocated until
+    /// finish_relocation() is invoked, which should be done once the new
+    /// layout has been recorded in the pool metadata.
+    ///
+    /// Precondition: the upper device that uses self.segments is suspended,
+    /// so that the data being copied can not change.
+    ///
+    /// WARNING: metadata changing event
+    pub fn relocate_encryption_space<F>(
+        &mut self,
+        reload: F,
+    ) -> StratisResult<Option<RelocatedSegments>>
+    where
+        F: FnOnce(&[BlkDevSegment]) -> StratisResult<()>,
+    {
+        let ends = self.unreserved_ends();
+        if ends.is_empty() {
+            return Ok(None);
+        }
+
+        // Split any segment that extends into the space to be reserved, so
+        // that only the part within that space is moved.
+        let mut segments = Vec::new();
+        for bseg in self.segments.iter() {
+            match ends.get(&bseg.uuid) {
+                Some(&end_start)
+                    if bseg.segment.start < end_start
+                        && end_start < bseg.segment.start + bseg.segment.length =>
+                {
+                    let device = bseg.segment.device;
+                    let first_length = end_start - bseg.segment.start;
+                    segments.push(BlkDevSegment::new(
+                        bseg.uuid,
+                        Segment::new(device, bseg.segment.start, first_length),
+                    ));
+                    segments.push(BlkDevSegment::new(
+                        bseg.uuid,
+                        Segment::new(device, end_start, bseg.segment.length - first_length),
+                    ));
+                }
+                _ => segments.push(bseg.clone()),
+            }
+        }
+        let in_end = |bseg: &BlkDevSegment| {
+            ends.get(&bseg.uuid)
+                .map(|&end_start| bseg.segment.start >= end_start)
+                .unwrap_or(false)
+        };
+
+        let moved = segments
+            .iter()
+            .filter(|bseg| in_end(*bseg))
+            .cloned()
+            .collect::<Vec<_>>();
+        let sizes = moved
+            .iter()
+            .map(|bseg| bseg.segment.length)
+            .collect::<Vec<_>>();
+        let excluded = ends.keys().cloned().collect::<Vec<_>>();
+        let replacements = match self.block_mgr.alloc_space_excluding(&sizes, &excluded) {
+            Some(replacements) => replacements,
+            None => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "The data tier does not have the {} free sectors required to \
+                        move the data out of the space needed to encrypt its \
+                        blockdevs in place",
+                        sizes.iter().cloned().sum::<Sectors>()
+                    ),
+                ))
+            }
+        };
+
+        let layout = match copy_segments(&self.block_mgr, &segments, in_end, &replacements)
+            .and_then(|layout| reload(&layout).map(|_| layout))
+        {
+            Ok(layout) => layout,
+            Err(err) => {
+                let allocated = replacements.into_iter().flatten().collect::<Vec<_>>();
+                if let Err(e) = self.block_mgr.release_space(&allocated) {
+                    warn!(
+                        "Failed to return space allocated for moving data out of the space reserved for in place encryption: {}",
+                        e
+                    );
+                }
+                return Err(err);
+            }
+        };
+
+        let previous = std::mem::replace(&mut self.segments, layout);
+        Ok(Some(RelocatedSegments {
+            previous,
+            moved,
+            copies: replacements.into_iter().flatten().collect(),
+        }))
+    }
+
+    /// Complete relocate_encryption_space(). Release the space that the data
+    /// was moved out of and reserve it.
+    pub fn finish_relocation(&mut self, relocated: RelocatedSegments) -> StratisResult<()> {
+        self.block_mgr.release_space(&relocated.moved)?;
+        self.reserve_ends();
+        Ok(())
+    }
+
+    /// Undo relocate_encryption_space(). Reload the DM device with the
+    /// arrangement of segments from before the data was moved and release
+    /// the space to which the data was copied.
+    pub fn undo_relocation<F>(
+        &mut self,
+        relocated: RelocatedSegments,
+        reload: F,
+    ) -> StratisResult<()>
+    where
+        F: FnOnce(&[BlkDevSegment]) -> StratisResult<()>,
+    {
+        reload(&relocated.previous)?;
+        self.segments = relocated.previous;
+        self.block_mgr.release_space(&relocated.copies)
     }
 
     /// Allocate at least request sectors from unallocated segments in
@@ -90,6 +377,110 @@ impl DataTier {
         }
     }
 
+    /// Remove the blockdevs with the specified UUIDs from this tier, first
+    /// moving the data in any segments allocated from them onto free space
+    /// on the remaining blockdevs. Once the data has been copied, reload is
+    /// invoked with the new layout; it must switch the upper device to that
+    /// layout. If copying or reload fails, the newly allocated space is
+    /// returned and nothing is removed.
+    ///
+    /// Returns the removed blockdevs, which still carry Stratis metadata.
+    /// It is the caller's responsibility to wipe them.
+    ///
+    /// Precondition: the upper device that uses self.segments is suspended,
+    /// so that the data being copied can not change.
+    ///
+    /// WARNING: metadata changing event
+    pub fn remove<F>(&mut self, uuids: &[DevUuid], reload: F) -> StratisResult<RemovedBlockdevs>
+    where
+        F: FnOnce(&[BlkDevSegment]) -> StratisResult<()>,
+    {
+        let removing = uuids.iter().cloned().collect::<HashSet<_>>();
+        if let Some(uuid) = removing
+            .iter()
+            .find(|&&uuid| self.block_mgr.get_blockdev_by_uuid(uuid).is_none())
+        {
+            return Err(StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!(
+                    "Blockdev corresponding to UUID: {} not found in the data tier.",
+                    uuid.to_simple_ref()
+                ),
+            ));
+        }
+        if removing.len() == self.block_mgr.blockdevs().len() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "At least one blockdev must remain in the data tier".to_string(),
+            ));
+        }
+
+        let uuids = removing.iter().cloned().collect::<Vec<_>>();
+        let sizes = self
+            .segments
+            .iter()
+            .filter(|bseg| removing.contains(&bseg.uuid))
+            .map(|bseg| bseg.segment.length)
+            .collect::<Vec<_>>();
+        let replacements = match self.block_mgr.alloc_space_excluding(&sizes, &uuids) {
+            Some(replacements) => replacements,
+            None => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "The remaining blockdevs in the data tier do not have the {} free \
+                        sectors required to hold the data on the blockdevs to be removed",
+                        sizes.iter().cloned().sum::<Sectors>()
+                    ),
+                ))
+            }
+        };
+
+        let layout = match copy_segments(
+            &self.block_mgr,
+            &self.segments,
+            |bseg| removing.contains(&bseg.uuid),
+            &replacements,
+        )
+        .and_then(|layout| reload(&layout).map(|_| layout))
+        {
+            Ok(layout) => layout,
+            Err(err) => {
+                let allocated = replacements.into_iter().flatten().collect::<Vec<_>>();
+                if let Err(e) = self.block_mgr.release_space(&allocated) {
+                    warn!(
+                        "Failed to return space allocated for moving data off removed blockdevs: {}",
+                        e
+                    );
+                }
+                return Err(err);
+            }
+        };
+
+        let previous = std::mem::replace(&mut self.segments, layout);
+        Ok(RemovedBlockdevs {
+            blockdevs: self.block_mgr.detach_blockdevs(&uuids)?,
+            previous,
+            copies: replacements.into_iter().flatten().collect(),
+        })
+    }
+
+    /// Undo remove(). Reload the DM device with the arrangement of segments
+    /// from before the blockdevs were removed, attach the removed blockdevs
+    /// again, and release the space on the remaining blockdevs to which the
+    /// data on them was copied. The data on the removed blockdevs is
+    /// unchanged as long as nothing has been written to the DM device since
+    /// remove() was invoked.
+    pub fn undo_remove<F>(&mut self, removed: RemovedBlockdevs, reload: F) -> StratisResult<()>
+    where
+        F: FnOnce(&[BlkDevSegment]) -> StratisResult<()>,
+    {
+        reload(&removed.previous)?;
+        self.segments = removed.previous;
+        self.block_mgr.attach_blockdevs(removed.blockdevs);
+        self.block_mgr.release_space(&removed.copies)
+    }
+
     /// The sum of the lengths of all the sectors that have been mapped to an
     /// upper device.
     #[cfg(test)]
@@ -110,9 +501,19 @@ impl DataTier {
         self.block_mgr.metadata_size()
     }
 
+    /// The number of sectors reserved at the end of the blockdevs so that
+    /// they can be encrypted in place
+    pub fn reserved_size(&self) -> Sectors {
+        self.block_mgr
+            .blockdevs()
+            .iter()
+            .map(|(_, bd)| bd.reserved_size())
+            .sum()
+    }
+
     /// The total usable size of all the blockdevs combined
     pub fn usable_size(&self) -> Sectors {
-        self.size() - self.metadata_size()
+        self.size() - self.metadata_size() - self.reserved_size()
     }
 
     /// Destroy the store. Wipe its blockdevs.
@@ -160,6 +561,7 @@ impl Recordable<DataTierSave> for DataTier {
                 allocs: vec![self.segments.record()],
                 devs: self.block_mgr.record(),
             },
+            reserve_encryption_space: self.reserve_encryption_space,
         }
     }
 }
@@ -193,6 +595,8 @@ mod tests {
         assert_eq!(allocated, Sectors(0));
         assert!(size != Sectors(0));
         assert_eq!(paths1.len(), data_tier.blockdevs().len());
+        assert_eq!(data_tier.reserved_size(), Sectors(0));
+        assert_eq!(data_tier.block_mgr.avail_space(), data_tier.usable_size());
 
         let last_request_amount = size;
 
@@ -246,4 +650,78 @@ mod tests {
             test_add_and_alloc,
         );
     }
+
+    /// Allocate all the space in a data tier, then reserve the space needed
+    /// to encrypt its blockdevs in place. Verify that nothing can be reserved
+    /// until more blockdevs are added and the data at the ends of the
+    /// original blockdevs is moved onto them. Then stop reserving the space
+    /// and verify that it is available again.
+    fn test_reserve_encryption_space(paths: &[&Path]) {
+        assert!(paths.len() > 1);
+        let (paths1, paths2) = paths.split_at(paths.len() / 2);
+
+        let pool_uuid = PoolUuid::new_v4();
+
+        let mgr = BlockDevMgr::initialize(pool_uuid, paths1, MDADataSize::default(), None).unwrap();
+        let mut data_tier = DataTier::new(mgr);
+        assert!(!data_tier.reserves_encryption_space());
+
+        let available = data_tier.block_mgr.avail_space();
+        assert!(data_tier.alloc(available));
+
+        data_tier.set_reserve_encryption_space(true).unwrap();
+        assert!(data_tier.reserves_encryption_space());
+        assert!(data_tier.encryption_space_pending());
+        assert_eq!(data_tier.reserved_size(), Sectors(0));
+        assert!(data_tier.relocate_encryption_space(|_| Ok(())).is_err());
+
+        data_tier.add(pool_uuid, paths2).unwrap();
+        assert_eq!(
+            data_tier.reserved_size(),
+            IN_PLACE_ENCRYPTION_SPACE * paths2.len()
+        );
+
+        let allocated = data_tier.allocated();
+        let relocated = data_tier
+            .relocate_encryption_space(|_| Ok(()))
+            .unwrap()
+            .unwrap();
+        data_tier.finish_relocation(relocated).unwrap();
+        assert!(!data_tier.encryption_space_pending());
+        assert_eq!(data_tier.allocated(), allocated);
+        assert_eq!(
+            data_tier.reserved_size(),
+            IN_PLACE_ENCRYPTION_SPACE * paths.len()
+        );
+        assert!(data_tier
+            .relocate_encryption_space(|_| Ok(()))
+            .unwrap()
+            .is_none());
+
+        let usable_size = data_tier.usable_size();
+        data_tier.set_reserve_encryption_space(false).unwrap();
+        assert_eq!(data_tier.reserved_size(), Sectors(0));
+        assert_eq!(
+            data_tier.usable_size(),
+            usable_size + IN_PLACE_ENCRYPTION_SPACE * paths.len()
+        );
+
+        data_tier.destroy().unwrap();
+    }
+
+    #[test]
+    fn loop_test_reserve_encryption_space() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_reserve_encryption_space,
+        );
+    }
+
+    #[test]
+    fn real_test_reserve_encryption_space() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(2, None, None),
+            test_reserve_encryption_space,
+        );
+    }
 }
@@ -5,6 +5,7 @@
 // Functions for dealing with devices.
 
 use std::{
+    cmp::max,
     collections::{HashMap, HashSet},
     fs::OpenOptions,
     path::{Path, PathBuf},
@@ -12,7 +13,6 @@ use std::{
 
 use chrono::Utc;
 use itertools::Itertools;
-use serde_json::Value;
 
 use devicemapper::{Bytes, Device, Sectors, IEC};
 
@@ -21,7 +21,7 @@ use crate::{
         strat_engine::{
             backstore::{
                 blockdev::StratBlockDev,
-                crypt::{CryptHandle, CryptInitializer},
+                crypt::{CryptHandle, CryptInitializer, IN_PLACE_ENCRYPTION_SPACE},
             },
             device::blkdev_size,
             keys::MemoryPrivateFilesystem,
@@ -30,9 +30,10 @@ use crate::{
                 BDA,
             },
             names::KeyDescription,
+            serde_structs::BackstoreSave,
             udev::{block_device_apply, decide_ownership, get_udev_property, UdevOwnership},
         },
-        types::{DevUuid, EncryptionInfo, PoolUuid},
+        types::{ClevisBinding, DevUuid, EncryptionInfo, EncryptionParams, PoolUuid},
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
@@ -399,18 +400,10 @@ pub fn initialize_devices(
     mda_data_size: MDADataSize,
     encryption_info: Option<EncryptionInfo>,
 ) -> StratisResult<Vec<StratBlockDev>> {
-    /// Map a major/minor device number of a physical device
-    /// to the corresponding major/minor number of the encrypted
-    /// device that uses the physical device as storage.
-    fn map_device_nums(logical_path: &Path) -> StratisResult<Device> {
-        let result = nix::sys::stat::stat(logical_path)?;
-        Ok(Device::from(result.st_rdev))
-    }
-
     /// Initialize an encrypted device on the given physical device
-    /// using the pool and device UUIDs of the new Stratis block device
-    /// and the key description for the key to use for encrypting the
-    /// data.
+    /// using the pool and device UUIDs of the new Stratis block device,
+    /// the key description for the key to use for encrypting the
+    /// data, and the parameters with which to format the device.
     ///
     /// On failure, this method will roll back the initialization
     /// process and clean up the device that it has just initialized.
@@ -419,30 +412,50 @@ pub fn initialize_devices(
         pool_uuid: PoolUuid,
         dev_uuid: DevUuid,
         key_description: &KeyDescription,
-        enable_clevis: Option<(&str, &Value)>,
+        encryption_params: &EncryptionParams,
+        clevis_bindings: &[ClevisBinding],
+        auto_unlock: bool,
     ) -> StratisResult<(CryptHandle, Device, Sectors)> {
         fn initialize_encrypted_with_err(
             handle: &mut CryptHandle,
             key_description: &KeyDescription,
-            enable_clevis: Option<(&str, &Value)>,
+            clevis_bindings: &[ClevisBinding],
+            auto_unlock: bool,
         ) -> StratisResult<(Device, Sectors)> {
             let device_size = handle.logical_device_size()?;
 
-            if let Some((pin, json)) = enable_clevis {
+            if !clevis_bindings.is_empty() {
                 let mem_fs = MemoryPrivateFilesystem::new()?;
-                mem_fs.key_op(key_description, |key_path| {
-                    handle
-                        .clevis_bind(key_path, pin, &json, false)
-                        .map_err(|e| StratisError::Error(e.to_string()))
-                })?;
+                for binding in clevis_bindings {
+                    mem_fs.key_op(key_description, |key_path| {
+                        handle
+                            .clevis_bind(
+                                key_path,
+                                &binding.pin,
+                                &binding.config,
+                                false,
+                                Some(binding.id),
+                            )
+                            .map_err(|e| StratisError::Error(e.to_string()))
+                    })?;
+                }
             };
 
+            if auto_unlock {
+                handle.set_auto_unlock(true).map_err(StratisError::Crypt)?;
+            }
+
             map_device_nums(handle.activated_device_path()).map(|dn| (dn, device_size))
         }
 
         let mut handle = CryptInitializer::new(physical_path.to_owned(), pool_uuid, dev_uuid)
-            .initialize(key_description)?;
-        match initialize_encrypted_with_err(&mut handle, key_description, enable_clevis) {
+            .initialize(key_description, encryption_params)?;
+        match initialize_encrypted_with_err(
+            &mut handle,
+            key_description,
+            clevis_bindings,
+            auto_unlock,
+        ) {
             Ok((devno, devsize)) => Ok((handle, devno, devsize)),
             Err(error) => {
                 let path = handle.luks2_device_path().display().to_string();
@@ -558,9 +571,9 @@ pub fn initialize_devices(
                 pool_uuid,
                 dev_uuid,
                 &info.key_description,
-                info.clevis_info
-                    .as_ref()
-                    .map(|(pin, json)| (pin.as_str(), json)),
+                &info.encryption_params,
+                &info.clevis_info,
+                info.auto_unlock,
             )
             .map(|(handle, devno, devsize)| {
                 debug!(
@@ -643,6 +656,104 @@ pub fn initialize_devices(
     Ok(initialized_blockdevs)
 }
 
+/// Map a major/minor device number of a physical device
+/// to the corresponding major/minor number of the encrypted
+/// device that uses the physical device as storage.
+fn map_device_nums(logical_path: &Path) -> StratisResult<Device> {
+    let result = nix::sys::stat::stat(logical_path)?;
+    Ok(Device::from(result.st_rdev))
+}
+
+/// Check that the contents of some data devices of a pool can be encrypted
+/// in place. The last IN_PLACE_ENCRYPTION_SPACE sectors of each device must
+/// not be allocated. `dev_sizes` gives the size of each data device to check.
+pub fn check_in_place_encryption_space(
+    backstore_save: &BackstoreSave,
+    dev_sizes: &HashMap<DevUuid, Sectors>,
+) -> StratisResult<()> {
+    let mut alloc_ends: HashMap<DevUuid, Sectors> = HashMap::new();
+    for seg in backstore_save.data_tier.blockdev.allocs.iter().flatten() {
+        let end = alloc_ends.entry(seg.parent).or_insert(Sectors(0));
+        *end = max(*end, seg.start + seg.length);
+    }
+
+    for (dev_uuid, size) in dev_sizes.iter() {
+        let alloc_end = alloc_ends.get(dev_uuid).cloned().unwrap_or(Sectors(0));
+        if alloc_end + IN_PLACE_ENCRYPTION_SPACE > *size {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Data device with UUID {} has space allocated up to {} of its \
+                    total size of {}; the last {} must be unallocated to encrypt \
+                    it in place",
+                    dev_uuid.to_simple_ref(),
+                    alloc_end,
+                    size,
+                    IN_PLACE_ENCRYPTION_SPACE,
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Encrypt the contents of a Stratis block device in place, or resume the
+/// encryption if it was interrupted, and finish the encryption.
+///
+/// Returns the handle for the encrypted device and the device number of its
+/// logical device.
+///
+/// Precondition: The last IN_PLACE_ENCRYPTION_SPACE sectors of the device
+/// are unused.
+pub fn encrypt_device_in_place(
+    physical_path: &Path,
+    identifiers: StratisIdentifiers,
+    key_description: &KeyDescription,
+    encryption_params: &EncryptionParams,
+) -> StratisResult<(CryptHandle, Device)> {
+    let mut handle = CryptInitializer::new(
+        physical_path.to_owned(),
+        identifiers.pool_uuid,
+        identifiers.device_uuid,
+    )
+    .encrypt_in_place(key_description, encryption_params)?;
+    finish_in_place_encryption(&mut handle)?;
+    let devno = map_device_nums(handle.activated_device_path())?;
+    Ok((handle, devno))
+}
+
+/// Finish the in place encryption of a device whose contents have been
+/// encrypted. The logical device is smaller than the physical device was, so
+/// the size recorded in the BDA is updated to the size of the logical device
+/// before the encryption is marked as finished.
+fn finish_in_place_encryption(handle: &mut CryptHandle) -> StratisResult<()> {
+    if !handle.encryption_in_progress() {
+        return Ok(());
+    }
+
+    let logical_size = handle.logical_device_size()?;
+    let mut f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(handle.activated_device_path())?;
+    let mut bda = BDA::load(&mut f)?.ok_or_else(|| {
+        StratisError::Engine(
+            ErrorEnum::NotFound,
+            format!(
+                "No Stratis metadata was found on the logical device {} of an \
+                encrypted device",
+                handle.activated_device_path().display()
+            ),
+        )
+    })?;
+    if bda.dev_size().sectors() > logical_size {
+        bda.set_dev_size(&mut f, BlockdevSize::new(logical_size))?;
+    }
+
+    handle.finish_encryption()?;
+    Ok(())
+}
+
 /// Wipe some blockdevs of their identifying headers.
 /// Return an error if any of the blockdevs could not be wiped.
 /// If an error occurs while wiping a blockdev, attempt to wipe all remaining.
@@ -713,7 +824,9 @@ mod tests {
             MDADataSize::default(),
             key_description.map(|kd| EncryptionInfo {
                 key_description: kd.clone(),
-                clevis_info: None,
+                clevis_info: Vec::new(),
+                encryption_params: EncryptionParams::default(),
+                auto_unlock: false,
             }),
         )?;
 
@@ -991,7 +1104,9 @@ mod tests {
             MDADataSize::default(),
             key_desc.map(|kd| EncryptionInfo {
                 key_description: kd.clone(),
-                clevis_info: None,
+                clevis_info: Vec::new(),
+                encryption_params: EncryptionParams::default(),
+                auto_unlock: false,
             }),
         )
         .is_ok()
@@ -17,6 +17,8 @@ pub use self::{
     backstore::Backstore,
     blockdev::StratBlockDev,
     crypt::{CryptActivationHandle, CryptHandle},
+    devices::{check_in_place_encryption_space, encrypt_device_in_place, wipe_blockdevs},
+    range_alloc::{PerDevSegments, RangeAllocator},
 };
 
 #[cfg(test)]
@@ -403,6 +403,22 @@ impl RangeAllocator {
         segs
     }
 
+    /// Mark the specified range as used, so that it is never allocated.
+    /// Return an error if any part of the range is already in use.
+    pub fn reserve(&mut self, range: &(Sectors, Sectors)) -> StratisResult<()> {
+        self.segments.insert(range)
+    }
+
+    /// Return the specified ranges to the pool of available sectors.
+    /// Return an error if any of the ranges is not entirely allocated.
+    /// The operation is atomic; either all ranges or none will be released.
+    pub fn release(&mut self, ranges: &[(Sectors, Sectors)]) -> StratisResult<()> {
+        let mut free = self.segments.complement();
+        free.insert_all(ranges)?;
+        self.segments = free.complement();
+        Ok(())
+    }
+
     #[cfg(test)]
     fn invariant(&self) {
         // Verify that calling request_all() has the identical effect to
@@ -558,6 +574,37 @@ mod tests {
         allocator.invariant();
     }
 
+    #[test]
+    /// Verify that release() returns allocated ranges to the allocator and
+    /// fails, without effect, if any range is not entirely allocated.
+    fn test_allocator_release() {
+        let mut allocator = RangeAllocator::new(BlockdevSize::new(Sectors(128)), &[]).unwrap();
+
+        allocator.request(Sectors(100));
+        assert_eq!(allocator.used(), Sectors(100));
+
+        allocator
+            .release(&[(Sectors(10), Sectors(10)), (Sectors(30), Sectors(20))])
+            .unwrap();
+        assert_eq!(allocator.used(), Sectors(70));
+        assert_eq!(
+            allocator.segments.iter().collect::<Vec<_>>(),
+            vec![
+                (&Sectors(0), &Sectors(10)),
+                (&Sectors(20), &Sectors(10)),
+                (&Sectors(50), &Sectors(50))
+            ]
+        );
+
+        assert_matches!(
+            allocator.release(&[(Sectors(50), Sectors(10)), (Sectors(90), Sectors(20))]),
+            Err(_)
+        );
+        assert_eq!(allocator.used(), Sectors(70));
+
+        allocator.invariant();
+    }
+
     #[test]
     /// Verify that insert() errors when an element outside the range
     /// limit is requested.
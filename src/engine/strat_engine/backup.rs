@@ -0,0 +1,823 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Back up the metadata of a pool to a versioned JSON archive, and restore
+//! it from one.
+//!
+//! The archive holds the static header of every device in the pool, the
+//! pool-level metadata and the filesystem records on the pool's metadata
+//! volume. Restoring it rewrites the BDA of every device and the filesystem
+//! records; the contents of the thin pool and any LUKS2 headers are not
+//! restored.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir, read_dir, rename, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde_json::Value;
+
+use devicemapper::Sectors;
+
+use crate::{
+    engine::{
+        strat_engine::{
+            fsck::{
+                choose_metadata, is_set_up, mdv_table, named_devices, open_exclusive, pool_devices,
+                read_devices, read_mdv, recorded_devices, rewrite_bda, single_pool,
+                superseding_time, with_mdv_mounted, FILESYSTEM_DIR,
+            },
+            metadata::{BlockdevSize, MDASize, StaticHeader, StratisIdentifiers},
+            pool::check_metadata,
+            serde_structs::{FilesystemSave, PoolSave},
+            udev::{block_device_apply, get_udev_property},
+        },
+        types::{DevUuid, PoolUuid, METADATA_ARCHIVE_VERSION},
+    },
+    stratis::{ErrorEnum, StratisError, StratisResult},
+};
+
+/// A backup of all the metadata of a pool.
+#[derive(Debug, Deserialize, Serialize)]
+struct MetadataArchive {
+    version: u64,
+    created: String,
+    pool_uuid: PoolUuid,
+    devices: Vec<DeviceRecord>,
+    pool: PoolSave,
+    filesystems: Vec<FilesystemSave>,
+}
+
+/// The static header of a single device in the pool, with the devnode of
+/// the device when the archive was made.
+#[derive(Debug, Deserialize, Serialize)]
+struct DeviceRecord {
+    devnode: PathBuf,
+    device_uuid: DevUuid,
+    blkdev_size: Sectors,
+    mda_size: Sectors,
+    initialization_time: u64,
+}
+
+impl DeviceRecord {
+    fn new(devnode: &Path, header: &StaticHeader) -> DeviceRecord {
+        DeviceRecord {
+            devnode: devnode.to_owned(),
+            device_uuid: header.identifiers.device_uuid,
+            blkdev_size: header.blkdev_size.sectors(),
+            mda_size: header.mda_size.sectors(),
+            initialization_time: header.initialization_time,
+        }
+    }
+
+    fn header(&self, pool_uuid: PoolUuid) -> StaticHeader {
+        StaticHeader::new(
+            StratisIdentifiers::new(pool_uuid, self.device_uuid),
+            MDASize(self.mda_size),
+            BlockdevSize::new(self.blkdev_size),
+            self.initialization_time,
+        )
+    }
+}
+
+/// Make an archive of the metadata of the pool with the given UUID from the
+/// devnode and static header of each of its devices, its pool-level metadata
+/// and its filesystem records.
+pub fn make_archive(
+    pool_uuid: PoolUuid,
+    devices: &[(&Path, &StaticHeader)],
+    pool: PoolSave,
+    filesystems: Vec<FilesystemSave>,
+) -> StratisResult<Value> {
+    Ok(serde_json::to_value(&MetadataArchive {
+        version: METADATA_ARCHIVE_VERSION,
+        created: Utc::now().to_rfc3339(),
+        pool_uuid,
+        devices: devices
+            .iter()
+            .map(|(devnode, header)| DeviceRecord::new(devnode, header))
+            .collect(),
+        pool,
+        filesystems,
+    })?)
+}
+
+/// Make an archive of the metadata of the pool with the given UUID, which
+/// must not be set up, from the metadata on its devices. All the devices
+/// recorded in the newest valid copy of the pool metadata must be present
+/// and unlocked, and every filesystem record must be readable.
+pub fn backup_pool_metadata(pool_uuid: PoolUuid) -> StratisResult<Value> {
+    let (devices, locked) = pool_devices(pool_uuid)?;
+    if !locked.is_empty() {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "{} encrypted devices of pool with UUID {} are locked, so their metadata can not be read",
+                locked.len(),
+                pool_uuid.to_simple_ref()
+            ),
+        ));
+    }
+
+    let contents = read_devices(&devices)?;
+    single_pool(&contents)?;
+    let (_, pool_save) = choose_metadata(&contents, None)?;
+
+    let recorded = recorded_devices(&pool_save);
+    let members = contents
+        .iter()
+        .filter_map(|c| {
+            c.header()
+                .filter(|h| recorded.contains(&h.identifiers.device_uuid))
+                .map(|h| (c, h))
+        })
+        .collect::<Vec<_>>();
+    if members.len() != recorded.len() {
+        return Err(StratisError::Engine(
+            ErrorEnum::NotFound,
+            format!(
+                "Only {} of the {} devices recorded in the pool metadata were found",
+                members.len(),
+                recorded.len()
+            ),
+        ));
+    }
+
+    let device_numbers = members
+        .iter()
+        .filter_map(|(c, h)| c.device_number.map(|n| (h.identifiers.device_uuid, n)))
+        .collect::<HashMap<_, _>>();
+    let table = mdv_table(pool_uuid, &pool_save, &device_numbers).map_err(|reason| {
+        StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!("The metadata volume can not be read: {}", reason),
+        )
+    })?;
+    let filesystems = read_mdv(pool_uuid, table)?
+        .into_iter()
+        .map(|(filename, record)| {
+            record.map_err(|err| {
+                StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!("Filesystem record {} could not be read: {}", filename, err),
+                )
+            })
+        })
+        .collect::<StratisResult<Vec<_>>>()?;
+
+    make_archive(
+        pool_uuid,
+        &members
+            .iter()
+            .map(|(c, h)| (c.devnode.as_path(), *h))
+            .collect::<Vec<_>>(),
+        pool_save,
+        filesystems,
+    )
+}
+
+/// Restore the metadata of a pool, which must not be set up, from an archive
+/// made by backup_pool_metadata() or Pool::backup_metadata().
+///
+/// Every device in the archive must be found at the devnode recorded for it;
+/// if a device has moved, its devnode can be edited in the archive. Each
+/// device must be exactly the size it was when the archive was made, and
+/// must either carry the signature block of that device or no valid
+/// signature block at all. A device with no valid signature block must have
+/// the WWN recorded for it in the archive, otherwise the restore is refused
+/// unless force is true, since the device at that devnode may not be the
+/// one in the archive.
+///
+/// The layout of the pool recorded in the newest copy of the pool metadata
+/// that can still be read from the devices must match that in the archive:
+/// the same devices, the same segments allocated from them and the same
+/// space allocated to each of the pool's internal devices. Otherwise
+/// restoring the archive could, for example, shrink the thin pool's data
+/// device and lose the data beyond its end, so the restore is refused unless
+/// force is true. It is also refused unless force is true if no copy of the
+/// pool metadata can be read, since the layout can then not be verified.
+///
+/// The filesystem records in the archive are written to the metadata volume,
+/// replacing any records of the same filesystems; records of filesystems
+/// not in the archive are left alone. Then the BDA of every device is
+/// rewritten, with the pool metadata in the archive stamped with a time
+/// later than that of any metadata already on the devices.
+///
+/// If dry_run is true, everything is verified but nothing is written.
+/// Returns a description of what was, or would be, restored, including any
+/// differences in layout and the records of filesystems not in the archive.
+pub fn restore_pool_metadata(archive: &Value, dry_run: bool, force: bool) -> StratisResult<Value> {
+    match archive.get("version").and_then(Value::as_u64) {
+        Some(METADATA_ARCHIVE_VERSION) => (),
+        Some(version) => {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Archive version {} is not supported; only version {} is",
+                    version, METADATA_ARCHIVE_VERSION
+                ),
+            ))
+        }
+        None => {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "Not a Stratis metadata archive: it has no version".into(),
+            ))
+        }
+    }
+    let archive = serde_json::from_value::<MetadataArchive>(archive.clone())?;
+    let pool_uuid = archive.pool_uuid;
+    validate(&archive)?;
+
+    if is_set_up(pool_uuid)? {
+        return Err(StratisError::Engine(
+            ErrorEnum::Busy,
+            format!(
+                "Pool with UUID {} is set up; stop stratisd and tear down the pool before restoring its metadata",
+                pool_uuid.to_simple_ref()
+            ),
+        ));
+    }
+
+    let contents = read_devices(&named_devices(
+        &archive
+            .devices
+            .iter()
+            .map(|d| d.devnode.clone())
+            .collect::<Vec<_>>(),
+    )?)?;
+
+    let recorded_wwns = Some(&archive.pool.backstore.data_tier.blockdev)
+        .into_iter()
+        .chain(
+            archive
+                .pool
+                .backstore
+                .cache_tier
+                .as_ref()
+                .map(|c| &c.blockdev),
+        )
+        .flat_map(|tier| tier.devs.iter())
+        .map(|dev| (dev.uuid, dev.hardware_info.as_ref()))
+        .collect::<HashMap<_, _>>();
+
+    let mut devices = Vec::new();
+    let mut unidentified = Vec::new();
+    for (record, device) in archive.devices.iter().zip(contents.iter()) {
+        let signature = match device.header() {
+            Some(header)
+                if header.identifiers == StratisIdentifiers::new(pool_uuid, record.device_uuid) =>
+            {
+                "matches"
+            }
+            Some(header) => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Device {} is device {} of pool {}, not device {} of pool {}",
+                        device.devnode.display(),
+                        header.identifiers.device_uuid.to_simple_ref(),
+                        header.identifiers.pool_uuid.to_simple_ref(),
+                        record.device_uuid.to_simple_ref(),
+                        pool_uuid.to_simple_ref()
+                    ),
+                ))
+            }
+            None => match recorded_wwns.get(&record.device_uuid).cloned().flatten() {
+                Some(wwn) if device_wwn(&device.devnode).as_ref() == Some(wwn) => {
+                    "missing, identified by WWN"
+                }
+                _ => {
+                    unidentified.push(device.devnode.display().to_string());
+                    "missing"
+                }
+            },
+        };
+        match device.size {
+            Some(size) if size.sectors() == record.blkdev_size => (),
+            Some(size) => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Device {} is {} but was {} when the archive was made",
+                        device.devnode.display(),
+                        size.sectors(),
+                        record.blkdev_size
+                    ),
+                ))
+            }
+            None => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "The size of device {} could not be determined",
+                        device.devnode.display()
+                    ),
+                ))
+            }
+        }
+        devices.push(json!({
+            "devnode": Value::from(record.devnode.display().to_string()),
+            "device_uuid": Value::from(record.device_uuid.to_string()),
+            "signature": Value::from(signature),
+        }));
+    }
+
+    if !unidentified.is_empty() && !force && !dry_run {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!(
+                "Devices {} have no Stratis signature and can not be identified by a WWN recorded in the archive; if they are the devices in the archive, restore with force",
+                unidentified.join(", ")
+            ),
+        ));
+    }
+
+    let on_disk = choose_metadata(&contents, None)
+        .ok()
+        .map(|(_, on_disk)| on_disk);
+    let differences = on_disk
+        .as_ref()
+        .map(|on_disk| layout_differences(&archive.pool, on_disk))
+        .unwrap_or_default();
+    if !force && !dry_run {
+        if on_disk.is_none() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "No copy of the pool metadata can be read from the devices, so the layout of the pool in the archive can not be verified; restore with force if it is known to be current".into(),
+            ));
+        }
+        if !differences.is_empty() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "The layout of the pool in the archive differs from that recorded on the devices, so restoring it could lose data: {}",
+                    differences.join("; ")
+                ),
+            ));
+        }
+    }
+
+    let device_numbers = archive
+        .devices
+        .iter()
+        .zip(contents.iter())
+        .filter_map(|(record, device)| device.device_number.map(|n| (record.device_uuid, n)))
+        .collect::<HashMap<_, _>>();
+    let table = mdv_table(pool_uuid, &archive.pool, &device_numbers).map_err(|reason| {
+        StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!("The metadata volume can not be written: {}", reason),
+        )
+    })?;
+
+    let (unarchived, written) = if dry_run {
+        let archived = archived_filenames(&archive.filesystems);
+        let unarchived = read_mdv(pool_uuid, table)?
+            .into_iter()
+            .map(|(filename, _)| filename)
+            .filter(|filename| !archived.contains(filename))
+            .collect::<Vec<_>>();
+        (unarchived, None)
+    } else {
+        let unarchived = with_mdv_mounted(pool_uuid, table, false, |mount_pt| {
+            write_filesystem_records(&mount_pt.join(FILESYSTEM_DIR), &archive.filesystems)
+        })?;
+
+        // The metadata volume is mapped over the devices while the records
+        // are written, so the devices can only be held exclusively now.
+        let mut files = open_exclusive(archive.devices.iter().map(|d| d.devnode.as_path()))?;
+        if is_set_up(pool_uuid)? {
+            return Err(StratisError::Engine(
+                ErrorEnum::Busy,
+                format!(
+                    "Pool with UUID {} was set up while its metadata was being restored",
+                    pool_uuid.to_simple_ref()
+                ),
+            ));
+        }
+
+        let data = serde_json::to_string(&archive.pool)?;
+        let time = superseding_time(&contents);
+        for record in archive.devices.iter() {
+            let f = files
+                .get_mut(&record.devnode)
+                .expect("every device was opened");
+            rewrite_bda(f, &record.header(pool_uuid), &time, data.as_bytes())?;
+        }
+        (unarchived, Some(time))
+    };
+
+    Ok(json!({
+        "pool_uuid": Value::from(pool_uuid.to_string()),
+        "pool_name": Value::from(archive.pool.name.clone()),
+        "archive_created": Value::from(archive.created.clone()),
+        "dry_run": Value::from(dry_run),
+        "devices": Value::Array(devices),
+        "layout_verified": Value::from(on_disk.is_some()),
+        "layout_differences": Value::Array(differences.into_iter().map(Value::from).collect()),
+        "filesystems": Value::Array(
+            archive
+                .filesystems
+                .iter()
+                .map(|record| json!({
+                    "name": Value::from(record.name.clone()),
+                    "uuid": Value::from(record.uuid.to_string()),
+                }))
+                .collect()
+        ),
+        "unarchived_filesystem_records": Value::Array(unarchived.into_iter().map(Value::from).collect()),
+        "written_at": written
+            .map(|time| Value::from(time.to_rfc3339()))
+            .unwrap_or(Value::Null),
+    }))
+}
+
+/// Check that the contents of an archive are consistent with each other.
+fn validate(archive: &MetadataArchive) -> StratisResult<()> {
+    let flex_devs = &archive.pool.flex_devs;
+    if flex_devs.meta_dev.is_empty()
+        || flex_devs.thin_meta_dev.is_empty()
+        || flex_devs.thin_data_dev.is_empty()
+        || flex_devs.thin_meta_dev_spare.is_empty()
+    {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "Some of the pool's internal devices have no space allocated to them in the archive"
+                .into(),
+        ));
+    }
+    check_metadata(&archive.pool)?;
+
+    let device_uuids = archive
+        .devices
+        .iter()
+        .map(|d| d.device_uuid)
+        .collect::<HashSet<_>>();
+    let devnodes = archive
+        .devices
+        .iter()
+        .map(|d| &d.devnode)
+        .collect::<HashSet<_>>();
+    if device_uuids.len() != archive.devices.len() || devnodes.len() != archive.devices.len() {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "The archive records some device or devnode more than once".into(),
+        ));
+    }
+    if device_uuids != recorded_devices(&archive.pool) {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "The devices in the archive are not those recorded in its pool metadata".into(),
+        ));
+    }
+
+    let filesystem_uuids = archive
+        .filesystems
+        .iter()
+        .map(|fs| fs.uuid)
+        .collect::<HashSet<_>>();
+    if filesystem_uuids.len() != archive.filesystems.len() {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "The archive records some filesystem more than once".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Describe how the layout of the pool recorded in the archived metadata
+/// differs from that recorded in the metadata on the devices, one entry per
+/// difference.
+fn layout_differences(archived: &PoolSave, on_disk: &PoolSave) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    let archived_devices = recorded_devices(archived);
+    let on_disk_devices = recorded_devices(on_disk);
+    let describe = |uuids: HashSet<&DevUuid>| {
+        uuids
+            .iter()
+            .map(|uuid| uuid.to_simple_ref().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let only_archived = archived_devices
+        .difference(&on_disk_devices)
+        .collect::<HashSet<_>>();
+    if !only_archived.is_empty() {
+        differences.push(format!(
+            "devices {} are recorded only in the archive",
+            describe(only_archived)
+        ));
+    }
+    let only_on_disk = on_disk_devices
+        .difference(&archived_devices)
+        .collect::<HashSet<_>>();
+    if !only_on_disk.is_empty() {
+        differences.push(format!(
+            "devices {} are recorded only on the devices",
+            describe(only_on_disk)
+        ));
+    }
+
+    let (archived_backstore, on_disk_backstore) = (&archived.backstore, &on_disk.backstore);
+    if archived_backstore.data_tier.blockdev.allocs != on_disk_backstore.data_tier.blockdev.allocs {
+        differences.push("different segments are allocated from the data tier".into());
+    }
+    if archived_backstore
+        .cache_tier
+        .as_ref()
+        .map(|c| &c.blockdev.allocs)
+        != on_disk_backstore
+            .cache_tier
+            .as_ref()
+            .map(|c| &c.blockdev.allocs)
+    {
+        differences.push("different segments are allocated from the cache tier".into());
+    }
+    if archived_backstore.cap.allocs != on_disk_backstore.cap.allocs {
+        differences.push(format!(
+            "the cap device is {} in the archive but {} on the devices",
+            total(&archived_backstore.cap.allocs),
+            total(&on_disk_backstore.cap.allocs)
+        ));
+    }
+
+    let (archived_flex, on_disk_flex) = (&archived.flex_devs, &on_disk.flex_devs);
+    for (role, archived_segments, on_disk_segments) in [
+        (
+            "metadata volume",
+            &archived_flex.meta_dev,
+            &on_disk_flex.meta_dev,
+        ),
+        (
+            "thin pool metadata device",
+            &archived_flex.thin_meta_dev,
+            &on_disk_flex.thin_meta_dev,
+        ),
+        (
+            "thin pool data device",
+            &archived_flex.thin_data_dev,
+            &on_disk_flex.thin_data_dev,
+        ),
+        (
+            "thin pool metadata spare device",
+            &archived_flex.thin_meta_dev_spare,
+            &on_disk_flex.thin_meta_dev_spare,
+        ),
+    ]
+    .iter()
+    {
+        if archived_segments != on_disk_segments {
+            differences.push(format!(
+                "the {} is {} in the archive but {} on the devices",
+                role,
+                total(archived_segments),
+                total(on_disk_segments)
+            ));
+        }
+    }
+
+    differences
+}
+
+/// The total length of a list of segments.
+fn total(segments: &[(Sectors, Sectors)]) -> Sectors {
+    segments.iter().map(|&(_, length)| length).sum()
+}
+
+/// The names of the files holding the records of the given filesystems.
+fn archived_filenames(records: &[FilesystemSave]) -> HashSet<String> {
+    records
+        .iter()
+        .map(|record| format!("{}.json", record.uuid.to_simple_ref()))
+        .collect()
+}
+
+/// The WWN of the device at the given devnode according to udev, if it has
+/// one.
+fn device_wwn(devnode: &Path) -> Option<String> {
+    block_device_apply(devnode, |d| get_udev_property(d, "ID_WWN"))
+        .ok()
+        .flatten()
+        .flatten()
+        .and_then(|wwn| wwn.ok())
+}
+
+/// Write the given filesystem records to the given directory, each to a
+/// temporary file that is then renamed, as MetadataVol::save_fs() does. The
+/// records of any other filesystems are left alone. Returns the names of
+/// the files holding them.
+fn write_filesystem_records(dir: &Path, records: &[FilesystemSave]) -> StratisResult<Vec<String>> {
+    if let Err(err) = create_dir(dir) {
+        if err.kind() != ErrorKind::AlreadyExists {
+            return Err(From::from(err));
+        }
+    }
+
+    for record in records {
+        let path = dir
+            .join(record.uuid.to_simple_ref().to_string())
+            .with_extension("json");
+        let temp_path = path.with_extension("temp");
+
+        // Braces to ensure f is closed before renaming
+        {
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&temp_path)?;
+            f.write_all(serde_json::to_string(record)?.as_bytes())?;
+            f.sync_all()?;
+        }
+
+        rename(temp_path, path)?;
+    }
+
+    let archived = archived_filenames(records);
+    let mut unarchived = Vec::new();
+    for dir_e in read_dir(dir)? {
+        let path = dir_e?.path();
+        if path.extension().map(|e| e == "temp").unwrap_or(false) {
+            continue;
+        }
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !archived.contains(&filename) {
+            unarchived.push(filename);
+        }
+    }
+
+    Ok(unarchived)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        strat_engine::{
+            metadata::disown_device,
+            tests::{loopbacked, real},
+            StratEngine,
+        },
+        types::BlockDevTier,
+        Engine, Pool, SimEngine,
+    };
+
+    use super::*;
+
+    #[test]
+    /// Verify that the archive made by the simulator has the same schema as
+    /// the archives made by the real engine.
+    fn test_sim_archive() {
+        let mut engine = SimEngine::default();
+        let uuid = engine
+            .create_pool(
+                "pool_name",
+                strs_to_paths!(["/dev/one", "/dev/two"]),
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        pool.create_filesystems(uuid, &[("fs_name", None, None)])
+            .unwrap();
+
+        let archive = serde_json::from_value::<MetadataArchive>(
+            pool.backup_metadata(uuid, &*pool_name).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(archive.pool_uuid, uuid);
+        assert_eq!(archive.devices.len(), 2);
+        assert_eq!(archive.filesystems.len(), 1);
+    }
+
+    /// Verify that a pool can be set up again after its metadata has been
+    /// wiped and restored from an archive, and that a restore which would
+    /// change the layout of the pool is refused.
+    /// 1. Create a pool with a filesystem and back up its metadata.
+    /// 2. Create another filesystem, stop the pool and wipe the signature
+    /// blocks of its devices.
+    /// 3. Verify that, since the layout of the pool can not be verified, the
+    /// metadata is only restored with force, and that the pool is then set
+    /// up with both filesystems.
+    /// 4. Add a device to the pool, stop it, and verify that restoring the
+    /// archive again is refused, and the difference reported by a dry run.
+    fn test_backup_restore(paths: &[&Path]) {
+        assert!(paths.len() > 1);
+
+        let (pool_paths, added_paths) = paths.split_at(paths.len() - 1);
+
+        let mut engine = StratEngine::initialize().unwrap();
+        let name = "pool_name";
+        let uuid = engine
+            .create_pool(name, pool_paths, None, None, None)
+            .unwrap()
+            .changed()
+            .unwrap();
+        let (_, pool) = engine.get_mut_pool(uuid).unwrap();
+        pool.create_filesystems(uuid, &[("fs1", None, None)])
+            .unwrap();
+        let archive = pool.backup_metadata(uuid, name).unwrap();
+        pool.create_filesystems(uuid, &[("fs2", None, None)])
+            .unwrap();
+        engine.teardown().unwrap();
+
+        for path in pool_paths {
+            disown_device(&mut OpenOptions::new().write(true).open(path).unwrap()).unwrap();
+        }
+        let engine = StratEngine::initialize().unwrap();
+        assert!(engine.get_pool(uuid).is_none());
+
+        let report = restore_pool_metadata(&archive, true, false).unwrap();
+        assert_eq!(report["written_at"], Value::Null);
+        assert_eq!(report["layout_verified"], Value::from(false));
+        assert_eq!(report["layout_differences"], json!([]));
+        assert_eq!(
+            report["unarchived_filesystem_records"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_matches!(
+            restore_pool_metadata(&archive, false, false),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        let report = restore_pool_metadata(&archive, false, true).unwrap();
+        assert!(report["written_at"].is_string());
+
+        let mut engine = StratEngine::initialize().unwrap();
+        let (pool_name, pool) = engine.get_mut_pool(uuid).unwrap();
+        assert_eq!(&*pool_name, name);
+        let mut fs_names = pool
+            .filesystems()
+            .iter()
+            .map(|(fs_name, _, _)| fs_name.to_string())
+            .collect::<Vec<_>>();
+        fs_names.sort();
+        assert_eq!(fs_names, vec!["fs1".to_string(), "fs2".to_string()]);
+        pool.add_blockdevs(uuid, name, added_paths, BlockDevTier::Data)
+            .unwrap();
+        engine.teardown().unwrap();
+
+        assert_matches!(
+            restore_pool_metadata(&archive, false, false),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        let report = restore_pool_metadata(&archive, true, false).unwrap();
+        assert_eq!(report["layout_verified"], Value::from(true));
+        assert!(!report["layout_differences"].as_array().unwrap().is_empty());
+
+        let mut engine = StratEngine::initialize().unwrap();
+        assert_eq!(
+            engine.get_pool(uuid).unwrap().1.blockdevs().len(),
+            paths.len()
+        );
+        engine.destroy_pool(uuid).unwrap();
+    }
+
+    #[test]
+    fn loop_test_backup_restore() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_backup_restore,
+        );
+    }
+
+    #[test]
+    fn real_test_backup_restore() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(2, None, None),
+            test_backup_restore,
+        );
+    }
+
+    #[test]
+    /// Verify that an archive is rejected before anything is read from any
+    /// device if its version is missing or is not the current one.
+    fn test_restore_version() {
+        assert_matches!(
+            restore_pool_metadata(&json!({"pool_uuid": "nil"}), true, false),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            restore_pool_metadata(
+                &json!({"version": METADATA_ARCHIVE_VERSION + 1}),
+                true,
+                false
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+    }
+}
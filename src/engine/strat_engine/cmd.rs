@@ -22,6 +22,8 @@ use std::{
 
 use serde_json::Value;
 
+use devicemapper::ThinDevId;
+
 use crate::{
     engine::types::{FilesystemUuid, StratisUuid},
     stratis::{StratisError, StratisResult},
@@ -46,6 +48,7 @@ fn find_binary(name: &str) -> Option<PathBuf> {
 // and vice-versa.
 const MKFS_XFS: &str = "mkfs.xfs";
 const THIN_CHECK: &str = "thin_check";
+const THIN_LS: &str = "thin_ls";
 const THIN_REPAIR: &str = "thin_repair";
 const UDEVADM: &str = "udevadm";
 const XFS_DB: &str = "xfs_db";
@@ -99,6 +102,7 @@ lazy_static! {
     static ref BINARIES: HashMap<String, Option<PathBuf>> = [
         (MKFS_XFS.to_string(), find_binary(MKFS_XFS)),
         (THIN_CHECK.to_string(), find_binary(THIN_CHECK)),
+        (THIN_LS.to_string(), find_binary(THIN_LS)),
         (THIN_REPAIR.to_string(), find_binary(THIN_REPAIR)),
         (UDEVADM.to_string(), find_binary(UDEVADM)),
         (XFS_DB.to_string(), find_binary(XFS_DB)),
@@ -149,6 +153,12 @@ pub fn verify_binaries() -> StratisResult<()> {
 /// Invoke the specified command. Return an error if invoking the command
 /// fails or if the command itself fails.
 fn execute_cmd(cmd: &mut Command) -> StratisResult<()> {
+    execute_cmd_output(cmd).map(|_| ())
+}
+
+/// Invoke the specified command and return its standard output. Return an
+/// error if invoking the command fails or if the command itself fails.
+fn execute_cmd_output(cmd: &mut Command) -> StratisResult<Vec<u8>> {
     match cmd.output() {
         Err(err) => Err(StratisError::Error(format!(
             "Failed to execute command {:?}, err: {:?}",
@@ -156,7 +166,7 @@ fn execute_cmd(cmd: &mut Command) -> StratisResult<()> {
         ))),
         Ok(result) => {
             if result.status.success() {
-                Ok(())
+                Ok(result.stdout)
             } else {
                 let exit_reason = result
                     .status
@@ -186,6 +196,23 @@ fn get_executable(name: &str) -> &Path {
         .expect("verify_binaries() was previously called and returned no error")
 }
 
+/// Get an absolute path for the executable with the given name, or return an
+/// error if it was not found. For use by tools which do not invoke
+/// verify_binaries().
+fn get_executable_checked(name: &str) -> StratisResult<&Path> {
+    BINARIES
+        .get(name)
+        .expect("name arguments are all constants defined with BINARIES, lookup can not fail")
+        .as_deref()
+        .ok_or_else(|| {
+            StratisError::Error(format!(
+                "Unable to find executable \"{}\" in any of {}",
+                name,
+                BINARIES_PATHS.join(", ")
+            ))
+        })
+}
+
 /// Get an absolute path for the Clevis executable or return an error if Clevis
 /// support is disabled.
 fn get_clevis_executable() -> StratisResult<&'static Path> {
@@ -247,6 +274,28 @@ pub fn thin_check(devnode: &Path) -> StratisResult<()> {
     )
 }
 
+/// Call thin_ls on the metadata device of a thin pool that is not active.
+/// Return the ids of the thin devices that the metadata records.
+pub fn thin_ls_dev_ids(meta_dev: &Path) -> StratisResult<Vec<ThinDevId>> {
+    let output = execute_cmd_output(
+        Command::new(get_executable_checked(THIN_LS)?.as_os_str())
+            .arg("--no-headers")
+            .arg("-o")
+            .arg("DEV")
+            .arg(meta_dev),
+    )?;
+    String::from_utf8_lossy(&output)
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse::<ThinDevId>().map_err(|_| {
+                StratisError::Error(format!("Unexpected output from thin_ls: {}", line))
+            })
+        })
+        .collect()
+}
+
 /// Call thin_repair on a thinpool
 pub fn thin_repair(meta_dev: &Path, new_meta_dev: &Path) -> StratisResult<()> {
     execute_cmd(
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{clone::Clone, collections::HashMap, path::Path};
+use std::{clone::Clone, collections::HashMap, os::unix::io::RawFd, path::Path, time::Duration};
 
 use serde_json::Value;
 
@@ -10,21 +10,24 @@ use devicemapper::{DmNameBuf, DM};
 
 use crate::{
     engine::{
-        engine::KeyActions,
+        engine::{KeyActions, REENCRYPT_STEP_DURATION},
         event::get_engine_listener_list,
-        shared::{create_pool_idempotent_or_err, validate_name, validate_paths},
+        shared::{
+            create_pool_encryption_info, create_pool_idempotent_or_err, missing_keys_report,
+            unlock_passphrase_shared, validate_name, validate_paths,
+        },
         strat_engine::{
             cmd::verify_binaries,
             devlinks,
             dm::{get_dm, get_dm_init},
-            keys::{MemoryFilesystem, StratKeyActions},
+            keys::{search_key_persistent, MemoryFilesystem, StratKeyActions},
             liminal::{find_all, LiminalDevices},
             pool::StratPool,
         },
         structures::Table,
         types::{
-            CreateAction, DeleteAction, DevUuid, EncryptionInfo, KeyDescription, RenameAction,
-            ReportType, SetUnlockAction, UnlockMethod,
+            CreateAction, DeleteAction, DevUuid, EncryptionInfo, EncryptionParams, Key,
+            KeyDescription, RenameAction, ReportType, SetUnlockAction, UnlockMethod,
         },
         Engine, EngineEvent, Name, Pool, PoolUuid, Report,
     },
@@ -161,6 +164,7 @@ impl Report for StratEngine {
     fn get_report(&self, report_type: ReportType) -> Value {
         match report_type {
             ReportType::ErroredPoolDevices => (&self.liminal_devices).into(),
+            ReportType::MissingKeys => missing_keys_report(self),
         }
     }
 }
@@ -190,6 +194,7 @@ impl Engine for StratEngine {
         blockdev_paths: &[&Path],
         redundancy: Option<u16>,
         key_desc: Option<KeyDescription>,
+        encryption_params: Option<EncryptionParams>,
     ) -> StratisResult<CreateAction<PoolUuid>> {
         let redundancy = calculate_redundancy!(redundancy);
 
@@ -197,8 +202,12 @@ impl Engine for StratEngine {
 
         validate_paths(blockdev_paths)?;
 
+        let encryption_info = create_pool_encryption_info(key_desc, encryption_params)?;
+
         match self.pools.get_by_name(name) {
-            Some((_, pool)) => create_pool_idempotent_or_err(pool, name, blockdev_paths),
+            Some((_, pool)) => {
+                create_pool_idempotent_or_err(pool, name, blockdev_paths, encryption_info.as_ref())
+            }
             None => {
                 if blockdev_paths.is_empty() {
                     Err(StratisError::Engine(
@@ -206,8 +215,12 @@ impl Engine for StratEngine {
                         "At least one blockdev is required to create a pool.".to_string(),
                     ))
                 } else {
-                    let (uuid, pool) =
-                        StratPool::initialize(name, blockdev_paths, redundancy, key_desc.as_ref())?;
+                    let (uuid, pool) = StratPool::initialize(
+                        name,
+                        blockdev_paths,
+                        redundancy,
+                        encryption_info.as_ref(),
+                    )?;
 
                     let name = Name::new(name.to_owned());
                     self.pools.insert(name, uuid, pool);
@@ -238,6 +251,7 @@ impl Engine for StratEngine {
             self.pools.insert(pool_name, uuid, pool);
             Err(err)
         } else {
+            self.liminal_devices.pool_destroyed(uuid);
             Ok(DeleteAction::Deleted(uuid))
         }
     }
@@ -278,13 +292,73 @@ impl Engine for StratEngine {
         &mut self,
         pool_uuid: PoolUuid,
         unlock_method: UnlockMethod,
+        passphrase_fd: Option<RawFd>,
     ) -> StratisResult<SetUnlockAction<DevUuid>> {
-        let unlocked = self
-            .liminal_devices
-            .unlock_pool(&self.pools, pool_uuid, unlock_method)?;
+        let passphrase = unlock_passphrase_shared(unlock_method, passphrase_fd)?;
+        let unlocked =
+            self.liminal_devices
+                .unlock_pool(&self.pools, pool_uuid, unlock_method, passphrase)?;
         Ok(SetUnlockAction::new(unlocked))
     }
 
+    fn encrypt_pool(
+        &mut self,
+        pool_uuid: PoolUuid,
+        key_desc: &KeyDescription,
+        encryption_params: Option<EncryptionParams>,
+    ) -> StratisResult<CreateAction<Key>> {
+        let encryption_info =
+            create_pool_encryption_info(Some(key_desc.clone()), encryption_params)?
+                .expect("a key description was specified");
+
+        if let Some((name, pool)) = self.pools.get_by_uuid(pool_uuid) {
+            match pool.encryption_info() {
+                Some(info) if &info.key_description == key_desc => {
+                    return Ok(CreateAction::Identity);
+                }
+                Some(info) => {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "Pool {} is already encrypted with key description {}",
+                            name,
+                            info.key_description.as_application_str()
+                        ),
+                    ));
+                }
+                None => pool.check_encrypt_in_place(&name)?,
+            }
+        }
+
+        if search_key_persistent(key_desc)?.is_none() {
+            return Err(StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!(
+                    "Key {} was not found in the keyring",
+                    key_desc.as_application_str()
+                ),
+            ));
+        }
+
+        if let Some((_, pool)) = self.pools.remove_by_uuid(pool_uuid) {
+            if let Err(err) = self.liminal_devices.stop_pool(pool_uuid, pool) {
+                if let Some((name, pool)) = self
+                    .liminal_devices
+                    .setup_errored_pool(&self.pools, pool_uuid)
+                {
+                    self.pools.insert(name, pool_uuid, pool);
+                }
+                return Err(err);
+            }
+        }
+
+        let (name, pool) =
+            self.liminal_devices
+                .encrypt_pool(&self.pools, pool_uuid, &encryption_info)?;
+        self.pools.insert(name, pool_uuid, pool);
+        Ok(CreateAction::Created(Key))
+    }
+
     fn get_pool(&self, uuid: PoolUuid) -> Option<(Name, &dyn Pool)> {
         get_pool!(self; uuid)
     }
@@ -340,6 +414,18 @@ impl Engine for StratEngine {
         Ok(())
     }
 
+    fn reencrypt_step(&mut self) -> bool {
+        let mut remaining = false;
+        for (_, _, pool) in &mut self.pools {
+            remaining |= pool.reencrypt_step(REENCRYPT_STEP_DURATION);
+        }
+        remaining
+    }
+
+    fn process_auto_unlocks(&mut self) -> Option<Duration> {
+        self.liminal_devices.process_auto_unlocks()
+    }
+
     fn get_key_handler(&self) -> &dyn KeyActions {
         &self.key_handler as &dyn KeyActions
     }
@@ -355,7 +441,13 @@ impl Engine for StratEngine {
 
 #[cfg(test)]
 mod test {
-    use crate::engine::strat_engine::tests::{loopbacked, real};
+    use std::{error::Error, fs::OpenOptions};
+
+    use crate::engine::strat_engine::{
+        fsck::{fsck_devices, repair_devices},
+        metadata::BDA,
+        tests::{crypt, loopbacked, real},
+    };
 
     use crate::engine::types::EngineAction;
 
@@ -367,7 +459,7 @@ mod test {
 
         let name1 = "name1";
         let uuid1 = engine
-            .create_pool(name1, paths, None, None)
+            .create_pool(name1, paths, None, None, None)
             .unwrap()
             .changed()
             .unwrap();
@@ -399,6 +491,90 @@ mod test {
         );
     }
 
+    /// Verify that a pool whose most recent metadata can not be read on any
+    /// of its devices is set up from older metadata and reported as such,
+    /// and that repairing its metadata makes it whole again.
+    /// 1. Create a pool and rename it, so that the newer metadata has the
+    /// new name and the older metadata the original one.
+    /// 2. Make the newer metadata on every device unreadable and verify
+    /// that the offline check notices.
+    /// 3. Verify that the pool is set up with its original name and that
+    /// the fallback is reported.
+    /// 4. Repair the metadata and verify that the check finds no errors and
+    /// that the pool is set up without a fallback.
+    fn test_metadata_fallback(paths: &[&Path]) {
+        fn corrupt_newest_metadata(paths: &[&Path]) {
+            for path in paths {
+                let mut f = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)
+                    .unwrap();
+                BDA::load(&mut f)
+                    .unwrap()
+                    .unwrap()
+                    .corrupt_state(&mut f)
+                    .unwrap();
+            }
+        }
+
+        fn has_fallback(engine: &StratEngine, uuid: PoolUuid) -> bool {
+            engine.get_report(ReportType::ErroredPoolDevices)["errored_pools"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|entry| {
+                    entry["pool_uuid"] == Value::from(uuid.to_string())
+                        && entry.get("metadata_fallback").is_some()
+                })
+        }
+
+        let mut engine = StratEngine::initialize().unwrap();
+
+        let name1 = "name1";
+        let uuid = engine
+            .create_pool(name1, paths, None, None, None)
+            .unwrap()
+            .changed()
+            .unwrap();
+        engine.rename_pool(uuid, "name2").unwrap();
+        engine.teardown().unwrap();
+
+        corrupt_newest_metadata(paths);
+        let devnodes = paths.iter().map(|p| p.to_path_buf()).collect::<Vec<_>>();
+        assert!(!fsck_devices(&devnodes).unwrap().problems().is_empty());
+
+        let engine = StratEngine::initialize().unwrap();
+        let pool_name: String = engine.get_pool(uuid).unwrap().0.to_owned();
+        assert_eq!(pool_name, name1);
+        assert!(has_fallback(&engine, uuid));
+        engine.teardown().unwrap();
+
+        assert_eq!(repair_devices(&devnodes, None).unwrap().len(), paths.len());
+        assert!(!fsck_devices(&devnodes).unwrap().has_errors());
+
+        let mut engine = StratEngine::initialize().unwrap();
+        assert!(engine.get_pool(uuid).is_some());
+        assert!(!has_fallback(&engine, uuid));
+        engine.destroy_pool(uuid).unwrap();
+    }
+
+    #[test]
+    fn loop_test_metadata_fallback() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(1, 3, None),
+            test_metadata_fallback,
+        );
+    }
+
+    #[test]
+    fn real_test_metadata_fallback() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(1, None, None),
+            test_metadata_fallback,
+        );
+    }
+
     /// Test engine setup.
     /// 1. Create two pools.
     /// 2. Verify that both exist.
@@ -417,14 +593,14 @@ mod test {
 
         let name1 = "name1";
         let uuid1 = engine
-            .create_pool(name1, paths1, None, None)
+            .create_pool(name1, paths1, None, None, None)
             .unwrap()
             .changed()
             .unwrap();
 
         let name2 = "name2";
         let uuid2 = engine
-            .create_pool(name2, paths2, None, None)
+            .create_pool(name2, paths2, None, None, None)
             .unwrap()
             .changed()
             .unwrap();
@@ -458,4 +634,70 @@ mod test {
     fn real_test_setup() {
         real::test_with_spec(&real::DeviceLimits::AtLeast(2, None, None), test_setup);
     }
+
+    /// Verify that a pool that has been set up, and whose thin pool has
+    /// therefore been checked and extended, can be encrypted in place, and
+    /// that it is still encrypted and has its filesystem when it is set up
+    /// again.
+    fn test_encrypt_pool(paths: &[&Path]) {
+        fn encrypt_test(
+            paths: &[&Path],
+            key_desc: &KeyDescription,
+            _: Option<()>,
+        ) -> std::result::Result<(), Box<dyn Error>> {
+            let mut engine = StratEngine::initialize()?;
+
+            let name = "encrypt_pool";
+            let uuid = engine
+                .create_pool(name, paths, None, None, None)?
+                .changed()
+                .expect("no pool with this name exists");
+            engine
+                .get_mut_pool(uuid)
+                .expect("pool was just created")
+                .1
+                .create_filesystems(uuid, &[("fs", None, None)])?;
+
+            engine.teardown()?;
+            let mut engine = StratEngine::initialize()?;
+
+            assert_matches!(
+                engine.encrypt_pool(uuid, key_desc, None)?,
+                CreateAction::Created(_)
+            );
+            let (_, pool) = engine.get_pool(uuid).expect("pool was set up again");
+            assert_eq!(
+                pool.encryption_info().map(|info| &info.key_description),
+                Some(key_desc)
+            );
+            assert_eq!(pool.filesystems().len(), 1);
+
+            engine.teardown()?;
+            let mut engine = StratEngine::initialize()?;
+            let (_, pool) = engine.get_pool(uuid).expect("pool was set up again");
+            assert!(pool.is_encrypted());
+            assert_eq!(pool.filesystems().len(), 1);
+
+            engine.destroy_pool(uuid)?;
+            Ok(())
+        }
+
+        crypt::insert_and_cleanup_key(paths, encrypt_test);
+    }
+
+    #[test]
+    fn loop_test_encrypt_pool() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(1, 3, None),
+            test_encrypt_pool,
+        );
+    }
+
+    #[test]
+    fn real_test_encrypt_pool() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(1, None, None),
+            test_encrypt_pool,
+        );
+    }
 }
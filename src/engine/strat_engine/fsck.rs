@@ -0,0 +1,1916 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Check the metadata of a pool that is not set up, without modifying it.
+//!
+//! Every copy of the metadata that Stratis keeps on each device of the pool
+//! is read and compared with the others. The newest copy of the pool-level
+//! metadata is then checked against the devices it describes, and, where
+//! possible, the filesystem records on the pool's metadata volume are
+//! checked as well, and compared with the thin devices that the thin pool
+//! metadata records. Nothing is ever written to any of the devices.
+
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{read_dir, File, OpenOptions},
+    io::Read,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use nix::{
+    mount::{mount, umount, MsFlags},
+    sys::stat::{stat, SFlag},
+};
+use serde_json::Value;
+use tempfile::TempDir;
+
+use devicemapper::{
+    Bytes, DevId, Device, DmDevice, LinearDev, LinearDevTargetParams, LinearTargetParams, Sectors,
+    TargetLine, ThinDevId,
+};
+
+use crate::{
+    engine::{
+        strat_engine::{
+            backstore::{PerDevSegments, RangeAllocator},
+            cmd::thin_ls_dev_ids,
+            device::blkdev_size,
+            dm::{get_dm, get_dm_init},
+            liminal::find_all,
+            metadata::{BDAContents, MDARegionContents, StaticHeader, BDA},
+            names::{format_flex_ids, format_fsck_mdv_name, format_fsck_thin_meta_name, FlexRole},
+            pool::check_metadata,
+            serde_structs::{BaseDevSave, FilesystemSave, PoolSave},
+        },
+        types::{CacheMode, DevUuid, PoolUuid},
+    },
+    stratis::{ErrorEnum, StratisError, StratisResult},
+};
+
+/// The directory on the MDV in which filesystem records are kept.
+pub(super) const FILESYSTEM_DIR: &str = "filesystems";
+
+/// How serious a problem found in the metadata is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// stratisd can set up the pool, but some copy of the metadata is
+    /// damaged or out of date.
+    Warning,
+    /// stratisd may be unable to set up the pool, or may set it up wrongly.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found in the metadata, with a suggested repair, if
+/// there is one.
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    pub description: String,
+    pub repair: Option<String>,
+}
+
+impl<'a> Into<Value> for &'a Problem {
+    fn into(self) -> Value {
+        json!({
+            "severity": Value::from(self.severity.to_string()),
+            "description": Value::from(self.description.clone()),
+            "repair": self.repair.clone().map(Value::from).unwrap_or(Value::Null),
+        })
+    }
+}
+
+/// The result of checking the metadata of a pool.
+#[derive(Debug)]
+pub struct FsckReport {
+    pool_uuid: Option<PoolUuid>,
+    devices: Vec<Value>,
+    metadata: Option<Value>,
+    filesystems: Option<Value>,
+    problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    fn new() -> FsckReport {
+        FsckReport {
+            pool_uuid: None,
+            devices: Vec::new(),
+            metadata: None,
+            filesystems: None,
+            problems: Vec::new(),
+        }
+    }
+
+    fn error<S>(&mut self, description: S, repair: Option<&str>)
+    where
+        S: Into<String>,
+    {
+        self.problems.push(Problem {
+            severity: Severity::Error,
+            description: description.into(),
+            repair: repair.map(|r| r.to_string()),
+        });
+    }
+
+    fn warning<S>(&mut self, description: S, repair: Option<&str>)
+    where
+        S: Into<String>,
+    {
+        self.problems.push(Problem {
+            severity: Severity::Warning,
+            description: description.into(),
+            repair: repair.map(|r| r.to_string()),
+        });
+    }
+
+    /// All the problems found, in the order in which they were found.
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Whether any problem was found that may prevent stratisd from
+    /// setting up the pool correctly.
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+}
+
+impl<'a> Into<Value> for &'a FsckReport {
+    fn into(self) -> Value {
+        let verdict = if self.has_errors() {
+            "errors"
+        } else if self.problems.is_empty() {
+            "clean"
+        } else {
+            "warnings"
+        };
+        json!({
+            "pool_uuid": self
+                .pool_uuid
+                .map(|uuid| Value::from(uuid.to_string()))
+                .unwrap_or(Value::Null),
+            "devices": Value::Array(self.devices.clone()),
+            "metadata": self.metadata.clone().unwrap_or(Value::Null),
+            "filesystems": self.filesystems.clone().unwrap_or(Value::Null),
+            "problems": Value::Array(self.problems.iter().map(|p| p.into()).collect()),
+            "verdict": Value::from(verdict),
+        })
+    }
+}
+
+/// A device to be checked.
+pub(super) struct DeviceToCheck {
+    devnode: PathBuf,
+    device_number: Option<Device>,
+}
+
+/// What was read from a single device.
+pub(super) struct DeviceContents {
+    pub(super) devnode: PathBuf,
+    pub(super) device_number: Option<Device>,
+    pub(super) size: Option<Bytes>,
+    bda: BDAContents,
+}
+
+impl DeviceContents {
+    pub(super) fn header(&self) -> Option<&StaticHeader> {
+        self.bda.header()
+    }
+
+    fn dev_uuid(&self) -> Option<DevUuid> {
+        self.header().map(|h| h.identifiers.device_uuid)
+    }
+
+    /// Every copy of the variable length metadata on this device that could
+    /// be read, with the time it was written and its CRC.
+    fn metadata_copies(&self) -> impl Iterator<Item = (&DateTime<Utc>, u32, &[u8])> {
+        self.bda.regions.iter().flatten().filter_map(|region| {
+            match (&region.header, &region.data) {
+                (Ok(Some(header)), Some(Ok(data))) => {
+                    Some((header.last_updated(), header.data_crc(), data.as_slice()))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// The newest copy of the variable length metadata on this device that
+    /// could be read, with the time it was written and its CRC.
+    fn newest_metadata(&self) -> Option<(&DateTime<Utc>, u32, &[u8])> {
+        self.metadata_copies().max_by_key(|(time, _, _)| *time)
+    }
+
+    /// The time at which the newest valid MDA header on this device claims
+    /// that metadata was written, whether or not the metadata itself can be
+    /// read.
+    fn newest_header_time(&self) -> Option<&DateTime<Utc>> {
+        self.bda
+            .regions
+            .iter()
+            .flatten()
+            .filter_map(|region| match region.header {
+                Ok(Some(ref header)) => Some(header.last_updated()),
+                _ => None,
+            })
+            .max()
+    }
+}
+
+/// Check the pool with the given UUID, using all devices that udev
+/// identifies as belonging to it.
+pub fn fsck_pool(pool_uuid: PoolUuid) -> StratisResult<FsckReport> {
+    let (devices, locked) = pool_devices(pool_uuid)?;
+    Ok(check(&devices, &locked))
+}
+
+/// Check the pool to which the given devices belong. Only the given devices
+/// are read.
+pub fn fsck_devices(devnodes: &[PathBuf]) -> StratisResult<FsckReport> {
+    Ok(check(&named_devices(devnodes)?, &HashSet::new()))
+}
+
+/// Rewrite the metadata on every device of the pool with the given UUID
+/// that can be read, using a good copy of the pool metadata. See repair().
+pub fn repair_pool(
+    pool_uuid: PoolUuid,
+    copy_time: Option<DateTime<Utc>>,
+) -> StratisResult<Vec<PathBuf>> {
+    let (devices, _) = pool_devices(pool_uuid)?;
+    repair(&devices, copy_time)
+}
+
+/// Rewrite the metadata on the given devices, using a good copy of the pool
+/// metadata found on one of them. See repair().
+pub fn repair_devices(
+    devnodes: &[PathBuf],
+    copy_time: Option<DateTime<Utc>>,
+) -> StratisResult<Vec<PathBuf>> {
+    repair(&named_devices(devnodes)?, copy_time)
+}
+
+/// Describe everything that can be read from the BDA of the given device:
+/// both copies of the signature block and every MDA region header. If
+/// decode is true, include the pool metadata decoded from each MDA region.
+/// If regions is specified, describe only the MDA regions with those
+/// indices.
+pub fn dump_device(
+    devnode: &Path,
+    decode: bool,
+    regions: Option<&[usize]>,
+) -> StratisResult<Value> {
+    let mut f = OpenOptions::new().read(true).open(devnode)?;
+    let size = device_size(&f);
+    Ok(bda_json(
+        devnode,
+        size,
+        &BDA::read_contents(&mut f),
+        decode,
+        regions,
+    ))
+}
+
+/// Read the filesystem records on the metadata volume of the pool with the
+/// given UUID, located using the newest valid copy of the pool metadata.
+/// Returns an error if the metadata volume can not be read safely, for
+/// example because the pool is set up.
+pub fn dump_filesystem_records(pool_uuid: PoolUuid) -> StratisResult<Value> {
+    let (devices, _) = pool_devices(pool_uuid)?;
+    let contents = read_devices(&devices)?;
+    let (_, pool_save) = choose_metadata(&contents, None)?;
+
+    let device_numbers = contents
+        .iter()
+        .filter_map(|c| match (c.dev_uuid(), c.device_number) {
+            (Some(dev_uuid), Some(number)) => Some((dev_uuid, number)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let table = mdv_table(pool_uuid, &pool_save, &device_numbers).map_err(|reason| {
+        StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!("The metadata volume can not be read: {}", reason),
+        )
+    })?;
+
+    let records = read_mdv(pool_uuid, table)?
+        .into_iter()
+        .map(|(filename, record)| match record {
+            Ok(record) => json!({
+                "file": Value::from(filename),
+                "record": serde_json::to_value(&record).unwrap_or(Value::Null),
+            }),
+            Err(err) => json!({
+                "file": Value::from(filename),
+                "error": Value::from(err.to_string()),
+            }),
+        })
+        .collect();
+
+    Ok(json!({
+        "pool_uuid": Value::from(pool_uuid.to_string()),
+        "records": Value::Array(records),
+    }))
+}
+
+/// Find all the devices of the pool with the given UUID that can be read,
+/// and the UUIDs of those that can not, because they are encrypted and
+/// locked.
+pub(super) fn pool_devices(
+    pool_uuid: PoolUuid,
+) -> StratisResult<(Vec<DeviceToCheck>, HashSet<DevUuid>)> {
+    let (luks_devices, stratis_devices) = find_all()?;
+
+    let stratis_devices = stratis_devices.get(&pool_uuid).cloned().unwrap_or_default();
+    let locked = luks_devices
+        .get(&pool_uuid)
+        .map(|infos| {
+            infos
+                .iter()
+                .map(|info| info.info.identifiers.device_uuid)
+                .filter(|dev_uuid| {
+                    !stratis_devices
+                        .iter()
+                        .any(|info| info.identifiers.device_uuid == *dev_uuid)
+                })
+                .collect::<HashSet<_>>()
+        })
+        .unwrap_or_default();
+
+    if stratis_devices.is_empty() && locked.is_empty() {
+        return Err(StratisError::Engine(
+            ErrorEnum::NotFound,
+            format!(
+                "No devices belonging to pool with UUID {} were found",
+                pool_uuid.to_simple_ref()
+            ),
+        ));
+    }
+
+    let devices = stratis_devices
+        .into_iter()
+        .map(|info| DeviceToCheck {
+            devnode: info.devnode,
+            device_number: Some(info.device_number),
+        })
+        .collect::<Vec<_>>();
+
+    Ok((devices, locked))
+}
+
+/// The devices with the given devnodes.
+pub(super) fn named_devices(devnodes: &[PathBuf]) -> StratisResult<Vec<DeviceToCheck>> {
+    devnodes
+        .iter()
+        .map(|devnode| -> StratisResult<DeviceToCheck> {
+            let st = stat(devnode)?;
+            let is_block = st.st_mode & SFlag::S_IFMT.bits() == SFlag::S_IFBLK.bits();
+            Ok(DeviceToCheck {
+                devnode: devnode.to_owned(),
+                device_number: if is_block {
+                    Some(Device::from(st.st_rdev))
+                } else {
+                    None
+                },
+            })
+        })
+        .collect()
+}
+
+/// Read the BDA of a single device and check it in isolation.
+fn check_device(report: &mut FsckReport, device: &DeviceToCheck) -> Option<DeviceContents> {
+    let devnode = device.devnode.display();
+    let mut f = match OpenOptions::new().read(true).open(&device.devnode) {
+        Ok(f) => f,
+        Err(err) => {
+            report.error(
+                format!("Device {} could not be opened: {}", devnode, err),
+                None,
+            );
+            return None;
+        }
+    };
+    let size = device_size(&f);
+    let bda = BDA::read_contents(&mut f);
+
+    let sigblocks_agree = match bda.sigblocks {
+        (Ok(Some(ref first)), Ok(Some(ref second))) => first == second,
+        _ => false,
+    };
+
+    match bda.sigblocks {
+        (Ok(Some(_)), Ok(Some(_))) => {
+            if !sigblocks_agree {
+                report.warning(
+                    format!("The two copies of the signature block on device {} differ", devnode),
+                    Some("stratisd rewrites the older copy from the newer one when it next sets up the device"),
+                );
+            }
+        }
+        (Ok(Some(_)), _) | (_, Ok(Some(_))) => report.warning(
+            format!("One copy of the signature block on device {} is missing or damaged", devnode),
+            Some("stratisd rewrites the damaged copy from the good one when it next sets up the device"),
+        ),
+        (Ok(None), Ok(None)) => report.error(
+            format!("No Stratis signature block was found on device {}", devnode),
+            Some("Check that the device is a member of the pool; if so, its Stratis metadata has been overwritten"),
+        ),
+        _ => report.error(
+            format!("Neither copy of the signature block on device {} can be read", devnode),
+            None,
+        ),
+    }
+
+    report
+        .devices
+        .push(bda_json(&device.devnode, size, &bda, false, None));
+
+    let contents = DeviceContents {
+        devnode: device.devnode.clone(),
+        device_number: device.device_number,
+        size,
+        bda,
+    };
+
+    if let Some(header) = contents.header() {
+        match size {
+            Some(size) if size.sectors() < header.blkdev_size.sectors() => report.error(
+                format!(
+                    "Device {} is {} but its metadata records a size of {}",
+                    devnode,
+                    size.sectors(),
+                    header.blkdev_size.sectors()
+                ),
+                Some("Restore the device to at least its recorded size"),
+            ),
+            Some(_) => (),
+            None => report.warning(
+                format!("The size of device {} could not be determined", devnode),
+                None,
+            ),
+        }
+    }
+
+    if let Some(ref regions) = contents.bda.regions {
+        let region_status = |index: usize| -> bool {
+            matches!(
+                (&regions[index].header, &regions[index].data),
+                (Ok(_), None) | (Ok(_), Some(Ok(_)))
+            )
+        };
+        let num_primary = regions.len() / 2;
+        for index in 0..num_primary {
+            let backup = index + num_primary;
+            match (region_status(index), region_status(backup)) {
+                (true, true) => {
+                    let same = match (&regions[index].header, &regions[backup].header) {
+                        (Ok(Some(primary_header)), Ok(Some(backup_header))) => {
+                            primary_header.last_updated() == backup_header.last_updated()
+                                && primary_header.data_crc() == backup_header.data_crc()
+                        }
+                        (Ok(None), Ok(None)) => true,
+                        _ => false,
+                    };
+                    if !same {
+                        report.warning(
+                            format!("The primary and backup copies of MDA region {} on device {} differ", index, devnode),
+                            Some("The region is rewritten in full by a later metadata update"),
+                        );
+                    }
+                }
+                (true, false) | (false, true) => report.warning(
+                    format!("One copy of MDA region {} on device {} is damaged", index, devnode),
+                    Some("stratisd uses the good copy; the region is rewritten in full by a later metadata update"),
+                ),
+                (false, false) => report.error(
+                    format!("Both copies of MDA region {} on device {} are damaged", index, devnode),
+                    Some("stratisd can not set up this device; run stratis_fsck --repair to rewrite its metadata from a good copy"),
+                ),
+            }
+        }
+    }
+
+    Some(contents)
+}
+
+/// Describe everything read from the BDA of a device. If decode is true,
+/// include the pool metadata decoded from each MDA region. If only_regions
+/// is specified, describe only the MDA regions with those indices.
+fn bda_json(
+    devnode: &Path,
+    size: Option<Bytes>,
+    bda: &BDAContents,
+    decode: bool,
+    only_regions: Option<&[usize]>,
+) -> Value {
+    let sigblocks_agree = match bda.sigblocks {
+        (Ok(Some(ref first)), Ok(Some(ref second))) => first == second,
+        _ => false,
+    };
+    let last_updated = bda
+        .regions
+        .iter()
+        .flatten()
+        .filter_map(|region| match (&region.header, &region.data) {
+            (Ok(Some(header)), Some(Ok(_))) => Some(header.last_updated()),
+            _ => None,
+        })
+        .max();
+
+    json!({
+        "devnode": Value::from(devnode.display().to_string()),
+        "size": size.map(|s| Value::from(*s.sectors())).unwrap_or(Value::Null),
+        "pool_uuid": bda
+            .header()
+            .map(|h| Value::from(h.identifiers.pool_uuid.to_string()))
+            .unwrap_or(Value::Null),
+        "device_uuid": bda
+            .header()
+            .map(|h| Value::from(h.identifiers.device_uuid.to_string()))
+            .unwrap_or(Value::Null),
+        "sigblocks": Value::Array(vec![
+            sigblock_json(&bda.sigblocks.0),
+            sigblock_json(&bda.sigblocks.1),
+        ]),
+        "sigblocks_agree": Value::from(sigblocks_agree),
+        "regions": bda
+            .regions
+            .as_ref()
+            .map(|regions| {
+                Value::Array(
+                    regions
+                        .iter()
+                        .filter(|r| only_regions.map_or(true, |only| only.contains(&r.index)))
+                        .map(|r| region_json(r, decode))
+                        .collect(),
+                )
+            })
+            .unwrap_or(Value::Null),
+        "last_updated": last_updated
+            .map(|time| Value::from(time.to_rfc3339()))
+            .unwrap_or(Value::Null),
+    })
+}
+
+/// Describe a single copy of the signature block.
+fn sigblock_json(sigblock: &StratisResult<Option<StaticHeader>>) -> Value {
+    let (status, header, error) = match sigblock {
+        Ok(Some(header)) => ("valid", header.into(), Value::Null),
+        Ok(None) => ("missing", Value::Null, Value::Null),
+        Err(err) => ("invalid", Value::Null, Value::from(err.to_string())),
+    };
+    json!({
+        "status": Value::from(status),
+        "header": header,
+        "error": error,
+    })
+}
+
+/// Describe a single MDA region. If decode is true, include the pool
+/// metadata decoded from the region.
+fn region_json(region: &MDARegionContents, decode: bool) -> Value {
+    let (status, error) = match (&region.header, &region.data) {
+        (Err(err), _) => ("invalid_header", Some(err.to_string())),
+        (Ok(None), _) => ("empty", None),
+        (Ok(Some(_)), Some(Err(err))) => ("invalid_data", Some(err.to_string())),
+        (Ok(Some(_)), _) => ("valid", None),
+    };
+    let mut json = json!({
+        "index": Value::from(region.index),
+        "backup": Value::from(region.is_backup()),
+        "status": Value::from(status),
+        "header": match region.header {
+            Ok(Some(ref header)) => header.into(),
+            _ => Value::Null,
+        },
+        "data_crc_valid": region
+            .data
+            .as_ref()
+            .map(|data| Value::from(data.is_ok()))
+            .unwrap_or(Value::Null),
+        "error": error.map(Value::from).unwrap_or(Value::Null),
+    });
+    if let (true, Some(Ok(data)), Value::Object(map)) = (decode, &region.data, &mut json) {
+        match serde_json::from_slice::<PoolSave>(data).and_then(|m| serde_json::to_value(&m)) {
+            Ok(metadata) => map.insert("pool_metadata".into(), metadata),
+            Err(err) => map.insert("pool_metadata_error".into(), Value::from(err.to_string())),
+        };
+    }
+    json
+}
+
+/// The size of a block device or, failing that, of a regular file.
+fn device_size(f: &File) -> Option<Bytes> {
+    blkdev_size(f)
+        .ok()
+        .or_else(|| f.metadata().ok().map(|m| Bytes::from(m.len())))
+}
+
+/// Check all the given devices, and the pool they make up.
+/// `locked` holds the UUIDs of encrypted devices known to belong to the
+/// pool that could not be read, because they are not unlocked.
+fn check(devices: &[DeviceToCheck], locked: &HashSet<DevUuid>) -> FsckReport {
+    let mut report = FsckReport::new();
+
+    let contents = devices
+        .iter()
+        .filter_map(|device| check_device(&mut report, device))
+        .filter(|contents| contents.header().is_some())
+        .collect::<Vec<_>>();
+
+    for dev_uuid in locked {
+        report.warning(
+            format!(
+                "Encrypted device with UUID {} is locked, so its metadata can not be checked",
+                dev_uuid.to_simple_ref()
+            ),
+            Some("Unlock the pool and run the check again"),
+        );
+    }
+
+    let pool_uuids = contents
+        .iter()
+        .filter_map(|c| c.header().map(|h| h.identifiers.pool_uuid))
+        .collect::<HashSet<_>>();
+    let pool_uuid = match pool_uuids.len() {
+        0 => {
+            report.error(
+                "No device with a valid Stratis signature block was found",
+                None,
+            );
+            return report;
+        }
+        1 => *pool_uuids.iter().next().expect("len() == 1"),
+        _ => {
+            report.error(
+                format!(
+                    "The devices belong to {} different pools: {}",
+                    pool_uuids.len(),
+                    pool_uuids
+                        .iter()
+                        .map(|uuid| uuid.to_simple_ref().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Some("Check the devices of each pool separately"),
+            );
+            return report;
+        }
+    };
+    report.pool_uuid = Some(pool_uuid);
+
+    let mut by_uuid: HashMap<DevUuid, &DeviceContents> = HashMap::new();
+    for device in contents.iter() {
+        let dev_uuid = device.dev_uuid().expect("filtered on header().is_some()");
+        if let Some(other) = by_uuid.insert(dev_uuid, device) {
+            report.error(
+                format!(
+                    "Devices {} and {} both have device UUID {}",
+                    other.devnode.display(),
+                    device.devnode.display(),
+                    dev_uuid.to_simple_ref()
+                ),
+                Some("Disconnect or wipe the device that is not a member of the pool"),
+            );
+        }
+    }
+
+    let pool_save = match check_newest_metadata(&mut report, &contents) {
+        Some(pool_save) => pool_save,
+        None => return report,
+    };
+
+    check_pool_save(&mut report, &pool_save, &by_uuid, locked);
+
+    let filesystems = check_mdv(&mut report, pool_uuid, &pool_save, &by_uuid);
+    report.filesystems = Some(filesystems);
+
+    report
+}
+
+/// Find the newest copy of the pool-level metadata, and check that every
+/// device agrees with it. Returns the metadata, if it could be read and
+/// parsed.
+fn check_newest_metadata(report: &mut FsckReport, contents: &[DeviceContents]) -> Option<PoolSave> {
+    let newest = contents
+        .iter()
+        .filter_map(|c| c.newest_metadata())
+        .max_by_key(|(time, _, _)| *time);
+    let (newest_time, newest_crc, newest_data) = match newest {
+        Some(newest) => newest,
+        None => {
+            report.error(
+                "No readable copy of the pool metadata was found on any device",
+                None,
+            );
+            return None;
+        }
+    };
+
+    if let Some(header_time) = contents.iter().filter_map(|c| c.newest_header_time()).max() {
+        if header_time > newest_time {
+            report.error(
+                format!(
+                    "The newest pool metadata, written at {}, can not be read from any device; the newest readable copy was written at {}",
+                    header_time.to_rfc3339(),
+                    newest_time.to_rfc3339()
+                ),
+                Some("stratisd sets the pool up from the older copy, losing any changes made after it was written; run stratis_fsck --repair to rewrite every device with it"),
+            );
+        }
+    }
+
+    let mut current = 0;
+    for device in contents {
+        match device.newest_metadata() {
+            Some((time, crc, _)) if time == newest_time => {
+                if crc != newest_crc {
+                    report.error(
+                        format!(
+                            "Device {} holds pool metadata written at {} that differs from that on other devices written at the same time",
+                            device.devnode.display(),
+                            time.to_rfc3339()
+                        ),
+                        None,
+                    );
+                } else {
+                    current += 1;
+                }
+            }
+            Some((time, _, _)) => report.warning(
+                format!(
+                    "Device {} holds out of date pool metadata, written at {}",
+                    device.devnode.display(),
+                    time.to_rfc3339()
+                ),
+                Some("stratisd writes the current metadata to every device in the pool whenever the pool metadata is next updated"),
+            ),
+            None => report.warning(
+                format!("Device {} holds no readable pool metadata", device.devnode.display()),
+                Some("stratisd writes the current metadata to every device in the pool whenever the pool metadata is next updated"),
+            ),
+        }
+    }
+
+    let pool_save = match serde_json::from_slice::<PoolSave>(newest_data) {
+        Ok(pool_save) => pool_save,
+        Err(err) => {
+            report.error(
+                format!("The newest pool metadata could not be parsed: {}", err),
+                None,
+            );
+            return None;
+        }
+    };
+
+    report.metadata = Some(json!({
+        "name": Value::from(pool_save.name.clone()),
+        "last_updated": Value::from(newest_time.to_rfc3339()),
+        "devices_up_to_date": Value::from(current),
+    }));
+
+    Some(pool_save)
+}
+
+/// Check the pool metadata against the devices that were found.
+fn check_pool_save(
+    report: &mut FsckReport,
+    pool_save: &PoolSave,
+    by_uuid: &HashMap<DevUuid, &DeviceContents>,
+    locked: &HashSet<DevUuid>,
+) {
+    let backstore = &pool_save.backstore;
+    let tiers = Some(&backstore.data_tier.blockdev)
+        .into_iter()
+        .chain(backstore.cache_tier.as_ref().map(|c| &c.blockdev))
+        .collect::<Vec<_>>();
+
+    let recorded = tiers
+        .iter()
+        .flat_map(|tier| tier.devs.iter().map(|dev| dev.uuid))
+        .collect::<HashSet<_>>();
+
+    for dev_uuid in recorded.iter() {
+        if !by_uuid.contains_key(dev_uuid) && !locked.contains(dev_uuid) {
+            report.error(
+                format!(
+                    "Device with UUID {} is recorded in the pool metadata but was not found",
+                    dev_uuid.to_simple_ref()
+                ),
+                Some("Connect the device; the pool can not be set up without it"),
+            );
+        }
+    }
+    for (dev_uuid, device) in by_uuid.iter() {
+        if !recorded.contains(dev_uuid) {
+            report.warning(
+                format!(
+                    "Device {} claims to belong to the pool but is not recorded in the pool metadata",
+                    device.devnode.display()
+                ),
+                Some("If the device is not in use, remove its Stratis signature with wipefs"),
+            );
+        }
+    }
+
+    // Every device's allocations must lie within its recorded size and
+    // must not overlap with each other or with the BDA.
+    let allocs = tiers
+        .iter()
+        .flat_map(|tier| tier.allocs.iter().flatten())
+        .fold(
+            HashMap::new(),
+            |mut acc: HashMap<DevUuid, Vec<(Sectors, Sectors)>>, seg| {
+                acc.entry(seg.parent)
+                    .or_insert_with(Vec::new)
+                    .push((seg.start, seg.length));
+                acc
+            },
+        );
+    for (dev_uuid, device) in by_uuid.iter() {
+        let header = device.header().expect("filtered on header().is_some()");
+        let mut used = vec![(Sectors(0), header.bda_extended_size().sectors())];
+        used.extend(allocs.get(dev_uuid).into_iter().flatten());
+        if let Err(err) = RangeAllocator::new(header.blkdev_size, &used) {
+            report.error(
+                format!(
+                    "The segments allocated from device {} overlap or exceed its size: {}",
+                    device.devnode.display(),
+                    err
+                ),
+                None,
+            );
+        }
+    }
+    for dev_uuid in allocs.keys() {
+        if !recorded.contains(dev_uuid) {
+            report.error(
+                format!(
+                    "Segments are allocated from device with UUID {}, which is not recorded as a member of the pool",
+                    dev_uuid.to_simple_ref()
+                ),
+                None,
+            );
+        }
+    }
+
+    // The flex devices are allocated from the cap device, which is made up
+    // of the segments allocated from the data tier.
+    let cap_size = backstore
+        .data_tier
+        .blockdev
+        .allocs
+        .iter()
+        .flatten()
+        .map(|seg| seg.length)
+        .sum::<Sectors>();
+    let flex_devs = &pool_save.flex_devs;
+    let mut flex_segments = PerDevSegments::new(cap_size);
+    if let Err(err) = flex_segments.insert_all(
+        &flex_devs
+            .meta_dev
+            .iter()
+            .chain(flex_devs.thin_meta_dev.iter())
+            .chain(flex_devs.thin_data_dev.iter())
+            .chain(flex_devs.thin_meta_dev_spare.iter())
+            .cloned()
+            .collect::<Vec<_>>(),
+    ) {
+        report.error(
+            format!(
+                "The segments allocated to the pool's internal devices overlap or exceed the space allocated from the data tier: {}",
+                err
+            ),
+            None,
+        );
+    }
+
+    if flex_devs.meta_dev.is_empty()
+        || flex_devs.thin_meta_dev.is_empty()
+        || flex_devs.thin_data_dev.is_empty()
+        || flex_devs.thin_meta_dev_spare.is_empty()
+        || backstore.cap.allocs.is_empty()
+        || backstore.data_tier.blockdev.allocs.is_empty()
+    {
+        report.error(
+            "Some of the pool's internal devices have no space allocated to them",
+            None,
+        );
+    } else if let Err(err) = check_metadata(pool_save) {
+        report.error(format!("The pool metadata is inconsistent: {}", err), None);
+    }
+}
+
+/// Map a list of ranges in the cap device onto the devices of the data tier,
+/// whose segments, in order, make up the cap device. Return None if some
+/// range lies beyond the end of the cap device or on a device whose device
+/// number is not known.
+fn map_to_data_tier(
+    data_segments: &[(Option<Device>, Sectors, Sectors)],
+    ranges: &[(Sectors, Sectors)],
+) -> Option<Vec<(Device, Sectors, Sectors)>> {
+    let mut mapped = Vec::new();
+    for &(start, length) in ranges {
+        let mut start = start;
+        let mut remaining = length;
+        let mut seg_start = Sectors(0);
+        for &(device, offset, seg_length) in data_segments {
+            if remaining == Sectors(0) {
+                break;
+            }
+            let seg_end = seg_start + seg_length;
+            if start < seg_end {
+                let within = start - seg_start;
+                let used = cmp::min(remaining, seg_length - within);
+                mapped.push((device?, offset + within, used));
+                start += used;
+                remaining -= used;
+            }
+            seg_start = seg_end;
+        }
+        if remaining != Sectors(0) {
+            return None;
+        }
+    }
+    Some(mapped)
+}
+
+/// Check the filesystem records on the pool's metadata volume, if that can
+/// be done safely. Returns a description of the records found, or of the
+/// reason why they could not be checked.
+fn check_mdv(
+    report: &mut FsckReport,
+    pool_uuid: PoolUuid,
+    pool_save: &PoolSave,
+    by_uuid: &HashMap<DevUuid, &DeviceContents>,
+) -> Value {
+    let not_checked = |reason: &str| json!({"checked": false, "reason": Value::from(reason)});
+
+    let device_numbers = by_uuid
+        .iter()
+        .filter_map(|(dev_uuid, device)| device.device_number.map(|number| (*dev_uuid, number)))
+        .collect::<HashMap<_, _>>();
+    let table = match mdv_table(pool_uuid, pool_save, &device_numbers) {
+        Ok(table) => table,
+        Err(reason) => return not_checked(reason),
+    };
+
+    let records = match read_mdv(pool_uuid, table) {
+        Ok(records) => records,
+        Err(err) => {
+            report.error(
+                format!("The metadata volume could not be read: {}", err),
+                Some("Run xfs_repair on the metadata volume"),
+            );
+            return not_checked("the metadata volume could not be read");
+        }
+    };
+
+    let thin_dev_ids = flex_dev_table(
+        pool_uuid,
+        pool_save,
+        &device_numbers,
+        &pool_save.flex_devs.thin_meta_dev,
+    )
+    .map_err(|reason| reason.to_string())
+    .and_then(|table| read_thin_dev_ids(pool_uuid, table).map_err(|err| err.to_string()));
+    match thin_dev_ids {
+        Ok(thin_dev_ids) => check_filesystem_records(report, &records, Some(&thin_dev_ids)),
+        Err(reason) => {
+            report.warning(
+                format!(
+                    "The thin devices in the thin pool could not be listed, so the filesystem records were not compared with them: {}",
+                    reason
+                ),
+                Some("Run thin_check on the thin pool metadata device"),
+            );
+            check_filesystem_records(report, &records, None)
+        }
+    }
+}
+
+/// Construct the table of a linear device through which the metadata volume
+/// of a pool that is not set up can be read, from the pool's metadata and
+/// the device numbers of its devices. Returns the reason if the metadata
+/// volume can not be read safely.
+pub(super) fn mdv_table(
+    pool_uuid: PoolUuid,
+    pool_save: &PoolSave,
+    device_numbers: &HashMap<DevUuid, Device>,
+) -> Result<Vec<TargetLine<LinearDevTargetParams>>, &'static str> {
+    flex_dev_table(
+        pool_uuid,
+        pool_save,
+        device_numbers,
+        &pool_save.flex_devs.meta_dev,
+    )
+}
+
+/// Construct the table of a linear device through which the flex device
+/// made up of the given ranges of the cap device of a pool that is not set
+/// up can be read. Returns the reason if the flex device can not be read
+/// safely.
+fn flex_dev_table(
+    pool_uuid: PoolUuid,
+    pool_save: &PoolSave,
+    device_numbers: &HashMap<DevUuid, Device>,
+    ranges: &[(Sectors, Sectors)],
+) -> Result<Vec<TargetLine<LinearDevTargetParams>>, &'static str> {
+    if let Some(ref cache_tier) = pool_save.backstore.cache_tier {
+        if cache_tier.mode == CacheMode::Writeback {
+            return Err("the pool has a writeback cache, so the data tier may be out of date");
+        }
+    }
+
+    match is_set_up(pool_uuid) {
+        Ok(false) => (),
+        Ok(true) => return Err("the pool is set up; stop stratisd and tear down the pool first"),
+        Err(_) => return Err("devicemapper is not available"),
+    }
+
+    let data_segments = pool_save
+        .backstore
+        .data_tier
+        .blockdev
+        .allocs
+        .iter()
+        .flatten()
+        .map(
+            |&BaseDevSave {
+                 parent,
+                 start,
+                 length,
+             }| (device_numbers.get(&parent).cloned(), start, length),
+        )
+        .collect::<Vec<_>>();
+    let segments = map_to_data_tier(&data_segments, ranges)
+        .ok_or("some of the devices on which it is stored are not available as block devices")?;
+
+    let mut logical_start = Sectors(0);
+    Ok(segments
+        .into_iter()
+        .map(|(device, start, length)| {
+            let line = TargetLine::new(
+                logical_start,
+                length,
+                LinearDevTargetParams::Linear(LinearTargetParams::new(device, start)),
+            );
+            logical_start += length;
+            line
+        })
+        .collect())
+}
+
+/// Set up a temporary linear device with the given table, mount the
+/// metadata volume on it read-only, read the filesystem records, and tear
+/// it all down again.
+pub(super) fn read_mdv(
+    pool_uuid: PoolUuid,
+    table: Vec<TargetLine<LinearDevTargetParams>>,
+) -> StratisResult<Vec<(String, StratisResult<FilesystemSave>)>> {
+    with_mdv_mounted(pool_uuid, table, true, |mount_pt| {
+        read_filesystem_records(&mount_pt.join(FILESYSTEM_DIR))
+    })
+}
+
+/// Set up a temporary linear device with the given table, mount the
+/// metadata volume on it, read-only if so specified, call action with the
+/// mount point, and tear it all down again.
+pub(super) fn with_mdv_mounted<F, T>(
+    pool_uuid: PoolUuid,
+    table: Vec<TargetLine<LinearDevTargetParams>>,
+    read_only: bool,
+    action: F,
+) -> StratisResult<T>
+where
+    F: FnOnce(&Path) -> StratisResult<T>,
+{
+    let mut mdv = LinearDev::setup(get_dm(), &format_fsck_mdv_name(pool_uuid), None, table)?;
+
+    let result = TempDir::new()
+        .map_err(StratisError::from)
+        .and_then(|mount_pt| {
+            let (flags, data) = if read_only {
+                (MsFlags::MS_RDONLY, Some("norecovery"))
+            } else {
+                (MsFlags::empty(), None)
+            };
+            mount(
+                Some(&mdv.devnode()),
+                mount_pt.path(),
+                Some("xfs"),
+                flags,
+                data,
+            )?;
+            let result = action(mount_pt.path());
+            if let Err(err) = umount(mount_pt.path()) {
+                warn!("Could not unmount the metadata volume: {}", err);
+            }
+            result
+        });
+
+    if let Err(err) = mdv.teardown(get_dm()) {
+        warn!("Could not tear down the metadata volume: {}", err);
+    }
+
+    result
+}
+
+/// Set up a temporary linear device with the given table, through which the
+/// thin pool metadata device of a pool that is not set up can be read, list
+/// the thin devices that it records, and tear it down again.
+fn read_thin_dev_ids(
+    pool_uuid: PoolUuid,
+    table: Vec<TargetLine<LinearDevTargetParams>>,
+) -> StratisResult<HashSet<ThinDevId>> {
+    let mut thin_meta = LinearDev::setup(
+        get_dm(),
+        &format_fsck_thin_meta_name(pool_uuid),
+        None,
+        table,
+    )?;
+
+    let result = thin_ls_dev_ids(&thin_meta.devnode());
+
+    if let Err(err) = thin_meta.teardown(get_dm()) {
+        warn!("Could not tear down the thin pool metadata device: {}", err);
+    }
+
+    Ok(result?.into_iter().collect())
+}
+
+/// Read every filesystem record in the given directory. Each record is
+/// returned with the name of the file it was read from, or with the reason
+/// it could not be read.
+fn read_filesystem_records(
+    dir: &Path,
+) -> StratisResult<Vec<(String, StratisResult<FilesystemSave>)>> {
+    let mut records = Vec::new();
+    for dir_e in read_dir(dir)? {
+        let path = dir_e?.path();
+        if path.extension().map(|e| e == "temp").unwrap_or(false) {
+            continue;
+        }
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let record = OpenOptions::new()
+            .read(true)
+            .open(&path)
+            .and_then(|mut f| {
+                let mut data = Vec::new();
+                f.read_to_end(&mut data).map(|_| data)
+            })
+            .map_err(StratisError::from)
+            .and_then(|data| Ok(serde_json::from_slice::<FilesystemSave>(&data)?));
+        records.push((filename, record));
+    }
+    Ok(records)
+}
+
+/// Check the filesystem records for consistency with each other and, if the
+/// ids of the thin devices in the thin pool are given, with the thin pool.
+fn check_filesystem_records(
+    report: &mut FsckReport,
+    records: &[(String, StratisResult<FilesystemSave>)],
+    thin_dev_ids: Option<&HashSet<ThinDevId>>,
+) -> Value {
+    let mut thin_ids: HashMap<ThinDevId, &str> = HashMap::new();
+    let mut names: HashMap<&str, &str> = HashMap::new();
+    let mut filesystems = Vec::new();
+
+    for (filename, record) in records {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                report.error(
+                    format!("Filesystem record {} could not be read: {}", filename, err),
+                    Some("The filesystem can not be set up until the record is restored"),
+                );
+                continue;
+            }
+        };
+
+        if *filename != format!("{}.json", record.uuid.to_simple_ref()) {
+            report.warning(
+                format!(
+                    "Filesystem record {} holds the record of filesystem with UUID {}",
+                    filename,
+                    record.uuid.to_simple_ref()
+                ),
+                None,
+            );
+        }
+        if let Some(thin_dev_ids) = thin_dev_ids {
+            if !thin_dev_ids.contains(&record.thin_id) {
+                report.error(
+                    format!(
+                        "Filesystem {} is recorded with thin device id {}, but the thin pool has no thin device with that id",
+                        record.name, record.thin_id
+                    ),
+                    Some("The filesystem's data has been lost; remove its record"),
+                );
+            }
+        }
+        if let Some(other) = thin_ids.insert(record.thin_id, filename.as_str()) {
+            report.error(
+                format!(
+                    "Filesystem records {} and {} both use thin device id {}",
+                    other, filename, record.thin_id
+                ),
+                None,
+            );
+        }
+        if let Some(other) = names.insert(record.name.as_str(), filename.as_str()) {
+            report.error(
+                format!(
+                    "Filesystem records {} and {} both have the name {}",
+                    other, filename, record.name
+                ),
+                None,
+            );
+        }
+        if let Some(limit) = record.size_limit {
+            if limit < record.size {
+                report.warning(
+                    format!(
+                        "Filesystem {} is {}, larger than its size limit of {}",
+                        record.name, record.size, limit
+                    ),
+                    None,
+                );
+            }
+        }
+
+        filesystems.push(json!({
+            "name": Value::from(record.name.clone()),
+            "uuid": Value::from(record.uuid.to_string()),
+            "thin_id": Value::from(u32::from(record.thin_id)),
+            "size": Value::from(*record.size),
+        }));
+    }
+
+    let mut unrecorded = thin_dev_ids
+        .map(|ids| {
+            ids.iter()
+                .filter(|id| !thin_ids.contains_key(id))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(Vec::new);
+    unrecorded.sort_by_key(|id| u32::from(*id));
+    for id in unrecorded {
+        report.warning(
+            format!(
+                "Thin device {} in the thin pool belongs to no filesystem record; its space can not be used",
+                id
+            ),
+            None,
+        );
+    }
+
+    json!({
+        "checked": true,
+        "records": Value::Array(filesystems),
+        "thin_devices_compared": thin_dev_ids.is_some(),
+    })
+}
+
+/// Whether the pool with the given UUID is currently set up.
+pub(super) fn is_set_up(pool_uuid: PoolUuid) -> StratisResult<bool> {
+    let (mdv_name, _) = format_flex_ids(pool_uuid, FlexRole::MetadataVolume);
+    Ok(get_dm_init()?.device_info(&DevId::Name(&mdv_name)).is_ok())
+}
+
+/// Rewrite the MDA regions, and any damaged signature block, of every given
+/// device that is recorded in the chosen copy of the pool metadata, so that
+/// both MDA regions on every device hold that copy. All the given devices
+/// are held open exclusively until the repair is done, so that the pool
+/// can not be set up meanwhile.
+///
+/// The chosen copy is the one written at copy_time, if specified, otherwise
+/// the newest copy that can be read, parsed and found consistent; it is stamped with a time
+/// later than that of any metadata on the devices, so that it supersedes
+/// any newer copies that could not be read. A device on which neither
+/// signature block can be read can not be repaired and is left alone.
+///
+/// Returns the devnodes of the devices that were rewritten. Returns an error
+/// if the devices do not all belong to the same pool, if some device is in
+/// use or the pool is set up, or if no suitable copy of the metadata can be
+/// found.
+fn repair(
+    devices: &[DeviceToCheck],
+    copy_time: Option<DateTime<Utc>>,
+) -> StratisResult<Vec<PathBuf>> {
+    let mut files = open_exclusive(devices.iter().map(|d| d.devnode.as_path()))?;
+    let contents = read_devices(devices)?;
+    let pool_uuid = single_pool(&contents)?;
+    if is_set_up(pool_uuid)? {
+        return Err(StratisError::Engine(
+            ErrorEnum::Busy,
+            format!(
+                "Pool with UUID {} is set up; stop stratisd and tear down the pool before repairing its metadata",
+                pool_uuid.to_simple_ref()
+            ),
+        ));
+    }
+
+    let (data, pool_save) = choose_metadata(&contents, copy_time)?;
+
+    let recorded = recorded_devices(&pool_save);
+
+    let time = superseding_time(&contents);
+
+    let mut repaired = Vec::new();
+    for device in contents.iter() {
+        let header = match device.header() {
+            Some(header) => header,
+            None => continue,
+        };
+        if !recorded.contains(&header.identifiers.device_uuid) {
+            continue;
+        }
+        let f = files
+            .get_mut(&device.devnode)
+            .expect("every device was opened");
+        rewrite_bda(f, header, &time, data)?;
+        repaired.push(device.devnode.clone());
+    }
+
+    Ok(repaired)
+}
+
+/// The UUIDs of all the devices recorded in the pool metadata.
+pub(super) fn recorded_devices(pool_save: &PoolSave) -> HashSet<DevUuid> {
+    Some(&pool_save.backstore.data_tier.blockdev)
+        .into_iter()
+        .chain(pool_save.backstore.cache_tier.as_ref().map(|c| &c.blockdev))
+        .flat_map(|tier| tier.devs.iter().map(|dev| dev.uuid))
+        .collect()
+}
+
+/// A time at which to write pool metadata to the given devices so that it
+/// supersedes all the metadata already on them: now, unless some metadata
+/// claims to have been written later than that.
+pub(super) fn superseding_time(contents: &[DeviceContents]) -> DateTime<Utc> {
+    let newest = contents.iter().filter_map(|c| c.newest_header_time()).max();
+    let now = Utc::now();
+    match newest {
+        Some(newest) if *newest >= now => *newest + Duration::seconds(1),
+        _ => now,
+    }
+}
+
+/// Rewrite the BDA of the device open as f so that both MDA regions hold the
+/// given pool metadata, stamped with the given time and a time just after
+/// it. A copy of the signature block that does not match the given static
+/// header is rewritten; the MDA regions are written one at a time, each
+/// with its backup, so that the metadata already on the device is only
+/// overwritten once a new copy has been written.
+pub(super) fn rewrite_bda(
+    f: &mut File,
+    header: &StaticHeader,
+    time: &DateTime<Utc>,
+    data: &[u8],
+) -> StratisResult<()> {
+    let mut bda = BDA::load_for_rewrite(f, header)?;
+    bda.save_state(time, data, f)?;
+    bda.save_state(&(*time + Duration::nanoseconds(1)), data, f)
+}
+
+/// Open the given devices for writing, each exclusively, so that nothing
+/// else can use them while the returned files are open. In particular,
+/// stratisd can not set up a pool on them. Returns an error if some device
+/// is in use, e.g., because its pool is set up.
+pub(super) fn open_exclusive<'a, I>(devnodes: I) -> StratisResult<HashMap<PathBuf, File>>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    devnodes
+        .into_iter()
+        .map(|devnode| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_EXCL)
+                .open(devnode)
+                .map(|f| (devnode.to_owned(), f))
+                .map_err(|err| {
+                    if err.raw_os_error() == Some(libc::EBUSY) {
+                        StratisError::Engine(
+                            ErrorEnum::Busy,
+                            format!(
+                                "Device {} is in use; stop stratisd and tear down the pool first",
+                                devnode.display()
+                            ),
+                        )
+                    } else {
+                        StratisError::from(err)
+                    }
+                })
+        })
+        .collect()
+}
+
+/// Read the BDA of every given device.
+pub(super) fn read_devices(devices: &[DeviceToCheck]) -> StratisResult<Vec<DeviceContents>> {
+    devices
+        .iter()
+        .map(|device| -> StratisResult<DeviceContents> {
+            let mut f = OpenOptions::new().read(true).open(&device.devnode)?;
+            Ok(DeviceContents {
+                devnode: device.devnode.clone(),
+                device_number: device.device_number,
+                size: device_size(&f),
+                bda: BDA::read_contents(&mut f),
+            })
+        })
+        .collect()
+}
+
+/// The UUID of the pool to which all the devices with a valid signature
+/// block belong. Returns an error if there is no such device, if they do not
+/// all belong to the same pool, or if any two share a device UUID.
+pub(super) fn single_pool(contents: &[DeviceContents]) -> StratisResult<PoolUuid> {
+    let headers = contents
+        .iter()
+        .filter_map(|c| c.header())
+        .collect::<Vec<_>>();
+
+    let pool_uuids = headers
+        .iter()
+        .map(|header| header.identifiers.pool_uuid)
+        .collect::<HashSet<_>>();
+    let pool_uuid = match pool_uuids.len() {
+        0 => {
+            return Err(StratisError::Engine(
+                ErrorEnum::NotFound,
+                "No device with a valid Stratis signature block was found".into(),
+            ))
+        }
+        1 => *pool_uuids.iter().next().expect("len() == 1"),
+        _ => {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                "The devices do not all belong to the same pool".into(),
+            ))
+        }
+    };
+    if headers
+        .iter()
+        .map(|header| header.identifiers.device_uuid)
+        .collect::<HashSet<_>>()
+        .len()
+        != headers.len()
+    {
+        return Err(StratisError::Engine(
+            ErrorEnum::Invalid,
+            "Some devices share a device UUID".into(),
+        ));
+    }
+    Ok(pool_uuid)
+}
+
+/// The copy of the pool metadata written at copy_time, if specified,
+/// otherwise the newest copy that can be read, parsed and found consistent,
+/// both as it was read and parsed.
+pub(super) fn choose_metadata(
+    contents: &[DeviceContents],
+    copy_time: Option<DateTime<Utc>>,
+) -> StratisResult<(&[u8], PoolSave)> {
+    let mut copies = contents
+        .iter()
+        .flat_map(|c| c.metadata_copies())
+        .filter(|(time, _, _)| copy_time.map(|t| **time == t).unwrap_or(true))
+        .collect::<Vec<_>>();
+    copies.sort_by(|(time1, _, _), (time2, _, _)| time2.cmp(time1));
+    copies
+        .into_iter()
+        .find_map(|(_, _, data)| {
+            serde_json::from_slice::<PoolSave>(data)
+                .ok()
+                .filter(|pool_save| check_metadata(pool_save).is_ok())
+                .map(|pool_save| (data, pool_save))
+        })
+        .ok_or_else(|| {
+            StratisError::Engine(
+                ErrorEnum::NotFound,
+                match copy_time {
+                    Some(time) => format!(
+                        "No valid copy of the pool metadata written at {} was found",
+                        time.to_rfc3339()
+                    ),
+                    None => "No valid copy of the pool metadata was found".into(),
+                },
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::engine::{
+        strat_engine::{
+            metadata::{BlockdevSize, MDADataSize, MDASize, StratisIdentifiers},
+            serde_structs::{
+                BackstoreSave, BaseBlockDevSave, BlockDevSave, CapSave, DataTierSave, FlexDevsSave,
+                ThinPoolDevSave,
+            },
+        },
+        types::{FilesystemUuid, SpaceThresholds},
+    };
+
+    use super::*;
+
+    /// A device of the given size whose first signature block belongs to
+    /// the given pool and device.
+    fn device_contents(pool_uuid: PoolUuid, dev_uuid: DevUuid, size: Sectors) -> DeviceContents {
+        DeviceContents {
+            devnode: PathBuf::from(format!("/dev/{}", dev_uuid.to_simple_ref())),
+            device_number: None,
+            size: Some(size.bytes()),
+            bda: BDAContents {
+                sigblocks: (
+                    Ok(Some(StaticHeader::new(
+                        StratisIdentifiers::new(pool_uuid, dev_uuid),
+                        MDASize::default(),
+                        BlockdevSize::new(size),
+                        0,
+                    ))),
+                    Ok(None),
+                ),
+                regions: None,
+            },
+        }
+    }
+
+    /// Consistent pool metadata with a single data device, which allocates
+    /// 1000 sectors immediately after the device's BDA and divides them
+    /// among the flex devices.
+    fn pool_save(dev_uuid: DevUuid, bda_size: Sectors) -> PoolSave {
+        PoolSave {
+            name: "pool".into(),
+            backstore: BackstoreSave {
+                data_tier: DataTierSave {
+                    blockdev: BlockDevSave {
+                        allocs: vec![vec![BaseDevSave {
+                            parent: dev_uuid,
+                            start: bda_size,
+                            length: Sectors(1000),
+                        }]],
+                        devs: vec![BaseBlockDevSave {
+                            uuid: dev_uuid,
+                            user_info: None,
+                            hardware_info: None,
+                        }],
+                    },
+                    reserve_encryption_space: false,
+                },
+                cap: CapSave {
+                    allocs: vec![(Sectors(0), Sectors(1000))],
+                },
+                cache_tier: None,
+            },
+            flex_devs: FlexDevsSave {
+                meta_dev: vec![(Sectors(0), Sectors(100))],
+                thin_meta_dev: vec![(Sectors(100), Sectors(100))],
+                thin_data_dev: vec![(Sectors(200), Sectors(700))],
+                thin_meta_dev_spare: vec![(Sectors(900), Sectors(100))],
+            },
+            thinpool_dev: ThinPoolDevSave {
+                data_block_size: Sectors(128),
+            },
+            overprovisioning: true,
+            space_thresholds: SpaceThresholds::default(),
+        }
+    }
+
+    #[test]
+    /// Verify that each inconsistency between the pool metadata and the
+    /// devices found is reported as an error, and that consistent metadata
+    /// is not.
+    fn test_check_pool_save() {
+        type Mutation = fn(&mut PoolSave, &mut Vec<DeviceContents>);
+
+        let dev_size = Sectors(1_000_000);
+        let cases: &[(&str, Mutation, Option<&str>)] = &[
+            ("consistent", |_, _| {}, None),
+            (
+                "overlapping segments",
+                |save, _| {
+                    let seg = &save.backstore.data_tier.blockdev.allocs[0][0];
+                    let overlapping = BaseDevSave {
+                        parent: seg.parent,
+                        start: seg.start + Sectors(500),
+                        length: Sectors(1000),
+                    };
+                    save.backstore.data_tier.blockdev.allocs[0].push(overlapping);
+                },
+                Some("overlap or exceed its size"),
+            ),
+            (
+                "segment overlapping the BDA",
+                |save, _| save.backstore.data_tier.blockdev.allocs[0][0].start = Sectors(0),
+                Some("overlap or exceed its size"),
+            ),
+            (
+                "segment beyond the end of the device",
+                |save, _| save.backstore.data_tier.blockdev.allocs[0][0].start = Sectors(999_500),
+                Some("overlap or exceed its size"),
+            ),
+            (
+                "segment on an unrecorded device",
+                |save, _| {
+                    save.backstore.data_tier.blockdev.allocs[0].push(BaseDevSave {
+                        parent: DevUuid::new_v4(),
+                        start: Sectors(0),
+                        length: Sectors(1000),
+                    })
+                },
+                Some("not recorded as a member of the pool"),
+            ),
+            (
+                "recorded device missing",
+                |_, devices| devices.clear(),
+                Some("was not found"),
+            ),
+            (
+                "flex devices exceed the cap device",
+                |save, _| save.flex_devs.thin_data_dev[0].1 = Sectors(10_000),
+                Some("exceed the space allocated from the data tier"),
+            ),
+            (
+                "overlapping flex devices",
+                |save, _| save.flex_devs.thin_meta_dev[0].0 = Sectors(50),
+                Some("exceed the space allocated from the data tier"),
+            ),
+            (
+                "empty flex device",
+                |save, _| save.flex_devs.thin_meta_dev_spare.clear(),
+                Some("have no space allocated to them"),
+            ),
+            (
+                "cap smaller than flex devices",
+                |save, _| save.backstore.cap.allocs[0].1 = Sectors(900),
+                Some("inconsistent"),
+            ),
+        ];
+
+        for &(name, mutate, expected) in cases {
+            let pool_uuid = PoolUuid::new_v4();
+            let dev_uuid = DevUuid::new_v4();
+            let mut devices = vec![device_contents(pool_uuid, dev_uuid, dev_size)];
+            let bda_size = devices[0].header().unwrap().bda_extended_size().sectors();
+            let mut save = pool_save(dev_uuid, bda_size);
+
+            mutate(&mut save, &mut devices);
+
+            let by_uuid = devices
+                .iter()
+                .map(|device| (device.dev_uuid().unwrap(), device))
+                .collect::<HashMap<_, _>>();
+            let mut report = FsckReport::new();
+            check_pool_save(&mut report, &save, &by_uuid, &HashSet::new());
+
+            assert_problems(name, &report, Severity::Error, expected);
+        }
+    }
+
+    /// Assert that the report of the named test case holds a problem of the
+    /// given severity whose description contains expected, or no problem of
+    /// that severity if expected is None.
+    fn assert_problems(
+        name: &str,
+        report: &FsckReport,
+        severity: Severity,
+        expected: Option<&str>,
+    ) {
+        let problems = report
+            .problems()
+            .iter()
+            .filter(|problem| problem.severity == severity)
+            .map(|problem| problem.description.as_str())
+            .collect::<Vec<_>>();
+        match expected {
+            None => assert!(problems.is_empty(), "{}: {:?}", name, problems),
+            Some(expected) => assert!(
+                problems.iter().any(|problem| problem.contains(expected)),
+                "{}: expected a problem containing \"{}\", got {:?}",
+                name,
+                expected,
+                problems
+            ),
+        }
+    }
+
+    #[test]
+    /// Verify that unreadable filesystem records, records which share a thin
+    /// id or a name, and records of thin devices that are not in the thin
+    /// pool are reported as errors, and that thin devices in the thin pool
+    /// without a record are reported as warnings.
+    fn test_check_filesystem_records() {
+        fn record(name: &str, thin_id: u64) -> (String, StratisResult<FilesystemSave>) {
+            let uuid = FilesystemUuid::new_v4();
+            (
+                format!("{}.json", uuid.to_simple_ref()),
+                Ok(FilesystemSave {
+                    name: name.into(),
+                    uuid,
+                    thin_id: ThinDevId::new_u64(thin_id).unwrap(),
+                    size: Sectors(1024),
+                    created: 0,
+                    size_limit: None,
+                }),
+            )
+        }
+
+        let cases = vec![
+            (
+                "consistent",
+                vec![record("a", 0), record("b", 1)],
+                None,
+                Severity::Error,
+                None,
+            ),
+            (
+                "consistent with the thin pool",
+                vec![record("a", 0), record("b", 1)],
+                Some(vec![0, 1]),
+                Severity::Warning,
+                None,
+            ),
+            (
+                "duplicate thin id",
+                vec![record("a", 0), record("b", 0)],
+                None,
+                Severity::Error,
+                Some("both use thin device id"),
+            ),
+            (
+                "duplicate name",
+                vec![record("a", 0), record("a", 1)],
+                None,
+                Severity::Error,
+                Some("both have the name"),
+            ),
+            (
+                "unreadable record",
+                vec![
+                    record("a", 0),
+                    (
+                        "unreadable.json".into(),
+                        Err(StratisError::Error("bad JSON".into())),
+                    ),
+                ],
+                None,
+                Severity::Error,
+                Some("could not be read"),
+            ),
+            (
+                "thin device missing",
+                vec![record("a", 0), record("b", 1)],
+                Some(vec![0]),
+                Severity::Error,
+                Some("has no thin device with that id"),
+            ),
+            (
+                "thin device without a record",
+                vec![record("a", 0)],
+                Some(vec![0, 1]),
+                Severity::Warning,
+                Some("belongs to no filesystem record"),
+            ),
+        ];
+
+        for (name, records, thin_dev_ids, severity, expected) in cases {
+            let thin_dev_ids = thin_dev_ids.map(|ids| {
+                ids.into_iter()
+                    .map(|id| ThinDevId::new_u64(id).unwrap())
+                    .collect::<HashSet<_>>()
+            });
+            let mut report = FsckReport::new();
+            check_filesystem_records(&mut report, &records, thin_dev_ids.as_ref());
+
+            assert_problems(name, &report, severity, expected);
+        }
+    }
+
+    #[test]
+    /// Verify that ranges in the cap device are split across the data tier
+    /// segments that make it up, and that ranges beyond its end are
+    /// rejected.
+    fn test_map_to_data_tier() {
+        let dev1 = Some(Device::from(1u64));
+        let dev2 = Some(Device::from(2u64));
+        let data_segments = [
+            (dev1, Sectors(100), Sectors(50)),
+            (dev2, Sectors(0), Sectors(50)),
+        ];
+
+        assert_eq!(
+            map_to_data_tier(&data_segments, &[(Sectors(10), Sectors(20))]),
+            Some(vec![(dev1.unwrap(), Sectors(110), Sectors(20))])
+        );
+        assert_eq!(
+            map_to_data_tier(&data_segments, &[(Sectors(40), Sectors(20))]),
+            Some(vec![
+                (dev1.unwrap(), Sectors(140), Sectors(10)),
+                (dev2.unwrap(), Sectors(0), Sectors(10))
+            ])
+        );
+        assert_eq!(
+            map_to_data_tier(&data_segments, &[(Sectors(90), Sectors(20))]),
+            None
+        );
+        assert_eq!(
+            map_to_data_tier(
+                &[(None, Sectors(0), Sectors(50))],
+                &[(Sectors(0), Sectors(10))]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    /// Verify that the description of a BDA reports the regions whose data
+    /// does not match its CRC, decodes the metadata in the others if asked
+    /// to, and can be restricted to some of the regions.
+    fn test_bda_json() {
+        let data_size = MDADataSize::default();
+        let buf_size = convert_test!(
+            *data_size
+                .region_size()
+                .mda_size()
+                .bda_size()
+                .sectors()
+                .bytes(),
+            u128,
+            usize
+        );
+        let mut buf = Cursor::new(vec![0; buf_size]);
+        let mut bda = BDA::initialize(
+            &mut buf,
+            StratisIdentifiers::new(PoolUuid::new_v4(), DevUuid::new_v4()),
+            data_size,
+            BlockdevSize::new(Sectors(1 << 20)),
+            Utc::now().timestamp() as u64,
+        )
+        .unwrap();
+        let older = Utc::now();
+        let newer = older + Duration::seconds(1);
+        bda.save_state(&older, b"older metadata", &mut buf).unwrap();
+        bda.save_state(&newer, b"newer metadata", &mut buf).unwrap();
+        bda.corrupt_state(&mut buf).unwrap();
+
+        let contents = BDA::read_contents(&mut buf);
+        let devnode = Path::new("/dev/test");
+
+        let json = bda_json(devnode, None, &contents, true, None);
+        assert_eq!(json["sigblocks_agree"], Value::from(true));
+        assert_eq!(json["last_updated"], Value::from(older.to_rfc3339()));
+        let regions = json["regions"].as_array().unwrap();
+        assert_eq!(regions.len(), 4);
+        for region in regions {
+            let header_time = region["header"]["last_updated"].as_str().unwrap();
+            if header_time == newer.to_rfc3339() {
+                assert_eq!(region["status"], Value::from("invalid_data"));
+                assert_eq!(region["data_crc_valid"], Value::from(false));
+                assert!(region["error"].is_string());
+                assert!(region.get("pool_metadata_error").is_none());
+            } else {
+                assert_eq!(header_time, older.to_rfc3339());
+                assert_eq!(region["status"], Value::from("valid"));
+                assert_eq!(region["data_crc_valid"], Value::from(true));
+                assert!(region["pool_metadata_error"].is_string());
+            }
+        }
+
+        let json = bda_json(devnode, None, &contents, false, Some(&[0, 2]));
+        let regions = json["regions"].as_array().unwrap();
+        assert_eq!(
+            regions
+                .iter()
+                .map(|region| region["index"].as_u64().unwrap())
+                .collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+        assert!(regions
+            .iter()
+            .all(|region| region.get("pool_metadata").is_none()
+                && region.get("pool_metadata_error").is_none()));
+    }
+}
@@ -3,13 +3,15 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
+    collections::HashMap,
     ffi::CString,
-    fs::{create_dir_all, remove_file, OpenOptions},
+    fs::{create_dir_all, read_to_string, remove_file, OpenOptions},
     io::{self, Write},
     mem::size_of,
     os::unix::io::{AsRawFd, RawFd},
     path::{Path, PathBuf},
     ptr, slice, str,
+    time::Duration,
 };
 
 use libc::{syscall, SYS_add_key, SYS_keyctl};
@@ -39,6 +41,9 @@ use crate::{
 /// this is represented as the C type `key_serial_t`.
 type KeySerial = u32;
 
+/// The file in which the kernel lists the keys the process can view.
+const PROC_KEYS_PATH: &str = "/proc/keys";
+
 /// Search the persistent keyring for the given key description.
 pub(super) fn search_key_persistent(key_desc: &KeyDescription) -> StratisResult<Option<KeySerial>> {
     let keyring_id = get_persistent_keyring()?;
@@ -173,12 +178,13 @@ fn reset_key(
 }
 
 /// Add the key to the given keyring attaching it to the provided key description.
+/// Returns the ID of the new key.
 // Precondition: The key description was not already present.
 fn set_key(
     key_desc: &KeyDescription,
     key_data: SizedKeyMemory,
     keyring_id: KeySerial,
-) -> StratisResult<()> {
+) -> StratisResult<KeySerial> {
     let key_desc_cstring = CString::new(key_desc.to_system_string()).map_err(|_| {
         StratisError::Engine(
             ErrorEnum::Invalid,
@@ -186,7 +192,7 @@ fn set_key(
         )
     })?;
     // Add a key to the kernel keyring
-    if unsafe {
+    match unsafe {
         libc::syscall(
             SYS_add_key,
             concat!("user", "\0").as_ptr(),
@@ -195,15 +201,90 @@ fn set_key(
             key_data.as_ref().len(),
             keyring_id,
         )
-    } < 0
-    {
-        Err(io::Error::last_os_error().into())
-    } else {
-        Ok(())
+    } {
+        i if i < 0 => Err(io::Error::last_os_error().into()),
+        i => convert_int!(i, libc::c_long, KeySerial),
+    }
+}
+
+/// Set the timeout after which the key with ID `key_id` expires and is
+/// removed from the keyring. If no timeout is given, the key never expires.
+fn set_key_timeout(key_id: KeySerial, timeout: Option<Duration>) -> StratisResult<()> {
+    // A timeout of 0 tells the kernel to clear any timeout on the key.
+    let timeout_secs = match timeout {
+        Some(t) => convert_int!(t.as_secs(), u64, libc::c_uint)?,
+        None => 0,
+    };
+    match unsafe { syscall(SYS_keyctl, libc::KEYCTL_SET_TIMEOUT, key_id, timeout_secs) } {
+        i if i < 0 => Err(io::Error::last_os_error().into()),
+        _ => Ok(()),
     }
 }
 
+/// Parse the expiry field of a line in /proc/keys into the remaining lifetime
+/// of the key. The kernel truncates the remaining lifetime to whole weeks,
+/// days, hours, minutes, or seconds, so the value returned may be less than
+/// the actual remaining lifetime. None is returned if the key never expires.
+fn parse_proc_keys_expiry(expiry: &str) -> StratisResult<Option<Duration>> {
+    let invalid = || {
+        StratisError::Engine(
+            ErrorEnum::Invalid,
+            format!("Invalid key expiry {} found in {}", expiry, PROC_KEYS_PATH),
+        )
+    };
+    match expiry {
+        "perm" => Ok(None),
+        "expd" => Ok(Some(Duration::from_secs(0))),
+        _ => {
+            let unit = expiry.chars().last().ok_or_else(invalid)?;
+            let unit_secs = match unit {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+            let count = expiry[..expiry.len() - unit.len_utf8()]
+                .parse::<u64>()
+                .map_err(|_| invalid())?;
+            Ok(Some(Duration::from_secs(count * unit_secs)))
+        }
+    }
+}
+
+/// Read the remaining lifetime of every key that the process can view from
+/// /proc/keys. A key that never expires is mapped to None.
+fn read_key_lifetimes() -> StratisResult<HashMap<KeySerial, Option<Duration>>> {
+    let mut lifetimes = HashMap::new();
+    for line in read_to_string(PROC_KEYS_PATH)?.lines() {
+        // The fields of each line are, in order, the key ID in hexadecimal,
+        // the flags, the usage count, and the expiry, followed by the
+        // permissions, owner, type, and description of the key.
+        let mut fields = line.split_whitespace();
+        let (key_id, expiry) = match (fields.next(), fields.nth(2)) {
+            (Some(key_id), Some(expiry)) => (key_id, expiry),
+            _ => {
+                return Err(StratisError::Error(format!(
+                    "Malformed line \"{}\" found in {}",
+                    line, PROC_KEYS_PATH
+                )))
+            }
+        };
+        let key_id = KeySerial::from_str_radix(key_id, 16).map_err(|e| {
+            StratisError::Error(format!(
+                "Invalid key ID {} found in {}: {}",
+                key_id, PROC_KEYS_PATH, e
+            ))
+        })?;
+        lifetimes.insert(key_id, parse_proc_keys_expiry(expiry)?);
+    }
+    Ok(lifetimes)
+}
+
 /// Perform an idempotent add of the given key data with the given key description.
+/// The key expires after the given timeout, if any; the timeout is applied
+/// even if the key data is unchanged, replacing any previous timeout.
 ///
 /// The unit type is returned as the inner type for `MappingCreateAction` as no
 /// new external data (like a UUID) can be returned when setting a key. Keys
@@ -215,23 +296,51 @@ fn set_key(
 /// appropriate key description and key data.
 /// * `Ok(MappingCreateAction::Created(()))`: The key was newly added to the keyring.
 /// * `Ok(MappingCreateAction::ValueChanged(()))`: The key description was already present
-/// in the keyring but the key data was updated.
+/// in the keyring but the key data or its expiry was updated.
 fn set_key_idem(
     key_desc: &KeyDescription,
     key_data: SizedKeyMemory,
+    timeout: Option<Duration>,
 ) -> StratisResult<MappingCreateAction<Key>> {
+    shared::validate_key_timeout(timeout)?;
+
     let keyring_id = get_persistent_keyring()?;
     match read_key(keyring_id, key_desc) {
         Ok(Some((key_id, old_key_data))) => {
+            // Setting a timeout restarts the lifetime of the key, so only
+            // leaving a key that does not expire without a timeout leaves
+            // its expiry unchanged.
+            let expiry_changed = timeout.is_some()
+                || read_key_lifetimes()
+                    .map(|lifetimes| lifetimes.get(&key_id).cloned().flatten().is_some())
+                    .unwrap_or_else(|e| {
+                        warn!(
+                            "Failed to read the lifetime of the key with key description {}: {}",
+                            key_desc.as_application_str(),
+                            e
+                        );
+                        true
+                    });
             let changed = reset_key(key_id, old_key_data, key_data)?;
-            if changed {
+            set_key_timeout(key_id, timeout)?;
+            if changed || expiry_changed {
                 Ok(MappingCreateAction::ValueChanged(Key))
             } else {
                 Ok(MappingCreateAction::Identity)
             }
         }
         Ok(None) => {
-            set_key(key_desc, key_data, keyring_id)?;
+            let key_id = set_key(key_desc, key_data, keyring_id)?;
+            if let Err(e) = set_key_timeout(key_id, timeout) {
+                if let Err(unset_err) = unset_key(key_id) {
+                    warn!(
+                        "Failed to remove key with key description {} after failing to set its timeout: {}",
+                        key_desc.as_application_str(),
+                        unset_err
+                    );
+                }
+                return Err(e);
+            }
             Ok(MappingCreateAction::Created(Key))
         }
         Err(e) => Err(e),
@@ -302,8 +411,8 @@ impl KeyIdList {
 
     /// Get the list of key descriptions corresponding to the kernel key IDs.
     /// Return the subset of key descriptions that have a prefix that identify
-    /// them as belonging to Stratis.
-    fn to_key_descs(&self) -> StratisResult<Vec<KeyDescription>> {
+    /// them as belonging to Stratis, each paired with its key ID.
+    fn to_key_descs(&self) -> StratisResult<Vec<(KeySerial, KeyDescription)>> {
         let mut key_descs = Vec::new();
 
         for id in self.key_ids.iter() {
@@ -350,7 +459,7 @@ impl KeyIdList {
                 })?;
             let parsed_string = parse_keyctl_describe_string(keyctl_str)?;
             if let Some(kd) = KeyDescription::from_system_key_desc(&parsed_string).map(|k| k.expect("parse_keyctl_desribe_string() ensures the key description can not have semi-colons in it")) {
-                key_descs.push(kd);
+                key_descs.push((*id, kd));
             }
         }
         Ok(key_descs)
@@ -383,7 +492,7 @@ impl StratKeyActions {
         key_desc: &KeyDescription,
         key: SizedKeyMemory,
     ) -> StratisResult<MappingCreateAction<Key>> {
-        Ok(set_key_idem(&key_desc, key)?)
+        Ok(set_key_idem(&key_desc, key, None)?)
     }
 }
 
@@ -392,16 +501,27 @@ impl KeyActions for StratKeyActions {
         &mut self,
         key_desc: &KeyDescription,
         key_fd: RawFd,
+        timeout: Option<Duration>,
     ) -> StratisResult<MappingCreateAction<Key>> {
         let memory = shared::set_key_shared(key_fd)?;
 
-        Ok(set_key_idem(key_desc, memory)?)
+        Ok(set_key_idem(key_desc, memory, timeout)?)
     }
 
-    fn list(&self) -> StratisResult<Vec<KeyDescription>> {
+    fn list(&self) -> StratisResult<Vec<(KeyDescription, Option<Duration>)>> {
         let mut key_ids = KeyIdList::new();
         key_ids.populate()?;
-        key_ids.to_key_descs()
+        let key_descs = key_ids.to_key_descs()?;
+        // The lifetimes are only informational, so the keys are listed
+        // without them if they can not be read.
+        let lifetimes = read_key_lifetimes().unwrap_or_else(|e| {
+            warn!("Failed to read the lifetimes of the keys: {}", e);
+            HashMap::new()
+        });
+        Ok(key_descs
+            .into_iter()
+            .map(|(key_id, kd)| (kd, lifetimes.get(&key_id).cloned().flatten()))
+            .collect())
     }
 
     fn unset(&mut self, key_desc: &KeyDescription) -> StratisResult<DeleteAction<Key>> {
@@ -552,9 +672,9 @@ impl MemoryPrivateFilesystem {
         Ok(MemoryPrivateFilesystem(private_fs_path))
     }
 
-    pub fn key_op<F>(&self, key_desc: &KeyDescription, mut f: F) -> StratisResult<()>
+    pub fn key_op<F, R>(&self, key_desc: &KeyDescription, f: F) -> StratisResult<R>
     where
-        F: FnMut(&Path) -> StratisResult<()>,
+        F: FnMut(&Path) -> StratisResult<R>,
     {
         let persistent_id = get_persistent_keyring()?;
         let key_data = if let Some((_, mem)) = read_key(persistent_id, key_desc)? {
@@ -568,8 +688,25 @@ impl MemoryPrivateFilesystem {
                 ),
             )));
         };
+        self.keyfile_op(key_desc.as_application_str(), key_data, f)
+    }
+
+    /// Perform an operation with a keyfile containing a passphrase that was
+    /// provided directly rather than read from the kernel keyring. The keyfile
+    /// is removed as soon as the operation completes.
+    pub fn passphrase_op<F, R>(&self, passphrase: SizedKeyMemory, f: F) -> StratisResult<R>
+    where
+        F: FnMut(&Path) -> StratisResult<R>,
+    {
+        self.keyfile_op("passphrase", passphrase, f)
+    }
+
+    fn keyfile_op<F, R>(&self, name: &str, key_data: SizedKeyMemory, mut f: F) -> StratisResult<R>
+    where
+        F: FnMut(&Path) -> StratisResult<R>,
+    {
         let mut mem_file_path = PathBuf::from(&self.0);
-        mem_file_path.push(key_desc.as_application_str());
+        mem_file_path.push(name);
         let mem_file = MemoryMappedKeyfile::new(&mem_file_path, key_data)?;
         f(mem_file.keyfile_path())
     }
@@ -0,0 +1,285 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Scheduling of the automatic unlocking of pools with their Clevis bindings.
+
+use std::{
+    cmp::min,
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::engine::{
+    strat_engine::backstore::CryptActivationHandle,
+    types::{PoolUuid, UnlockMethod},
+};
+
+/// The delay before the attempt that follows a failed automatic unlock of a
+/// pool.
+const AUTO_UNLOCK_INITIAL_DELAY: Duration = Duration::from_secs(5);
+
+/// The longest delay between attempts to unlock a pool automatically. The
+/// delay doubles after every failed attempt until it reaches this value.
+const AUTO_UNLOCK_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// How often to check whether attempts in progress have finished.
+const AUTO_UNLOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The automatic unlocking of a single pool.
+#[derive(Debug)]
+struct Attempt {
+    /// When the next attempt is due, or None while an attempt is in progress
+    due: Option<Instant>,
+    /// The delay before the attempt that follows if the next one fails
+    delay: Duration,
+}
+
+/// Pools to be unlocked automatically, because a locked device of the pool
+/// appeared or because the last attempt to unlock some device failed, e.g.,
+/// because a tang server was not reachable.
+///
+/// Unlocking with Clevis may have to wait for a tang server, so every
+/// attempt is made in a thread of its own; its result is collected by
+/// process() once the thread has finished.
+#[derive(Debug)]
+pub struct AutoUnlocks {
+    attempts: HashMap<PoolUuid, Attempt>,
+    sender: Sender<(PoolUuid, bool)>,
+    receiver: Receiver<(PoolUuid, bool)>,
+}
+
+impl Default for AutoUnlocks {
+    fn default() -> AutoUnlocks {
+        let (sender, receiver) = channel();
+        AutoUnlocks {
+            attempts: HashMap::new(),
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl AutoUnlocks {
+    /// Schedule an attempt to unlock the pool, to be made as soon as
+    /// process() is next called. If an attempt is already scheduled or in
+    /// progress, it is left as it is.
+    pub fn schedule(&mut self, pool_uuid: PoolUuid, now: Instant) {
+        self.attempts.entry(pool_uuid).or_insert(Attempt {
+            due: Some(now),
+            delay: AUTO_UNLOCK_INITIAL_DELAY,
+        });
+    }
+
+    /// Stop unlocking the pool automatically.
+    pub fn cancel(&mut self, pool_uuid: PoolUuid) {
+        self.attempts.remove(&pool_uuid);
+    }
+
+    /// Stop unlocking those pools automatically for which f returns false.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(PoolUuid) -> bool,
+    {
+        self.attempts.retain(|pool_uuid, _| f(*pool_uuid));
+    }
+
+    /// Record the result of an attempt. If it failed, schedule another
+    /// attempt after a delay that doubles with every failed attempt.
+    /// Results of attempts for pools that have been cancelled are ignored.
+    fn finished(&mut self, pool_uuid: PoolUuid, succeeded: bool, now: Instant) {
+        if succeeded {
+            self.attempts.remove(&pool_uuid);
+        } else if let Some(attempt) = self.attempts.get_mut(&pool_uuid) {
+            info!(
+                "Trying again to unlock pool with UUID {} automatically in {} seconds",
+                pool_uuid.to_simple_ref(),
+                attempt.delay.as_secs(),
+            );
+            attempt.due = Some(now + attempt.delay);
+            attempt.delay = min(attempt.delay * 2, AUTO_UNLOCK_MAX_DELAY);
+        }
+    }
+
+    /// Return the pools for which an attempt is due, and record that an
+    /// attempt is in progress for each.
+    fn take_due(&mut self, now: Instant) -> Vec<PoolUuid> {
+        let mut due = Vec::new();
+        for (pool_uuid, attempt) in self.attempts.iter_mut() {
+            if attempt.due.map(|due| due <= now).unwrap_or(false) {
+                attempt.due = None;
+                due.push(*pool_uuid);
+            }
+        }
+        due
+    }
+
+    /// The time until process() must next be called: when the next attempt
+    /// is due or, if some attempt is in progress, when to check whether it
+    /// has finished.
+    fn next_due(&self, now: Instant) -> Option<Duration> {
+        self.attempts
+            .values()
+            .map(|attempt| {
+                attempt
+                    .due
+                    .map(|due| due.saturating_duration_since(now))
+                    .unwrap_or(AUTO_UNLOCK_POLL_INTERVAL)
+            })
+            .min()
+    }
+
+    /// Collect the results of the attempts that have finished, and start
+    /// those attempts that are due. devices_for returns the devnodes of the
+    /// locked devices of a pool.
+    ///
+    /// Returns the time until this method must next be called, if any
+    /// attempt remains.
+    pub fn process<F>(&mut self, devices_for: F) -> Option<Duration>
+    where
+        F: Fn(PoolUuid) -> Vec<PathBuf>,
+    {
+        let now = Instant::now();
+        while let Ok((pool_uuid, succeeded)) = self.receiver.try_recv() {
+            self.finished(pool_uuid, succeeded, now);
+        }
+
+        for pool_uuid in self.take_due(now) {
+            let devnodes = devices_for(pool_uuid);
+            let sender = self.sender.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("auto-unlock-{}", pool_uuid.to_simple_ref()))
+                .spawn(move || {
+                    let succeeded = unlock_devices(pool_uuid, &devnodes);
+                    // The receiver only goes away when stratisd is exiting.
+                    let _ = sender.send((pool_uuid, succeeded));
+                });
+            if let Err(err) = spawned {
+                warn!(
+                    "Failed to start unlocking pool with UUID {} automatically: {}",
+                    pool_uuid.to_simple_ref(),
+                    err
+                );
+                self.finished(pool_uuid, false, now);
+            }
+        }
+
+        self.next_due(now)
+    }
+}
+
+/// Try to unlock all the given devices of the pool with their Clevis
+/// bindings. Every device is attempted, even if unlocking another one
+/// failed. Returns true if all devices were unlocked.
+fn unlock_devices(pool_uuid: PoolUuid, devnodes: &[PathBuf]) -> bool {
+    let mut succeeded = true;
+    for devnode in devnodes {
+        match CryptActivationHandle::setup(devnode, UnlockMethod::Clevis, None) {
+            Ok(Some(_)) => info!(
+                "Automatically unlocked device {} belonging to pool with UUID {}",
+                devnode.display(),
+                pool_uuid.to_simple_ref(),
+            ),
+            Ok(None) => {
+                warn!(
+                    "Device {} does not appear to be formatted with the proper Stratis LUKS2 metadata; it could not be unlocked automatically",
+                    devnode.display(),
+                );
+                succeeded = false;
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to unlock device {} belonging to pool with UUID {} automatically: {}",
+                    devnode.display(),
+                    pool_uuid.to_simple_ref(),
+                    e,
+                );
+                succeeded = false;
+            }
+        }
+    }
+    succeeded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An attempt is due as soon as it is scheduled. Scheduling a pool
+    /// again while an attempt is due or in progress changes nothing.
+    #[test]
+    fn test_schedule() {
+        let mut auto_unlocks = AutoUnlocks::default();
+        let pool_uuid = PoolUuid::new_v4();
+        let now = Instant::now();
+        assert_eq!(auto_unlocks.next_due(now), None);
+
+        auto_unlocks.schedule(pool_uuid, now);
+        assert_eq!(auto_unlocks.next_due(now), Some(Duration::from_secs(0)));
+
+        auto_unlocks.schedule(pool_uuid, now + Duration::from_secs(10));
+        assert_eq!(auto_unlocks.take_due(now), vec![pool_uuid]);
+        assert_eq!(auto_unlocks.take_due(now), vec![]);
+        assert_eq!(auto_unlocks.next_due(now), Some(AUTO_UNLOCK_POLL_INTERVAL));
+
+        auto_unlocks.schedule(pool_uuid, now);
+        assert_eq!(auto_unlocks.take_due(now), vec![]);
+
+        auto_unlocks.finished(pool_uuid, true, now);
+        assert_eq!(auto_unlocks.next_due(now), None);
+    }
+
+    /// The delay after each failed attempt doubles, up to the maximum, and
+    /// starts over once the pool has been unlocked.
+    #[test]
+    fn test_backoff() {
+        let mut auto_unlocks = AutoUnlocks::default();
+        let pool_uuid = PoolUuid::new_v4();
+        let mut now = Instant::now();
+
+        auto_unlocks.schedule(pool_uuid, now);
+        let mut expected = AUTO_UNLOCK_INITIAL_DELAY;
+        for _ in 0..10 {
+            assert_eq!(auto_unlocks.take_due(now), vec![pool_uuid]);
+            auto_unlocks.finished(pool_uuid, false, now);
+            assert_eq!(auto_unlocks.next_due(now), Some(expected));
+
+            assert_eq!(auto_unlocks.take_due(now + expected / 2), vec![]);
+            now += expected;
+            expected = min(expected * 2, AUTO_UNLOCK_MAX_DELAY);
+        }
+        assert_eq!(expected, AUTO_UNLOCK_MAX_DELAY);
+
+        assert_eq!(auto_unlocks.take_due(now), vec![pool_uuid]);
+        auto_unlocks.finished(pool_uuid, true, now);
+        auto_unlocks.schedule(pool_uuid, now);
+        assert_eq!(auto_unlocks.take_due(now), vec![pool_uuid]);
+        auto_unlocks.finished(pool_uuid, false, now);
+        assert_eq!(auto_unlocks.next_due(now), Some(AUTO_UNLOCK_INITIAL_DELAY));
+    }
+
+    /// The result of an attempt for a pool that is no longer unlocked
+    /// automatically is ignored, and the earliest attempt of several pools
+    /// determines when the next one is due.
+    #[test]
+    fn test_cancel() {
+        let mut auto_unlocks = AutoUnlocks::default();
+        let pool_uuid = PoolUuid::new_v4();
+        let other_uuid = PoolUuid::new_v4();
+        let now = Instant::now();
+
+        auto_unlocks.schedule(pool_uuid, now);
+        auto_unlocks.schedule(other_uuid, now + Duration::from_secs(30));
+        assert_eq!(auto_unlocks.take_due(now), vec![pool_uuid]);
+
+        auto_unlocks.retain(|uuid| uuid != pool_uuid);
+        auto_unlocks.finished(pool_uuid, false, now);
+        assert_eq!(auto_unlocks.next_due(now), Some(Duration::from_secs(30)));
+
+        auto_unlocks.cancel(other_uuid);
+        assert_eq!(auto_unlocks.next_due(now), None);
+    }
+}
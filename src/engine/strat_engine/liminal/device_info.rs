@@ -25,6 +25,8 @@ pub struct LLuksInfo {
     /// Generic information + Stratis identifiers
     pub ids: StratisInfo,
     pub encryption_info: EncryptionInfo,
+    /// Whether the contents of the device are being encrypted in place
+    pub encryption_in_progress: bool,
 }
 
 impl fmt::Display for LLuksInfo {
@@ -38,6 +40,7 @@ impl From<LuksInfo> for LLuksInfo {
         LLuksInfo {
             ids: info.info,
             encryption_info: info.encryption_info,
+            encryption_in_progress: info.encryption_in_progress,
         }
     }
 }
@@ -59,6 +62,10 @@ impl<'a> Into<Value> for &'a LLuksInfo {
                 unreachable!("EncryptionInfo conversion returns a JSON object");
             },
         );
+        map.insert(
+            "encryption_in_progress".to_string(),
+            Value::from(self.encryption_in_progress),
+        );
         json
     }
 }
@@ -282,6 +289,13 @@ impl LInfo {
                 }))
             }
             (LInfo::Stratis(strat_info), DeviceInfo::Luks(luks_info)) => {
+                // The unencrypted device has since been encrypted in place,
+                // so the information about it is stale.
+                if strat_info.luks.is_none()
+                    && strat_info.ids.device_number == luks_info.info.device_number
+                {
+                    return Ok(LInfo::Luks(LLuksInfo::from(luks_info.clone())));
+                }
                 if let Some(luks) = strat_info.luks.as_ref() {
                     if !luks_luks_compatible(luks, &luks_info) {
                         return Err(());
@@ -410,6 +424,15 @@ impl DeviceSet {
             .next()
     }
 
+    /// Replace the information about a device with information obtained by
+    /// the engine itself, e.g., after the engine has encrypted the device.
+    /// Unlike the information from a udev event, this information is not
+    /// checked for compatibility with the existing information.
+    pub fn replace(&mut self, info: LInfo) {
+        let device_uuid = info.stratis_identifiers().device_uuid;
+        self.internal.insert(device_uuid, info);
+    }
+
     /// Process the data from a remove udev event. Since remove events are
     /// always subtractive, this method can never introduce a key_description
     /// which is incompatible with the existing key description.
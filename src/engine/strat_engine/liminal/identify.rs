@@ -69,6 +69,8 @@ pub struct LuksInfo {
     pub info: StratisInfo,
     /// Encryption information
     pub encryption_info: EncryptionInfo,
+    /// Whether the contents of the device are being encrypted in place
+    pub encryption_in_progress: bool,
 }
 
 impl fmt::Display for LuksInfo {
@@ -228,6 +230,7 @@ fn process_luks_device(dev: &libudev::Device) -> Option<LuksInfo> {
                         devnode: handle.luks2_device_path().to_path_buf(),
                     },
                     encryption_info: handle.encryption_info().to_owned(),
+                    encryption_in_progress: handle.encryption_in_progress(),
                 }),
             },
         },
@@ -444,7 +447,7 @@ mod tests {
                 tests::{crypt, loopbacked, real},
                 udev::block_device_apply,
             },
-            types::{EncryptionInfo, KeyDescription},
+            types::{EncryptionInfo, EncryptionParams, KeyDescription},
             BlockDev,
         },
         stratis::StratisError,
@@ -475,7 +478,9 @@ mod tests {
                 MDADataSize::default(),
                 Some(EncryptionInfo {
                     key_description: key_description.clone(),
-                    clevis_info: None,
+                    clevis_info: Vec::new(),
+                    encryption_params: EncryptionParams::default(),
+                    auto_unlock: false,
                 }),
             )?;
 
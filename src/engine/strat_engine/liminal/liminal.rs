@@ -5,18 +5,29 @@
 //! Management of devices which are known to stratisd but not in a pool.
 
 use std::{
+    cmp::min,
     collections::{HashMap, HashSet},
     fmt,
+    path::Path,
+    time::{Duration, Instant},
 };
 
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 
+use devicemapper::Device;
+
 use crate::{
     engine::{
         engine::Pool,
         strat_engine::{
-            backstore::CryptActivationHandle,
+            backstore::{
+                check_in_place_encryption_space, encrypt_device_in_place, CryptActivationHandle,
+                CryptHandle,
+            },
+            keys::MemoryPrivateFilesystem,
             liminal::{
+                auto_unlock::AutoUnlocks,
                 device_info::{DeviceBag, DeviceSet, LInfo, LLuksInfo, LStratisInfo},
                 identify::{identify_block_device, DeviceInfo, LuksInfo, StratisInfo},
                 setup::{get_bdas, get_blockdevs, get_metadata},
@@ -25,7 +36,7 @@ use crate::{
             pool::StratPool,
         },
         structures::Table,
-        types::{DevUuid, EncryptionInfo, Name, PoolUuid, UnlockMethod},
+        types::{DevUuid, EncryptionInfo, Name, PoolUuid, SizedKeyMemory, UnlockMethod},
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
@@ -48,7 +59,7 @@ impl fmt::Display for Destination {
 
 /// Devices which stratisd has discovered but which have not been assembled
 /// into pools.
-#[derive(Debug, Default, Eq, PartialEq)]
+#[derive(Debug, Default)]
 pub struct LiminalDevices {
     /// Sets of devices which have not been promoted to pools, but which
     /// may still have a chance.
@@ -56,6 +67,13 @@ pub struct LiminalDevices {
     /// Sets of devices which possess some internal contradiction which makes
     /// it impossible for them to be made into sensible pools ever.
     hopeless_device_sets: HashMap<PoolUuid, DeviceBag>,
+    /// Pools to be unlocked automatically with their Clevis bindings
+    auto_unlocks: AutoUnlocks,
+    /// Pools which were set up from older metadata because no copy of their
+    /// most recent metadata could be read, with the time at which the most
+    /// recent metadata was written and the time at which the metadata used
+    /// was written.
+    metadata_fallbacks: HashMap<PoolUuid, (DateTime<Utc>, DateTime<Utc>)>,
 }
 
 impl LiminalDevices {
@@ -83,9 +101,20 @@ impl LiminalDevices {
         pools: &Table<PoolUuid, StratPool>,
         pool_uuid: PoolUuid,
         unlock_method: UnlockMethod,
+        passphrase: Option<SizedKeyMemory>,
     ) -> StratisResult<Vec<DevUuid>> {
-        fn handle_luks(luks_info: &LLuksInfo, unlock_method: UnlockMethod) -> StratisResult<()> {
-            if CryptActivationHandle::setup(&luks_info.ids.devnode, unlock_method)?.is_some() {
+        fn handle_luks(
+            luks_info: &LLuksInfo,
+            unlock_method: UnlockMethod,
+            passphrase_keyfile: Option<&Path>,
+        ) -> StratisResult<()> {
+            if CryptActivationHandle::setup(
+                &luks_info.ids.devnode,
+                unlock_method,
+                passphrase_keyfile,
+            )?
+            .is_some()
+            {
                 Ok(())
             } else {
                 Err(StratisError::Engine(
@@ -111,17 +140,30 @@ impl LiminalDevices {
                     ));
                 }
 
-                let mut unlocked = Vec::new();
-                for (dev_uuid, info) in map.iter() {
-                    match info {
-                        LInfo::Stratis(_) => (),
-                        LInfo::Luks(ref luks_info) => match handle_luks(luks_info, unlock_method) {
-                            Ok(()) => unlocked.push(*dev_uuid),
-                            Err(e) => return Err(e),
-                        },
+                let unlock_devices = |passphrase_keyfile: Option<&Path>| {
+                    let mut unlocked = Vec::new();
+                    for (dev_uuid, info) in map.iter() {
+                        match info {
+                            LInfo::Stratis(_) => (),
+                            LInfo::Luks(ref luks_info) => {
+                                match handle_luks(luks_info, unlock_method, passphrase_keyfile) {
+                                    Ok(()) => unlocked.push(*dev_uuid),
+                                    Err(e) => return Err(e),
+                                }
+                            }
+                        }
                     }
+                    Ok(unlocked)
+                };
+
+                // A passphrase is only ever placed in a keyfile in an
+                // in-memory filesystem private to stratisd for the duration
+                // of the unlock, never in the kernel keyring.
+                match passphrase {
+                    Some(passphrase) => MemoryPrivateFilesystem::new()?
+                        .passphrase_op(passphrase, |keyfile| unlock_devices(Some(keyfile)))?,
+                    None => unlock_devices(None)?,
                 }
-                unlocked
             }
             None => match pools.get_by_uuid(pool_uuid) {
                 Some((_, pool)) => {
@@ -152,6 +194,54 @@ impl LiminalDevices {
         Ok(unlocked)
     }
 
+    /// Whether the locked devices in the set should be unlocked automatically
+    /// with their Clevis bindings.
+    fn wants_auto_unlock(devices: &DeviceSet) -> bool {
+        devices.some_closed()
+            && devices
+                .encryption_info()
+                .map(|info| info.unlocks_automatically())
+                .unwrap_or(false)
+    }
+
+    /// Start the attempts to unlock pools automatically that are due, and
+    /// collect the results of those that have finished. Pools which have
+    /// been set up or whose devices have all been unlocked in the meantime
+    /// are no longer attempted.
+    ///
+    /// All locked devices of a pool are attempted. Which devices belong to
+    /// the pool is only recorded in the metadata on the encrypted devices,
+    /// so whether the set is complete can not be known before they are
+    /// unlocked; the pool is set up as usual once its unlocked devices
+    /// appear and the set is complete.
+    ///
+    /// Returns the time until this method must next be called, if any
+    /// attempt remains.
+    pub fn process_auto_unlocks(&mut self) -> Option<Duration> {
+        let errored_pool_devices = &self.errored_pool_devices;
+        self.auto_unlocks.retain(|pool_uuid| {
+            errored_pool_devices
+                .get(&pool_uuid)
+                .map(LiminalDevices::wants_auto_unlock)
+                .unwrap_or(false)
+        });
+
+        self.auto_unlocks.process(|pool_uuid| {
+            errored_pool_devices
+                .get(&pool_uuid)
+                .map(|devices| {
+                    devices
+                        .iter()
+                        .filter_map(|(_, info)| match info {
+                            LInfo::Luks(luks_info) => Some(luks_info.ids.devnode.clone()),
+                            LInfo::Stratis(_) => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_else(Vec::new)
+        })
+    }
+
     /// Get a mapping of pool UUIDs from all of the LUKS2 devices that are currently
     /// locked to their encryption info in the set of pools that are not yet set up.
     // Precondition: All devices for a given errored pool have been determined to have
@@ -165,6 +255,225 @@ impl LiminalDevices {
             .collect()
     }
 
+    /// Tear down a pool which has been removed from the table of pools and
+    /// record its devices as a set of errored devices, so that the devices
+    /// can be operated on while the pool is stopped.
+    ///
+    /// The devices are recorded even if the pool could not be torn down, so
+    /// that the pool can be set up again from them.
+    ///
+    /// Precondition: The pool is not encrypted.
+    pub fn stop_pool(&mut self, pool_uuid: PoolUuid, mut pool: StratPool) -> StratisResult<()> {
+        assert!(!pool.is_encrypted());
+
+        let mut devices = DeviceSet::new();
+        for (dev_uuid, _, _) in pool.blockdevs() {
+            let (_, blockdev) = pool
+                .get_strat_blockdev(dev_uuid)
+                .expect("blockdev was just listed");
+            devices.replace(LInfo::Stratis(LStratisInfo::from(StratisInfo {
+                identifiers: StratisIdentifiers::new(pool_uuid, dev_uuid),
+                device_number: *blockdev.device(),
+                devnode: blockdev.physical_path().to_owned(),
+            })));
+        }
+
+        let result = pool.teardown();
+        self.errored_pool_devices.insert(pool_uuid, devices);
+        self.metadata_fallbacks.remove(&pool_uuid);
+        result
+    }
+
+    /// Forget everything recorded about a pool that has been destroyed.
+    pub fn pool_destroyed(&mut self, pool_uuid: PoolUuid) {
+        self.metadata_fallbacks.remove(&pool_uuid);
+    }
+
+    /// Try to set up a pool from its set of errored devices, e.g., after
+    /// stop_pool() failed to tear the pool down.
+    pub fn setup_errored_pool(
+        &mut self,
+        pools: &Table<PoolUuid, StratPool>,
+        pool_uuid: PoolUuid,
+    ) -> Option<(Name, StratPool)> {
+        self.errored_pool_devices
+            .remove(&pool_uuid)
+            .and_then(|devices| self.try_setup_pool(pools, pool_uuid, devices))
+    }
+
+    /// Encrypt the contents of the data devices of a stopped pool in place
+    /// and set the pool up again.
+    ///
+    /// The devices are encrypted one at a time. Devices whose encryption was
+    /// interrupted, e.g., by a crash or by the failure of a previous attempt,
+    /// are finished first, with the encryption parameters recorded on them.
+    /// If the encryption of some device fails, the devices remain among the
+    /// errored devices and the encryption can be resumed by calling this
+    /// method again.
+    pub fn encrypt_pool(
+        &mut self,
+        pools: &Table<PoolUuid, StratPool>,
+        pool_uuid: PoolUuid,
+        encryption_info: &EncryptionInfo,
+    ) -> StratisResult<(Name, StratPool)> {
+        // The information about a device once its contents have been
+        // encrypted.
+        fn encrypted_info(handle: &CryptHandle, devno: Device, physical: &StratisInfo) -> LInfo {
+            LInfo::Stratis(LStratisInfo {
+                ids: StratisInfo {
+                    identifiers: *handle.device_identifiers(),
+                    device_number: devno,
+                    devnode: handle.activated_device_path().to_owned(),
+                },
+                luks: Some(LLuksInfo {
+                    ids: physical.clone(),
+                    encryption_info: handle.encryption_info().clone(),
+                    encryption_in_progress: handle.encryption_in_progress(),
+                }),
+            })
+        }
+
+        fn encrypt_devices(
+            pool_uuid: PoolUuid,
+            devices: &mut DeviceSet,
+            encryption_info: &EncryptionInfo,
+        ) -> StratisResult<()> {
+            let encryption_info = match devices.encryption_info() {
+                Some(info) if info.key_description != encryption_info.key_description => {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "Some devices of pool with UUID {} are already encrypted with key description {}",
+                            pool_uuid.to_simple_ref(),
+                            info.key_description.as_application_str(),
+                        ),
+                    ));
+                }
+                Some(info) => info.clone(),
+                None => encryption_info.clone(),
+            };
+
+            let interrupted = devices
+                .iter()
+                .filter_map(|(_, info)| match info {
+                    LInfo::Luks(luks) => Some(luks.ids.clone()),
+                    LInfo::Stratis(LStratisInfo {
+                        luks: Some(luks), ..
+                    }) if luks.encryption_in_progress => Some(luks.ids.clone()),
+                    LInfo::Stratis(_) => None,
+                })
+                .collect::<Vec<_>>();
+            for physical in interrupted {
+                let (handle, devno) = encrypt_device_in_place(
+                    &physical.devnode,
+                    physical.identifiers,
+                    &encryption_info.key_description,
+                    &encryption_info.encryption_params,
+                )?;
+                devices.replace(encrypted_info(&handle, devno, &physical));
+            }
+
+            let unencrypted = {
+                let opened = devices.as_opened_set().ok_or_else(|| {
+                    StratisError::Error(format!(
+                        "Some devices of pool with UUID {} could not be opened",
+                        pool_uuid.to_simple_ref()
+                    ))
+                })?;
+                let bdas = get_bdas(&opened)?;
+                let metadata = get_metadata(&opened, &bdas)?
+                    .ok_or_else(|| {
+                        StratisError::Engine(
+                            ErrorEnum::NotFound,
+                            format!(
+                                "No metadata found on devices associated with pool UUID {}",
+                                pool_uuid.to_simple_ref()
+                            ),
+                        )
+                    })?
+                    .metadata;
+                if metadata.backstore.cache_tier.is_some() {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "Pool with UUID {} has a cache; use of a cache is not supported with an encrypted pool",
+                            pool_uuid.to_simple_ref()
+                        ),
+                    ));
+                }
+
+                let mut unencrypted = Vec::new();
+                let mut dev_sizes = HashMap::new();
+                for dev in metadata.backstore.data_tier.blockdev.devs.iter() {
+                    let info = opened.get(&dev.uuid).ok_or_else(|| {
+                        StratisError::Engine(
+                            ErrorEnum::NotFound,
+                            format!(
+                                "Data device with UUID {} of pool with UUID {} was not found",
+                                dev.uuid.to_simple_ref(),
+                                pool_uuid.to_simple_ref()
+                            ),
+                        )
+                    })?;
+                    if info.luks.is_none() {
+                        let bda = bdas
+                            .get(&dev.uuid)
+                            .expect("a BDA was read from every device");
+                        dev_sizes.insert(dev.uuid, bda.dev_size().sectors());
+                        unencrypted.push(info.ids.clone());
+                    }
+                }
+                check_in_place_encryption_space(&metadata.backstore, &dev_sizes)?;
+                unencrypted
+            };
+
+            for physical in unencrypted {
+                let (handle, devno) = encrypt_device_in_place(
+                    &physical.devnode,
+                    physical.identifiers,
+                    &encryption_info.key_description,
+                    &encryption_info.encryption_params,
+                )?;
+                info!(
+                    "Encrypted device {} belonging to pool with UUID {}",
+                    physical.devnode.display(),
+                    pool_uuid.to_simple_ref()
+                );
+                devices.replace(encrypted_info(&handle, devno, &physical));
+            }
+
+            Ok(())
+        }
+
+        let mut devices = self
+            .errored_pool_devices
+            .remove(&pool_uuid)
+            .ok_or_else(|| {
+                StratisError::Engine(
+                    ErrorEnum::NotFound,
+                    format!(
+                        "No stopped pool with UUID {} was found",
+                        pool_uuid.to_simple_ref()
+                    ),
+                )
+            })?;
+
+        match encrypt_devices(pool_uuid, &mut devices, encryption_info) {
+            Ok(()) => self
+                .try_setup_pool(pools, pool_uuid, devices)
+                .ok_or_else(|| {
+                    StratisError::Error(format!(
+                        "The data devices of pool with UUID {} were encrypted, but the pool could not be set up",
+                        pool_uuid.to_simple_ref()
+                    ))
+                }),
+            Err(err) => {
+                self.errored_pool_devices.insert(pool_uuid, devices);
+                Err(err)
+            }
+        }
+    }
+
     /// Take maps of pool UUIDs to sets of devices and return a list of
     /// information about created pools.
     ///
@@ -216,6 +525,9 @@ impl LiminalDevices {
                 }
 
                 if !self.hopeless_device_sets.contains_key(pool_uuid) {
+                    if LiminalDevices::wants_auto_unlock(&info_map) {
+                        self.auto_unlocks.schedule(*pool_uuid, Instant::now());
+                    }
                     self.try_setup_pool(&table, *pool_uuid, info_map)
                         .map(|(pool_name, pool)| (pool_name, *pool_uuid, pool))
                 } else {
@@ -255,7 +567,8 @@ impl LiminalDevices {
             pools: &Table<PoolUuid, StratPool>,
             pool_uuid: PoolUuid,
             infos: &HashMap<DevUuid, &LStratisInfo>,
-        ) -> Result<(Name, StratPool), Destination> {
+        ) -> Result<(Name, StratPool, Option<(DateTime<Utc>, DateTime<Utc>)>), Destination>
+        {
             let bdas = match get_bdas(infos) {
                 Err(err) => Err(
                     Destination::Errored(format!(
@@ -276,7 +589,7 @@ impl LiminalDevices {
                         )));
             }
 
-            let (timestamp, metadata) = match get_metadata(infos, &bdas) {
+            let loaded = match get_metadata(infos, &bdas) {
                 Err(err) => return Err(
                     Destination::Errored(format!(
                         "There was an error encountered when reading the metadata for the devices found for pool with UUID {}: {}",
@@ -286,8 +599,12 @@ impl LiminalDevices {
                     Destination::Errored(format!(
                         "No metadata found on devices associated with pool UUID {}",
                         pool_uuid.to_simple_ref()))),
-                Ok(Some((timestamp, metadata))) => (timestamp, metadata),
+                Ok(Some(loaded)) => loaded,
             };
+            let metadata = &loaded.metadata;
+            let fallback = loaded
+                .fallback_time
+                .map(|fallback_time| (loaded.last_update_time, fallback_time));
 
             if let Some((uuid, _)) = pools.get_by_name(&metadata.name) {
                 return Err(
@@ -336,7 +653,15 @@ impl LiminalDevices {
                             &metadata.name)));
             }
 
-            StratPool::setup(pool_uuid, datadevs, cachedevs, timestamp, &metadata).map_err(|err| {
+            StratPool::setup(
+                pool_uuid,
+                datadevs,
+                cachedevs,
+                loaded.last_update_time,
+                metadata,
+            )
+            .map(|(pool_name, pool)| (pool_name, pool, fallback))
+            .map_err(|err| {
                 Destination::Errored(format!(
                     "An attempt to set up pool with UUID {} from the assembled devices failed: {}",
                     pool_uuid.to_simple_ref(),
@@ -355,13 +680,25 @@ impl LiminalDevices {
 
         let result = setup_pool(pools, pool_uuid, &opened);
 
+        self.metadata_fallbacks.remove(&pool_uuid);
         match result {
-            Ok((pool_name, pool)) => {
+            Ok((pool_name, pool, fallback)) => {
                 info!(
                     "Pool with name \"{}\" and UUID \"{}\" set up",
                     pool_name,
                     pool_uuid.to_simple_ref()
                 );
+                if let Some((last_update_time, fallback_time)) = fallback {
+                    warn!(
+                        "Pool with name \"{}\" and UUID \"{}\" was set up from metadata written at {}, because its most recent metadata, written at {}, could not be read; changes made in between are lost",
+                        pool_name,
+                        pool_uuid.to_simple_ref(),
+                        fallback_time,
+                        last_update_time
+                    );
+                    self.metadata_fallbacks
+                        .insert(pool_uuid, (last_update_time, fallback_time));
+                }
                 Some((pool_name, pool))
             }
             Err(Destination::Hopeless(err)) => {
@@ -417,11 +754,20 @@ impl LiminalDevices {
                         .remove(&pool_uuid)
                         .unwrap_or_else(DeviceSet::new);
 
+                    let locked = matches!(info, DeviceInfo::Luks(_));
                     if let Err(hopeless) = devices.process_info_add(info) {
                         self.hopeless_device_sets.insert(pool_uuid, hopeless);
+                        self.auto_unlocks.cancel(pool_uuid);
                         return None;
                     }
 
+                    // The arrival of a locked device of a pool that is
+                    // unlocked automatically prompts an attempt to unlock
+                    // it, unless one is already scheduled or in progress.
+                    if locked && LiminalDevices::wants_auto_unlock(&devices) {
+                        self.auto_unlocks.schedule(pool_uuid, Instant::now());
+                    }
+
                     // FIXME: An attempt to set up the pool is made, even if no
                     // new device has been added to the set of devices that appear
                     // to belong to the pool. The reason for this is that there
@@ -481,6 +827,17 @@ impl<'a> Into<Value> for &'a LiminalDevices {
                             "devices": <&DeviceSet as Into<Value>>::into(&map),
                         })
                     })
+                    .chain(self.metadata_fallbacks.iter().map(
+                        |(uuid, (last_update_time, fallback_time))| {
+                            json!({
+                                "pool_uuid": uuid.to_string(),
+                                "metadata_fallback": {
+                                    "last_update_time": last_update_time.to_rfc3339(),
+                                    "loaded_time": fallback_time.to_rfc3339(),
+                                },
+                            })
+                        }
+                    ))
                     .collect(),
             ),
             "hopeless_devices": Value::Array(
@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod auto_unlock;
 mod device_info;
 mod identify;
 #[allow(clippy::module_inception)]
@@ -21,6 +21,7 @@ use crate::{
             device::blkdev_size,
             liminal::device_info::LStratisInfo,
             metadata::BDA,
+            pool::check_metadata,
             serde_structs::{BackstoreSave, BaseBlockDevSave, PoolSave},
         },
         types::{BlockDevTier, DevUuid},
@@ -49,10 +50,26 @@ pub fn get_bdas(infos: &HashMap<DevUuid, &LStratisInfo>) -> StratisResult<HashMa
         .collect()
 }
 
-/// Get the most recent metadata from a set of devices.
+/// Pool metadata read from a set of devices.
+#[derive(Debug)]
+pub struct LoadedMetadata {
+    /// The most recent time at which metadata was written to any of the
+    /// devices. Any later update of the metadata must be stamped with a
+    /// later time, even if older metadata was loaded.
+    pub last_update_time: DateTime<Utc>,
+    /// The time at which the loaded metadata was written, if it is older
+    /// than the most recent metadata, because no copy of that could be read.
+    pub fallback_time: Option<DateTime<Utc>>,
+    pub metadata: PoolSave,
+}
+
+/// Get the most recent metadata that can be read from a set of devices.
 /// Returns None if no metadata found for this pool on any device. This can
 /// happen if the pool was constructed but failed in the interval before the
 /// metadata could be written.
+/// If no copy of the most recent metadata can be read, parsed and found
+/// consistent, falls back to the newest copy that can, from any region on
+/// any device.
 /// Returns an error if there is a last update time, but no metadata could
 /// be obtained from any of the devices.
 ///
@@ -60,7 +77,7 @@ pub fn get_bdas(infos: &HashMap<DevUuid, &LStratisInfo>) -> StratisResult<HashMa
 pub fn get_metadata(
     infos: &HashMap<DevUuid, &LStratisInfo>,
     bdas: &HashMap<DevUuid, BDA>,
-) -> StratisResult<Option<(DateTime<Utc>, PoolSave)>> {
+) -> StratisResult<Option<LoadedMetadata>> {
     // Most recent time should never be None if this was a properly
     // created pool; this allows for the method to be called in other
     // circumstances.
@@ -70,31 +87,43 @@ pub fn get_metadata(
             .filter_map(|(_, bda)| bda.last_update_time())
             .max()
         {
-            Some(time) => time,
+            Some(time) => *time,
             None => return Ok(None),
         }
     };
 
-    // Try to read from all available devnodes that could contain most
-    // recent metadata. In the event of errors, continue to try until all are
-    // exhausted.
-    bdas.iter()
+    // Gather every copy of the metadata from all available devnodes,
+    // newest first. In the event of errors, continue to try until all
+    // are exhausted.
+    let mut copies = bdas
+        .iter()
         .filter_map(|(uuid, bda)| {
-            if bda.last_update_time() == Some(most_recent_time) {
-                OpenOptions::new()
-                    .read(true)
-                    .open(
-                        &infos
-                            .get(uuid)
-                            .expect("equal sets of UUID keys")
-                            .ids
-                            .devnode,
-                    )
-                    .ok()
-                    .and_then(|mut f| bda.load_state(&mut f).unwrap_or(None))
-                    .and_then(|data| serde_json::from_slice(&data).ok())
-            } else {
-                None
+            let info = infos.get(uuid).expect("equal sets of UUID keys");
+            OpenOptions::new()
+                .read(true)
+                .open(&info.ids.devnode)
+                .ok()
+                .map(|mut f| (info, bda.load_states(&mut f)))
+        })
+        .flat_map(|(info, states)| states.into_iter().map(move |state| (info, state)))
+        .collect::<Vec<_>>();
+    copies.sort_by(|(_, (time1, _)), (_, (time2, _))| time2.cmp(time1));
+
+    let (time, metadata) = copies
+        .into_iter()
+        .filter_map(|(info, (time, data))| {
+            match data
+                .and_then(|data| Ok(serde_json::from_slice::<PoolSave>(&data)?))
+                .and_then(|metadata| check_metadata(&metadata).map(|_| metadata))
+            {
+                Ok(metadata) => Some((time, metadata)),
+                Err(err) => {
+                    warn!(
+                        "Failed to load the metadata written at {} from device with {}: {}",
+                        time, info.ids, err
+                    );
+                    None
+                }
             }
         })
         .next()
@@ -103,8 +132,23 @@ pub fn get_metadata(
                 ErrorEnum::NotFound,
                 "timestamp indicates data was written, but no data successfully read".into(),
             )
-        })
-        .map(|psave| Some((*most_recent_time, psave)))
+        })?;
+
+    let fallback_time = if time < most_recent_time {
+        warn!(
+            "The most recent metadata, written at {}, could not be read from any device; using the metadata written at {} instead. Changes made in between are lost.",
+            most_recent_time, time
+        );
+        Some(time)
+    } else {
+        None
+    };
+
+    Ok(Some(LoadedMetadata {
+        last_update_time: most_recent_time,
+        fallback_time,
+        metadata,
+    }))
 }
 
 /// Get all the blockdevs corresponding to this pool that can be obtained from
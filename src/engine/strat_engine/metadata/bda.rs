@@ -21,6 +21,32 @@ use crate::{
     stratis::StratisResult,
 };
 
+/// Everything that could be read from the BDA of a device, gathered without
+/// making any attempt to repair it. Used to inspect metadata that may be
+/// damaged.
+#[derive(Debug)]
+pub struct BDAContents {
+    /// The two copies of the signature block, in the order in which they
+    /// occur on the device.
+    pub sigblocks: (
+        StratisResult<Option<StaticHeader>>,
+        StratisResult<Option<StaticHeader>>,
+    ),
+    /// All MDA regions, primary and backup. None if neither signature block
+    /// is valid, since the location of the regions is then unknown.
+    pub regions: Option<Vec<mda::MDARegionContents>>,
+}
+
+impl BDAContents {
+    /// The first valid signature block, if any.
+    pub fn header(&self) -> Option<&StaticHeader> {
+        match self.sigblocks {
+            (Ok(Some(ref sh)), _) | (_, Ok(Some(ref sh))) => Some(sh),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BDA {
     header: StaticHeader,
@@ -77,6 +103,59 @@ impl BDA {
         Ok(Some(BDA { header, regions }))
     }
 
+    /// Load the BDA of a device whose metadata is to be rewritten, using the
+    /// given signature block. Each copy of the signature block on the device
+    /// that does not match it is overwritten with it; a copy that matches is
+    /// left alone. The MDA headers are not initialized, so the metadata
+    /// already on the device remains until save_state() overwrites it; an
+    /// MDA header that can not be read is treated as if no metadata had been
+    /// written to its region.
+    pub fn load_for_rewrite<F>(f: &mut F, header: &StaticHeader) -> StratisResult<BDA>
+    where
+        F: Read + Seek + SyncAll,
+    {
+        let matches = |sigblock: StratisResult<Option<StaticHeader>>| match sigblock {
+            Ok(Some(ref sh)) => sh == header,
+            _ => false,
+        };
+        let (first, second) = StaticHeader::read_sigblocks(f);
+        match (matches(first), matches(second)) {
+            (true, true) => (),
+            (true, false) => header.write(f, MetadataLocation::Second)?,
+            (false, true) => header.write(f, MetadataLocation::First)?,
+            (false, false) => header.write(f, MetadataLocation::Both)?,
+        }
+
+        let regions = mda::MDARegions::load_damaged(
+            STATIC_HEADER_SIZE.sectors().bytes(),
+            header.mda_size,
+            f,
+        )?;
+
+        Ok(BDA {
+            header: header.clone(),
+            regions,
+        })
+    }
+
+    /// Read the signature blocks and all the MDA regions of a device,
+    /// without writing to it. The regions are located using the first valid
+    /// signature block.
+    pub fn read_contents<F>(f: &mut F) -> BDAContents
+    where
+        F: Read + Seek,
+    {
+        let sigblocks = StaticHeader::read_sigblocks(f);
+        let mda_size = match sigblocks {
+            (Ok(Some(ref sh)), _) | (_, Ok(Some(ref sh))) => Some(sh.mda_size),
+            _ => None,
+        };
+        let regions = mda_size.map(|mda_size| {
+            mda::MDARegions::read_regions(STATIC_HEADER_SIZE.sectors().bytes(), mda_size, f)
+        });
+        BDAContents { sigblocks, regions }
+    }
+
     /// Save metadata to the disk
     pub fn save_state<F>(
         &mut self,
@@ -100,12 +179,27 @@ impl BDA {
             .load_state(STATIC_HEADER_SIZE.sectors().bytes(), &mut f)
     }
 
+    /// Read every copy of the metadata on the disk, newest first, with the
+    /// time at which it was written.
+    pub fn load_states<F>(&self, mut f: &mut F) -> Vec<(DateTime<Utc>, StratisResult<Vec<u8>>)>
+    where
+        F: Read + Seek,
+    {
+        self.regions
+            .load_states(STATIC_HEADER_SIZE.sectors().bytes(), &mut f)
+    }
+
     /// The time when the most recent metadata was written to the BDA,
     /// if any.
     pub fn last_update_time(&self) -> Option<&DateTime<Utc>> {
         self.regions.last_update_time()
     }
 
+    /// The static header of the device.
+    pub fn header(&self) -> &StaticHeader {
+        &self.header
+    }
+
     /// The UUID of the device.
     pub fn dev_uuid(&self) -> DevUuid {
         self.header.identifiers.device_uuid
@@ -121,6 +215,18 @@ impl BDA {
         self.header.blkdev_size
     }
 
+    /// Record a new size for the device in both copies of the static header.
+    /// This is necessary if the device the BDA is on has shrunk, e.g., because
+    /// its contents were moved onto an encrypted layer.
+    pub fn set_dev_size<F>(&mut self, f: &mut F, blkdev_size: BlockdevSize) -> StratisResult<()>
+    where
+        F: Seek + SyncAll,
+    {
+        self.header.blkdev_size = blkdev_size;
+        self.header.write(f, MetadataLocation::Both)?;
+        Ok(())
+    }
+
     /// The number of sectors the BDA itself occupies.
     pub fn extended_size(&self) -> BDAExtendedSize {
         self.header.bda_extended_size()
@@ -135,6 +241,16 @@ impl BDA {
     pub fn initialization_time(&self) -> u64 {
         self.header.initialization_time
     }
+
+    #[cfg(test)]
+    /// Make both copies of the most recent metadata unreadable.
+    pub fn corrupt_state<F>(&self, f: &mut F) -> StratisResult<()>
+    where
+        F: Seek + SyncAll,
+    {
+        self.regions
+            .corrupt_newer(STATIC_HEADER_SIZE.sectors().bytes(), f)
+    }
 }
 
 #[cfg(test)]
@@ -143,8 +259,9 @@ mod tests {
 
     use proptest::{collection::vec, num};
 
-    use crate::engine::strat_engine::metadata::static_header::tests::{
-        random_static_header, static_header_strategy,
+    use crate::engine::strat_engine::metadata::{
+        sizes::{mda_size, static_header_size},
+        static_header::tests::{random_static_header, static_header_strategy},
     };
 
     use super::*;
@@ -221,6 +338,112 @@ mod tests {
         assert_matches!(bda.save_state(&timestamp2, &data, &mut buf), Err(_));
     }
 
+    #[test]
+    /// Construct a BDA, save some metadata, and damage the first signature
+    /// block. Verify that reading the contents of the BDA reports the
+    /// damaged signature block, and still finds both copies of the metadata
+    /// using the other one.
+    fn test_read_contents() {
+        let data = [1u8, 2, 3];
+
+        let sh = random_static_header(0, 0);
+        let mut buf = Cursor::new(vec![
+            0;
+            convert_test!(
+                *sh.blkdev_size.sectors().bytes(),
+                u128,
+                usize
+            )
+        ]);
+        let mut bda = BDA::initialize(
+            &mut buf,
+            sh.identifiers,
+            sh.mda_size.region_size().data_size(),
+            sh.blkdev_size,
+            Utc::now().timestamp() as u64,
+        )
+        .unwrap();
+        bda.save_state(&Utc::now(), &data, &mut buf).unwrap();
+
+        let offset = bytes!(static_header_size::FIRST_SIGBLOCK_START_SECTORS) + 20;
+        buf.get_mut()[offset] ^= 0xff;
+
+        let contents = BDA::read_contents(&mut buf);
+        assert_matches!(contents.sigblocks.0, Err(_));
+        assert_matches!(contents.sigblocks.1, Ok(Some(_)));
+        assert_eq!(
+            contents.header().map(|h| h.identifiers),
+            Some(sh.identifiers)
+        );
+
+        let regions = contents.regions.unwrap();
+        assert_eq!(regions.len(), mda_size::NUM_MDA_REGIONS);
+        let written = regions
+            .iter()
+            .filter_map(|region| match region.data {
+                Some(Ok(ref written)) => Some(written.as_slice()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(written, vec![&data[..], &data[..]]);
+    }
+
+    #[test]
+    /// Construct a BDA, save two versions of some metadata, and damage the
+    /// first signature block. Verify that loading the BDA for rewriting
+    /// repairs the signature block without discarding either version, and
+    /// that saving new metadata then replaces only the older version.
+    fn test_load_for_rewrite() {
+        let (old, new, newest) = ([1u8, 2, 3], [4u8, 5, 6], [7u8, 8, 9]);
+
+        let sh = random_static_header(0, 0);
+        let mut buf = Cursor::new(vec![
+            0;
+            convert_test!(
+                *sh.blkdev_size.sectors().bytes(),
+                u128,
+                usize
+            )
+        ]);
+        let mut bda = BDA::initialize(
+            &mut buf,
+            sh.identifiers,
+            sh.mda_size.region_size().data_size(),
+            sh.blkdev_size,
+            Utc::now().timestamp() as u64,
+        )
+        .unwrap();
+        let time = Utc::now();
+        bda.save_state(&time, &old, &mut buf).unwrap();
+        bda.save_state(&(time + chrono::Duration::seconds(1)), &new, &mut buf)
+            .unwrap();
+
+        let offset = bytes!(static_header_size::FIRST_SIGBLOCK_START_SECTORS) + 20;
+        buf.get_mut()[offset] ^= 0xff;
+
+        let header = BDA::read_contents(&mut buf).header().cloned().unwrap();
+        let mut bda = BDA::load_for_rewrite(&mut buf, &header).unwrap();
+        let contents = BDA::read_contents(&mut buf);
+        assert_matches!(contents.sigblocks, (Ok(Some(_)), Ok(Some(_))));
+        let states = bda
+            .load_states(&mut buf)
+            .into_iter()
+            .map(|(_, state)| state.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(states, vec![new.to_vec(), old.to_vec()]);
+
+        bda.save_state(&(time + chrono::Duration::seconds(2)), &newest, &mut buf)
+            .unwrap();
+        let states = BDA::load(&mut buf)
+            .unwrap()
+            .unwrap()
+            .load_states(&mut buf)
+            .into_iter()
+            .map(|(_, state)| state.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(states, vec![newest.to_vec(), new.to_vec()]);
+    }
+
     proptest! {
         #[test]
         /// Construct an arbitrary StaticHeader object.
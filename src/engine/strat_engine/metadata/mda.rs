@@ -10,6 +10,7 @@ use std::{
 use byteorder::{ByteOrder, LittleEndian};
 use chrono::{DateTime, TimeZone, Utc};
 use crc::crc32;
+use serde_json::Value;
 
 use devicemapper::Bytes;
 
@@ -24,6 +25,31 @@ use crate::{
 const STRAT_REGION_HDR_VERSION: u8 = 1;
 const STRAT_METADATA_VERSION: u8 = 1;
 
+/// The contents of a single MDA region as found on a device, read without
+/// any attempt to repair them.
+#[derive(Debug)]
+pub struct MDARegionContents {
+    /// The index of the region. The regions with an index of
+    /// NUM_PRIMARY_MDA_REGIONS or greater hold the backup copies of the
+    /// primary regions.
+    pub index: usize,
+    /// The header of the region. None if no variable length metadata has
+    /// been written to the region, an error if the header could not be read
+    /// or is invalid.
+    pub header: StratisResult<Option<MDAHeader>>,
+    /// The variable length metadata in the region, if the header indicates
+    /// that there is some. An error if it could not be read or does not
+    /// match the CRC in the header.
+    pub data: Option<StratisResult<Vec<u8>>>,
+}
+
+impl MDARegionContents {
+    /// Whether the region holds the backup copy of a primary region.
+    pub fn is_backup(&self) -> bool {
+        self.index >= mda_size::NUM_PRIMARY_MDA_REGIONS
+    }
+}
+
 /// Manages the MDA regions which hold the variable length metadata.
 #[derive(Debug)]
 pub struct MDARegions {
@@ -103,6 +129,54 @@ impl MDARegions {
         F: Read + Seek,
     {
         let region_size = mda_size.region_size();
+
+        Ok(MDARegions {
+            region_size,
+            mda_headers: [
+                MDARegions::load_header(header_size, region_size, 0, f)?,
+                MDARegions::load_header(header_size, region_size, 1, f)?,
+            ],
+        })
+    }
+
+    /// Construct an MDARegions struct from data on a disk whose MDA headers
+    /// may be damaged. An MDA header that can be read from neither its
+    /// primary nor its backup region is treated as if no variable length
+    /// metadata had been written to its region, so that the region is the
+    /// first to be overwritten by save_state(). Nothing is written to the
+    /// device.
+    pub fn load_damaged<F>(
+        header_size: Bytes,
+        mda_size: MDASize,
+        f: &mut F,
+    ) -> StratisResult<MDARegions>
+    where
+        F: Read + Seek,
+    {
+        let region_size = mda_size.region_size();
+
+        Ok(MDARegions {
+            region_size,
+            mda_headers: [
+                MDARegions::load_header(header_size, region_size, 0, f).unwrap_or(None),
+                MDARegions::load_header(header_size, region_size, 1, f).unwrap_or(None),
+            ],
+        })
+    }
+
+    /// Get the MDAHeader of the primary region specified by index.
+    /// If there is a failure reading it, fall back on its backup. If there
+    /// is a failure reading both, return an error. If it appears that no
+    /// metadata has been written to the region, return None.
+    fn load_header<F>(
+        header_size: Bytes,
+        region_size: MDARegionSize,
+        index: usize,
+        f: &mut F,
+    ) -> StratisResult<Option<MDAHeader>>
+    where
+        F: Read + Seek,
+    {
         let region_size_bytes = region_size.sectors().bytes();
 
         // Load a single region at the location specified by index.
@@ -120,18 +194,49 @@ impl MDARegions {
             Ok(MDAHeader::from_buf(&hdr_buf)?)
         };
 
-        // Get an MDAHeader for the given index.
-        // If there is a failure reading the first, fall back on the
-        // second. If there is a failure reading both, return an error.
-        let mut get_mda = |index: usize| -> StratisResult<Option<MDAHeader>> {
-            load_a_region(index)
-                .or_else(|_| load_a_region(index + mda_size::NUM_PRIMARY_MDA_REGIONS))
+        load_a_region(index).or_else(|_| load_a_region(index + mda_size::NUM_PRIMARY_MDA_REGIONS))
+    }
+
+    /// Read the header and the variable length metadata of every MDA
+    /// region, including the backup regions, independently of each other.
+    /// Nothing is written to the device.
+    pub fn read_regions<F>(
+        header_size: Bytes,
+        mda_size: MDASize,
+        f: &mut F,
+    ) -> Vec<MDARegionContents>
+    where
+        F: Read + Seek,
+    {
+        let region_size_bytes = mda_size.region_size().sectors().bytes();
+
+        // Read the header of a single region at the location specified by
+        // index, leaving the file positioned at the start of its data.
+        let read_header = |f: &mut F, index: usize| -> StratisResult<Option<MDAHeader>> {
+            let mut hdr_buf = [0u8; mda_size::_MDA_REGION_HDR_SIZE];
+            f.seek(SeekFrom::Start(convert_int!(
+                MDARegions::mda_offset(header_size, index, region_size_bytes),
+                u128,
+                u64
+            )?))?;
+            f.read_exact(&mut hdr_buf)?;
+            Ok(MDAHeader::from_buf(&hdr_buf)?)
         };
 
-        Ok(MDARegions {
-            region_size,
-            mda_headers: [get_mda(0)?, get_mda(1)?],
-        })
+        let mut regions = Vec::with_capacity(mda_size::NUM_MDA_REGIONS);
+        for index in 0..mda_size::NUM_MDA_REGIONS {
+            let header = read_header(f, index);
+            let data = match header {
+                Ok(Some(ref header)) => Some(header.load_region(f)),
+                _ => None,
+            };
+            regions.push(MDARegionContents {
+                index,
+                header,
+                data,
+            });
+        }
+        regions
     }
 
     /// Write metadata to the older of the metadata regions.
@@ -209,27 +314,55 @@ impl MDARegions {
     where
         F: Read + Seek,
     {
-        let newer_region = self.newer();
-        let mda = match self.mda_headers[newer_region] {
-            None => return Ok(None),
-            Some(ref mda) => mda,
-        };
+        self.load_slot(header_size, self.newer(), f).transpose()
+    }
+
+    /// Load metadata from every MDA region that has a record of some, newest
+    /// first, together with the time at which it was written. Each entry is
+    /// an error if the metadata recorded in that region could not be read.
+    pub fn load_states<F>(
+        &self,
+        header_size: Bytes,
+        f: &mut F,
+    ) -> Vec<(DateTime<Utc>, StratisResult<Vec<u8>>)>
+    where
+        F: Read + Seek,
+    {
+        [self.newer(), self.older()]
+            .iter()
+            .filter_map(|&index| {
+                let time = self.mda_headers[index].as_ref()?.last_updated;
+                self.load_slot(header_size, index, f)
+                    .map(|data| (time, data))
+            })
+            .collect()
+    }
+
+    /// Load the metadata recorded in the primary region specified by index,
+    /// or, if it can not be read, in its backup. Return None if there is no
+    /// record of metadata in the region.
+    fn load_slot<F>(
+        &self,
+        header_size: Bytes,
+        index: usize,
+        f: &mut F,
+    ) -> Option<StratisResult<Vec<u8>>>
+    where
+        F: Read + Seek,
+    {
+        let mda = self.mda_headers[index].as_ref()?;
         let region_size = self.region_size.sectors().bytes();
 
         // Load the metadata region specified by index.
         // It is an error if the metadata can not be found.
-        let mut load_region = |index: usize| -> StratisResult<Vec<u8>> {
-            let offset = MDARegions::mda_offset(header_size, index, region_size)
+        let mut load_region = |region: usize| -> StratisResult<Vec<u8>> {
+            let offset = MDARegions::mda_offset(header_size, region, region_size)
                 + mda_size::_MDA_REGION_HDR_SIZE as u128;
             f.seek(SeekFrom::Start(convert_int!(offset, u128, u64)?))?;
             mda.load_region(f)
         };
 
-        // TODO: Figure out if there is an action to take if the
-        // first read returns an error.
-        load_region(newer_region)
-            .or_else(|_| load_region(newer_region + mda_size::NUM_PRIMARY_MDA_REGIONS))
-            .map(Some)
+        Some(load_region(index).or_else(|_| load_region(index + mda_size::NUM_PRIMARY_MDA_REGIONS)))
     }
 
     /// The index of the older region, or 0 if there is a tie.
@@ -260,6 +393,30 @@ impl MDARegions {
             .map(|h| &h.last_updated)
     }
 
+    #[cfg(test)]
+    /// Overwrite the start of the variable length metadata in the newer
+    /// region and in its backup, leaving their headers intact, so that
+    /// neither copy matches its CRC.
+    pub fn corrupt_newer<F>(&self, header_size: Bytes, f: &mut F) -> StratisResult<()>
+    where
+        F: Seek + SyncAll,
+    {
+        let region_size = self.region_size.sectors().bytes();
+        for &region in [
+            self.newer(),
+            self.newer() + mda_size::NUM_PRIMARY_MDA_REGIONS,
+        ]
+        .iter()
+        {
+            let offset = MDARegions::mda_offset(header_size, region, region_size)
+                + mda_size::_MDA_REGION_HDR_SIZE as u128;
+            f.seek(SeekFrom::Start(convert_int!(offset, u128, u64)?))?;
+            f.write_all(&[0xff; 8])?;
+        }
+        f.sync_all()?;
+        Ok(())
+    }
+
     #[cfg(test)]
     /// An invariant on MDARegions structs.
     /// 1. If an MDAHeader in the regions is not None, then its used
@@ -320,6 +477,21 @@ impl Default for MDAHeader {
 }
 
 impl MDAHeader {
+    /// The time at which the variable length metadata was written.
+    pub fn last_updated(&self) -> &DateTime<Utc> {
+        &self.last_updated
+    }
+
+    /// The size of the variable length metadata.
+    pub fn used(&self) -> MetaDataSize {
+        self.used
+    }
+
+    /// The CRC of the variable length metadata.
+    pub fn data_crc(&self) -> u32 {
+        self.data_crc
+    }
+
     /// Parse a valid MDAHeader from buf.
     /// If the amount used by the variable length metadata is 0, return None,
     /// as this means that no variable length metadata has been written.
@@ -420,6 +592,16 @@ impl MDAHeader {
     }
 }
 
+impl<'a> Into<Value> for &'a MDAHeader {
+    fn into(self) -> Value {
+        json!({
+            "last_updated": Value::from(self.last_updated.to_rfc3339()),
+            "used": Value::from(*self.used.bytes() as u64),
+            "data_crc": Value::from(self.data_crc),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -469,6 +651,47 @@ mod tests {
         assert_matches!(regions.last_update_time(), None);
     }
 
+    #[test]
+    /// Save two different states and damage both copies of the newer one.
+    /// Verify that loading the newest state fails, but that loading all
+    /// states still yields the older one.
+    fn test_load_states_after_damage() {
+        let offset = Bytes(100);
+        let buf_length = convert_test!(
+            *(offset + MDASize::default().sectors().bytes()),
+            u128,
+            usize
+        );
+        let mut buf = Cursor::new(vec![0; buf_length]);
+        let mut regions = MDARegions::initialize(offset, MDASize::default(), &mut buf).unwrap();
+
+        let old_time = Utc.timestamp(1, 0);
+        let new_time = Utc.timestamp(2, 0);
+        regions
+            .save_state(offset, &old_time, &[1u8, 2, 3], &mut buf)
+            .unwrap();
+        regions
+            .save_state(offset, &new_time, &[4u8, 5, 6], &mut buf)
+            .unwrap();
+
+        let region_size = regions.region_size.sectors().bytes();
+        let newer = regions.newer();
+        for index in &[newer, newer + mda_size::NUM_PRIMARY_MDA_REGIONS] {
+            let data_offset = MDARegions::mda_offset(offset, *index, region_size)
+                + mda_size::_MDA_REGION_HDR_SIZE as u128;
+            buf.get_mut()[convert_test!(data_offset, u128, usize)] ^= 0xff;
+        }
+
+        assert_matches!(regions.load_state(offset, &mut buf), Err(_));
+
+        let states = regions.load_states(offset, &mut buf);
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].0, new_time);
+        assert_matches!(states[0].1, Err(_));
+        assert_eq!(states[1].0, old_time);
+        assert_eq!(states[1].1.as_ref().unwrap(), &vec![1u8, 2, 3]);
+    }
+
     proptest! {
         #[test]
         /// Using an arbitrary data buffer, construct an mda header buffer
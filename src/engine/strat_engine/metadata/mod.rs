@@ -16,7 +16,8 @@ mod sizes;
 mod static_header;
 
 pub use self::{
-    bda::BDA,
-    sizes::{BDAExtendedSize, BlockdevSize, MDADataSize},
-    static_header::{device_identifiers, disown_device, StratisIdentifiers},
+    bda::{BDAContents, BDA},
+    mda::{MDAHeader, MDARegionContents},
+    sizes::{BDAExtendedSize, BlockdevSize, MDADataSize, MDASize},
+    static_header::{device_identifiers, disown_device, StaticHeader, StratisIdentifiers},
 };
@@ -93,7 +93,7 @@ where
     StaticHeader::wipe(f)
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct StaticHeader {
     pub blkdev_size: BlockdevSize,
     pub identifiers: StratisIdentifiers,
@@ -368,6 +368,33 @@ impl StaticHeader {
         }
     }
 
+    /// Read and parse both copies of the signature block, without making
+    /// any attempt to repair either of them.
+    ///
+    /// The results are returned in the same order in which the signature
+    /// blocks occur on the device. Each is None if no Stratis magic number
+    /// was found at that location, or an error if the signature block could
+    /// not be read or is invalid.
+    pub fn read_sigblocks<F>(
+        f: &mut F,
+    ) -> (
+        StratisResult<Option<StaticHeader>>,
+        StratisResult<Option<StaticHeader>>,
+    )
+    where
+        F: Read + Seek,
+    {
+        let (maybe_buf_1, maybe_buf_2) = StaticHeader::read(f);
+        (
+            maybe_buf_1
+                .map_err(StratisError::from)
+                .and_then(|buf| StaticHeader::sigblock_from_buf(&buf)),
+            maybe_buf_2
+                .map_err(StratisError::from)
+                .and_then(|buf| StaticHeader::sigblock_from_buf(&buf)),
+        )
+    }
+
     /// Generate a buf suitable for writing to blockdev
     fn sigblock_to_buf(&self) -> [u8; bytes!(static_header_size::SIGBLOCK_SECTORS)] {
         let mut buf = [0u8; bytes!(static_header_size::SIGBLOCK_SECTORS)];
@@ -456,6 +483,20 @@ impl StaticHeader {
     }
 }
 
+impl<'a> Into<Value> for &'a StaticHeader {
+    fn into(self) -> Value {
+        json!({
+            "pool_uuid": Value::from(self.identifiers.pool_uuid.to_string()),
+            "device_uuid": Value::from(self.identifiers.device_uuid.to_string()),
+            "blkdev_size": Value::from(*self.blkdev_size.sectors()),
+            "mda_size": Value::from(*self.mda_size.sectors()),
+            "reserved_size": Value::from(*self.reserved_size.sectors()),
+            "flags": Value::from(self.flags),
+            "initialization_time": Value::from(self.initialization_time),
+        })
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::io::Cursor;
@@ -3,11 +3,13 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 mod backstore;
+mod backup;
 mod cmd;
 mod device;
 mod devlinks;
 mod dm;
 mod engine;
+mod fsck;
 mod keys;
 mod liminal;
 mod metadata;
@@ -18,7 +20,16 @@ mod thinpool;
 mod udev;
 mod writing;
 
-pub use self::{engine::StratEngine, keys::StratKeyActions, metadata::BDA};
+pub use self::{
+    backup::{backup_pool_metadata, restore_pool_metadata},
+    engine::StratEngine,
+    fsck::{
+        dump_device, dump_filesystem_records, fsck_devices, fsck_pool, repair_devices, repair_pool,
+        FsckReport, Problem, Severity,
+    },
+    keys::StratKeyActions,
+    metadata::BDA,
+};
 
 #[cfg(test)]
 mod tests;
@@ -166,6 +166,48 @@ pub fn format_flex_ids(pool_uuid: PoolUuid, role: FlexRole) -> (DmNameBuf, DmUui
     )
 }
 
+/// Format a name for the temporary device through which an offline check
+/// reads the metadata volume of a pool that is not set up.
+///
+/// Prerequisite: len(format!("{}", FORMAT_VERSION)
+///             + len("stratis")                         7
+///             + len("private")                         7
+///             + len("fsck-mdv")                        8
+///             + num_dashes                             4
+///             + len(pool uuid)                         32
+///             < 128
+///
+/// which is equivalent to len(format!("{}", FORMAT_VERSION) < 70
+pub fn format_fsck_mdv_name(pool_uuid: PoolUuid) -> DmNameBuf {
+    let value = format!(
+        "stratis-{}-private-{}-fsck-mdv",
+        FORMAT_VERSION,
+        pool_uuid.to_simple_ref()
+    );
+    DmNameBuf::new(value).expect("FORMAT_VERSION display length < 70")
+}
+
+/// Format a name for the temporary device through which an offline check
+/// reads the thin pool metadata device of a pool that is not set up.
+///
+/// Prerequisite: len(format!("{}", FORMAT_VERSION)
+///             + len("stratis")                         7
+///             + len("private")                         7
+///             + len("fsck-thinmeta")                   13
+///             + num_dashes                             4
+///             + len(pool uuid)                         32
+///             < 128
+///
+/// which is equivalent to len(format!("{}", FORMAT_VERSION) < 65
+pub fn format_fsck_thin_meta_name(pool_uuid: PoolUuid) -> DmNameBuf {
+    let value = format!(
+        "stratis-{}-private-{}-fsck-thinmeta",
+        FORMAT_VERSION,
+        pool_uuid.to_simple_ref()
+    );
+    DmNameBuf::new(value).expect("FORMAT_VERSION display length < 65")
+}
+
 /// Format a name & uuid for the thin layer.
 ///
 /// Prerequisite: len(format!("{}", FORMAT_VERSION)
@@ -2,7 +2,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{collections::HashMap, path::Path, vec::Vec};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Duration,
+    vec::Vec,
+};
 
 use chrono::{DateTime, Utc};
 use serde_json::{Map, Value};
@@ -12,18 +17,25 @@ use devicemapper::{DmName, DmNameBuf, Sectors};
 use crate::{
     engine::{
         engine::{BlockDev, Filesystem, Pool},
-        shared::{init_cache_idempotent_or_err, validate_name, validate_paths},
+        shared::{
+            init_cache_idempotent_or_err, validate_cache_config, validate_name, validate_paths,
+            validate_space_thresholds,
+        },
         strat_engine::{
-            backstore::{Backstore, StratBlockDev},
+            backstore::{
+                check_in_place_encryption_space, wipe_blockdevs, Backstore, StratBlockDev,
+            },
+            backup::make_archive,
             metadata::MDADataSize,
-            names::KeyDescription,
             serde_structs::{FlexDevsSave, PoolSave, Recordable},
             thinpool::{ThinPool, ThinPoolSizeParams, DATA_BLOCK_SIZE},
         },
         types::{
-            BlockDevTier, Clevis, CreateAction, DeleteAction, DevUuid, EncryptionInfo,
-            FilesystemUuid, MaybeDbusPath, Name, PoolUuid, Redundancy, RenameAction,
-            SetCreateAction, SetDeleteAction,
+            BlockDevTier, CacheConfig, CacheMode, CacheStatistics, Clevis, CreateAction,
+            DeleteAction, DevUuid, EncryptionInfo, FilesystemUuid, Key, KeyDescription,
+            MaybeDbusPath, Name, PoolUuid, PropChangeAction, Redundancy, Reencryption,
+            RenameAction, SetCreateAction, SetDeleteAction, SpaceThresholds, ThinPoolStatusDigest,
+            ThinPoolUsage,
         },
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
@@ -35,8 +47,8 @@ use crate::{
 /// last segment for each is the highest. This allows avoiding sorting all the
 /// segments and just sorting the set consisting of the last segment from
 /// each list of segments.
-/// Precondition: This method is called only when setting up a pool, which
-/// ensures that the flex devs metadata lists are all non-empty.
+/// Precondition: The flex devs metadata lists are all non-empty, which
+/// check_metadata verifies before calling this method.
 fn next_index(flex_devs: &FlexDevsSave) -> Sectors {
     let expect_msg = "Setting up rather than initializing a pool, so each flex dev must have been allocated at least some segments.";
     [
@@ -64,10 +76,26 @@ fn next_index(flex_devs: &FlexDevsSave) -> Sectors {
 }
 
 /// Check the metadata of an individual pool for consistency.
-/// Precondition: This method is called only when setting up a pool, which
-/// ensures that the flex devs metadata lists are all non-empty.
-fn check_metadata(metadata: &PoolSave) -> StratisResult<()> {
+pub fn check_metadata(metadata: &PoolSave) -> StratisResult<()> {
     let flex_devs = &metadata.flex_devs;
+
+    // Metadata read from a device that is damaged or that was written
+    // before the pool was fully initialized may be missing some
+    // allocations altogether.
+    if flex_devs.meta_dev.is_empty()
+        || flex_devs.thin_meta_dev.is_empty()
+        || flex_devs.thin_data_dev.is_empty()
+        || flex_devs.thin_meta_dev_spare.is_empty()
+        || metadata.backstore.cap.allocs.is_empty()
+        || metadata.backstore.data_tier.blockdev.allocs.is_empty()
+    {
+        let err_msg = format!(
+            "some of the internal devices of pool {} have no segments allocated to them",
+            metadata.name
+        );
+        return Err(StratisError::Engine(ErrorEnum::Invalid, err_msg));
+    }
+
     let next = next_index(flex_devs);
     let allocated_from_cap = metadata.backstore.cap.allocs[0].1;
 
@@ -146,7 +174,7 @@ impl StratPool {
         name: &str,
         paths: &[&Path],
         redundancy: Redundancy,
-        key_desc: Option<&KeyDescription>,
+        encryption_info: Option<&EncryptionInfo>,
     ) -> StratisResult<(PoolUuid, StratPool)> {
         let pool_uuid = PoolUuid::new_v4();
 
@@ -154,7 +182,7 @@ impl StratPool {
         // enough. If there are enough devices specified, more space will be
         // required.
         let mut backstore =
-            Backstore::initialize(pool_uuid, paths, MDADataSize::default(), key_desc)?;
+            Backstore::initialize(pool_uuid, paths, MDADataSize::default(), encryption_info)?;
 
         let thinpool = ThinPool::new(
             pool_uuid,
@@ -212,6 +240,8 @@ impl StratPool {
             &metadata.thinpool_dev,
             &metadata.flex_devs,
             &backstore,
+            metadata.overprovisioning,
+            metadata.space_thresholds,
         )?;
 
         let changed = thinpool.check(uuid, &mut backstore)?;
@@ -239,16 +269,135 @@ impl StratPool {
     }
 
     /// Teardown a pool.
-    #[cfg(test)]
     pub fn teardown(&mut self) -> StratisResult<()> {
         self.thin_pool.teardown()?;
         self.backstore.teardown()
     }
 
+    /// Check that the data devices of the pool can be encrypted in place.
+    /// The pool is stopped while its devices are encrypted, so none of its
+    /// filesystems may be mounted. A cache is not supported with an
+    /// encrypted pool, so the pool must not have a cache.
+    pub fn check_encrypt_in_place(&self, pool_name: &str) -> StratisResult<()> {
+        if self.backstore.has_cache() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Pool {} has a cache; use of a cache is not supported with an \
+                    encrypted pool",
+                    pool_name
+                ),
+            ));
+        }
+        if self.thin_pool.has_mounted_filesystems()? {
+            return Err(StratisError::Engine(
+                ErrorEnum::Busy,
+                format!(
+                    "Pool {} has mounted filesystems; they must be unmounted before \
+                    the pool can be encrypted",
+                    pool_name
+                ),
+            ));
+        }
+
+        let dev_sizes = self
+            .backstore
+            .datadevs()
+            .into_iter()
+            .map(|(uuid, bd)| (uuid, bd.total_size().sectors()))
+            .collect::<HashMap<_, _>>();
+        check_in_place_encryption_space(&self.record(pool_name).backstore, &dev_sizes).map_err(
+            |err| {
+                if self.backstore.reserves_encryption_space() {
+                    StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "{}; add data devices to pool {} with enough free space to hold \
+                            the data at the end of its existing data devices",
+                            err, pool_name
+                        ),
+                    )
+                } else {
+                    StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "{}; reserve the space needed for in place encryption in pool {} first",
+                            err, pool_name
+                        ),
+                    )
+                }
+            },
+        )
+    }
+
     pub fn has_filesystems(&self) -> bool {
         self.thin_pool.has_filesystems()
     }
 
+    /// Move the data out of the space at the end of the data devices that
+    /// should be reserved for in place encryption, if the other data devices
+    /// have enough free space to hold it. As in remove_blockdevs(), the thin
+    /// pool remains suspended until the metadata records the new locations
+    /// of the data, and the move is undone if the metadata can not be
+    /// written.
+    fn relocate_encryption_space(&mut self, pool_name: &str) -> StratisResult<()> {
+        if !self.backstore.encryption_space_pending() {
+            return Ok(());
+        }
+
+        self.thin_pool.suspend()?;
+        let relocated_res = match self.backstore.relocate_encryption_space() {
+            Ok(Some(relocated)) => match self.write_metadata(pool_name) {
+                Ok(_) => Ok(Some(relocated)),
+                Err(err) => {
+                    if let Err(undo_err) = self.backstore.undo_relocation(relocated) {
+                        error!(
+                            "Failed to undo moving data in pool {} after the pool metadata could not be written; the pool's devices are not in the state that the metadata records: {}",
+                            pool_name, undo_err
+                        );
+                    }
+                    Err(err)
+                }
+            },
+            other => other,
+        };
+        self.thin_pool.resume()?;
+
+        if let Some(relocated) = relocated_res? {
+            self.backstore.finish_relocation(relocated)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the pool is degraded: its thin pool was found to be
+    /// in a state other than good when it was last checked, or some of its
+    /// data devices can not be found. Returns a description of the problem
+    /// if the pool is degraded.
+    fn degraded(&self) -> Option<String> {
+        match self.thin_pool.status_digest() {
+            None | Some(ThinPoolStatusDigest::Good) => (),
+            Some(status) => return Some(format!("its thin pool status is {}", status)),
+        }
+        self.backstore
+            .datadevs()
+            .into_iter()
+            .find(|(_, bd)| !bd.physical_path().exists() || !bd.metadata_path().exists())
+            .map(|(uuid, bd)| {
+                format!(
+                    "data device {} with UUID {} can not be found",
+                    bd.physical_path().display(),
+                    uuid.to_simple_ref()
+                )
+            })
+    }
+
+    /// Reencrypt the data devices of the pool for approximately the given
+    /// duration at most, if they are being reencrypted.
+    /// Returns true if some reencryption remains to be done.
+    pub fn reencrypt_step(&mut self, duration: Duration) -> bool {
+        self.backstore.reencrypt_step(duration)
+    }
+
     /// The names of DM devices belonging to this pool that may generate events
     pub fn get_eventing_dev_names(&self, pool_uuid: PoolUuid) -> Vec<DmNameBuf> {
         self.thin_pool.get_eventing_dev_names(pool_uuid)
@@ -280,6 +429,8 @@ impl StratPool {
             backstore: self.backstore.record(),
             flex_devs: self.thin_pool.record(),
             thinpool_dev: self.thin_pool.record(),
+            overprovisioning: self.thin_pool.overprov_enabled(),
+            space_thresholds: self.thin_pool.space_thresholds(),
         }
     }
 
@@ -298,6 +449,45 @@ impl StratPool {
         self.backstore.get_mut_blockdev_by_uuid(uuid)
     }
 
+    /// Rebuild the cache, whose meta sub-device has been extended, and
+    /// switch the thin pool to it.
+    ///
+    /// If the cache can not be made, the cache tier has been removed along
+    /// with it. The metadata is then written and the cachedevs are wiped,
+    /// and an error is returned.
+    ///
+    /// Precondition: the thin pool has been switched to the origin
+    /// sub-device returned by Backstore::add_cachedevs().
+    fn rebuild_cache(&mut self, pool_uuid: PoolUuid, pool_name: &str) -> StratisResult<()> {
+        let mut removed = self.backstore.rebuild_cache(pool_uuid)?;
+        if removed.is_empty() {
+            self.thin_pool.suspend()?;
+            let switch_res = self.thin_pool.set_device(self.backstore.device().expect(
+                "Since thin pool exists, space must have been allocated \
+                 from the backstore, so backstore must have a cap device",
+            ));
+            self.thin_pool.resume()?;
+            return switch_res;
+        }
+
+        self.write_metadata(pool_name)?;
+        let wipe_res = wipe_blockdevs(&mut removed);
+        Err(StratisError::Engine(
+            ErrorEnum::Error,
+            format!(
+                "The cache of pool with UUID {} could not be rebuilt after cachedevs were added to it, so the cache and all its cachedevs were removed{}",
+                pool_uuid.to_simple_ref(),
+                match wipe_res {
+                    Ok(_) => String::new(),
+                    Err(err) => format!(
+                        "; some cachedevs could not be wiped, they must be wiped before the pool is next set up: {}",
+                        err
+                    ),
+                }
+            ),
+        ))
+    }
+
     /// Destroy the pool.
     /// Precondition: All filesystems belonging to this pool must be
     /// unmounted.
@@ -326,6 +516,15 @@ impl<'a> Into<Value> for &'a StratPool {
                 unreachable!("Backstore conversion returns a JSON object")
             },
         );
+        match self.backstore.cache_statistics() {
+            Ok(Some(statistics)) => {
+                map.insert("cache_statistics".to_string(), (&statistics).into());
+            }
+            Ok(None) => (),
+            Err(err) => {
+                warn!("Failed to obtain cache statistics for report: {}", err);
+            }
+        }
         Value::from(map)
     }
 }
@@ -336,8 +535,10 @@ impl Pool for StratPool {
         pool_uuid: PoolUuid,
         pool_name: &str,
         blockdevs: &[&Path],
+        cache_config: CacheConfig,
     ) -> StratisResult<SetCreateAction<DevUuid>> {
         validate_paths(blockdevs)?;
+        validate_cache_config(&cache_config)?;
 
         if self.is_encrypted() {
             return Err(StratisError::Engine(
@@ -356,7 +557,9 @@ impl Pool for StratPool {
             // If adding cache devices, must suspend the pool, since the cache
             // must be augmented with the new devices.
             self.thin_pool.suspend()?;
-            let devices_result = self.backstore.init_cache(pool_uuid, blockdevs);
+            let devices_result = self
+                .backstore
+                .init_cache(pool_uuid, blockdevs, cache_config);
             self.thin_pool.resume()?;
             let devices = devices_result?;
             self.write_metadata(pool_name)?;
@@ -372,34 +575,178 @@ impl Pool for StratPool {
         }
     }
 
+    fn destroy_cache(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+    ) -> StratisResult<SetDeleteAction<DevUuid>> {
+        let uuids = self
+            .backstore
+            .cachedevs()
+            .iter()
+            .map(|&(uuid, _)| uuid)
+            .collect::<Vec<_>>();
+        self.remove_cachedevs(pool_uuid, pool_name, &uuids)
+    }
+
+    fn remove_cachedevs(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+        uuids: &[DevUuid],
+    ) -> StratisResult<SetDeleteAction<DevUuid>> {
+        let mut to_remove = HashSet::new();
+        for &uuid in uuids {
+            match self.backstore.get_blockdev_by_uuid(uuid) {
+                Some((BlockDevTier::Cache, _)) => {
+                    to_remove.insert(uuid);
+                }
+                Some((BlockDevTier::Data, _)) => {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "Blockdev with UUID {} belongs to the data tier of pool with UUID {}; use remove_blockdevs to remove it",
+                            uuid.to_simple_ref(),
+                            pool_uuid.to_simple_ref()
+                        ),
+                    ));
+                }
+                None => (),
+            }
+        }
+
+        if to_remove.is_empty() {
+            return Ok(SetDeleteAction::new(vec![]));
+        }
+        let to_remove = to_remove.into_iter().collect::<Vec<_>>();
+
+        // Once the cache is clean, the thin pool is switched to the origin
+        // sub-device, so that the cache device can be torn down.
+        self.thin_pool.suspend()?;
+        let switch_res = self
+            .backstore
+            .flush_cache()
+            .and_then(|origin| self.thin_pool.set_device(origin));
+        self.thin_pool.resume()?;
+        switch_res?;
+
+        let mut removed = self.backstore.remove_cachedevs(pool_uuid, &to_remove)?;
+
+        // If a new cache was made from the remaining cachedevs, the thin pool
+        // must be switched to it.
+        if self.backstore.has_cache() {
+            self.thin_pool.suspend()?;
+            let switch_res = self.thin_pool.set_device(self.backstore.device().expect(
+                "Since thin pool exists, space must have been allocated \
+                 from the backstore, so backstore must have a cap device",
+            ));
+            self.thin_pool.resume()?;
+            switch_res?;
+        }
+
+        self.write_metadata(pool_name)?;
+
+        // A removed cachedev still carrying the pool's signature would be
+        // taken for a member of the pool when the pool is next set up.
+        wipe_blockdevs(&mut removed).map_err(|err| {
+            StratisError::Engine(
+                ErrorEnum::Error,
+                format!(
+                    "Cachedevs were removed from pool with UUID {} but some could not be wiped; they must be wiped before the pool is next set up: {}",
+                    pool_uuid.to_simple_ref(),
+                    err
+                ),
+            )
+        })?;
+
+        Ok(SetDeleteAction::new(
+            removed.iter().map(|bd| bd.uuid()).collect(),
+        ))
+    }
+
     fn bind_clevis(
         &mut self,
         pin: String,
         clevis_info: Value,
     ) -> StratisResult<CreateAction<Clevis>> {
-        let changed = self.backstore.bind_clevis(pin, clevis_info)?;
+        match self.backstore.bind_clevis(pin, clevis_info)? {
+            Some(id) => Ok(CreateAction::Created(Clevis(id))),
+            None => Ok(CreateAction::Identity),
+        }
+    }
+
+    fn unbind_clevis(&mut self, binding_id: u32) -> StratisResult<DeleteAction<Clevis>> {
+        let changed = self.backstore.unbind_clevis(binding_id)?;
         if changed {
-            Ok(CreateAction::Created(Clevis))
+            Ok(DeleteAction::Deleted(Clevis(binding_id)))
         } else {
-            Ok(CreateAction::Identity)
+            Ok(DeleteAction::Identity)
         }
     }
 
-    fn unbind_clevis(&mut self) -> StratisResult<DeleteAction<Clevis>> {
-        let changed = self.backstore.unbind_clevis()?;
+    fn rebind_clevis(&mut self, binding_id: u32) -> StratisResult<RenameAction<Clevis>> {
+        let rebound = self.backstore.rebind_clevis(binding_id)?;
+        if rebound {
+            Ok(RenameAction::Renamed(Clevis(binding_id)))
+        } else {
+            Ok(RenameAction::NoSource)
+        }
+    }
+
+    fn rebind_keyring(
+        &mut self,
+        new_key_desc: &KeyDescription,
+    ) -> StratisResult<RenameAction<Key>> {
+        let changed = self.backstore.rebind_keyring(new_key_desc)?;
         if changed {
-            Ok(DeleteAction::Deleted(Clevis))
+            Ok(RenameAction::Renamed(Key))
         } else {
-            Ok(DeleteAction::Identity)
+            Ok(RenameAction::Identity)
+        }
+    }
+
+    fn set_auto_unlock(&mut self, auto_unlock: bool) -> StratisResult<PropChangeAction<bool>> {
+        if self.backstore.set_auto_unlock(auto_unlock)? {
+            Ok(PropChangeAction::NewValue(auto_unlock))
+        } else {
+            Ok(PropChangeAction::Identity)
+        }
+    }
+
+    fn reencrypt(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+    ) -> StratisResult<CreateAction<Reencryption>> {
+        if let Some(problem) = self.degraded() {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Pool {} with UUID {} is degraded: {}; it can not be reencrypted",
+                    pool_name,
+                    pool_uuid.to_simple_ref(),
+                    problem
+                ),
+            ));
+        }
+
+        let changed = self.backstore.start_reencryption()?;
+        if changed {
+            Ok(CreateAction::Created(Reencryption))
+        } else {
+            Ok(CreateAction::Identity)
         }
     }
 
     fn create_filesystems<'a, 'b>(
         &'a mut self,
         pool_uuid: PoolUuid,
-        specs: &[(&'b str, Option<Sectors>)],
+        specs: &[(&'b str, Option<Sectors>, Option<Sectors>)],
     ) -> StratisResult<SetCreateAction<(&'b str, FilesystemUuid)>> {
-        let names: HashMap<_, _> = specs.iter().map(|&tup| (tup.0, tup.1)).collect();
+        let names: HashMap<_, _> = specs
+            .iter()
+            .map(|&(name, size, size_limit)| (name, (size, size_limit)))
+            .collect();
 
         names.iter().fold(Ok(()), |res, (name, _)| {
             res.and_then(|()| validate_name(name))
@@ -407,9 +754,11 @@ impl Pool for StratPool {
 
         // TODO: Roll back on filesystem initialization failure.
         let mut result = Vec::new();
-        for (name, size) in names {
+        for (name, (size, size_limit)) in names {
             if self.thin_pool.get_mut_filesystem_by_name(name).is_none() {
-                let fs_uuid = self.thin_pool.create_filesystem(pool_uuid, name, size)?;
+                let fs_uuid = self
+                    .thin_pool
+                    .create_filesystem(pool_uuid, name, size, size_limit)?;
                 result.push((name, fs_uuid));
             }
         }
@@ -437,26 +786,42 @@ impl Pool for StratPool {
             return Ok(SetCreateAction::new(vec![]));
         } else if tier == BlockDevTier::Cache {
             // If adding cache devices, must suspend the pool; the cache
-            // must be augmented with the new devices.
+            // must be augmented with the new devices. If the cache must be
+            // rebuilt, the thin pool is switched to the origin sub-device
+            // once the cache is clean, so that the cache can be torn down.
             self.thin_pool.suspend()?;
-            let bdev_info_res = self
-                .backstore
-                .add_cachedevs(pool_uuid, paths)
-                .and_then(|bdi| {
-                    self.thin_pool
-                        .set_device(self.backstore.device().expect(
-                            "Since thin pool exists, space must have been allocated \
+            let add_res =
+                self.backstore
+                    .add_cachedevs(pool_uuid, paths)
+                    .and_then(|(bdi, origin)| {
+                        let device = origin.unwrap_or_else(|| {
+                            self.backstore.device().expect(
+                                "Since thin pool exists, space must have been allocated \
                              from the backstore, so backstore must have a cap device",
-                        ))
-                        .and(Ok(bdi))
-                });
+                            )
+                        });
+                        self.thin_pool
+                            .set_device(device)
+                            .and(Ok((bdi, origin.is_some())))
+                    });
             self.thin_pool.resume()?;
-            let bdev_info = bdev_info_res?;
-            Ok(SetCreateAction::new(bdev_info))
+            add_res.and_then(|(bdi, rebuild)| {
+                if rebuild {
+                    self.rebuild_cache(pool_uuid, pool_name)?;
+                }
+                Ok(SetCreateAction::new(bdi))
+            })
         } else {
-            // If just adding data devices, no need to suspend the pool.
-            // No action will be taken on the DM devices.
+            // If just adding data devices, no need to suspend the pool,
+            // unless data must be moved onto the new devices out of the
+            // space reserved for in place encryption.
             let bdev_info = self.backstore.add_datadevs(pool_uuid, paths)?;
+            if let Err(err) = self.relocate_encryption_space(pool_name) {
+                warn!(
+                    "Failed to move data out of the space reserved for in place encryption in pool {}: {}",
+                    pool_name, err
+                );
+            }
 
             // Adding data devices does not change the state of the thin
             // pool at all. However, if the thin pool is in a state
@@ -467,12 +832,90 @@ impl Pool for StratPool {
             self.thin_pool.check(pool_uuid, &mut self.backstore)?;
             Ok(SetCreateAction::new(bdev_info))
         };
+        // The metadata is written even if adding cachedevs failed, since
+        // the cache tier may have changed.
         self.write_metadata(pool_name)?;
         bdev_info
     }
 
+    fn remove_blockdevs(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+        uuids: &[DevUuid],
+    ) -> StratisResult<SetDeleteAction<DevUuid>> {
+        let mut to_remove = Vec::new();
+        for &uuid in uuids {
+            match self.backstore.get_blockdev_by_uuid(uuid) {
+                Some((BlockDevTier::Data, _)) => to_remove.push(uuid),
+                Some((BlockDevTier::Cache, _)) => {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        format!(
+                            "Blockdev with UUID {} belongs to the cache tier of pool with UUID {}; only data tier blockdevs can be removed",
+                            uuid.to_simple_ref(),
+                            pool_uuid.to_simple_ref()
+                        ),
+                    ));
+                }
+                None => (),
+            }
+        }
+
+        if to_remove.is_empty() {
+            return Ok(SetDeleteAction::new(vec![]));
+        }
+
+        // The thin pool must be suspended while its data is being moved.
+        // It remains suspended until the metadata has been updated, so that
+        // nothing is written to the new locations of the data unless the
+        // metadata records them. If the metadata can not be updated, the
+        // removal is undone while the data is still at its original
+        // locations as well.
+        self.thin_pool.suspend()?;
+        let removed_res = match self.backstore.remove_datadevs(&to_remove) {
+            Ok(removed) => match self.write_metadata(pool_name) {
+                Ok(_) => Ok(removed),
+                Err(err) => {
+                    if let Err(undo_err) = self.backstore.undo_remove_datadevs(removed) {
+                        error!(
+                            "Failed to undo the removal of blockdevs from pool with UUID {} after the pool metadata could not be written; the pool's devices are not in the state that the metadata records: {}",
+                            pool_uuid.to_simple_ref(),
+                            undo_err
+                        );
+                    }
+                    Err(err)
+                }
+            },
+            Err(err) => Err(err),
+        };
+        self.thin_pool.resume()?;
+        let mut removed = removed_res?.blockdevs;
+
+        // A removed blockdev still carrying the pool's signature would be
+        // taken for a member of the pool when the pool is next set up.
+        wipe_blockdevs(&mut removed).map_err(|err| {
+            StratisError::Engine(
+                ErrorEnum::Error,
+                format!(
+                    "Blockdevs were removed from pool with UUID {} but some could not be wiped; they must be wiped before the pool is next set up: {}",
+                    pool_uuid.to_simple_ref(),
+                    err
+                ),
+            )
+        })?;
+
+        self.thin_pool
+            .update_space_alert_level(pool_uuid, &self.backstore)?;
+
+        Ok(SetDeleteAction::new(
+            removed.iter().map(|bd| bd.uuid()).collect(),
+        ))
+    }
+
     fn destroy_filesystems<'a>(
         &'a mut self,
+        pool_uuid: PoolUuid,
         pool_name: &str,
         fs_uuids: &[FilesystemUuid],
     ) -> StratisResult<SetDeleteAction<FilesystemUuid>> {
@@ -483,6 +926,11 @@ impl Pool for StratPool {
             }
         }
 
+        if !removed.is_empty() {
+            self.thin_pool
+                .update_space_alert_level(pool_uuid, &self.backstore)?;
+        }
+
         Ok(SetDeleteAction::new(removed))
     }
 
@@ -506,6 +954,93 @@ impl Pool for StratPool {
         }
     }
 
+    fn set_filesystem_size(
+        &mut self,
+        uuid: FilesystemUuid,
+        new_size: Sectors,
+    ) -> StratisResult<PropChangeAction<Sectors>> {
+        if self.thin_pool.set_filesystem_size(uuid, new_size)? {
+            Ok(PropChangeAction::NewValue(new_size))
+        } else {
+            Ok(PropChangeAction::Identity)
+        }
+    }
+
+    fn overprov_enabled(&self) -> bool {
+        self.thin_pool.overprov_enabled()
+    }
+
+    fn set_overprov_mode(
+        &mut self,
+        pool_name: &str,
+        enabled: bool,
+    ) -> StratisResult<PropChangeAction<bool>> {
+        if self.thin_pool.set_overprov_mode(enabled)? {
+            self.write_metadata(pool_name)?;
+            Ok(PropChangeAction::NewValue(enabled))
+        } else {
+            Ok(PropChangeAction::Identity)
+        }
+    }
+
+    fn encryption_space_reserved(&self) -> bool {
+        self.backstore.reserves_encryption_space()
+    }
+
+    fn set_encryption_space_reserved(
+        &mut self,
+        pool_name: &str,
+        reserved: bool,
+    ) -> StratisResult<PropChangeAction<bool>> {
+        if self.backstore.reserves_encryption_space() == reserved {
+            return Ok(PropChangeAction::Identity);
+        }
+
+        self.backstore.set_reserve_encryption_space(reserved)?;
+        if let Err(err) = self.relocate_encryption_space(pool_name) {
+            info!(
+                "The data at the end of some data devices in pool {} could not be moved yet; it will be moved when data devices are added to the pool: {}",
+                pool_name, err
+            );
+        }
+        self.write_metadata(pool_name)?;
+        Ok(PropChangeAction::NewValue(reserved))
+    }
+
+    fn space_thresholds(&self) -> SpaceThresholds {
+        self.thin_pool.space_thresholds()
+    }
+
+    fn set_space_thresholds(
+        &mut self,
+        pool_uuid: PoolUuid,
+        pool_name: &str,
+        thresholds: SpaceThresholds,
+    ) -> StratisResult<PropChangeAction<SpaceThresholds>> {
+        validate_space_thresholds(thresholds)?;
+
+        if self.thin_pool.set_space_thresholds(thresholds) {
+            self.thin_pool.check(pool_uuid, &mut self.backstore)?;
+            self.write_metadata(pool_name)?;
+            Ok(PropChangeAction::NewValue(thresholds))
+        } else {
+            Ok(PropChangeAction::Identity)
+        }
+    }
+
+    fn set_cache_mode(
+        &mut self,
+        pool_name: &str,
+        mode: CacheMode,
+    ) -> StratisResult<PropChangeAction<CacheMode>> {
+        if self.backstore.set_cache_mode(mode)? {
+            self.write_metadata(pool_name)?;
+            Ok(PropChangeAction::NewValue(mode))
+        } else {
+            Ok(PropChangeAction::Identity)
+        }
+    }
+
     fn snapshot_filesystem(
         &mut self,
         pool_uuid: PoolUuid,
@@ -543,6 +1078,14 @@ impl Pool for StratPool {
             .map(|v| v + self.backstore.datatier_metadata_size())
     }
 
+    fn thin_pool_usage(&self) -> StratisResult<ThinPoolUsage> {
+        self.thin_pool.usage()
+    }
+
+    fn thin_pool_status(&self) -> Option<ThinPoolStatusDigest> {
+        self.thin_pool.status_digest()
+    }
+
     fn filesystems(&self) -> Vec<(Name, FilesystemUuid, &dyn Filesystem)> {
         self.thin_pool.filesystems()
     }
@@ -619,6 +1162,10 @@ impl Pool for StratPool {
         self.backstore.has_cache()
     }
 
+    fn cache_statistics(&self) -> StratisResult<Option<CacheStatistics>> {
+        self.backstore.cache_statistics()
+    }
+
     fn is_encrypted(&self) -> bool {
         self.datadevs_encrypted()
     }
@@ -626,6 +1173,19 @@ impl Pool for StratPool {
     fn encryption_info(&self) -> Option<&EncryptionInfo> {
         self.backstore.data_tier_encryption_info()
     }
+
+    fn backup_metadata(&self, pool_uuid: PoolUuid, pool_name: &str) -> StratisResult<Value> {
+        let blockdevs = self.backstore.blockdevs();
+        make_archive(
+            pool_uuid,
+            &blockdevs
+                .iter()
+                .map(|(_, _, bd)| (bd.metadata_path(), bd.header()))
+                .collect::<Vec<_>>(),
+            self.record(pool_name),
+            self.thin_pool.filesystem_records()?,
+        )
+    }
 }
 
 #[cfg(test)]
@@ -640,7 +1200,10 @@ mod tests {
     use devicemapper::{Bytes, ThinPoolStatus, ThinPoolStatusSummary, IEC, SECTOR_SIZE};
 
     use crate::engine::{
-        strat_engine::tests::{loopbacked, real},
+        strat_engine::{
+            metadata::device_identifiers,
+            tests::{loopbacked, real},
+        },
         types::{EngineAction, Redundancy},
     };
 
@@ -692,7 +1255,7 @@ mod tests {
         assert_matches!(metadata1.backstore.cache_tier, None);
 
         let (_, fs_uuid) = pool
-            .create_filesystems(uuid, &[("stratis-filesystem", None)])
+            .create_filesystems(uuid, &[("stratis-filesystem", None, None)])
             .unwrap()
             .changed()
             .and_then(|mut fs| fs.pop())
@@ -724,7 +1287,8 @@ mod tests {
                 .unwrap();
         }
 
-        pool.init_cache(uuid, name, paths1).unwrap();
+        pool.init_cache(uuid, name, paths1, CacheConfig::default())
+            .unwrap();
         invariant(&pool, name);
 
         let metadata2 = pool.record(name);
@@ -760,6 +1324,103 @@ mod tests {
         );
     }
 
+    /// Verify that adding cachedevs to an existing cache extends the meta
+    /// sub-device and that the data written before the cache was rebuilt
+    /// can be read afterwards.
+    fn test_grow_cache(paths: &[&Path]) {
+        assert!(paths.len() > 2);
+
+        let (initcachepaths, paths) = paths.split_at(1);
+        let (cachedevpaths, datapaths) = paths.split_at(1);
+
+        let name = "stratis-test-pool";
+        let (uuid, mut pool) =
+            StratPool::initialize(name, datapaths, Redundancy::NONE, None).unwrap();
+        invariant(&pool, name);
+
+        pool.init_cache(uuid, name, initcachepaths, CacheConfig::default())
+            .unwrap();
+        invariant(&pool, name);
+
+        let meta_size = |pool: &StratPool| {
+            pool.record(name)
+                .backstore
+                .cache_tier
+                .unwrap()
+                .blockdev
+                .allocs[1]
+                .iter()
+                .map(|seg| seg.length)
+                .sum::<Sectors>()
+        };
+        let initial_meta_size = meta_size(&pool);
+
+        let (_, fs_uuid) = pool
+            .create_filesystems(uuid, &[("stratis-filesystem", None, None)])
+            .unwrap()
+            .changed()
+            .and_then(|mut fs| fs.pop())
+            .unwrap();
+        invariant(&pool, name);
+
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("stratis_testing")
+            .tempdir()
+            .unwrap();
+        let new_file = tmp_dir.path().join("stratis_test.txt");
+        let bytestring = b"some bytes";
+        {
+            let (_, fs) = pool.get_filesystem(fs_uuid).unwrap();
+            mount(
+                Some(&fs.devnode()),
+                tmp_dir.path(),
+                Some("xfs"),
+                MsFlags::empty(),
+                None as Option<&str>,
+            )
+            .unwrap();
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(&new_file)
+                .unwrap()
+                .write_all(bytestring)
+                .unwrap();
+        }
+
+        pool.add_blockdevs(uuid, name, cachedevpaths, BlockDevTier::Cache)
+            .unwrap();
+        invariant(&pool, name);
+
+        assert!(meta_size(&pool) > initial_meta_size);
+
+        let mut buf = [0u8; 10];
+        {
+            OpenOptions::new()
+                .read(true)
+                .open(&new_file)
+                .unwrap()
+                .read_exact(&mut buf)
+                .unwrap();
+        }
+        assert_eq!(&buf, bytestring);
+        umount(tmp_dir.path()).unwrap();
+        pool.teardown().unwrap();
+    }
+
+    #[test]
+    fn loop_test_grow_cache() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(3, 4, None),
+            test_grow_cache,
+        );
+    }
+
+    #[test]
+    fn real_test_grow_cache() {
+        real::test_with_spec(&real::DeviceLimits::AtLeast(3, None, None), test_grow_cache);
+    }
+
     /// Verify that adding additional blockdevs will cause a pool that is
     /// out of space to be extended.
     fn test_add_datadevs(paths: &[&Path]) {
@@ -774,7 +1435,7 @@ mod tests {
 
         let fs_name = "stratis_test_filesystem";
         let (_, fs_uuid) = pool
-            .create_filesystems(pool_uuid, &[(fs_name, None)])
+            .create_filesystems(pool_uuid, &[(fs_name, None, None)])
             .unwrap()
             .changed()
             .and_then(|mut fs| fs.pop())
@@ -842,4 +1503,243 @@ mod tests {
             test_add_datadevs,
         );
     }
+
+    /// Verify that removing a data device moves the data on it to the
+    /// remaining devices. Verify that data written before the device was
+    /// removed can be read afterwards, that the pool metadata no longer
+    /// refers to the removed device, and that the removed device no longer
+    /// carries a Stratis header.
+    fn test_remove_datadevs(paths: &[&Path]) {
+        assert!(paths.len() > 1);
+
+        let (paths1, paths2) = paths.split_at(1);
+
+        let name = "stratis-test-pool";
+        let (uuid, mut pool) = StratPool::initialize(name, paths1, Redundancy::NONE, None).unwrap();
+        invariant(&pool, name);
+
+        let removed_uuid = pool.backstore.datadevs()[0].0;
+
+        pool.add_blockdevs(uuid, name, paths2, BlockDevTier::Data)
+            .unwrap();
+        invariant(&pool, name);
+
+        let (_, fs_uuid) = pool
+            .create_filesystems(uuid, &[("stratis-filesystem", None, None)])
+            .unwrap()
+            .changed()
+            .and_then(|mut fs| fs.pop())
+            .unwrap();
+        invariant(&pool, name);
+
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("stratis_testing")
+            .tempdir()
+            .unwrap();
+        let new_file = tmp_dir.path().join("stratis_test.txt");
+        let bytestring = b"some bytes";
+        let devnode = pool.get_filesystem(fs_uuid).unwrap().1.devnode();
+        mount(
+            Some(&devnode),
+            tmp_dir.path(),
+            Some("xfs"),
+            MsFlags::empty(),
+            None as Option<&str>,
+        )
+        .unwrap();
+        {
+            let mut f = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(&new_file)
+                .unwrap();
+            f.write_all(bytestring).unwrap();
+            f.sync_all().unwrap();
+        }
+        umount(tmp_dir.path()).unwrap();
+
+        assert_matches!(
+            pool.remove_blockdevs(uuid, name, &[removed_uuid])
+                .unwrap()
+                .changed(),
+            Some(ref uuids) if uuids == &[removed_uuid]
+        );
+        invariant(&pool, name);
+
+        assert!(pool.get_blockdev(removed_uuid).is_none());
+        assert!(pool
+            .record(name)
+            .backstore
+            .data_tier
+            .blockdev
+            .devs
+            .iter()
+            .all(|dev| dev.uuid != removed_uuid));
+        assert_eq!(
+            device_identifiers(&mut OpenOptions::new().read(true).open(paths1[0]).unwrap())
+                .unwrap(),
+            None
+        );
+
+        let mut buf = [0u8; 10];
+        mount(
+            Some(&devnode),
+            tmp_dir.path(),
+            Some("xfs"),
+            MsFlags::empty(),
+            None as Option<&str>,
+        )
+        .unwrap();
+        {
+            OpenOptions::new()
+                .read(true)
+                .open(&new_file)
+                .unwrap()
+                .read_exact(&mut buf)
+                .unwrap();
+        }
+        assert_eq!(&buf, bytestring);
+        umount(tmp_dir.path()).unwrap();
+        pool.teardown().unwrap();
+    }
+
+    #[test]
+    fn loop_test_remove_datadevs() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_remove_datadevs,
+        );
+    }
+
+    #[test]
+    fn real_test_remove_datadevs() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(2, None, None),
+            test_remove_datadevs,
+        );
+    }
+
+    /// Verify that cachedevs can be removed from a pool's cache and that the
+    /// cache can be destroyed. Verify that data written while the cache
+    /// was in use can be read afterwards and that the metadata reflects the
+    /// state of the cache.
+    fn test_remove_cachedevs(paths: &[&Path]) {
+        assert!(paths.len() > 2);
+
+        let (paths1, paths2) = paths.split_at(1);
+
+        let name = "stratis-test-pool";
+        let (uuid, mut pool) = StratPool::initialize(name, paths1, Redundancy::NONE, None).unwrap();
+        invariant(&pool, name);
+
+        let (_, fs_uuid) = pool
+            .create_filesystems(uuid, &[("stratis-filesystem", None, None)])
+            .unwrap()
+            .changed()
+            .and_then(|mut fs| fs.pop())
+            .unwrap();
+
+        let cache_uuids = pool
+            .init_cache(uuid, name, paths2, CacheConfig::default())
+            .unwrap()
+            .changed()
+            .unwrap();
+        invariant(&pool, name);
+
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("stratis_testing")
+            .tempdir()
+            .unwrap();
+        let new_file = tmp_dir.path().join("stratis_test.txt");
+        let bytestring = b"some bytes";
+        let devnode = pool.get_filesystem(fs_uuid).unwrap().1.devnode();
+        mount(
+            Some(&devnode),
+            tmp_dir.path(),
+            Some("xfs"),
+            MsFlags::empty(),
+            None as Option<&str>,
+        )
+        .unwrap();
+        {
+            let mut f = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(&new_file)
+                .unwrap();
+            f.write_all(bytestring).unwrap();
+            f.sync_all().unwrap();
+        }
+
+        assert_eq!(
+            pool.remove_cachedevs(uuid, name, &cache_uuids[..1])
+                .unwrap()
+                .changed(),
+            Some(vec![cache_uuids[0]])
+        );
+        invariant(&pool, name);
+        assert!(pool.has_cache());
+        assert_eq!(
+            pool.record(name)
+                .backstore
+                .cache_tier
+                .map(|tier| tier.blockdev.devs.len()),
+            Some(cache_uuids.len() - 1)
+        );
+
+        assert_eq!(
+            pool.destroy_cache(uuid, name)
+                .unwrap()
+                .changed()
+                .map(|uuids| uuids.into_iter().collect::<HashSet<_>>()),
+            Some(cache_uuids[1..].iter().cloned().collect::<HashSet<_>>())
+        );
+        invariant(&pool, name);
+        assert!(!pool.has_cache());
+        assert_matches!(pool.record(name).backstore.cache_tier, None);
+        for path in paths2 {
+            assert_eq!(
+                device_identifiers(&mut OpenOptions::new().read(true).open(path).unwrap()).unwrap(),
+                None
+            );
+        }
+
+        umount(tmp_dir.path()).unwrap();
+        mount(
+            Some(&devnode),
+            tmp_dir.path(),
+            Some("xfs"),
+            MsFlags::empty(),
+            None as Option<&str>,
+        )
+        .unwrap();
+        let mut buf = [0u8; 10];
+        {
+            OpenOptions::new()
+                .read(true)
+                .open(&new_file)
+                .unwrap()
+                .read_exact(&mut buf)
+                .unwrap();
+        }
+        assert_eq!(&buf, bytestring);
+        umount(tmp_dir.path()).unwrap();
+        pool.teardown().unwrap();
+    }
+
+    #[test]
+    fn loop_test_remove_cachedevs() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(3, 4, None),
+            test_remove_cachedevs,
+        );
+    }
+
+    #[test]
+    fn real_test_remove_cachedevs() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(3, None, None),
+            test_remove_cachedevs,
+        );
+    }
 }
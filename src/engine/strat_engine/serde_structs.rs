@@ -12,11 +12,16 @@
 // can convert to or from them when saving our current state, or
 // restoring state from saved metadata.
 
+use std::collections::HashMap;
+
 use serde::Serialize;
 
 use devicemapper::{Sectors, ThinDevId};
 
-use crate::engine::types::{DevUuid, FilesystemUuid};
+use crate::engine::types::{
+    CacheMode, DevUuid, FilesystemUuid, SpaceThresholds, DEFAULT_CACHE_BLOCK_SIZE,
+    DEFAULT_CACHE_POLICY,
+};
 
 /// Implements saving struct data to a serializable form. The form should be
 /// sufficient, in conjunction with the environment, to reconstruct the
@@ -35,6 +40,16 @@ pub struct PoolSave {
     pub backstore: BackstoreSave,
     pub flex_devs: FlexDevsSave,
     pub thinpool_dev: ThinPoolDevSave,
+    // Pools saved before overprovisioning could be disabled allowed it.
+    #[serde(default = "default_overprovisioning")]
+    pub overprovisioning: bool,
+    // Pools saved before space thresholds could be set used the defaults.
+    #[serde(default)]
+    pub space_thresholds: SpaceThresholds,
+}
+
+fn default_overprovisioning() -> bool {
+    true
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -48,6 +63,10 @@ pub struct BackstoreSave {
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct DataTierSave {
     pub blockdev: BlockDevSave,
+    // Pools saved before the space for in place encryption could be
+    // reserved did not reserve it.
+    #[serde(default)]
+    pub reserve_encryption_space: bool,
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -80,6 +99,24 @@ pub struct CapSave {
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct CacheTierSave {
     pub blockdev: BlockDevSave,
+    // Caches saved before their parameters could be chosen used the
+    // defaults.
+    #[serde(default)]
+    pub mode: CacheMode,
+    #[serde(default = "default_cache_policy")]
+    pub policy: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub policy_args: HashMap<String, String>,
+    #[serde(default = "default_cache_block_size")]
+    pub block_size: Sectors,
+}
+
+fn default_cache_policy() -> String {
+    DEFAULT_CACHE_POLICY.to_string()
+}
+
+fn default_cache_block_size() -> Sectors {
+    DEFAULT_CACHE_BLOCK_SIZE
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -105,4 +142,6 @@ pub struct FilesystemSave {
     pub thin_id: ThinDevId,
     pub size: Sectors,
     pub created: u64, // Unix timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_limit: Option<Sectors>,
 }
@@ -5,6 +5,7 @@
 use chrono::{DateTime, TimeZone, Utc};
 
 use std::{
+    cmp::min,
     fs::File,
     io::Read,
     path::{Path, PathBuf},
@@ -37,7 +38,7 @@ use crate::{
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
 
-const DEFAULT_THIN_DEV_SIZE: Sectors = Sectors(2 * IEC::Gi); // 1 TiB
+pub const DEFAULT_THIN_DEV_SIZE: Sectors = Sectors(2 * IEC::Gi); // 1 TiB
 
 const TEMP_MNT_POINT_PREFIX: &str = "stratis_mp_";
 
@@ -49,27 +50,43 @@ pub const FILESYSTEM_LOWATER: Sectors = Sectors(4 * (DATA_LOWATER.0 * DATA_BLOCK
 pub struct StratFilesystem {
     thin_dev: ThinDev,
     created: DateTime<Utc>,
+    size_limit: Option<Sectors>,
     dbus_path: MaybeDbusPath,
 }
 
 impl StratFilesystem {
     /// Create a StratFilesystem on top of the given ThinDev.
+    /// If size_limit is specified, the filesystem will never be extended
+    /// beyond it. If size is not specified, the default size is used, unless
+    /// it exceeds size_limit, in which case size_limit is used.
+    ///
+    /// Returns an error if size exceeds size_limit.
     pub fn initialize(
         pool_uuid: PoolUuid,
         thinpool_dev: &ThinPoolDev,
         size: Option<Sectors>,
+        size_limit: Option<Sectors>,
         id: ThinDevId,
     ) -> StratisResult<(FilesystemUuid, StratFilesystem)> {
+        let size = match (size, size_limit) {
+            (Some(size), Some(limit)) if size > limit => {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Requested size {} exceeds requested size limit {}",
+                        size, limit
+                    ),
+                ));
+            }
+            (Some(size), _) => size,
+            (None, Some(limit)) => min(DEFAULT_THIN_DEV_SIZE, limit),
+            (None, None) => DEFAULT_THIN_DEV_SIZE,
+        };
+
         let fs_uuid = FilesystemUuid::new_v4();
         let (dm_name, dm_uuid) = format_thin_ids(pool_uuid, ThinRole::Filesystem(fs_uuid));
-        let mut thin_dev = ThinDev::new(
-            get_dm(),
-            &dm_name,
-            Some(&dm_uuid),
-            size.unwrap_or(DEFAULT_THIN_DEV_SIZE),
-            thinpool_dev,
-            id,
-        )?;
+        let mut thin_dev =
+            ThinDev::new(get_dm(), &dm_name, Some(&dm_uuid), size, thinpool_dev, id)?;
 
         if let Err(err) = create_fs(&thin_dev.devnode(), Some(StratisUuid::Fs(fs_uuid)), false) {
             udev_settle().unwrap_or_else(|err| {
@@ -94,6 +111,7 @@ impl StratFilesystem {
             StratFilesystem {
                 thin_dev,
                 created: Utc::now(),
+                size_limit,
                 dbus_path: MaybeDbusPath(None),
             },
         ))
@@ -117,6 +135,7 @@ impl StratFilesystem {
         Ok(StratFilesystem {
             thin_dev,
             created: Utc.timestamp(fssave.created as i64, 0),
+            size_limit: fssave.size_limit,
             dbus_path: MaybeDbusPath(None),
         })
     }
@@ -175,6 +194,7 @@ impl StratFilesystem {
                 Ok(StratFilesystem {
                     thin_dev,
                     created: Utc::now(),
+                    size_limit: self.size_limit,
                     dbus_path: MaybeDbusPath(None),
                 })
             }
@@ -189,17 +209,29 @@ impl StratFilesystem {
     }
 
     /// check if filesystem is getting full and needs to be extended
+    /// The filesystem is never extended beyond its size limit, if it has one,
+    /// nor by more than max_extend, if specified.
     /// TODO: deal with the thindev in a Fail state.
-    pub fn check(&mut self) -> StratisResult<bool> {
+    pub fn check(&mut self, max_extend: Option<Sectors>) -> StratisResult<bool> {
         match self.thin_dev.status(get_dm())? {
             ThinStatus::Working(_) => {
                 if let Some(mount_point) = self.mount_points()?.first() {
                     let (fs_total_bytes, fs_total_used_bytes) = fs_usage(mount_point)?;
                     let free_bytes = fs_total_bytes - fs_total_used_bytes;
                     if free_bytes.sectors() < FILESYSTEM_LOWATER {
+                        let current_size = self.thin_dev.size();
+                        let mut new_size = current_size + self.extend_size(current_size);
+                        if let Some(limit) = self.size_limit {
+                            new_size = min(new_size, limit);
+                        }
+                        if let Some(max_extend) = max_extend {
+                            new_size = min(new_size, current_size + max_extend);
+                        }
+                        if new_size <= current_size {
+                            return Ok(false);
+                        }
                         let mut table = self.thin_dev.table().table.clone();
-                        table.length =
-                            self.thin_dev.size() + self.extend_size(self.thin_dev.size());
+                        table.length = new_size;
                         if self.thin_dev.set_table(get_dm(), table).is_err() {
                             return Ok(false);
                         }
@@ -222,6 +254,84 @@ impl StratFilesystem {
         }
     }
 
+    /// Set the size of the thin device under the filesystem to new_size and
+    /// grow the XFS filesystem to fill it. If the filesystem is not mounted,
+    /// it is temporarily mounted so that it can be grown.
+    /// Returns true if the size was changed, false if the thin device already
+    /// had the requested size.
+    ///
+    /// XFS does not support shrinking, so a new_size that is smaller than the
+    /// current size is rejected, as is a new_size that exceeds the size limit.
+    pub fn set_size(&mut self, new_size: Sectors) -> StratisResult<bool> {
+        let current_size = self.thin_dev.size();
+        if new_size == current_size {
+            return Ok(false);
+        }
+        if new_size < current_size {
+            return Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!(
+                    "Requested size {} is smaller than current size {}; XFS filesystems can not be shrunk",
+                    new_size, current_size
+                ),
+            ));
+        }
+        if let Some(limit) = self.size_limit {
+            if new_size > limit {
+                return Err(StratisError::Engine(
+                    ErrorEnum::Invalid,
+                    format!(
+                        "Requested size {} exceeds the filesystem's size limit {}",
+                        new_size, limit
+                    ),
+                ));
+            }
+        }
+
+        let mut table = self.thin_dev.table().table.clone();
+        table.length = new_size;
+        self.thin_dev.set_table(get_dm(), table)?;
+
+        if let Err(err) = self.grow_xfs() {
+            // The XFS filesystem was not extended, so it is safe to restore
+            // the thin device to its previous size.
+            let mut table = self.thin_dev.table().table.clone();
+            table.length = current_size;
+            if let Err(err2) = self.thin_dev.set_table(get_dm(), table) {
+                warn!(
+                    "While handling xfs_growfs error, failed to restore size of thin device {}: {}",
+                    self.thin_dev.device(),
+                    err2
+                );
+            }
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+
+    /// Grow the XFS filesystem to the size of the thin device, mounting it on
+    /// a temporary mount point if it is not already mounted.
+    fn grow_xfs(&self) -> StratisResult<()> {
+        if let Some(mount_point) = self.mount_points()?.first() {
+            xfs_growfs(mount_point)
+        } else {
+            let tmp_dir = tempfile::Builder::new()
+                .prefix(TEMP_MNT_POINT_PREFIX)
+                .tempdir()?;
+            mount(
+                Some(&self.thin_dev.devnode()),
+                tmp_dir.path(),
+                Some("xfs"),
+                MsFlags::empty(),
+                None as Option<&str>,
+            )?;
+            let result = xfs_growfs(tmp_dir.path());
+            umount(tmp_dir.path())?;
+            result
+        }
+    }
+
     /// Return an extend size for the thindev under the filesystem
     /// TODO: returning the current size will double the space provisioned to
     /// the thin device.  We should determine if this is a reasonable value.
@@ -248,6 +358,7 @@ impl StratFilesystem {
             thin_id: self.thin_dev.id(),
             size: self.thin_dev.size(),
             created: self.created.timestamp() as u64,
+            size_limit: self.size_limit,
         }
     }
 
@@ -262,7 +373,7 @@ impl StratFilesystem {
     }
 
     /// Find places where this filesystem is mounted.
-    fn mount_points(&self) -> StratisResult<Vec<PathBuf>> {
+    pub fn mount_points(&self) -> StratisResult<Vec<PathBuf>> {
         // Use major:minor values to find mounts for this filesystem
         let major = u64::from(self.thin_dev.device().major);
         let minor = u64::from(self.thin_dev.device().minor);
@@ -288,7 +399,8 @@ impl StratFilesystem {
 
         Ok(ret_vec)
     }
-    #[cfg(test)]
+
+    /// The logical size of the thin device under the filesystem.
     pub fn thindev_size(&self) -> Sectors {
         self.thin_dev.size()
     }
@@ -324,6 +436,14 @@ impl Filesystem for StratFilesystem {
         }
     }
 
+    fn size(&self) -> Bytes {
+        self.thin_dev.size().bytes()
+    }
+
+    fn size_limit(&self) -> Option<Bytes> {
+        self.size_limit.map(|l| l.bytes())
+    }
+
     fn set_dbus_path(&mut self, path: MaybeDbusPath) {
         self.dbus_path = path
     }
@@ -13,20 +13,21 @@ use std::{
 
 use nix::mount::{mount, umount, MsFlags};
 
-use devicemapper::{DmDevice, LinearDev, LinearDevTargetParams, TargetLine};
+use devicemapper::{Bytes, DmDevice, LinearDev, LinearDevTargetParams, Sectors, TargetLine};
 
 use crate::{
     engine::{
         strat_engine::{
-            cmd::create_fs, dm::get_dm, serde_structs::FilesystemSave,
-            thinpool::filesystem::StratFilesystem,
+            cmd::{create_fs, xfs_growfs},
+            dm::get_dm,
+            serde_structs::FilesystemSave,
+            thinpool::filesystem::{fs_usage, StratFilesystem},
         },
         types::{FilesystemUuid, Name, PoolUuid, StratisUuid},
     },
     stratis::StratisResult,
 };
 
-// TODO: Monitor fs size and extend linear and fs if needed
 // TODO: Document format of stuff on MDV in SWDD (currently ad-hoc)
 
 const RUN_DIR: &str = "/run/stratisd";
@@ -217,6 +218,39 @@ impl MetadataVol {
         Ok(())
     }
 
+    /// The size of the backing device.
+    pub fn size(&self) -> Sectors {
+        self.dev.size()
+    }
+
+    /// Return total bytes allocated to the filesystem on the MDV, total
+    /// bytes used by data/metadata.
+    pub fn usage(&self) -> StratisResult<(Bytes, Bytes)> {
+        let mount = MountedMDV::mount(self)?;
+        fs_usage(mount.mount_pt())
+    }
+
+    /// Grow the filesystem on the MDV to fill the backing device, once the
+    /// backing device has been extended.
+    pub fn grow(&self) -> StratisResult<()> {
+        let mount = MountedMDV::mount(self)?;
+        xfs_growfs(mount.mount_pt())
+    }
+
+    #[cfg(test)]
+    /// Use up free space on the filesystem on the MDV by writing amount
+    /// bytes to a file outside of the directory of filesystem records.
+    pub fn fill(&self, amount: Bytes) -> StratisResult<()> {
+        let mount = MountedMDV::mount(self)?;
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(mount.mount_pt().join("fill"))?;
+        f.write_all(&vec![0u8; convert_int!(*amount, u128, usize)?])?;
+        f.sync_all()?;
+        Ok(())
+    }
+
     /// Get a reference to the backing device
     pub fn device(&self) -> &LinearDev {
         &self.dev
@@ -6,7 +6,6 @@
 
 use std::{
     cmp::{max, min},
-    fmt,
     thread::sleep,
     time::Duration,
 };
@@ -16,13 +15,14 @@ use serde_json::Value;
 use devicemapper::{
     device_exists, DataBlocks, Device, DmDevice, DmName, DmNameBuf, FlakeyTargetParams, LinearDev,
     LinearDevTargetParams, LinearTargetParams, MetaBlocks, Sectors, TargetLine, ThinDevId,
-    ThinPoolDev, ThinPoolStatus, ThinPoolStatusSummary, IEC,
+    ThinPoolDev, ThinPoolStatus, ThinPoolStatusSummary, ThinPoolWorkingStatus, IEC,
 };
 
 use crate::{
     engine::{
         engine::Filesystem,
         event::{get_engine_listener_list, EngineEvent},
+        shared::{check_overprov_disable, check_overprov_request, overprov_available_space},
         strat_engine::{
             backstore::Backstore,
             cmd::{thin_check, thin_repair, udev_settle},
@@ -32,12 +32,19 @@ use crate::{
                 format_flex_ids, format_thin_ids, format_thinpool_ids, FlexRole, ThinPoolRole,
                 ThinRole,
             },
-            serde_structs::{FlexDevsSave, Recordable, ThinPoolDevSave},
-            thinpool::{filesystem::StratFilesystem, mdv::MetadataVol, thinids::ThinDevIdPool},
+            serde_structs::{FilesystemSave, FlexDevsSave, Recordable, ThinPoolDevSave},
+            thinpool::{
+                filesystem::{StratFilesystem, DEFAULT_THIN_DEV_SIZE},
+                mdv::MetadataVol,
+                thinids::ThinDevIdPool,
+            },
             writing::wipe_sectors,
         },
         structures::Table,
-        types::{FilesystemUuid, MaybeDbusPath, Name, PoolUuid},
+        types::{
+            FilesystemUuid, MaybeDbusPath, Name, PoolUuid, SpaceAlertLevel, SpaceThresholds,
+            ThinPoolStatusDigest, ThinPoolUsage,
+        },
     },
     stratis::{ErrorEnum, StratisError, StratisResult},
 };
@@ -51,11 +58,16 @@ const MIN_META_SEGMENT_SIZE: MetaBlocks = MetaBlocks(4 * IEC::Ki);
 const INITIAL_DATA_SIZE: DataBlocks = DataBlocks(768);
 const INITIAL_MDV_SIZE: Sectors = Sectors(32 * IEC::Ki); // 16 MiB
 
+// The free space on the MDV's filesystem below which the MDV is extended
+const MDV_LOWATER: Sectors = Sectors(8 * IEC::Ki); // 4 MiB
+
+// The most by which the MDV is extended at one time, and so the most space
+// held back from the data device for its next extension
+const MAX_MDV_EXTEND_SIZE: Sectors = Sectors(128 * IEC::Ki); // 64 MiB
+
 // The maximum allowable size of the thinpool metadata device
 const MAX_META_SIZE: MetaBlocks = MetaBlocks(255 * ((1 << 14) - 64));
 
-const SPACE_CRIT_PCT: u8 = 95;
-
 fn sectors_to_datablocks(sectors: Sectors) -> DataBlocks {
     DataBlocks(sectors / DATA_BLOCK_SIZE)
 }
@@ -146,14 +158,25 @@ fn coalesce_segs(
 /// cap device.)
 /// Lowater needed for three things:
 /// 1. Extend data device (currently not applicable due to greedy allocation)
-/// 2. Get an event when pool exceeds SPACE_CRIT_PCT
+/// 2. Get an event when pool usage exceeds the lowest space threshold that
+///    it does not yet exceed
 /// 3. Get an event when usage has increased enough that we might need to
 ///    extend a filesystem
-fn calc_lowater(used: DataBlocks, data_dev_size: DataBlocks, available: DataBlocks) -> DataBlocks {
+fn calc_lowater(
+    used: DataBlocks,
+    data_dev_size: DataBlocks,
+    available: DataBlocks,
+    thresholds: SpaceThresholds,
+) -> DataBlocks {
     let total = data_dev_size + available;
 
     // Calculate #2. Calculated against total size.
-    let crit_percent_total = (total * SPACE_CRIT_PCT) / 100u8;
+    let alert_pct = if used < (total * thresholds.warning) / 100u8 {
+        thresholds.warning
+    } else {
+        thresholds.critical
+    };
+    let crit_percent_total = (total * alert_pct) / 100u8;
     assert!(crit_percent_total < total);
     let low_water_for_crit = total - crit_percent_total;
     assert!(DataBlocks(std::u64::MAX) - available >= DATA_LOWATER);
@@ -188,19 +211,6 @@ struct Segments {
     mdv_segments: Vec<(Sectors, Sectors)>,
 }
 
-/// A way of digesting the status reported on the thinpool into a value
-/// that can be checked for equality. This way, two statuses,
-/// collected at different times can be checked to determine whether their
-/// gross, as opposed to fine, differences are significant.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum ThinPoolStatusDigest {
-    Fail,
-    Error,
-    Good,
-    ReadOnly,
-    OutOfSpace,
-}
-
 impl From<&ThinPoolStatus> for ThinPoolStatusDigest {
     fn from(status: &ThinPoolStatus) -> ThinPoolStatusDigest {
         match status {
@@ -215,21 +225,6 @@ impl From<&ThinPoolStatus> for ThinPoolStatusDigest {
     }
 }
 
-/// In this implementation convert the status designations to strings which
-/// match those strings that the kernel uses to identify the different states
-/// in the ioctl result.
-impl fmt::Display for ThinPoolStatusDigest {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ThinPoolStatusDigest::Good => write!(f, "rw"),
-            ThinPoolStatusDigest::ReadOnly => write!(f, "ro"),
-            ThinPoolStatusDigest::OutOfSpace => write!(f, "out_of_data_space"),
-            ThinPoolStatusDigest::Fail => write!(f, "Fail"),
-            ThinPoolStatusDigest::Error => write!(f, "Error"),
-        }
-    }
-}
-
 pub struct ThinPoolSizeParams {
     meta_size: MetaBlocks,
     data_size: DataBlocks,
@@ -271,11 +266,30 @@ pub struct ThinPool {
     id_gen: ThinDevIdPool,
     filesystems: Table<FilesystemUuid, StratFilesystem>,
     mdv: MetadataVol,
+    /// The number of filesystem records added to the MDV since the pool was
+    /// set up. Only adding filesystem records uses up space on the MDV.
+    mdv_records_added: usize,
+    /// The value of mdv_records_added when the free space on the MDV was
+    /// last checked, None if it has not yet been checked or if the MDV
+    /// could not be extended when it needed to be. The free space is not
+    /// checked again until more records have been added.
+    mdv_checked_records: Option<usize>,
+    /// Whether the MDV has been extended but the filesystem on it could not
+    /// be grown to fill it. Growing it is retried before the free space on
+    /// the MDV is checked again.
+    mdv_grow_pending: bool,
     /// The single DM device that the backstore presents as its upper-most
     /// layer. All DM components obtain their storage from this layer.
     /// The device will change if the backstore adds or removes a cache.
     backstore_device: Device,
     thin_pool_status: Option<ThinPoolStatus>,
+    /// If false, the sum of the logical sizes of the filesystems may not
+    /// exceed the size of the thin pool's data device.
+    enable_overprov: bool,
+    space_thresholds: SpaceThresholds,
+    /// The space alert level as of the most recent check, None if the
+    /// thin pool has not yet been checked.
+    space_alert_level: Option<SpaceAlertLevel>,
     dbus_path: MaybeDbusPath,
 }
 
@@ -368,6 +382,7 @@ impl ThinPool {
                 DataBlocks(0),
                 sectors_to_datablocks(data_dev_size),
                 sectors_to_datablocks(backstore.available_in_backstore()),
+                SpaceThresholds::default(),
             ),
         )?;
 
@@ -382,8 +397,14 @@ impl ThinPool {
             id_gen: ThinDevIdPool::new_from_ids(&[]),
             filesystems: Table::default(),
             mdv,
+            mdv_records_added: 0,
+            mdv_checked_records: None,
+            mdv_grow_pending: false,
             backstore_device,
             thin_pool_status: None,
+            enable_overprov: true,
+            space_thresholds: SpaceThresholds::default(),
+            space_alert_level: None,
             dbus_path: MaybeDbusPath(None),
         })
     }
@@ -400,6 +421,8 @@ impl ThinPool {
         thin_pool_save: &ThinPoolDevSave,
         flex_devs: &FlexDevsSave,
         backstore: &Backstore,
+        enable_overprov: bool,
+        space_thresholds: SpaceThresholds,
     ) -> StratisResult<ThinPool> {
         let mdv_segments = flex_devs.meta_dev.to_vec();
         let meta_segments = flex_devs.thin_meta_dev.to_vec();
@@ -439,6 +462,7 @@ impl ThinPool {
                 DataBlocks(0),
                 sectors_to_datablocks(data_dev_size),
                 sectors_to_datablocks(backstore.available_in_backstore()),
+                space_thresholds,
             ),
         )?;
 
@@ -492,8 +516,14 @@ impl ThinPool {
             id_gen: ThinDevIdPool::new_from_ids(&thin_ids),
             filesystems: fs_table,
             mdv,
+            mdv_records_added: 0,
+            mdv_checked_records: None,
+            mdv_grow_pending: false,
             backstore_device,
             thin_pool_status: None,
+            enable_overprov,
+            space_thresholds,
+            space_alert_level: None,
             dbus_path: MaybeDbusPath(None),
         })
     }
@@ -522,6 +552,19 @@ impl ThinPool {
         );
 
         let mut should_save: bool = false;
+
+        // Extend the MDV before the data device, which is given all the
+        // remaining space in the backstore except the MDV's reserve.
+        let mdv_check_due = match self.mdv_checked_records {
+            Some(count) => count < self.mdv_records_added,
+            // Retry only once there is room in the backstore to extend the
+            // MDV, or if the filesystem on it must still be grown.
+            None => self.mdv_grow_pending || backstore.available_in_backstore() >= INITIAL_MDV_SIZE,
+        };
+        if mdv_check_due {
+            should_save |= self.check_mdv(pool_uuid, backstore);
+        }
+
         let thin_pool_status = self.thin_pool.status(get_dm())?;
 
         if let ThinPoolStatus::Working(status) = &thin_pool_status {
@@ -549,7 +592,7 @@ impl ThinPool {
             }
 
             // Expand data blocks to fill all available remaining space
-            let free_space = backstore.available_in_backstore();
+            let free_space = self.available_for_data(backstore);
             let total_extended = if free_space < DATA_BLOCK_SIZE {
                 DataBlocks(0)
             } else {
@@ -563,21 +606,34 @@ impl ThinPool {
             };
 
             let current_total = usage.total_data + total_extended;
+            let available = sectors_to_datablocks(self.available_for_data(backstore));
 
             let lowater = calc_lowater(
                 usage.used_data,
                 current_total,
-                sectors_to_datablocks(backstore.available_in_backstore()),
+                available,
+                self.space_thresholds,
             );
 
             self.thin_pool.set_low_water_mark(get_dm(), lowater)?;
             self.resume()?;
+
+            self.set_space_alert_level(
+                pool_uuid,
+                datablocks_to_sectors(usage.used_data),
+                datablocks_to_sectors(current_total + available),
+            );
         }
 
         self.set_state(thin_pool_status);
 
+        let mut available = self.available_logical_space();
         for (name, uuid, fs) in self.filesystems.iter_mut() {
-            let save_mdv = fs.check()?;
+            let size_before = fs.thindev_size();
+            let save_mdv = fs.check(available)?;
+            if let Some(ref mut available) = available {
+                *available -= fs.thindev_size() - size_before;
+            }
             if save_mdv {
                 if let Err(e) = self.mdv.save_fs(name, *uuid, fs) {
                     error!("Could not save MDV for fs with UUID {} and name {} belonging to pool with UUID {}, reason: {:?}",
@@ -621,6 +677,71 @@ impl ThinPool {
         self.thin_pool_status = Some(thin_pool_status);
     }
 
+    /// Set the space alert level according to the space used of the total
+    /// space available for filesystem data. If the level has changed,
+    /// notify the engine listeners and log the change. A pool found to be
+    /// within its thresholds when it is first checked is not reported.
+    fn set_space_alert_level(&mut self, pool_uuid: PoolUuid, used: Sectors, total: Sectors) {
+        let level = self.space_thresholds.level(used, total);
+        let previous = self.space_alert_level.replace(level);
+        if previous == Some(level) || (previous.is_none() && level == SpaceAlertLevel::Normal) {
+            return;
+        }
+
+        let previous_str = previous
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "none".to_string());
+        if level != SpaceAlertLevel::Normal {
+            warn!(
+                "Space alert level of pool with UUID {} changed from \"{}\" to \"{}\"; {} of {} in use",
+                pool_uuid.to_simple_ref(),
+                previous_str,
+                level,
+                used,
+                total
+            );
+        } else {
+            info!(
+                "Space alert level of pool with UUID {} changed from \"{}\" to \"{}\"; {} of {} in use",
+                pool_uuid.to_simple_ref(),
+                previous_str,
+                level,
+                used,
+                total
+            );
+        }
+
+        get_engine_listener_list().notify(&EngineEvent::PoolSpaceAlert {
+            dbus_path: &self.dbus_path,
+            pool_uuid,
+            level,
+            used,
+            total,
+        });
+    }
+
+    /// Re-evaluate the space alert level from the current usage of the
+    /// thin pool. The usage may drop, e.g., when filesystems are destroyed,
+    /// and the capacity may change, e.g., when blockdevs are removed,
+    /// without an event from the thin pool device, so the level must be
+    /// updated whenever that happens.
+    pub fn update_space_alert_level(
+        &mut self,
+        pool_uuid: PoolUuid,
+        backstore: &Backstore,
+    ) -> StratisResult<()> {
+        if let ThinPoolStatus::Working(status) = self.thin_pool.status(get_dm())? {
+            let usage = &status.usage;
+            let available = sectors_to_datablocks(self.available_for_data(backstore));
+            self.set_space_alert_level(
+                pool_uuid,
+                datablocks_to_sectors(usage.used_data),
+                datablocks_to_sectors(usage.total_data + available),
+            );
+        }
+        Ok(())
+    }
+
     /// Tear down the components managed here: filesystems, the MDV,
     /// and the actual thinpool device itself.
     pub fn teardown(&mut self) -> StratisResult<()> {
@@ -745,46 +866,207 @@ impl ThinPool {
         result
     }
 
+    /// Extend the MDV, and grow the filesystem on it, if the filesystem is
+    /// running out of free space. The MDV is extended by its current size,
+    /// but by no more than MAX_MDV_EXTEND_SIZE. If the filesystem on the MDV
+    /// could not be grown after a previous extension, growing it is retried
+    /// first, and the MDV is not extended again until it has been grown.
+    /// Returns true if the MDV was extended, so that its new segments must
+    /// be saved in the pool metadata.
+    fn check_mdv(&mut self, pool_uuid: PoolUuid, backstore: &mut Backstore) -> bool {
+        if self.mdv_grow_pending {
+            if let Err(err) = self.mdv.grow() {
+                warn!(
+                    "Could not grow the filesystem on the MDV belonging to pool with uuid {}: {}",
+                    pool_uuid.to_simple_ref(),
+                    err
+                );
+                return false;
+            }
+            self.mdv_grow_pending = false;
+        }
+
+        let free = match self.mdv.usage() {
+            Ok((total, used)) => (total - used).sectors(),
+            Err(err) => {
+                warn!(
+                    "Could not determine the free space on the MDV belonging to pool with uuid {}: {}",
+                    pool_uuid.to_simple_ref(),
+                    err
+                );
+                return false;
+            }
+        };
+
+        if free >= MDV_LOWATER {
+            self.mdv_checked_records = Some(self.mdv_records_added);
+            return false;
+        }
+
+        let extend_size = self.mdv_extend_size();
+        match self.extend_mdv(pool_uuid, backstore, extend_size) {
+            Ok(Sectors(0)) | Err(_) => {
+                self.mdv_checked_records = None;
+                false
+            }
+            Ok(_) => {
+                if self.mdv_grow_pending {
+                    self.mdv_checked_records = None;
+                } else {
+                    self.mdv_checked_records = Some(self.mdv_records_added);
+                }
+                true
+            }
+        }
+    }
+
+    /// The size by which the MDV is extended next: its current size, but no
+    /// more than MAX_MDV_EXTEND_SIZE.
+    fn mdv_extend_size(&self) -> Sectors {
+        min(self.mdv.size(), MAX_MDV_EXTEND_SIZE)
+    }
+
+    /// The space in the backstore into which the data device may be
+    /// extended. Space for the next extension of the MDV is held back so
+    /// that the MDV can still be extended after the data device has been
+    /// given the rest, unless the space for filesystem data has reached the
+    /// critical level, when it is better spent on filesystem data.
+    fn available_for_data(&self, backstore: &Backstore) -> Sectors {
+        let available = backstore.available_in_backstore();
+        if self.space_alert_level == Some(SpaceAlertLevel::Critical) {
+            available
+        } else {
+            Sectors(available.saturating_sub(*self.mdv_extend_size()))
+        }
+    }
+
+    /// Extend the MDV by at most extend_size, in multiples of the initial
+    /// MDV size, and grow the filesystem on it to fill the extended device.
+    /// The result is the value by which the MDV is extended, which may be 0
+    /// if nothing could be allocated. Records the new arrangement of
+    /// segments on the extended device. If the filesystem on the MDV could
+    /// not be grown, records that growing it is pending.
+    fn extend_mdv(
+        &mut self,
+        pool_uuid: PoolUuid,
+        backstore: &mut Backstore,
+        extend_size: Sectors,
+    ) -> StratisResult<Sectors> {
+        let pool_uuid_str = pool_uuid.to_simple_ref();
+        info!(
+            "Attempting to extend MDV belonging to pool {} by {}",
+            pool_uuid_str, extend_size,
+        );
+
+        let result =
+            if let Some(region) = backstore.request(pool_uuid, extend_size, INITIAL_MDV_SIZE)? {
+                let device = backstore
+                    .device()
+                    .expect("If request succeeded, backstore must have cap device.");
+                let segments = coalesce_segs(&self.segments.mdv_segments, &[region]);
+                self.mdv.set_table(segs_to_table(device, &segments))?;
+                self.mdv.resume()?;
+                self.segments.mdv_segments = segments;
+
+                Ok(region.1)
+            } else {
+                Ok(Sectors(0))
+            };
+        match result {
+            Ok(Sectors(0)) => {
+                warn!("Insufficient free space available in backstore; could not extend MDV belonging to pool with uuid {}, request was {}",
+                      pool_uuid_str,
+                      extend_size);
+            }
+            Ok(actual_extend_size) => {
+                info!(
+                    "Extended MDV belonging to pool with uuid {} by {}",
+                    pool_uuid_str, actual_extend_size
+                );
+                if let Err(ref err) = self.mdv.grow() {
+                    error!("Extended MDV belonging to pool with uuid {} but failed to grow the filesystem on it with error: {:?}",
+                           pool_uuid_str,
+                           err);
+                    self.mdv_grow_pending = true;
+                }
+            }
+            Err(ref err) => {
+                error!("Attempted to extend MDV belonging to pool with uuid {} by {} but failed with error: {:?}",
+                       pool_uuid_str,
+                       extend_size,
+                       err);
+            }
+        }
+        result
+    }
+
     /// The number of physical sectors in use by this thinpool abstraction.
     /// All sectors allocated to the mdv, all sectors allocated to the
     /// metadata spare, and all sectors actually in use by the thinpool DM
     /// device, either for the metadata device or for the data device.
     pub fn total_physical_used(&self) -> StratisResult<Sectors> {
-        let (data_dev_used, meta_dev_used) = match &self.thin_pool_status {
+        let usage = &self.working_status()?.usage;
+        let data_dev_used = datablocks_to_sectors(usage.used_data);
+        let meta_dev_used = usage.used_meta.sectors();
+
+        let spare_total = self.segments.meta_spare_segments.iter().map(|s| s.1).sum();
+
+        let mdv_total = self.segments.mdv_segments.iter().map(|s| s.1).sum();
+
+        Ok(data_dev_used + spare_total + meta_dev_used + mdv_total)
+    }
+
+    /// The usage of the thinpool device's data and metadata devices as of
+    /// the most recent check.
+    pub fn usage(&self) -> StratisResult<ThinPoolUsage> {
+        let usage = &self.working_status()?.usage;
+        Ok(ThinPoolUsage {
+            used_data: usage.used_data,
+            total_data: usage.total_data,
+            used_meta: usage.used_meta,
+            total_meta: usage.total_meta,
+        })
+    }
+
+    /// The digest of the status of the thinpool device as of the most recent
+    /// check. None if the thinpool device has not yet been checked.
+    pub fn status_digest(&self) -> Option<ThinPoolStatusDigest> {
+        self.thin_pool_status.as_ref().map(|status| status.into())
+    }
+
+    /// The status of the thinpool device as of the most recent check.
+    /// Return an error if the thinpool device has not yet been checked or
+    /// was not working when it was last checked.
+    fn working_status(&self) -> StratisResult<&ThinPoolWorkingStatus> {
+        match &self.thin_pool_status {
             None => {
                 let err_msg = format!(
                     "Unknown status for thin pool device with \"{}\"",
                     thin_pool_identifiers(&self.thin_pool)
                 );
-                return Err(StratisError::Engine(ErrorEnum::Invalid, err_msg));
+                Err(StratisError::Engine(ErrorEnum::Invalid, err_msg))
             }
-            Some(status) => match status {
-                ThinPoolStatus::Working(status) => (
-                    datablocks_to_sectors(status.usage.used_data),
-                    status.usage.used_meta.sectors(),
-                ),
-                ThinPoolStatus::Error => {
-                    let err_msg = format!(
-                        "Devicemapper could not obtain status for devicemapper thin pool device with \"{}\"",
-                        thin_pool_identifiers(&self.thin_pool)
-                    );
-                    return Err(StratisError::Engine(ErrorEnum::Invalid, err_msg));
-                }
-                ThinPoolStatus::Fail => {
-                    let err_msg = format!(
-                        "The thinpool device with \"{}\" has failed",
-                        thin_pool_identifiers(&self.thin_pool)
-                    );
-                    return Err(StratisError::Engine(ErrorEnum::Invalid, err_msg));
-                }
-            },
-        };
-
-        let spare_total = self.segments.meta_spare_segments.iter().map(|s| s.1).sum();
-
-        let mdv_total = self.segments.mdv_segments.iter().map(|s| s.1).sum();
+            Some(ThinPoolStatus::Working(status)) => Ok(status),
+            Some(ThinPoolStatus::Error) => {
+                let err_msg = format!(
+                    "Devicemapper could not obtain status for devicemapper thin pool device with \"{}\"",
+                    thin_pool_identifiers(&self.thin_pool)
+                );
+                Err(StratisError::Engine(ErrorEnum::Invalid, err_msg))
+            }
+            Some(ThinPoolStatus::Fail) => {
+                let err_msg = format!(
+                    "The thinpool device with \"{}\" has failed",
+                    thin_pool_identifiers(&self.thin_pool)
+                );
+                Err(StratisError::Engine(ErrorEnum::Invalid, err_msg))
+            }
+        }
+    }
 
-        Ok(data_dev_used + spare_total + meta_dev_used + mdv_total)
+    /// The records of the pool's filesystems, as stored on the MDV.
+    pub fn filesystem_records(&self) -> StratisResult<Vec<FilesystemSave>> {
+        self.mdv.filesystems()
     }
 
     pub fn get_filesystem_by_uuid(&self, uuid: FilesystemUuid) -> Option<(Name, &StratFilesystem)> {
@@ -813,6 +1095,81 @@ impl ThinPool {
         !self.filesystems.is_empty()
     }
 
+    /// Whether any of the filesystems in the thin pool is mounted.
+    pub fn has_mounted_filesystems(&self) -> StratisResult<bool> {
+        for (_, _, fs) in &self.filesystems {
+            if !fs.mount_points()?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The sum of the logical sizes of all the filesystems in the thin pool.
+    fn total_logical_size(&self) -> Sectors {
+        self.filesystems
+            .iter()
+            .map(|(_, _, fs)| fs.thindev_size())
+            .sum()
+    }
+
+    /// The amount by which the sum of the logical sizes of the filesystems
+    /// may still grow without exceeding the size of the thin pool's data
+    /// device. None if overprovisioning is enabled, in which case there is
+    /// no such restriction.
+    fn available_logical_space(&self) -> Option<Sectors> {
+        overprov_available_space(
+            self.enable_overprov,
+            self.total_logical_size(),
+            self.thin_pool.data_dev().size(),
+        )
+    }
+
+    /// Return an error if overprovisioning is disabled and adding
+    /// additional_size to the sum of the logical sizes of the filesystems
+    /// would make it exceed the size of the thin pool's data device.
+    fn check_overprov(&self, additional_size: Sectors) -> StratisResult<()> {
+        check_overprov_request(self.available_logical_space(), additional_size)
+    }
+
+    pub fn overprov_enabled(&self) -> bool {
+        self.enable_overprov
+    }
+
+    pub fn space_thresholds(&self) -> SpaceThresholds {
+        self.space_thresholds
+    }
+
+    /// Set the space thresholds. Return true if they were changed. The low
+    /// water mark of the thinpool device and the space alert level are
+    /// updated when the thinpool's check method is next invoked.
+    pub fn set_space_thresholds(&mut self, thresholds: SpaceThresholds) -> bool {
+        if thresholds == self.space_thresholds {
+            return false;
+        }
+        self.space_thresholds = thresholds;
+        true
+    }
+
+    /// Enable or disable overprovisioning.
+    ///
+    /// * Ok(true) is returned if the mode was changed
+    /// * Ok(false) is returned if the mode already had the requested value
+    /// * Err(StratisError::Engine(ErrorEnum::Invalid, _)) is returned if
+    /// overprovisioning is being disabled, but the sum of the logical sizes
+    /// of the filesystems already exceeds the size of the thin pool's data
+    /// device
+    pub fn set_overprov_mode(&mut self, enabled: bool) -> StratisResult<bool> {
+        if enabled == self.enable_overprov {
+            return Ok(false);
+        }
+        if !enabled {
+            check_overprov_disable(self.total_logical_size(), self.thin_pool.data_dev().size())?;
+        }
+        self.enable_overprov = enabled;
+        Ok(true)
+    }
+
     pub fn filesystems(&self) -> Vec<(Name, FilesystemUuid, &dyn Filesystem)> {
         self.filesystems
             .iter()
@@ -828,15 +1185,42 @@ impl ThinPool {
     }
 
     /// Create a filesystem within the thin pool. Given name must not
-    /// already be in use.
+    /// already be in use. If size_limit is specified, the filesystem will
+    /// never be extended beyond it. If overprovisioning is disabled and no
+    /// size is specified, the default size is reduced to the space remaining
+    /// for filesystems.
     pub fn create_filesystem(
         &mut self,
         pool_uuid: PoolUuid,
         name: &str,
         size: Option<Sectors>,
+        size_limit: Option<Sectors>,
     ) -> StratisResult<FilesystemUuid> {
-        let (fs_uuid, mut new_filesystem) =
-            StratFilesystem::initialize(pool_uuid, &self.thin_pool, size, self.id_gen.new_id()?)?;
+        let size = match self.available_logical_space() {
+            Some(available) => {
+                if available == Sectors(0) {
+                    return Err(StratisError::Engine(
+                        ErrorEnum::Invalid,
+                        "Overprovisioning is disabled and no space remains for filesystems".into(),
+                    ));
+                }
+                let size = size.unwrap_or_else(|| {
+                    let size = min(DEFAULT_THIN_DEV_SIZE, available);
+                    size_limit.map_or(size, |limit| min(size, limit))
+                });
+                self.check_overprov(size)?;
+                Some(size)
+            }
+            None => size,
+        };
+
+        let (fs_uuid, mut new_filesystem) = StratFilesystem::initialize(
+            pool_uuid,
+            &self.thin_pool,
+            size,
+            size_limit,
+            self.id_gen.new_id()?,
+        )?;
         let name = Name::new(name.to_owned());
         if let Err(err) = self.mdv.save_fs(&name, fs_uuid, &new_filesystem) {
             udev_settle().unwrap_or_else(|err| {
@@ -851,6 +1235,7 @@ impl ThinPool {
             }
             return Err(err);
         }
+        self.mdv_records_added += 1;
         self.filesystems.insert(name, fs_uuid, new_filesystem);
 
         Ok(fs_uuid)
@@ -867,6 +1252,9 @@ impl ThinPool {
         let snapshot_fs_uuid = FilesystemUuid::new_v4();
         let (snapshot_dm_name, snapshot_dm_uuid) =
             format_thin_ids(pool_uuid, ThinRole::Filesystem(snapshot_fs_uuid));
+        if let Some((_, filesystem)) = self.get_filesystem_by_uuid(origin_uuid) {
+            self.check_overprov(filesystem.thindev_size())?;
+        }
         let snapshot_id = self.id_gen.new_id()?;
         let new_filesystem = match self.get_filesystem_by_uuid(origin_uuid) {
             Some((fs_name, filesystem)) => filesystem.snapshot(
@@ -888,6 +1276,7 @@ impl ThinPool {
         let new_fs_name = Name::new(snapshot_name.to_owned());
         self.mdv
             .save_fs(&new_fs_name, snapshot_fs_uuid, &new_filesystem)?;
+        self.mdv_records_added += 1;
         self.filesystems
             .insert(new_fs_name, snapshot_fs_uuid, new_filesystem);
         Ok((
@@ -977,6 +1366,44 @@ impl ThinPool {
         }
     }
 
+    /// Set the size of a filesystem within the thin pool and save the new
+    /// size to the MDV.
+    ///
+    /// * Ok(true) is returned if the filesystem was resized
+    /// * Ok(false) is returned if the filesystem already had the requested size
+    /// * Err(StratisError::Engine(ErrorEnum::NotFound, _)) is returned if the
+    /// filesystem does not exist
+    /// * Err(StratisError::Engine(ErrorEnum::Invalid, _)) is returned if the
+    /// requested size is smaller than the current size, or if
+    /// overprovisioning is disabled and there is not enough space remaining
+    /// for filesystems to satisfy the request
+    pub fn set_filesystem_size(
+        &mut self,
+        uuid: FilesystemUuid,
+        new_size: Sectors,
+    ) -> StratisResult<bool> {
+        if let Some((_, filesystem)) = self.filesystems.get_by_uuid(uuid) {
+            let current_size = filesystem.thindev_size();
+            if new_size > current_size {
+                self.check_overprov(new_size - current_size)?;
+            }
+        }
+
+        let (name, filesystem) = self.filesystems.get_mut_by_uuid(uuid).ok_or_else(|| {
+            StratisError::Engine(
+                ErrorEnum::NotFound,
+                format!("Filesystem not found with UUID of {}", uuid),
+            )
+        })?;
+
+        if filesystem.set_size(new_size)? {
+            self.mdv.save_fs(&name, uuid, filesystem)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// The names of DM devices belonging to this pool that may generate events
     pub fn get_eventing_dev_names(&self, pool_uuid: PoolUuid) -> Vec<DmNameBuf> {
         vec![
@@ -1087,7 +1514,7 @@ impl ThinPool {
 
 impl<'a> Into<Value> for &'a ThinPool {
     fn into(self) -> Value {
-        json!({
+        let mut json = json!({
             "filesystems": Value::Array(
                 self.filesystems.iter()
                     .map(|(name, uuid, _)| json!({
@@ -1096,7 +1523,18 @@ impl<'a> Into<Value> for &'a ThinPool {
                     }))
                     .collect()
             )
-        })
+        });
+        let map = json.as_object_mut().expect("Created a JSON object above");
+        if let Some(digest) = self.status_digest() {
+            map.insert(
+                "thin_pool_status".to_string(),
+                Value::from(digest.to_string()),
+            );
+        }
+        if let Ok(usage) = self.usage() {
+            map.insert("thin_pool_usage".to_string(), (&usage).into());
+        }
+        json
     }
 }
 
@@ -1198,10 +1636,13 @@ mod tests {
 
     use devicemapper::{Bytes, SECTOR_SIZE};
 
-    use crate::engine::strat_engine::{
-        metadata::MDADataSize,
-        tests::{loopbacked, real},
-        writing::SyncAll,
+    use crate::engine::{
+        strat_engine::{
+            metadata::MDADataSize,
+            tests::{loopbacked, real},
+            writing::SyncAll,
+        },
+        types::CacheConfig,
     };
 
     use crate::engine::strat_engine::thinpool::filesystem::{fs_usage, FILESYSTEM_LOWATER};
@@ -1233,7 +1674,8 @@ mod tests {
 
         pool.check(pool_uuid, &mut backstore).unwrap();
 
-        assert!(backstore.available_in_backstore() < DATA_BLOCK_SIZE);
+        assert!(backstore.available_in_backstore() < pool.mdv_extend_size() + DATA_BLOCK_SIZE);
+        assert!(backstore.available_in_backstore() >= pool.mdv_extend_size());
 
         let meta_size = pool.thin_pool.meta_dev().size();
         let data_size = pool.thin_pool.data_dev().size();
@@ -1257,6 +1699,88 @@ mod tests {
         );
     }
 
+    /// Verify that the low water mark is set so that an event is received
+    /// when the lowest space threshold not yet exceeded is crossed, and
+    /// that the space alert level is determined by the thresholds.
+    #[test]
+    fn test_space_thresholds() {
+        let thresholds = SpaceThresholds {
+            warning: 50,
+            critical: 90,
+        };
+        let total = DataBlocks(200_000);
+
+        assert_eq!(
+            calc_lowater(DataBlocks(99_000), total, DataBlocks(0), thresholds),
+            DataBlocks(100_000)
+        );
+        assert_eq!(
+            calc_lowater(DataBlocks(179_000), total, DataBlocks(0), thresholds),
+            DataBlocks(20_000)
+        );
+
+        let total = Sectors(1000);
+        assert_eq!(
+            thresholds.level(Sectors(499), total),
+            SpaceAlertLevel::Normal
+        );
+        assert_eq!(
+            thresholds.level(Sectors(500), total),
+            SpaceAlertLevel::Warning
+        );
+        assert_eq!(
+            thresholds.level(Sectors(900), total),
+            SpaceAlertLevel::Critical
+        );
+    }
+
+    /// Verify that the usage and status of the thinpool device are unknown
+    /// until the thinpool has been checked and that afterwards they reflect
+    /// the sizes of the thinpool's sub-devices.
+    fn test_usage(paths: &[&Path]) {
+        let pool_uuid = PoolUuid::new_v4();
+
+        let mut backstore =
+            Backstore::initialize(pool_uuid, paths, MDADataSize::default(), None).unwrap();
+
+        let mut pool = ThinPool::new(
+            pool_uuid,
+            &ThinPoolSizeParams::default(),
+            DATA_BLOCK_SIZE,
+            &mut backstore,
+        )
+        .unwrap();
+
+        assert_matches!(pool.usage(), Err(_));
+        assert_eq!(pool.status_digest(), None);
+
+        pool.check(pool_uuid, &mut backstore).unwrap();
+
+        assert_eq!(pool.status_digest(), Some(ThinPoolStatusDigest::Good));
+        let usage = pool.usage().unwrap();
+        assert_eq!(
+            usage.total_data,
+            sectors_to_datablocks(pool.thin_pool.data_dev().size())
+        );
+        assert_eq!(
+            usage.total_meta,
+            pool.thin_pool.meta_dev().size().metablocks()
+        );
+        assert!(usage.used_data <= usage.total_data);
+        assert!(usage.used_meta > MetaBlocks(0));
+        assert!(usage.used_meta <= usage.total_meta);
+    }
+
+    #[test]
+    fn loop_test_usage() {
+        loopbacked::test_with_spec(&loopbacked::DeviceLimits::Range(1, 3, None), test_usage);
+    }
+
+    #[test]
+    fn real_test_usage() {
+        real::test_with_spec(&real::DeviceLimits::AtLeast(1, None, None), test_usage);
+    }
+
     /// Verify that a full pool extends properly when additional space is added.
     fn test_full_pool(paths: &[&Path]) {
         let pool_uuid = PoolUuid::new_v4();
@@ -1272,7 +1796,7 @@ mod tests {
         .unwrap();
 
         let fs_uuid = pool
-            .create_filesystem(pool_uuid, "stratis_test_filesystem", None)
+            .create_filesystem(pool_uuid, "stratis_test_filesystem", None, None)
             .unwrap();
         let write_buf = &[8u8; BYTES_PER_WRITE];
         let source_tmp_dir = tempfile::Builder::new()
@@ -1376,7 +1900,7 @@ mod tests {
         .unwrap();
 
         let fs_uuid = pool
-            .create_filesystem(pool_uuid, "stratis_test_filesystem", None)
+            .create_filesystem(pool_uuid, "stratis_test_filesystem", None, None)
             .unwrap();
 
         let write_buf = &[8u8; SECTOR_SIZE];
@@ -1486,7 +2010,9 @@ mod tests {
         .unwrap();
 
         let pool_name = "stratis_test_pool";
-        let fs_uuid = pool.create_filesystem(pool_uuid, name1, None).unwrap();
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, name1, None, None)
+            .unwrap();
 
         let action = pool.rename_filesystem(pool_name, fs_uuid, name2).unwrap();
         assert_matches!(action, Some(_));
@@ -1494,7 +2020,15 @@ mod tests {
         let thinpoolsave: ThinPoolDevSave = pool.record();
         pool.teardown().unwrap();
 
-        let pool = ThinPool::setup(pool_uuid, &thinpoolsave, &flexdevs, &backstore).unwrap();
+        let pool = ThinPool::setup(
+            pool_uuid,
+            &thinpoolsave,
+            &flexdevs,
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
 
         assert_eq!(&*pool.get_filesystem_by_uuid(fs_uuid).unwrap().0, name2);
     }
@@ -1515,6 +2049,255 @@ mod tests {
         );
     }
 
+    /// Verify that check() extends the MDV from the space held back for it
+    /// once the data device has been extended, also when a filesystem has
+    /// been destroyed before another was created, that a failed extension
+    /// is retried when space is added to the backstore, and that the new
+    /// segments are recorded and used when the pool is set up again.
+    fn test_extend_mdv(paths: &[&Path]) {
+        let pool_uuid = PoolUuid::new_v4();
+        let (first_path, remaining_paths) = paths.split_at(1);
+        let mut backstore =
+            Backstore::initialize(pool_uuid, first_path, MDADataSize::default(), None).unwrap();
+        let mut pool = ThinPool::new(
+            pool_uuid,
+            &ThinPoolSizeParams::default(),
+            DATA_BLOCK_SIZE,
+            &mut backstore,
+        )
+        .unwrap();
+
+        let fill_mdv = |pool: &ThinPool| {
+            let (total, used) = pool.mdv.usage().unwrap();
+            pool.mdv
+                .fill(total - used - MDV_LOWATER.bytes() / 2u64)
+                .unwrap();
+        };
+
+        pool.check(pool_uuid, &mut backstore).unwrap();
+        assert!(pool.thin_pool.data_dev().size() > datablocks_to_sectors(INITIAL_DATA_SIZE));
+        assert!(backstore.available_in_backstore() >= INITIAL_MDV_SIZE);
+
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, "fs_name", None, None)
+            .unwrap();
+        assert!(!pool.check(pool_uuid, &mut backstore).unwrap());
+        pool.destroy_filesystem("pool_name", fs_uuid).unwrap();
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, "fs_name_1", None, None)
+            .unwrap();
+        let (total_before, _) = pool.mdv.usage().unwrap();
+        fill_mdv(&pool);
+
+        assert!(pool.check(pool_uuid, &mut backstore).unwrap());
+        assert_eq!(pool.mdv.size(), INITIAL_MDV_SIZE * 2u64);
+        let (total_after, _) = pool.mdv.usage().unwrap();
+        assert!(total_after > total_before);
+
+        // The space held back for the MDV has been used and the data device
+        // holds the rest, so the MDV can not be extended again until devices
+        // are added.
+        assert!(backstore.available_in_backstore() < INITIAL_MDV_SIZE);
+        pool.create_filesystem(pool_uuid, "fs_name_2", None, None)
+            .unwrap();
+        fill_mdv(&pool);
+        assert!(!pool.check(pool_uuid, &mut backstore).unwrap());
+        assert_eq!(pool.mdv.size(), INITIAL_MDV_SIZE * 2u64);
+
+        backstore.add_datadevs(pool_uuid, remaining_paths).unwrap();
+        assert!(pool.check(pool_uuid, &mut backstore).unwrap());
+        assert_eq!(pool.mdv.size(), INITIAL_MDV_SIZE * 4u64);
+        assert!(backstore.available_in_backstore() >= pool.mdv_extend_size());
+
+        let flexdevs: FlexDevsSave = pool.record();
+        assert_eq!(
+            flexdevs.meta_dev.iter().map(|s| s.1).sum::<Sectors>(),
+            INITIAL_MDV_SIZE * 4u64
+        );
+        let thinpoolsave: ThinPoolDevSave = pool.record();
+        pool.teardown().unwrap();
+
+        let pool = ThinPool::setup(
+            pool_uuid,
+            &thinpoolsave,
+            &flexdevs,
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
+
+        assert_eq!(pool.mdv.size(), INITIAL_MDV_SIZE * 4u64);
+        assert!(pool.get_filesystem_by_uuid(fs_uuid).is_some());
+    }
+
+    #[test]
+    fn loop_test_extend_mdv() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(2, 3, None),
+            test_extend_mdv,
+        );
+    }
+
+    #[test]
+    fn real_test_extend_mdv() {
+        real::test_with_spec(&real::DeviceLimits::AtLeast(2, None, None), test_extend_mdv);
+    }
+
+    /// Verify that a filesystem can be grown to an exact size, that the XFS
+    /// filesystem on it is grown as well, and that the new size is recorded
+    /// in the MDV. Verify that shrinking the filesystem is refused.
+    fn test_set_filesystem_size(paths: &[&Path]) {
+        let pool_uuid = PoolUuid::new_v4();
+        let mut backstore =
+            Backstore::initialize(pool_uuid, paths, MDADataSize::default(), None).unwrap();
+        let mut pool = ThinPool::new(
+            pool_uuid,
+            &ThinPoolSizeParams::default(),
+            DATA_BLOCK_SIZE,
+            &mut backstore,
+        )
+        .unwrap();
+
+        let fs_size = Bytes::from(IEC::Gi).sectors();
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, "stratis_test_filesystem", Some(fs_size), None)
+            .unwrap();
+
+        let new_size = fs_size * 2u64;
+        assert!(pool.set_filesystem_size(fs_uuid, new_size).unwrap());
+        assert!(!pool.set_filesystem_size(fs_uuid, new_size).unwrap());
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, fs_size),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("stratis_testing")
+            .tempdir()
+            .unwrap();
+        mount(
+            Some(&pool.get_filesystem_by_uuid(fs_uuid).unwrap().1.devnode()),
+            tmp_dir.path(),
+            Some("xfs"),
+            MsFlags::empty(),
+            None as Option<&str>,
+        )
+        .unwrap();
+        let (fs_total_bytes, _) = fs_usage(tmp_dir.path()).unwrap();
+        assert!(fs_total_bytes > fs_size.bytes());
+        umount(tmp_dir.path()).unwrap();
+
+        let flexdevs: FlexDevsSave = pool.record();
+        let thinpoolsave: ThinPoolDevSave = pool.record();
+        pool.teardown().unwrap();
+
+        let pool = ThinPool::setup(
+            pool_uuid,
+            &thinpoolsave,
+            &flexdevs,
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            pool.get_filesystem_by_uuid(fs_uuid)
+                .unwrap()
+                .1
+                .thindev_size(),
+            new_size
+        );
+    }
+
+    #[test]
+    fn loop_test_set_filesystem_size() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(1, 3, None),
+            test_set_filesystem_size,
+        );
+    }
+
+    #[test]
+    fn real_test_set_filesystem_size() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(1, None, None),
+            test_set_filesystem_size,
+        );
+    }
+
+    /// Verify that when overprovisioning is disabled, creating a filesystem,
+    /// taking a snapshot, or growing a filesystem is refused if the sum of
+    /// the filesystems' logical sizes would exceed the size of the data
+    /// device, and that overprovisioning can not be disabled once the pool
+    /// is overprovisioned.
+    fn test_overprov(paths: &[&Path]) {
+        let pool_uuid = PoolUuid::new_v4();
+        let mut backstore =
+            Backstore::initialize(pool_uuid, paths, MDADataSize::default(), None).unwrap();
+        let mut pool = ThinPool::new(
+            pool_uuid,
+            &ThinPoolSizeParams::default(),
+            DATA_BLOCK_SIZE,
+            &mut backstore,
+        )
+        .unwrap();
+        pool.check(pool_uuid, &mut backstore).unwrap();
+
+        assert!(pool.overprov_enabled());
+        assert!(pool.set_overprov_mode(false).unwrap());
+        assert!(!pool.set_overprov_mode(false).unwrap());
+
+        let data_dev_size = pool.thin_pool.data_dev().size();
+        assert_matches!(
+            pool.create_filesystem(
+                pool_uuid,
+                "stratis_test_filesystem",
+                Some(data_dev_size + DATA_BLOCK_SIZE),
+                None
+            ),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        let fs_size = data_dev_size / 2u64;
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, "stratis_test_filesystem", Some(fs_size), None)
+            .unwrap();
+        pool.snapshot_filesystem(pool_uuid, fs_uuid, "stratis_test_snapshot")
+            .unwrap();
+        assert_matches!(
+            pool.snapshot_filesystem(pool_uuid, fs_uuid, "stratis_test_snapshot_2"),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            pool.set_filesystem_size(fs_uuid, fs_size + DATA_BLOCK_SIZE),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert_matches!(
+            pool.create_filesystem(pool_uuid, "stratis_test_filesystem_2", None, None),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+
+        assert!(pool.set_overprov_mode(true).unwrap());
+        pool.snapshot_filesystem(pool_uuid, fs_uuid, "stratis_test_snapshot_2")
+            .unwrap();
+        assert_matches!(
+            pool.set_overprov_mode(false),
+            Err(StratisError::Engine(ErrorEnum::Invalid, _))
+        );
+        assert!(pool.overprov_enabled());
+    }
+
+    #[test]
+    fn loop_test_overprov() {
+        loopbacked::test_with_spec(&loopbacked::DeviceLimits::Range(2, 3, None), test_overprov);
+    }
+
+    #[test]
+    fn real_test_overprov() {
+        real::test_with_spec(&real::DeviceLimits::AtLeast(2, None, None), test_overprov);
+    }
+
     /// Verify that setting up a pool when the pool has not been previously torn
     /// down does not fail. Clutter the original pool with a filesystem with
     /// some data on it.
@@ -1530,7 +2313,9 @@ mod tests {
         )
         .unwrap();
 
-        let fs_uuid = pool.create_filesystem(pool_uuid, "fsname", None).unwrap();
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, "fsname", None, None)
+            .unwrap();
 
         let tmp_dir = tempfile::Builder::new()
             .prefix("stratis_testing")
@@ -1559,8 +2344,15 @@ mod tests {
         }
         let thinpooldevsave: ThinPoolDevSave = pool.record();
 
-        let new_pool =
-            ThinPool::setup(pool_uuid, &thinpooldevsave, &pool.record(), &backstore).unwrap();
+        let new_pool = ThinPool::setup(
+            pool_uuid,
+            &thinpooldevsave,
+            &pool.record(),
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
 
         assert!(new_pool.get_filesystem_by_uuid(fs_uuid).is_some());
     }
@@ -1593,7 +2385,9 @@ mod tests {
         .unwrap();
         let pool_name = "stratis_test_pool";
         let fs_name = "stratis_test_filesystem";
-        let fs_uuid = pool.create_filesystem(pool_uuid, fs_name, None).unwrap();
+        let fs_uuid = pool
+            .create_filesystem(pool_uuid, fs_name, None, None)
+            .unwrap();
         pool.destroy_filesystem(pool_name, fs_uuid).unwrap();
         let flexdevs: FlexDevsSave = pool.record();
         let thinpooldevsave: ThinPoolDevSave = pool.record();
@@ -1602,7 +2396,15 @@ mod tests {
         // Check that destroyed fs is not present in MDV. If the record
         // had been left on the MDV that didn't match a thin_id in the
         // thinpool, ::setup() will fail.
-        let pool = ThinPool::setup(pool_uuid, &thinpooldevsave, &flexdevs, &backstore).unwrap();
+        let pool = ThinPool::setup(
+            pool_uuid,
+            &thinpooldevsave,
+            &flexdevs,
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
 
         assert_matches!(pool.get_filesystem_by_uuid(fs_uuid), None);
     }
@@ -1649,7 +2451,7 @@ mod tests {
 
         let fs_name = "stratis_test_filesystem";
         let fs_uuid = pool
-            .create_filesystem(pool_uuid, fs_name, Some(fs_size))
+            .create_filesystem(pool_uuid, fs_name, Some(fs_size), None)
             .unwrap();
         let tmp_dir = tempfile::Builder::new()
             .prefix("stratis_testing")
@@ -1696,7 +2498,15 @@ mod tests {
         let flexdevs: FlexDevsSave = pool.record();
         let thinpoolsave: ThinPoolDevSave = pool.record();
         pool.teardown().unwrap();
-        let mut pool = ThinPool::setup(pool_uuid, &thinpoolsave, &flexdevs, &backstore).unwrap();
+        let mut pool = ThinPool::setup(
+            pool_uuid,
+            &thinpoolsave,
+            &flexdevs,
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
         let filesystem = pool.get_mut_filesystem_by_uuid(fs_uuid).unwrap().1;
         let thindev_size = filesystem.thindev_size();
         assert!(thindev_size > start_thindev_size)
@@ -1718,6 +2528,117 @@ mod tests {
         );
     }
 
+    /// Verify that a filesystem with a size limit is extended by pool.check()
+    /// no further than its limit, and that the limit is preserved in the MDV.
+    fn test_thindev_expand_limit(paths: &[&Path]) {
+        let pool_uuid = PoolUuid::new_v4();
+        let mut backstore =
+            Backstore::initialize(pool_uuid, paths, MDADataSize::default(), None).unwrap();
+        let mut pool = ThinPool::new(
+            pool_uuid,
+            &ThinPoolSizeParams::default(),
+            DATA_BLOCK_SIZE,
+            &mut backstore,
+        )
+        .unwrap();
+
+        // Create a filesystem as small as possible, with a limit that is
+        // smaller than the size it would be extended to without one.
+        let fs_size = FILESYSTEM_LOWATER + Bytes::from(IEC::Mi).sectors();
+        let size_limit = fs_size + Bytes::from(IEC::Mi * 4).sectors();
+
+        let fs_uuid = pool
+            .create_filesystem(
+                pool_uuid,
+                "stratis_test_filesystem",
+                Some(fs_size),
+                Some(size_limit),
+            )
+            .unwrap();
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("stratis_testing")
+            .tempdir()
+            .unwrap();
+        mount(
+            Some(&pool.get_filesystem_by_uuid(fs_uuid).unwrap().1.devnode()),
+            tmp_dir.path(),
+            Some("xfs"),
+            MsFlags::empty(),
+            None as Option<&str>,
+        )
+        .unwrap();
+
+        // Write 2 MiB of data. The filesystem's free space is now 1 MiB
+        // below FILESYSTEM_LOWATER.
+        let write_size = Bytes::from(IEC::Mi * 2).sectors();
+        let buf = &[1u8; SECTOR_SIZE];
+        for i in 0..*write_size {
+            let file_path = tmp_dir.path().join(format!("stratis_test{}.txt", i));
+            let mut f = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(file_path)
+                .unwrap();
+            if f.write_all(buf).is_err() {
+                break;
+            }
+        }
+
+        pool.check(pool_uuid, &mut backstore).unwrap();
+        assert_eq!(
+            pool.get_filesystem_by_uuid(fs_uuid)
+                .unwrap()
+                .1
+                .thindev_size(),
+            size_limit
+        );
+
+        // The filesystem is still below FILESYSTEM_LOWATER, but already at
+        // its limit.
+        pool.check(pool_uuid, &mut backstore).unwrap();
+        assert_eq!(
+            pool.get_filesystem_by_uuid(fs_uuid)
+                .unwrap()
+                .1
+                .thindev_size(),
+            size_limit
+        );
+        umount(tmp_dir.path()).unwrap();
+
+        let flexdevs: FlexDevsSave = pool.record();
+        let thinpoolsave: ThinPoolDevSave = pool.record();
+        pool.teardown().unwrap();
+        let pool = ThinPool::setup(
+            pool_uuid,
+            &thinpoolsave,
+            &flexdevs,
+            &backstore,
+            true,
+            SpaceThresholds::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            pool.get_filesystem_by_uuid(fs_uuid).unwrap().1.size_limit(),
+            Some(size_limit.bytes())
+        );
+    }
+
+    #[test]
+    fn loop_test_thindev_expand_limit() {
+        loopbacked::test_with_spec(
+            &loopbacked::DeviceLimits::Range(1, 3, None),
+            test_thindev_expand_limit,
+        );
+    }
+
+    #[test]
+    fn real_test_thindev_expand_limit() {
+        real::test_with_spec(
+            &real::DeviceLimits::AtLeast(1, None, None),
+            test_thindev_expand_limit,
+        );
+    }
+
     /// Just suspend and resume the device and make sure it doesn't crash.
     /// Suspend twice in succession and then resume twice in succession
     /// to check idempotency.
@@ -1733,7 +2654,7 @@ mod tests {
         )
         .unwrap();
 
-        pool.create_filesystem(pool_uuid, "stratis_test_filesystem", None)
+        pool.create_filesystem(pool_uuid, "stratis_test_filesystem", None, None)
             .unwrap();
 
         pool.suspend().unwrap();
@@ -1779,7 +2700,7 @@ mod tests {
         .unwrap();
 
         let fs_uuid = pool
-            .create_filesystem(pool_uuid, "stratis_test_filesystem", None)
+            .create_filesystem(pool_uuid, "stratis_test_filesystem", None, None)
             .unwrap();
 
         let tmp_dir = tempfile::Builder::new()
@@ -1820,7 +2741,9 @@ mod tests {
         let old_device = backstore
             .device()
             .expect("Space already allocated from backstore, backstore must have device");
-        backstore.init_cache(pool_uuid, paths1).unwrap();
+        backstore
+            .init_cache(pool_uuid, paths1, CacheConfig::default())
+            .unwrap();
         let new_device = backstore
             .device()
             .expect("Space already allocated from backstore, backstore must have device");
@@ -9,13 +9,13 @@ use std::io::Cursor;
 use std::{
     cmp::min,
     fs::{File, OpenOptions},
-    io::{self, BufWriter, Seek, SeekFrom, Write},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
 use devicemapper::{Sectors, IEC, SECTOR_SIZE};
 
-use crate::stratis::StratisResult;
+use crate::stratis::{ErrorEnum, StratisError, StratisResult};
 
 /// The SyncAll trait unifies the File type with other types that do
 /// not implement sync_all().
@@ -83,3 +83,47 @@ pub fn wipe_sectors<P: AsRef<Path>>(
 ) -> StratisResult<()> {
     write_sectors(path, offset, length, &[0u8; SECTOR_SIZE])
 }
+
+/// Copy length sectors from from_offset on the device at from_path to
+/// to_offset on the device at to_path. The two regions must not overlap.
+/// Note that this method buffers the data and syncs only when all are
+/// written.
+pub fn copy_sectors<P: AsRef<Path>, Q: AsRef<Path>>(
+    from_path: P,
+    from_offset: Sectors,
+    to_path: Q,
+    to_offset: Sectors,
+    length: Sectors,
+) -> StratisResult<()> {
+    let buf_size = convert_const!(min(u128::from(IEC::Mi), *(length.bytes())), u128, usize);
+
+    let mut from = File::open(from_path)?;
+    from.seek(SeekFrom::Start(convert_int!(
+        *from_offset.bytes(),
+        u128,
+        u64
+    )?))?;
+    let mut to = OpenOptions::new().write(true).open(to_path)?;
+    to.seek(SeekFrom::Start(convert_int!(
+        *to_offset.bytes(),
+        u128,
+        u64
+    )?))?;
+
+    let length = convert_int!(*length.bytes(), u128, u64)?;
+    let mut reader = BufReader::with_capacity(buf_size, from).take(length);
+    let mut writer = BufWriter::with_capacity(buf_size, to);
+    let copied = io::copy(&mut reader, &mut writer)?;
+    if copied != length {
+        return Err(StratisError::Engine(
+            ErrorEnum::Error,
+            format!(
+                "Expected to copy {} bytes but reached the end of the source device after {}",
+                length, copied
+            ),
+        ));
+    }
+
+    writer.sync_all()?;
+    Ok(())
+}
@@ -10,16 +10,22 @@
 
 use std::fmt::{self, Display};
 
+use devicemapper::Sectors;
+
 use crate::engine::{
     engine::Filesystem,
-    types::{DevUuid, FilesystemUuid, PoolUuid},
+    types::{CacheMode, DevUuid, FilesystemUuid, PoolUuid, SpaceThresholds},
 };
 
 /// Return value indicating key operation
 pub struct Key;
 
-/// Return value indicating clevis operation
-pub struct Clevis;
+/// Return value indicating clevis operation; contains the id of the binding
+/// that was added or removed
+pub struct Clevis(pub u32);
+
+/// Return value indicating reencryption operation
+pub struct Reencryption;
 
 /// A trait for a generic kind of action. Defines the type of the thing to
 /// be changed, and also a method to indicate what changed.
@@ -76,16 +82,59 @@ impl Display for CreateAction<PoolUuid> {
 impl Display for CreateAction<Clevis> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            CreateAction::Created(Clevis) => {
+            CreateAction::Created(Clevis(id)) => {
+                write!(
+                    f,
+                    "Pool successfully bound to an unlocking mechanism using clevis with binding id {}",
+                    id
+                )
+            }
+            CreateAction::Identity => {
+                write!(
+                    f,
+                    "The pool requested for binding is already bound with this clevis \
+                    configuration; no action taken"
+                )
+            }
+        }
+    }
+}
+
+impl Display for CreateAction<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateAction::Created(Key) => {
                 write!(
                     f,
-                    "Pool successfully bound to an unlocking mechanism using clevis"
+                    "The data devices of the pool were successfully encrypted"
                 )
             }
             CreateAction::Identity => {
                 write!(
                     f,
-                    "The pool requested for binding is already bound; no action taken"
+                    "The pool requested for encryption is already encrypted with the \
+                    requested key description; no action taken"
+                )
+            }
+        }
+    }
+}
+
+impl Display for CreateAction<Reencryption> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateAction::Created(Reencryption) => {
+                write!(
+                    f,
+                    "Reencryption of the data devices of the pool under new volume keys \
+                    was successfully started"
+                )
+            }
+            CreateAction::Identity => {
+                write!(
+                    f,
+                    "The data devices of the pool requested for reencryption are already \
+                    being reencrypted; no action taken"
                 )
             }
         }
@@ -349,6 +398,54 @@ impl Display for RenameAction<FilesystemUuid> {
     }
 }
 
+impl Display for RenameAction<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenameAction::Identity => {
+                write!(
+                    f,
+                    "Pool is already bound to the requested key description; no action taken"
+                )
+            }
+            RenameAction::Renamed(_) => {
+                write!(
+                    f,
+                    "Pool was successfully bound to the requested key description"
+                )
+            }
+            RenameAction::NoSource => {
+                write!(f, "The pool requested to be rebound is not encrypted")
+            }
+        }
+    }
+}
+
+impl Display for RenameAction<Clevis> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenameAction::Identity => {
+                write!(
+                    f,
+                    "The clevis binding requested to be regenerated is up to date; no action taken"
+                )
+            }
+            RenameAction::Renamed(Clevis(id)) => {
+                write!(
+                    f,
+                    "The clevis binding with id {} was successfully regenerated",
+                    id
+                )
+            }
+            RenameAction::NoSource => {
+                write!(
+                    f,
+                    "The clevis binding requested to be regenerated does not exist"
+                )
+            }
+        }
+    }
+}
+
 impl Display for RenameAction<PoolUuid> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -408,8 +505,12 @@ impl Display for DeleteAction<PoolUuid> {
 impl Display for DeleteAction<Clevis> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            DeleteAction::Deleted(_) => {
-                write!(f, "A clevis binding was successfully removed from a pool")
+            DeleteAction::Deleted(Clevis(id)) => {
+                write!(
+                    f,
+                    "The clevis binding with id {} was successfully removed from a pool",
+                    id
+                )
             }
             DeleteAction::Identity => {
                 write!(
@@ -489,3 +590,116 @@ impl Display for SetDeleteAction<FilesystemUuid> {
         }
     }
 }
+
+impl Display for SetDeleteAction<DevUuid> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.changed.is_empty() {
+            write!(
+                f,
+                "The requested blockdevs are already absent; no action taken"
+            )
+        } else {
+            write!(
+                f,
+                "Blockdevs with UUIDs {} were successfully removed",
+                self.changed
+                    .iter()
+                    .map(|u| u.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// An action which may change the value of a single property.
+pub enum PropChangeAction<T> {
+    /// The property already had the requested value.
+    Identity,
+    /// The property was changed to the new value.
+    NewValue(T),
+}
+
+impl<T> EngineAction for PropChangeAction<T> {
+    type Return = T;
+
+    fn is_changed(&self) -> bool {
+        matches!(*self, PropChangeAction::NewValue(_))
+    }
+
+    fn changed(self) -> Option<T> {
+        match self {
+            PropChangeAction::NewValue(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PropChangeAction<Sectors> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropChangeAction::Identity => {
+                write!(
+                    f,
+                    "Filesystem already has the requested size; no action taken"
+                )
+            }
+            PropChangeAction::NewValue(size) => {
+                write!(f, "Filesystem was successfully resized to {}", size)
+            }
+        }
+    }
+}
+
+impl Display for PropChangeAction<bool> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropChangeAction::Identity => {
+                write!(
+                    f,
+                    "Property already has the requested value; no action taken"
+                )
+            }
+            PropChangeAction::NewValue(value) => {
+                write!(f, "Property was successfully set to {}", value)
+            }
+        }
+    }
+}
+
+impl Display for PropChangeAction<SpaceThresholds> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropChangeAction::Identity => {
+                write!(
+                    f,
+                    "The pool already has the requested space thresholds; no action taken"
+                )
+            }
+            PropChangeAction::NewValue(thresholds) => {
+                write!(
+                    f,
+                    "The space thresholds of the pool were successfully set to {}",
+                    thresholds
+                )
+            }
+        }
+    }
+}
+
+impl Display for PropChangeAction<CacheMode> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropChangeAction::Identity => {
+                write!(
+                    f,
+                    "The cache is already in the requested mode; no action taken"
+                )
+            }
+            PropChangeAction::NewValue(mode) => {
+                write!(f, "The cache was successfully switched to {} mode", mode)
+            }
+        }
+    }
+}
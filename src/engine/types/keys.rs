@@ -39,10 +39,172 @@ impl AsRef<[u8]> for SizedKeyMemory {
     }
 }
 
+/// The key derivation function used to derive the key that protects a
+/// LUKS2 keyslot from the passphrase.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pbkdf {
+    Pbkdf2,
+    Argon2i,
+    Argon2id,
+}
+
+impl<'a> TryFrom<&'a str> for Pbkdf {
+    type Error = StratisError;
+
+    fn try_from(s: &str) -> StratisResult<Pbkdf> {
+        match s {
+            "pbkdf2" => Ok(Pbkdf::Pbkdf2),
+            "argon2i" => Ok(Pbkdf::Argon2i),
+            "argon2id" => Ok(Pbkdf::Argon2id),
+            _ => Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!("{} is an invalid PBKDF", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Pbkdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pbkdf::Pbkdf2 => write!(f, "pbkdf2"),
+            Pbkdf::Argon2i => write!(f, "argon2i"),
+            Pbkdf::Argon2id => write!(f, "argon2id"),
+        }
+    }
+}
+
+/// The cipher with which block devices were encrypted before the encryption
+/// parameters were made configurable.
+const DEFAULT_CIPHER: &str = "aes-xts-plain64";
+
+/// The size in bits of the media encryption key generated for each block
+/// device if none is specified.
+const DEFAULT_KEY_SIZE: u32 = 512;
+
+/// The encryption sector size if none is specified.
+const DEFAULT_ENCRYPTION_SECTOR_SIZE: u32 = 512;
+
+/// The parameters with which each block device of an encrypted pool is
+/// formatted as a LUKS2 device.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct EncryptionParams {
+    /// The cipher in the form used by cryptsetup, e.g., "aes-xts-plain64".
+    pub cipher: String,
+    /// The size of the media encryption key in bits.
+    pub key_size: u32,
+    /// If None, the libcryptsetup default is used, as are the defaults for
+    /// any of the PBKDF costs that are not specified.
+    pub pbkdf: Option<Pbkdf>,
+    /// The maximum memory cost of an argon2 PBKDF in KiB.
+    pub pbkdf_memory_kb: Option<u32>,
+    /// The target time to spend on the PBKDF in milliseconds.
+    pub pbkdf_time_ms: Option<u32>,
+    /// The number of threads used by an argon2 PBKDF.
+    pub pbkdf_parallel_threads: Option<u32>,
+    /// The size of an encryption sector in bytes.
+    pub sector_size: u32,
+}
+
+impl EncryptionParams {
+    /// Split the cipher into the cipher proper and its mode, e.g.,
+    /// ("aes", "xts-plain64"), as libcryptsetup requires.
+    ///
+    /// Precondition: the cipher has been validated.
+    pub fn cipher_and_mode(&self) -> (&str, &str) {
+        let mut parts = self.cipher.splitn(2, '-');
+        (
+            parts.next().expect("splitn always yields one value"),
+            parts.next().unwrap_or(""),
+        )
+    }
+}
+
+impl Default for EncryptionParams {
+    fn default() -> EncryptionParams {
+        EncryptionParams {
+            cipher: DEFAULT_CIPHER.to_string(),
+            key_size: DEFAULT_KEY_SIZE,
+            pbkdf: None,
+            pbkdf_memory_kb: None,
+            pbkdf_time_ms: None,
+            pbkdf_parallel_threads: None,
+            sector_size: DEFAULT_ENCRYPTION_SECTOR_SIZE,
+        }
+    }
+}
+
+impl fmt::Display for EncryptionParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cipher: {}, key size: {} bits, sector size: {} bytes",
+            self.cipher, self.key_size, self.sector_size
+        )?;
+        match self.pbkdf {
+            Some(pbkdf) => write!(f, ", PBKDF: {}", pbkdf)?,
+            None => write!(f, ", default PBKDF")?,
+        }
+        if let Some(memory) = self.pbkdf_memory_kb {
+            write!(f, ", PBKDF memory: {} KiB", memory)?;
+        }
+        if let Some(time) = self.pbkdf_time_ms {
+            write!(f, ", PBKDF time: {} ms", time)?;
+        }
+        if let Some(threads) = self.pbkdf_parallel_threads {
+            write!(f, ", PBKDF threads: {}", threads)?;
+        }
+        Ok(())
+    }
+}
+
+/// A Clevis binding of the devices of an encrypted pool. The id identifies
+/// the binding among all the Clevis bindings of the pool.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClevisBinding {
+    pub id: u32,
+    pub pin: String,
+    pub config: Value,
+}
+
+impl fmt::Display for ClevisBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "clevis binding {}: pin \"{}\", configuration \"{}\"",
+            self.id, self.pin, self.config
+        )
+    }
+}
+
+impl<'a> Into<Value> for &'a ClevisBinding {
+    fn into(self) -> Value {
+        json!({
+            "id": self.id,
+            "pin": self.pin,
+            "config": self.config,
+        })
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct EncryptionInfo {
     pub key_description: KeyDescription,
-    pub clevis_info: Option<(String, Value)>,
+    pub clevis_info: Vec<ClevisBinding>,
+    pub encryption_params: EncryptionParams,
+    /// Whether the pool is unlocked with its Clevis bindings as soon as its
+    /// devices appear.
+    pub auto_unlock: bool,
+}
+
+impl EncryptionInfo {
+    /// Whether the pool is actually unlocked automatically: automatic
+    /// unlocking is enabled and the pool still has some Clevis binding to
+    /// unlock it with.
+    pub fn unlocks_automatically(&self) -> bool {
+        self.auto_unlock && !self.clevis_info.is_empty()
+    }
 }
 
 impl fmt::Display for EncryptionInfo {
@@ -51,15 +213,19 @@ impl fmt::Display for EncryptionInfo {
             "key description: \"{}\"",
             self.key_description.as_application_str()
         );
-        if let Some((pin, config)) = &self.clevis_info {
-            write!(
-                f,
-                "{}, clevis pin: \"{}\", clevis configuration: \"{}\"",
-                key_desc_str, pin, config
-            )
+        if self.clevis_info.is_empty() {
+            write!(f, "{}, no Clevis information", key_desc_str)?;
         } else {
-            write!(f, "{}, no Clevis information", key_desc_str)
+            write!(f, "{}", key_desc_str)?;
+            for binding in self.clevis_info.iter() {
+                write!(f, ", {}", binding)?;
+            }
+        }
+        write!(f, ", {}", self.encryption_params)?;
+        if self.unlocks_automatically() {
+            write!(f, ", unlocked automatically")?;
         }
+        Ok(())
     }
 }
 
@@ -73,17 +239,28 @@ impl fmt::Display for EncryptionInfo {
 impl Hash for EncryptionInfo {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.key_description.hash(state);
-        self.clevis_info.as_ref().map(|(pin, _)| pin).hash(state);
+        self.clevis_info
+            .iter()
+            .map(|binding| (binding.id, &binding.pin))
+            .for_each(|binding| binding.hash(state));
+        self.encryption_params.hash(state);
+        self.auto_unlock.hash(state);
     }
 }
 
 impl<'a> Into<Value> for &'a EncryptionInfo {
     fn into(self) -> Value {
-        let mut json = json!({"key_description": self.key_description.as_application_str()});
-        if let Some(ref info) = self.clevis_info {
+        let mut json = json!({
+            "key_description": self.key_description.as_application_str(),
+            "encryption_params": &self.encryption_params,
+            "auto_unlock": self.auto_unlock,
+        });
+        if !self.clevis_info.is_empty() {
             let map = json.as_object_mut().expect("Created a JSON object above");
-            map.insert("clevis_pin".to_string(), Value::from(info.0.to_owned()));
-            map.insert("clevis_config".to_string(), info.1.clone());
+            map.insert(
+                "clevis_bindings".to_string(),
+                Value::Array(self.clevis_info.iter().map(|b| b.into()).collect()),
+            );
         }
         json
     }
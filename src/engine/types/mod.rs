@@ -4,6 +4,7 @@
 
 use std::{
     borrow::Borrow,
+    collections::HashMap,
     convert::TryFrom,
     fmt::{self, Debug, Display},
     hash::Hash,
@@ -14,14 +15,20 @@ use std::{
 };
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use uuid::Uuid;
 
+use devicemapper::{Bytes, DataBlocks, MetaBlocks, Sectors};
+
 pub use crate::engine::types::{
     actions::{
-        Clevis, CreateAction, DeleteAction, EngineAction, Key, MappingCreateAction, RenameAction,
-        SetCreateAction, SetDeleteAction, SetUnlockAction,
+        Clevis, CreateAction, DeleteAction, EngineAction, Key, MappingCreateAction,
+        PropChangeAction, Reencryption, RenameAction, SetCreateAction, SetDeleteAction,
+        SetUnlockAction,
+    },
+    keys::{
+        ClevisBinding, EncryptionInfo, EncryptionParams, KeyDescription, Pbkdf, SizedKeyMemory,
     },
-    keys::{EncryptionInfo, KeyDescription, SizedKeyMemory},
 };
 use crate::stratis::{ErrorEnum, StratisError, StratisResult};
 
@@ -114,11 +121,13 @@ impl Display for StratisUuid {
     }
 }
 
-/// Use Clevis or keyring to unlock LUKS volume.
+/// Use Clevis, keyring, or a passphrase supplied with the request to unlock
+/// LUKS volume.
 #[derive(Clone, Copy)]
 pub enum UnlockMethod {
     Clevis,
     Keyring,
+    Passphrase,
 }
 
 impl<'a> TryFrom<&'a str> for UnlockMethod {
@@ -128,6 +137,7 @@ impl<'a> TryFrom<&'a str> for UnlockMethod {
         match s {
             "keyring" => Ok(UnlockMethod::Keyring),
             "clevis" => Ok(UnlockMethod::Clevis),
+            "passphrase" => Ok(UnlockMethod::Passphrase),
             _ => Err(StratisError::Error(format!(
                 "{} is an invalid unlock method",
                 s
@@ -163,6 +173,240 @@ pub enum BlockDevTier {
     Cache = 1,
 }
 
+/// The way in which a cache handles writes.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMode {
+    /// A write is complete only once it has reached both the cache and the
+    /// data tier.
+    Writethrough,
+    /// A write is complete once it has reached the cache; dirty blocks are
+    /// written back to the data tier later.
+    Writeback,
+    /// All I/O goes to the data tier; the cache is bypassed. A cache may
+    /// only be put in this mode if it contains no dirty blocks.
+    Passthrough,
+}
+
+impl Default for CacheMode {
+    fn default() -> CacheMode {
+        CacheMode::Writethrough
+    }
+}
+
+impl<'a> TryFrom<&'a str> for CacheMode {
+    type Error = StratisError;
+
+    fn try_from(s: &str) -> StratisResult<CacheMode> {
+        match s {
+            "writethrough" => Ok(CacheMode::Writethrough),
+            "writeback" => Ok(CacheMode::Writeback),
+            "passthrough" => Ok(CacheMode::Passthrough),
+            _ => Err(StratisError::Engine(
+                ErrorEnum::Invalid,
+                format!("{} is an invalid cache mode", s),
+            )),
+        }
+    }
+}
+
+impl Display for CacheMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheMode::Writethrough => write!(f, "writethrough"),
+            CacheMode::Writeback => write!(f, "writeback"),
+            CacheMode::Passthrough => write!(f, "passthrough"),
+        }
+    }
+}
+
+/// The version of the format of the archives made by Pool::backup_metadata().
+pub const METADATA_ARCHIVE_VERSION: u64 = 1;
+
+/// The name under which the kernel makes its preferred replacement policy
+/// available.
+pub const DEFAULT_CACHE_POLICY: &str = "default";
+
+/// The kernel docs indicate that this is the largest typical cache block
+/// size.
+pub const DEFAULT_CACHE_BLOCK_SIZE: Sectors = Sectors(2048); // 1024 KiB
+
+/// The parameters of the DM cache device made from a pool's cache tier.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheConfig {
+    pub mode: CacheMode,
+    /// The name of the replacement policy, e.g., "smq".
+    pub policy: String,
+    /// Tunables of the replacement policy, as key/value pairs.
+    pub policy_args: HashMap<String, String>,
+    /// The size of a cache block. Must be a multiple of 32 KiB between
+    /// 32 KiB and 1 GiB inclusive.
+    pub block_size: Sectors,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            mode: CacheMode::default(),
+            policy: DEFAULT_CACHE_POLICY.to_string(),
+            policy_args: HashMap::new(),
+            block_size: DEFAULT_CACHE_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Statistics on the operation of a pool's cache, as reported by the
+/// kernel. The counters are reset whenever the cache device is made.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStatistics {
+    pub read_hits: u64,
+    pub read_misses: u64,
+    pub write_hits: u64,
+    pub write_misses: u64,
+    /// The number of cache blocks whose contents differ from the origin.
+    pub dirty: u64,
+    pub promotions: u64,
+    pub demotions: u64,
+    pub used_meta: MetaBlocks,
+    pub total_meta: MetaBlocks,
+}
+
+impl<'a> Into<Value> for &'a CacheStatistics {
+    fn into(self) -> Value {
+        json!({
+            "read_hits": self.read_hits,
+            "read_misses": self.read_misses,
+            "write_hits": self.write_hits,
+            "write_misses": self.write_misses,
+            "dirty": self.dirty,
+            "promotions": self.promotions,
+            "demotions": self.demotions,
+            "used_meta": *self.used_meta,
+            "total_meta": *self.total_meta,
+        })
+    }
+}
+
+/// The usage of the data and metadata devices of a pool's thin pool, as
+/// reported by the kernel.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ThinPoolUsage {
+    pub used_data: DataBlocks,
+    pub total_data: DataBlocks,
+    pub used_meta: MetaBlocks,
+    pub total_meta: MetaBlocks,
+}
+
+impl<'a> Into<Value> for &'a ThinPoolUsage {
+    fn into(self) -> Value {
+        json!({
+            "used_data": *self.used_data,
+            "total_data": *self.total_data,
+            "used_meta": *self.used_meta,
+            "total_meta": *self.total_meta,
+        })
+    }
+}
+
+/// The progress of the reencryption of an encrypted block device under a
+/// new volume key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReencryptionProgress {
+    /// The number of bytes of the device reencrypted so far
+    pub processed: Bytes,
+    /// The total number of bytes of the device to reencrypt
+    pub total: Bytes,
+    /// Whether the reencryption was stopped by an error and must be
+    /// restarted explicitly
+    pub paused: bool,
+}
+
+/// A way of digesting the status reported on the thinpool into a value
+/// that can be checked for equality. This way, two statuses,
+/// collected at different times can be checked to determine whether their
+/// gross, as opposed to fine, differences are significant.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ThinPoolStatusDigest {
+    Fail,
+    Error,
+    Good,
+    ReadOnly,
+    OutOfSpace,
+}
+
+/// In this implementation convert the status designations to strings which
+/// match those strings that the kernel uses to identify the different states
+/// in the ioctl result.
+impl Display for ThinPoolStatusDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThinPoolStatusDigest::Good => write!(f, "rw"),
+            ThinPoolStatusDigest::ReadOnly => write!(f, "ro"),
+            ThinPoolStatusDigest::OutOfSpace => write!(f, "out_of_data_space"),
+            ThinPoolStatusDigest::Fail => write!(f, "Fail"),
+            ThinPoolStatusDigest::Error => write!(f, "Error"),
+        }
+    }
+}
+
+/// The percentages of the capacity of a pool available for filesystem data
+/// which, once in use, trigger a warning and a critical space alert.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SpaceThresholds {
+    pub warning: u8,
+    pub critical: u8,
+}
+
+impl SpaceThresholds {
+    /// The alert level of a pool which uses used of its total capacity.
+    pub fn level(&self, used: Sectors, total: Sectors) -> SpaceAlertLevel {
+        if *used * 100 >= *total * u64::from(self.critical) {
+            SpaceAlertLevel::Critical
+        } else if *used * 100 >= *total * u64::from(self.warning) {
+            SpaceAlertLevel::Warning
+        } else {
+            SpaceAlertLevel::Normal
+        }
+    }
+}
+
+impl Default for SpaceThresholds {
+    fn default() -> SpaceThresholds {
+        SpaceThresholds {
+            warning: 80,
+            critical: 95,
+        }
+    }
+}
+
+impl Display for SpaceThresholds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "warning at {}%, critical at {}%",
+            self.warning, self.critical
+        )
+    }
+}
+
+/// The level of a space alert, determined by the pool's SpaceThresholds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpaceAlertLevel {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Display for SpaceAlertLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpaceAlertLevel::Normal => write!(f, "normal"),
+            SpaceAlertLevel::Warning => write!(f, "warning"),
+            SpaceAlertLevel::Critical => write!(f, "critical"),
+        }
+    }
+}
+
 /// Redundancy classifications which the engine allows for pools.
 #[derive(Debug, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
@@ -218,8 +462,11 @@ impl fmt::Display for Name {
 ///
 /// * `ErroredPoolDevices` returns the state of devices that caused an error while
 /// attempting to reconstruct a pool.
+/// * `MissingKeys` returns, for each encrypted pool, whether the key it requires
+/// is in the kernel keyring and which unlock methods are currently viable.
 pub enum ReportType {
     ErroredPoolDevices,
+    MissingKeys,
 }
 
 impl<'a> TryFrom<&'a str> for ReportType {
@@ -228,6 +475,7 @@ impl<'a> TryFrom<&'a str> for ReportType {
     fn try_from(name: &str) -> StratisResult<ReportType> {
         match name {
             "errored_pool_report" => Ok(ReportType::ErroredPoolDevices),
+            "missing_keys_report" => Ok(ReportType::MissingKeys),
             _ => Err(StratisError::Engine(
                 ErrorEnum::NotFound,
                 format!("Report name {} not understood", name),
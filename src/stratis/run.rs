@@ -4,12 +4,14 @@
 
 //! Main loop
 
-use std::{cell::RefCell, os::unix::io::AsRawFd, rc::Rc};
+use std::{
+    cell::RefCell, cmp::min, convert::TryFrom, os::unix::io::AsRawFd, rc::Rc, time::Duration,
+};
 
 use nix::sys::signalfd::{signal, SfdFlags, SigSet, SignalFd};
 
 use crate::{
-    engine::{Engine, SimEngine, StratEngine},
+    engine::{Engine, SimEngine, StratEngine, REENCRYPT_STEP_DURATION},
     stratis::{
         dbus_support::MaybeDbusSupport,
         errors::{StratisError, StratisResult},
@@ -42,9 +44,12 @@ fn process_signal(sfd: &mut SignalFd) -> StratisResult<bool> {
     }
 }
 
-/// Handle blocking the event loop
-fn process_poll(fds: &mut Vec<libc::pollfd>) -> StratisResult<()> {
-    let poll_timeout = -1i32;
+/// Handle blocking the event loop. Block for at most the given timeout, or
+/// indefinitely if there is none.
+fn process_poll(fds: &mut Vec<libc::pollfd>, timeout: Option<Duration>) -> StratisResult<()> {
+    let poll_timeout = timeout
+        .map(|timeout| i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX))
+        .unwrap_or(-1i32);
 
     let r = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::c_ulong, poll_timeout) };
 
@@ -175,6 +180,24 @@ pub fn run(sim: bool) -> StratisResult<()> {
 
         dbus_support.process(&mut fds, dbus_client_index_start);
 
-        process_poll(&mut fds)?;
+        // Pools are reencrypted in short steps between the handling of other
+        // events, so the loop must not block for longer than a step while
+        // reencryption remains.
+        let reencrypting = engine.borrow_mut().reencrypt_step();
+
+        // Attempts to unlock pools automatically are started here, and
+        // failed attempts are made again later, so the loop must not block
+        // beyond the time the next attempt is due or the results of those
+        // in progress should be collected.
+        let next_auto_unlock = engine.borrow_mut().process_auto_unlocks();
+
+        let timeout = if reencrypting {
+            Some(next_auto_unlock.map_or(REENCRYPT_STEP_DURATION, |next| {
+                min(next, REENCRYPT_STEP_DURATION)
+            }))
+        } else {
+            next_auto_unlock
+        };
+        process_poll(&mut fds, timeout)?;
     }
 }